@@ -0,0 +1,383 @@
+use crate::apu::dmc::DMC;
+use crate::apu::resampler::Resampler;
+use crate::nes::state::{StateError, StateReader, StateWriter};
+
+// $4015
+const NOISE_ENABLE: u8 = 0x08;
+const DMC_ENABLE: u8 = 0x10;
+const DMC_INTERRUPT: u8 = 0x80;
+
+/// CPU cycles between noise shift register clocks for each period in $400E, NTSC.
+/// See: https://wiki.nesdev.com/w/index.php/APU_Noise
+const NOISE_PERIODS: [u16; 16] = [4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068];
+
+/// The 2A03's audio processing unit. The CPU talks to it through $4000-$4013, $4015 and $4017.
+///
+/// Only the DMC and the noise channel's timer and shift register are emulated so far. The noise
+/// channel has no length counter or envelope yet, so it plays at the volume in $400C whenever
+/// it's enabled in $4015. The other channels just hold on to their registers.
+/// See: https://wiki.nesdev.com/w/index.php/APU
+pub struct APU {
+    // Below taken from https://wiki.nesdev.com/w/index.php/APU_registers
+
+    // 	Pulse 1 channel (write)
+    // $4000	DDLC NNNN	Duty, loop envelope/disable length counter, constant volume, envelope period/volume
+    // $4001	EPPP NSSS	Sweep unit: enabled, period, negative, shift count
+    // $4002	LLLL LLLL	Timer low
+    // $4003	LLLL LHHH	Length counter load, timer high (also resets duty and starts envelope)
+    pulse_1: Pulse,
+
+    // Pulse 2 channel (write)
+    // $4004	DDLC NNNN	Duty, loop envelope/disable length counter, constant volume, envelope period/volume
+    // $4005	EPPP NSSS	Sweep unit: enabled, period, negative, shift count
+    // $4006	LLLL LLLL	Timer low
+    // $4007	LLLL LHHH	Length counter load, timer high (also resets duty and starts envelope)
+    pulse_2: Pulse,
+
+    // Triangle channel (write)
+    // $4008	CRRR RRRR	Length counter disable/linear counter control, linear counter reload value
+    // $400A	LLLL LLLL	Timer low
+    // $400B	LLLL LHHH	Length counter load, timer high (also reloads linear counter)
+    triangle: Triangle,
+
+    // Noise channel (write)
+    // $400C	--LC NNNN	Loop envelope/disable length counter, constant volume, envelope period/volume
+    // $400E	L--- PPPP	Loop noise, noise period
+    // $400F	LLLL L---	Length counter load (also starts envelope)
+    noise: Noise,
+
+    // DMC channel (write)
+    // $4010	IL-- FFFF	IRQ enable, loop sample, frequency index
+    // $4011	-DDD DDDD	Direct load
+    // $4012	AAAA AAAA	Sample address %11AAAAAA.AA000000
+    // $4013	LLLL LLLL	Sample length %0000LLLL.LLLL0001
+    dmc: DMC,
+
+    // TODO: $4014 writes to OAMDATA on PPU but not sure if ever actually used. Figure out if needed
+
+    // $4015	---D NT21	Control: DMC enable, length counter enables: noise, triangle, pulse 2, pulse 1 (write)
+    // $4015	IF-D NT21	Status: DMC interrupt, frame interrupt, length counter status: noise, triangle, pulse 2, pulse 1 (read)
+    control: u8,
+
+    // $4017	SD-- ----	Frame counter: 5-frame sequence, disable frame interrupt (write)
+    frame_counter: u8,
+
+    resampler: Resampler
+}
+
+impl APU {
+    pub fn new() -> APU {
+        APU {
+            pulse_1: Pulse::new(),
+            pulse_2: Pulse::new(),
+            triangle: Triangle::new(),
+            noise: Noise::new(),
+            dmc: DMC::new(),
+            control: 0x00,
+            frame_counter: 0x00,
+            resampler: Resampler::default()
+        }
+    }
+
+    /// `register` is the address minus $4000. Only $4015 can be read, the rest are write only and
+    /// return None. Whoever owns the APU decides what those read as, i.e. open bus.
+    pub fn read_register(&mut self, register: usize) -> Option<u8> {
+        match register {
+            0x15 => {
+                let mut status = 0;
+                if self.dmc.is_active() {
+                    status |= DMC_ENABLE;
+                }
+                if self.dmc.irq() {
+                    status |= DMC_INTERRUPT;
+                }
+                Some(status)
+            }
+            _ => None
+        }
+    }
+
+    /// `register` is the address minus $4000.
+    pub fn write_register(&mut self, register: usize, data: u8) {
+        match register {
+            // Pulse 1
+            0x00 => self.pulse_1.vol = data,
+            0x01 => self.pulse_1.sweep = data,
+            0x02 => self.pulse_1.lo = data,
+            0x03 => self.pulse_1.hi = data,
+
+            // Pulse 2
+            0x04 => self.pulse_2.vol = data,
+            0x05 => self.pulse_2.sweep = data,
+            0x06 => self.pulse_2.lo = data,
+            0x07 => self.pulse_2.hi = data,
+
+            // Triangle
+            0x08 => self.triangle.linear = data,
+            0x09 => {}, // Unused
+            0x0A => self.triangle.lo = data,
+            0x0B => self.triangle.hi = data,
+
+            // Noise
+            0x0C => self.noise.vol = data,
+            0x0D => {}, // Unused
+            0x0E => self.noise.lo = data,
+            0x0F => self.noise.hi = data,
+
+            // DMC
+            0x10..=0x13 => self.dmc.write_register(register - 0x10, data),
+
+            0x14 => {}, // TODO: Unsure if needed. The spec says this writes to PPU OAMDATA
+
+            0x15 => {
+                self.control = data;
+                self.dmc.set_enabled(data & DMC_ENABLE != 0);
+            }
+
+            0x16 => {}, // TODO: Unsure if needed. It says it's for feedback to joysticks
+
+            0x17 => self.frame_counter = data,
+
+            _ => unreachable!()
+        }
+    }
+
+    /// Advances the APU by a single CPU cycle.
+    pub fn tick(&mut self) {
+        self.dmc.tick();
+        self.noise.tick();
+        let output = self.output();
+        self.resampler.push(output);
+    }
+
+    /// The rate `drain_samples` produces samples at. Defaults to 44.1kHz.
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.resampler = Resampler::new(sample_rate);
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.resampler.sample_rate()
+    }
+
+    /// Fills `out` with audio at the sample rate and returns how many samples were written, which
+    /// is less than `out.len()` if the APU hasn't made that many yet.
+    pub fn drain_samples(&mut self, out: &mut [f32]) -> usize {
+        self.resampler.drain(out)
+    }
+
+    /// Samples waiting to be drained, see `drain_samples`.
+    pub fn samples_available(&self) -> usize {
+        self.resampler.len()
+    }
+
+    /// The address the DMC wants to read its next sample byte from. See `DMC::sample_request`.
+    pub fn dmc_sample_request(&self) -> Option<u16> {
+        self.dmc.sample_request()
+    }
+
+    pub fn load_dmc_sample(&mut self, data: u8) {
+        self.dmc.load_sample(data);
+    }
+
+    pub fn irq(&self) -> bool {
+        self.dmc.irq()
+    }
+
+    /// Samples waiting to be drained and the sample rate belong to the host, so they aren't saved.
+    pub(crate) fn save_state(&self, state: &mut StateWriter) {
+        for pulse in [&self.pulse_1, &self.pulse_2].iter() {
+            state.bytes(&[pulse.vol, pulse.sweep, pulse.lo, pulse.hi]);
+        }
+        state.bytes(&[self.triangle.linear, self.triangle.lo, self.triangle.hi]);
+        state.bytes(&[self.noise.vol, self.noise.lo, self.noise.hi]);
+        state.u16(self.noise.timer);
+        state.u16(self.noise.shift_register);
+        self.dmc.save_state(state);
+        state.u8(self.control);
+        state.u8(self.frame_counter);
+    }
+
+    pub(crate) fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        for pulse in [&mut self.pulse_1, &mut self.pulse_2].iter_mut() {
+            pulse.vol = state.u8()?;
+            pulse.sweep = state.u8()?;
+            pulse.lo = state.u8()?;
+            pulse.hi = state.u8()?;
+        }
+        self.triangle.linear = state.u8()?;
+        self.triangle.lo = state.u8()?;
+        self.triangle.hi = state.u8()?;
+        self.noise.vol = state.u8()?;
+        self.noise.lo = state.u8()?;
+        self.noise.hi = state.u8()?;
+        self.noise.timer = state.u16()?;
+        self.noise.shift_register = state.u16()?;
+        self.dmc.load_state(state)?;
+        self.control = state.u8()?;
+        self.frame_counter = state.u8()?;
+
+        Ok(())
+    }
+
+    /// The mixed output of every channel, 0.0-1.0. This uses the non-linear mixer from
+    /// https://wiki.nesdev.com/w/index.php/APU_Mixer where the triangle, noise and DMC share the
+    /// `tnd` group. Only the noise channel and the DMC make any sound for now.
+    pub fn output(&self) -> f32 {
+        let noise = self.noise.output(self.control & NOISE_ENABLE != 0) as f32;
+        let (triangle, dmc) = (0.0, self.dmc.output() as f32);
+        let tnd = triangle / 8227.0 + noise / 12241.0 + dmc / 22638.0;
+        if tnd == 0.0 { 0.0 } else { 159.79 / (1.0 / tnd + 100.0) }
+    }
+}
+
+/// Names of vars below based on: https://wiki.nesdev.com/w/index.php/2A03
+
+/// Pulse aka Square wave
+struct Pulse {
+    // DDLC NNNN	Duty, loop envelope/disable length counter, constant volume, envelope period/volume
+    vol: u8,
+
+    // EPPP NSSS	Sweep unit: enabled, period, negative, shift count
+    sweep: u8,
+
+    // LLLL LLLL	Timer low
+    lo: u8,
+
+    // LLLL LHHH	Length counter load, timer high (also resets duty and starts envelope)
+    hi: u8
+}
+
+impl Pulse {
+    fn new() -> Pulse {
+        Pulse {
+            vol: 0x00,
+            sweep: 0x00,
+            lo: 0x00,
+            hi: 0x00
+        }
+    }
+}
+struct Triangle {
+    // CRRR RRRR	Length counter disable/linear counter control, linear counter reload value
+    linear: u8,
+
+    // LLLL LLLL	Timer low
+    lo: u8,
+
+    // LLLL LHHH	Length counter load, timer high (also reloads linear counter)
+    hi: u8
+}
+
+impl Triangle {
+    fn new() -> Triangle {
+        Triangle {
+            linear: 0x00,
+            lo: 0x00,
+            hi: 0x00
+        }
+    }
+}
+
+struct Noise {
+    // --LC NNNN	Loop envelope/disable length counter, constant volume, envelope period/volume
+    vol: u8,
+
+    // L--- PPPP	Loop noise, noise period
+    lo: u8,
+
+    // LLLL L---	Length counter load (also starts envelope)
+    hi: u8,
+
+    // CPU cycles until the shift register is next clocked.
+    timer: u16,
+
+    // 15 bits. The channel is silent whenever bit 0 is set.
+    shift_register: u16
+}
+
+impl Noise {
+    fn new() -> Noise {
+        Noise {
+            vol: 0x00,
+            lo: 0x00,
+            hi: 0x00,
+            timer: 0,
+            // It's 1 at power on, on every console.
+            shift_register: 1
+        }
+    }
+
+    fn tick(&mut self) {
+        if self.timer == 0 {
+            self.timer = NOISE_PERIODS[(self.lo & 0x0F) as usize] - 1;
+            self.clock_shift_register();
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    /// Feedback is bit 0 XOR bit 1, or bit 6 in loop mode which gives a much shorter sequence.
+    fn clock_shift_register(&mut self) {
+        let tap = if self.lo & 0x80 != 0 { 6 } else { 1 };
+        let feedback = (self.shift_register ^ (self.shift_register >> tap)) & 1;
+        self.shift_register = (self.shift_register >> 1) | (feedback << 14);
+    }
+
+    /// 0-15. The volume bits are used as is since there's no envelope yet.
+    fn output(&self, enabled: bool) -> u8 {
+        if !enabled || self.shift_register & 1 != 0 { 0 } else { self.vol & 0x0F }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dmc_is_mixed_into_tnd() {
+        let mut apu = APU::new();
+        assert_eq!(apu.output(), 0.0);
+
+        apu.write_register(0x11, 127);
+
+        // The loudest the DMC can be on its own is about 0.57
+        assert!((apu.output() - 0.574).abs() < 0.001, "{}", apu.output());
+    }
+
+    #[test]
+    fn noise_shift_register_is_clocked_by_the_period() {
+        let mut apu = APU::new();
+        apu.write_register(0x0C, 0x0F);
+        apu.write_register(0x0E, 0x01); // Every 8 CPU cycles
+        assert_eq!(apu.noise.shift_register, 1);
+
+        // The first clock happens straight away. 1 has feedback 1 ^ 0 so it goes to the top.
+        apu.tick();
+        assert_eq!(apu.noise.shift_register, 0x4000);
+        for _ in 0..7 {
+            apu.tick();
+        }
+        assert_eq!(apu.noise.shift_register, 0x4000);
+        apu.tick();
+        assert_eq!(apu.noise.shift_register, 0x2000);
+
+        // Bit 0 is clear so it's only silent because the channel isn't enabled.
+        assert_eq!(apu.output(), 0.0);
+        apu.write_register(0x15, NOISE_ENABLE);
+        assert!(apu.output() > 0.0);
+    }
+
+    #[test]
+    fn noise_loop_mode_repeats_every_93_clocks() {
+        let mut noise = Noise::new();
+        noise.lo = 0x80;
+        let mut seen = Vec::new();
+        for _ in 0..93 {
+            seen.push(noise.shift_register);
+            noise.clock_shift_register();
+        }
+
+        assert_eq!(noise.shift_register, 1);
+        assert!(!seen[1..].contains(&1));
+    }
+}
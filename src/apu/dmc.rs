@@ -0,0 +1,269 @@
+use crate::nes::state::{StateError, StateReader, StateWriter};
+
+/// Timer periods in CPU cycles for each of the 16 rates, NTSC.
+/// See: https://wiki.nesdev.com/w/index.php/APU_DMC
+const RATES: [u16; 16] = [428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54];
+
+/// Number of cycles the CPU is stalled for while the DMC fetches a sample byte.
+pub const SAMPLE_FETCH_STALL: u8 = 4;
+
+/// The delta modulation channel. It plays 1 bit delta encoded samples straight out of PRG, each bit
+/// moving the output level up or down by 2. Reading the samples steals cycles from the CPU.
+pub struct DMC {
+    // $4010	IL-- FFFF	IRQ enable, loop sample, frequency index
+    irq_enabled: bool,
+    loop_sample: bool,
+    rate: u16,
+    timer: u16,
+
+    // $4011	-DDD DDDD	Direct load
+    output_level: u8,
+
+    // $4012	AAAA AAAA	Sample address %11AAAAAA.AA000000
+    sample_address: u16,
+
+    // $4013	LLLL LLLL	Sample length %0000LLLL.LLLL0001
+    sample_length: u16,
+
+    // Memory reader
+    current_address: u16,
+    bytes_remaining: u16,
+    sample_buffer: Option<u8>,
+
+    // Output unit
+    shift_register: u8,
+    bits_remaining: u8,
+    silence: bool,
+
+    irq_pending: bool
+}
+
+impl DMC {
+    pub fn new() -> DMC {
+        DMC {
+            irq_enabled: false,
+            loop_sample: false,
+            rate: RATES[0],
+            timer: RATES[0],
+            output_level: 0,
+            sample_address: 0xC000,
+            sample_length: 1,
+            current_address: 0xC000,
+            bytes_remaining: 0,
+            sample_buffer: None,
+            shift_register: 0,
+            bits_remaining: 8,
+            silence: true,
+            irq_pending: false
+        }
+    }
+
+    /// `register` is 0-3 for $4010-$4013.
+    pub fn write_register(&mut self, register: usize, data: u8) {
+        match register {
+            0 => {
+                self.irq_enabled = data & 0x80 != 0;
+                self.loop_sample = data & 0x40 != 0;
+                self.rate = RATES[(data & 0x0F) as usize];
+                if !self.irq_enabled {
+                    self.irq_pending = false;
+                }
+            }
+            1 => self.output_level = data & 0x7F,
+            2 => self.sample_address = 0xC000 | (data as u16) << 6,
+            3 => self.sample_length = (data as u16) << 4 | 1,
+            _ => unreachable!()
+        }
+    }
+
+    /// Bit 4 of $4015. Enabling restarts the sample if it had finished, disabling stops it.
+    /// Either way the IRQ is acknowledged.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.irq_pending = false;
+        if !enabled {
+            self.bytes_remaining = 0;
+        } else if self.bytes_remaining == 0 {
+            self.restart();
+        }
+    }
+
+    /// Whether there's still some of the sample left to read, i.e. bit 4 of $4015.
+    pub fn is_active(&self) -> bool {
+        self.bytes_remaining > 0
+    }
+
+    pub fn irq(&self) -> bool {
+        self.irq_pending
+    }
+
+    /// 0-127
+    pub fn output(&self) -> u8 {
+        self.output_level
+    }
+
+    /// The address the DMC wants the next sample byte from, if its buffer is empty. Whoever owns
+    /// the bus reads it and hands it back through `load_sample`.
+    pub fn sample_request(&self) -> Option<u16> {
+        if self.sample_buffer.is_none() && self.bytes_remaining > 0 {
+            Some(self.current_address)
+        } else {
+            None
+        }
+    }
+
+    pub fn load_sample(&mut self, data: u8) {
+        self.sample_buffer = Some(data);
+        // The address wraps around to $8000 rather than $0000.
+        self.current_address = if self.current_address == 0xFFFF { 0x8000 } else { self.current_address + 1 };
+        self.bytes_remaining -= 1;
+        if self.bytes_remaining == 0 {
+            if self.loop_sample {
+                self.restart();
+            } else if self.irq_enabled {
+                self.irq_pending = true;
+            }
+        }
+    }
+
+    /// Clocks the timer once. This is once per CPU cycle.
+    pub fn tick(&mut self) {
+        if self.timer > 1 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.rate;
+
+        if !self.silence {
+            // The level doesn't wrap. Deltas that would take it outside of 0-127 are skipped.
+            if self.shift_register & 1 != 0 {
+                if self.output_level <= 125 {
+                    self.output_level += 2;
+                }
+            } else if self.output_level >= 2 {
+                self.output_level -= 2;
+            }
+        }
+        self.shift_register >>= 1;
+        self.bits_remaining -= 1;
+
+        if self.bits_remaining == 0 {
+            self.bits_remaining = 8;
+            match self.sample_buffer.take() {
+                Some(sample) => {
+                    self.silence = false;
+                    self.shift_register = sample;
+                }
+                None => self.silence = true,
+            }
+        }
+    }
+
+    fn restart(&mut self) {
+        self.current_address = self.sample_address;
+        self.bytes_remaining = self.sample_length;
+    }
+
+    pub(crate) fn save_state(&self, state: &mut StateWriter) {
+        state.bool(self.irq_enabled);
+        state.bool(self.loop_sample);
+        state.u16(self.rate);
+        state.u16(self.timer);
+        state.u8(self.output_level);
+        state.u16(self.sample_address);
+        state.u16(self.sample_length);
+        state.u16(self.current_address);
+        state.u16(self.bytes_remaining);
+        state.bool(self.sample_buffer.is_some());
+        state.u8(self.sample_buffer.unwrap_or(0));
+        state.u8(self.shift_register);
+        state.u8(self.bits_remaining);
+        state.bool(self.silence);
+        state.bool(self.irq_pending);
+    }
+
+    pub(crate) fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.irq_enabled = state.bool()?;
+        self.loop_sample = state.bool()?;
+        self.rate = state.u16()?;
+        self.timer = state.u16()?;
+        self.output_level = state.u8()?;
+        self.sample_address = state.u16()?;
+        self.sample_length = state.u16()?;
+        self.current_address = state.u16()?;
+        self.bytes_remaining = state.u16()?;
+        let has_sample = state.bool()?;
+        let sample = state.u8()?;
+        self.sample_buffer = if has_sample { Some(sample) } else { None };
+        self.shift_register = state.u8()?;
+        self.bits_remaining = state.u8()?;
+        self.silence = state.bool()?;
+        self.irq_pending = state.bool()?;
+        // It's reloaded with 8 as soon as it counts down to 0.
+        if self.bits_remaining == 0 || self.bits_remaining > 8 {
+            return Err(StateError::Corrupt);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn each_bit_moves_level_by_two() {
+        let mut dmc = DMC::new();
+        dmc.write_register(0, 0x8F); // IRQ, fastest rate
+        dmc.write_register(1, 64);
+        dmc.write_register(2, 0x00); // $C000
+        dmc.write_register(3, 0x00); // 1 byte
+        dmc.set_enabled(true);
+
+        assert_eq!(dmc.sample_request(), Some(0xC000));
+        dmc.load_sample(0b0000_0101);
+        assert_eq!(dmc.sample_request(), None);
+        assert!(dmc.irq());
+
+        // The sample only starts after the current 8 bits, which are silent. The timer was
+        // already counting down at the slowest rate for the first of them.
+        for _ in 0..428 + 7 * 54 {
+            dmc.tick();
+        }
+        assert_eq!(dmc.output(), 64);
+
+        let play_bit = |dmc: &mut DMC| {
+            for _ in 0..54 {
+                dmc.tick();
+            }
+            dmc.output()
+        };
+        let levels: Vec<u8> = (0..8).map(|_| play_bit(&mut dmc)).collect();
+        assert_eq!(levels, vec![66, 64, 66, 64, 62, 60, 58, 56]);
+    }
+
+    #[test]
+    fn level_stays_in_range() {
+        let mut dmc = DMC::new();
+        dmc.write_register(0, 0x0F);
+        dmc.write_register(1, 127);
+        dmc.set_enabled(true);
+        dmc.load_sample(0xFF);
+        for _ in 0..54 * 16 {
+            dmc.tick();
+        }
+
+        assert_eq!(dmc.output(), 127);
+    }
+
+    #[test]
+    fn states_with_no_bits_remaining_are_corrupt() {
+        let mut dmc = DMC::new();
+        dmc.bits_remaining = 0;
+        let mut state = StateWriter::new();
+        dmc.save_state(&mut state);
+        let state = state.into_bytes();
+
+        assert_eq!(DMC::new().load_state(&mut StateReader::new(&state)), Err(StateError::Corrupt));
+    }
+}
@@ -0,0 +1,3 @@
+pub mod apu;
+pub mod dmc;
+pub mod resampler;
@@ -0,0 +1,138 @@
+use crate::cpu::cpu::NTSC_CPU_HZ;
+use alloc::collections::VecDeque;
+
+/// The APU makes a sample every CPU cycle, i.e. at the NTSC CPU clock rate.
+pub const NATIVE_SAMPLE_RATE: u32 = NTSC_CPU_HZ;
+
+pub const DEFAULT_SAMPLE_RATE: u32 = 44_100;
+
+/// Resampled audio that hasn't been drained is capped at this many seconds. Anything older is
+/// dropped so nothing builds up if nobody is listening.
+const MAX_BUFFERED_SECONDS: u32 = 1;
+
+/// Turns the APU's native rate output into something a host can play, e.g. 44.1kHz.
+///
+/// Each output sample is the average of the native samples that fall in its period. Averaging is a
+/// low pass filter, which stops anything above what the output rate can hold from aliasing back
+/// down into the audible range.
+pub struct Resampler {
+    sample_rate: u32,
+    // Native samples per output sample. Not a whole number so the remainder carries over.
+    step: f64,
+    position: f64,
+    sum: f32,
+    count: u32,
+    samples: VecDeque<f32>,
+}
+
+impl Resampler {
+    pub fn new(sample_rate: u32) -> Self {
+        Resampler {
+            sample_rate,
+            step: NATIVE_SAMPLE_RATE as f64 / sample_rate as f64,
+            position: 0.0,
+            sum: 0.0,
+            count: 0,
+            samples: VecDeque::new(),
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Output samples waiting to be drained.
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    /// Adds a sample at the native rate.
+    pub fn push(&mut self, sample: f32) {
+        self.sum += sample;
+        self.count += 1;
+        self.position += 1.0;
+        if self.position >= self.step {
+            self.position -= self.step;
+            if self.samples.len() >= (self.sample_rate * MAX_BUFFERED_SECONDS) as usize {
+                self.samples.pop_front();
+            }
+            self.samples.push_back(self.sum / self.count as f32);
+            self.sum = 0.0;
+            self.count = 0;
+        }
+    }
+
+    /// Moves as many resampled samples as fit into `out` and returns how many that was. Anything
+    /// left over stays for the next call.
+    pub fn drain(&mut self, out: &mut [f32]) -> usize {
+        let count = out.len().min(self.samples.len());
+        for (out, sample) in out.iter_mut().zip(self.samples.drain(..count)) {
+            *out = sample;
+        }
+
+        count
+    }
+}
+
+impl Default for Resampler {
+    fn default() -> Self {
+        Resampler::new(DEFAULT_SAMPLE_RATE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Number of times the signal crosses the middle of its range.
+    fn zero_crossings(samples: &[f32]) -> usize {
+        let middle = samples.iter().sum::<f32>() / samples.len() as f32;
+        samples.windows(2).filter(|pair| (pair[0] < middle) != (pair[1] < middle)).count()
+    }
+
+    #[test]
+    fn tone_keeps_its_frequency() {
+        let mut resampler = Resampler::new(44_100);
+        // A tenth of a second of a 440Hz tone
+        for i in 0..NATIVE_SAMPLE_RATE / 10 {
+            let t = i as f64 / NATIVE_SAMPLE_RATE as f64;
+            resampler.push((t * 440.0 * 2.0 * std::f64::consts::PI).sin() as f32);
+        }
+
+        let mut out = vec![0.0; 10_000];
+        let count = resampler.drain(&mut out);
+
+        // 4410 give or take the part of a sample still being averaged.
+        assert!((4409..=4410).contains(&count), "{}", count);
+        // 2 crossings per cycle
+        let crossings = zero_crossings(&out[..count]);
+        assert!((87..=89).contains(&crossings), "{}", crossings);
+    }
+
+    #[test]
+    fn leftovers_are_kept_for_the_next_drain() {
+        let mut resampler = Resampler::new(44_100);
+        for i in 0..NATIVE_SAMPLE_RATE / 100 {
+            resampler.push(i as f32);
+        }
+        let total = resampler.len();
+
+        let mut drained = vec![];
+        let mut out = [0.0; 100];
+        loop {
+            let count = resampler.drain(&mut out);
+            if count == 0 {
+                break;
+            }
+            drained.extend_from_slice(&out[..count]);
+        }
+
+        assert_eq!(drained.len(), total);
+        // The input only ever goes up so a dropped or repeated sample would show up here.
+        assert!(drained.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+}
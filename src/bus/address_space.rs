@@ -0,0 +1,78 @@
+/// Everything the CPU can address. The CPU is generic over this so it isn't tied to the NES, e.g.
+/// `Bus` is the NES' memory map while `FlatMemory` is plain 64kb of RAM for other 6502 machines.
+///
+/// Read and write functions that are expected to go through memory mapping in order to read/write
+/// to the correct memory mapped device.
+///
+/// The way this is intended to work is that something tries to write to a memory address on the
+/// Bus. This address is then passed through the memory map to a device and the address itself is
+/// converted to the literal address that the device on the bus can use.
+///
+/// e.g. A map containing two devices.
+///      One from memory $00 -> $19 and another from $20-$FF.
+///      Caller request address $A1. This calls the second device. The mapping in that second device
+///      determines that $A1 is actually $21 in the actual device.
+///
+/// Reads can have side effects on memory mapped devices, e.g. reading PPUSTATUS clears the vblank
+/// flag. `peek` is for debugging tools and has no side effects.
+pub trait AddressSpace {
+    fn read(&mut self, address: u16) -> u8;
+    fn write(&mut self, address: u16, data: u8) -> ();
+    fn peek(&self, address: u16) -> u8;
+
+    /// Reads a little endian word, i.e. the low byte from `address` and then the high byte from
+    /// the address after it, e.g. for vectors and absolute addresses.
+    fn read16(&mut self, address: u16) -> u16 {
+        let lo = self.read(address);
+        let hi = self.read(address.wrapping_add(1));
+        u16::from_le_bytes([lo, hi])
+    }
+
+    /// Like `read16` for a pointer in page zero, which is what the indirect addressing modes read.
+    /// A pointer at $FF gets its high byte from $00 rather than $0100.
+    fn read16_zp_wrap(&mut self, address: u8) -> u16 {
+        let lo = self.read(address as u16);
+        let hi = self.read(address.wrapping_add(1) as u16);
+        u16::from_le_bytes([lo, hi])
+    }
+
+    /// Writes a little endian word, the low byte first.
+    fn write16(&mut self, address: u16, data: u16) {
+        let [lo, hi] = data.to_le_bytes();
+        self.write(address, lo);
+        self.write(address.wrapping_add(1), hi);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::flat_memory::FlatMemory;
+
+    #[test]
+    fn words_are_little_endian() {
+        let mut memory = FlatMemory::default();
+        memory.write16(0x1234, 0xBEEF);
+
+        assert_eq!(memory.read(0x1234), 0xEF);
+        assert_eq!(memory.read(0x1235), 0xBE);
+        assert_eq!(memory.read16(0x1234), 0xBEEF);
+
+        // The whole address space wraps around.
+        memory.write16(0xFFFF, 0x1280);
+        assert_eq!(memory.read(0x0000), 0x12);
+        assert_eq!(memory.read16(0xFFFF), 0x1280);
+    }
+
+    #[test]
+    fn zero_page_pointers_wrap_within_page_zero() {
+        let mut memory = FlatMemory::default();
+        memory.write(0x00FF, 0x34);
+        memory.write(0x0000, 0x12);
+        memory.write(0x0100, 0x56);
+
+        assert_eq!(memory.read16_zp_wrap(0xFF), 0x1234);
+        assert_eq!(memory.read16(0x00FF), 0x5634);
+        assert_eq!(memory.read16_zp_wrap(0x00), memory.read16(0x0000));
+    }
+}
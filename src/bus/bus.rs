@@ -1,4 +1,15 @@
-use crate::rom::rom::{ROM, ROMError};
+use crate::apu::apu::APU;
+use crate::apu::dmc::SAMPLE_FETCH_STALL;
+use crate::bus::address_space::AddressSpace;
+use crate::bus::cheat::{Cheat, CheatError};
+use crate::bus::controller::{Button, Controller, FourScore};
+use crate::nes::state::{StateError, StateReader, StateWriter};
+use crate::ppu::ppu::PPU;
+use crate::rom::mapper::Mapper;
+use crate::rom::rom::{Cartridge, ROMError};
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::fmt;
 
 const ROM_START: usize = 0x8000;
 const ROM_END: usize = 0xFFFF;
@@ -8,89 +19,534 @@ const PPU_START: usize = 0x2000;
 const PPU_END: usize = 0x3FFF;
 const APU_IO_START: usize = 0x4000;
 const APU_IO_END: usize = 0x4017;
+const OAM_DMA: usize = 0x4014;
+const CONTROLLER_1: usize = 0x4016;
+const CONTROLLER_2: usize = 0x4017;
 const TEST_MODE_START: usize = 0x4018;
 const TEST_MODE_END: usize = 0x401F;
-const CARTRIDGE_START: usize = 0x4020;
-const CARTRIDGE_END: usize = 0xFFFF;
+const EXPANSION_START: usize = 0x4020;
+const EXPANSION_END: usize = 0x5FFF;
+const PRG_RAM_START: usize = 0x6000;
+const PRG_RAM_END: usize = 0x7FFF;
 
 const RAM_SIZE: usize = 0x800; // i.e. 2kb.
 
+/// Copying a page into OAM takes 512 cycles plus 1 waiting for the write to finish. Another one
+/// is needed when it starts on an odd cycle.
+const OAM_DMA_STALL: u16 = 513;
+
 #[derive(Debug, Clone)]
 pub enum BusError {}
 
+impl fmt::Display for BusError {
+    fn fmt(&self, _f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {}
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for BusError {}
+
 pub struct Bus {
     ram: RAM,
-    rom: ROM,
-    io_registers: IORegisters
+    // How RAM powers on. Kept so powering on again, e.g. after loading another rom, matches.
+    ram_init: RamInit,
+    cartridge: Cartridge,
+    ppu: PPU,
+    apu: APU,
+    // Controllers 3 and 4 can only be read through a Four Score.
+    controllers: [Controller; 4],
+    four_score: Option<FourScore>,
+    watchpoints: Vec<(u16, WatchKind)>,
+    // The most recent access that matched a watchpoint. Cleared when taken.
+    watchpoint_hit: Option<(u16, u8)>,
+    cheats: Vec<Cheat>,
+    // Set by writing to $4014. The copy happens on the next tick.
+    oam_dma_page: Option<u8>,
+    // CPU cycles the bus has been ticked for. OAM DMA needs to know whether a cycle is odd.
+    cycles: u64,
+    // The last value read or written. Reading something that doesn't drive the data bus, e.g. a
+    // write only register, gets this.
+    open_bus: u8,
+    // Reads and writes per address while profiling. A BTreeMap since HashMap needs std.
+    access_counts: Option<BTreeMap<u16, u64>>,
+}
+
+/// What's behind an address on the bus, e.g. for labelling a debugger's memory view.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MappedDevice {
+    Ram,
+    Ppu,
+    Apu,
+    /// $4016 and $4017. $4017 is also the APU frame counter when written.
+    Controller,
+    /// $4018-$401F. Normally disabled APU and IO test functionality.
+    TestMode,
+    /// $4020-$5FFF. Only a few mappers put anything here and none of them are supported yet, so
+    /// it's open bus.
+    Expansion,
+    PrgRam,
+    /// $8000-$FFFF, i.e. PRG through the mapper.
+    Cartridge,
+}
+
+/// Which kind of memory access a watchpoint triggers on.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    Access,
 }
 
 impl Bus {
     pub fn new(rom: Vec<u8>) -> Result<Bus, ROMError> { // TODO: Update the error handling here
-        Ok(Bus {
-            ram: RAM::new(),
-            rom: ROM::new(rom)?,
-            io_registers: IORegisters::new()
-        })
+        Bus::with_ram_init(rom, RamInit::Zeroed)
+    }
+
+    /// Like `new` but with the RAM and the cartridge's PRG RAM filled according to `ram_init`.
+    pub fn with_ram_init(rom: Vec<u8>, ram_init: RamInit) -> Result<Bus, ROMError> {
+        let mut cartridge = Cartridge::from_bytes(rom)?;
+        ram_init.fill(cartridge.prg_ram_mut());
+        cartridge.load_trainer();
+
+        Ok(Bus::with_cartridge(cartridge, ram_init))
+    }
+
+    /// For a rom that's already been parsed, e.g. to look at it before deciding to run it. The
+    /// bus takes the cartridge over since mappers and PRG RAM change as the game runs; it can
+    /// still be read through `cartridge()`. PRG RAM is left as it is, e.g. with a save loaded.
+    pub fn from_cartridge(cartridge: Cartridge) -> Bus {
+        Bus::with_cartridge(cartridge, RamInit::Zeroed)
+    }
+
+    fn with_cartridge(cartridge: Cartridge, ram_init: RamInit) -> Bus {
+        let mut ppu = PPU::new();
+        ppu.set_mirroring(cartridge.mirroring());
+        Bus {
+            ram: RAM::new(ram_init),
+            ram_init,
+            cartridge,
+            ppu,
+            apu: APU::new(),
+            controllers: [Controller::new(), Controller::new(), Controller::new(), Controller::new()],
+            four_score: None,
+            watchpoints: Vec::new(),
+            watchpoint_hit: None,
+            cheats: Vec::new(),
+            oam_dma_page: None,
+            cycles: 0,
+            open_bus: 0,
+            access_counts: None,
+        }
     }
 
-    /// This is just a helper function mapping of address to device.
-    fn get_mapped_device_and_real_address(&mut self, address: usize) -> (&mut dyn BusDevice, usize) {
+    /// Peeks `len` bytes starting at `start`, wrapping around at $FFFF. Registers read as 0, see
+    /// `peek`.
+    pub fn dump(&self, start: u16, len: usize) -> Vec<u8> {
+        (0..len).map(|offset| self.peek(start.wrapping_add(offset as u16))).collect()
+    }
+
+    pub fn device_for(&self, address: u16) -> MappedDevice {
+        self.device_and_offset(address).0
+    }
+
+    /// The device behind `address` and the address within that device it ends up at, after
+    /// mirroring, e.g. $0801 is RAM offset 1 and $8000 is offset 0 into the mapper's PRG window.
+    pub fn device_and_offset(&self, address: u16) -> (MappedDevice, usize) {
+        let address = address as usize;
         match address {
-            RAM_START..=RAM_END => (&mut self.ram, address),
-            PPU_START..=PPU_END => unimplemented!(),
-            APU_IO_START..=APU_IO_END => (&mut self.io_registers, address - APU_IO_START),
-            TEST_MODE_START..=TEST_MODE_END => unimplemented!(),
-            CARTRIDGE_START..=CARTRIDGE_END => (&mut self.rom, address - ROM_START), // FIXME: this shouldn't be hard coded
+            RAM_START..=RAM_END => (MappedDevice::Ram, address % RAM_SIZE),
+            PPU_START..=PPU_END => (MappedDevice::Ppu, (address - PPU_START) % 8),
+            CONTROLLER_1 | CONTROLLER_2 => (MappedDevice::Controller, address - CONTROLLER_1),
+            APU_IO_START..=APU_IO_END => (MappedDevice::Apu, address - APU_IO_START),
+            TEST_MODE_START..=TEST_MODE_END => (MappedDevice::TestMode, address - TEST_MODE_START),
+            EXPANSION_START..=EXPANSION_END => (MappedDevice::Expansion, address - EXPANSION_START),
+            PRG_RAM_START..=PRG_RAM_END => (MappedDevice::PrgRam, address - PRG_RAM_START),
+            ROM_START..=ROM_END => (MappedDevice::Cartridge, address - ROM_START),
             _ => unreachable!()
         }
     }
+
+    /// Adds a 6 or 8 letter Game Genie code. Reads of the code's address return the code's value.
+    pub fn add_cheat(&mut self, code: &str) -> Result<(), CheatError> {
+        self.cheats.push(Cheat::decode(code)?);
+        Ok(())
+    }
+
+    pub fn remove_cheat(&mut self, code: &str) {
+        let code = code.to_ascii_uppercase();
+        self.cheats.retain(|cheat| cheat.code != code);
+    }
+
+    pub fn clear_cheats(&mut self) {
+        self.cheats.clear();
+    }
+
+    pub fn add_watchpoint(&mut self, addr: u16, kind: WatchKind) {
+        self.watchpoints.push((addr, kind));
+    }
+
+    pub fn remove_watchpoint(&mut self, addr: u16) {
+        self.watchpoints.retain(|(watched, _)| *watched != addr);
+    }
+
+    pub fn clear_watchpoints(&mut self) {
+        self.watchpoints.clear();
+    }
+
+    /// Returns the address and value of the last access that hit a watchpoint, if there was one
+    /// since the last time this was called.
+    pub fn take_watchpoint_hit(&mut self) -> Option<(u16, u8)> {
+        self.watchpoint_hit.take()
+    }
+
+    fn check_watchpoints(&mut self, address: u16, data: u8, write: bool) {
+        let hit = self.watchpoints.iter().any(|(watched, kind)| {
+            *watched == address && match kind {
+                WatchKind::Read => !write,
+                WatchKind::Write => write,
+                WatchKind::Access => true,
+            }
+        });
+        if hit {
+            self.watchpoint_hit = Some((address, data));
+        }
+    }
+
+    /// Starts counting reads and writes of each address, or stops and throws the counts away.
+    /// When off the only cost is checking whether it's on.
+    pub fn enable_profiling(&mut self, enabled: bool) {
+        self.access_counts = if enabled { Some(self.access_counts.take().unwrap_or_default()) } else { None };
+    }
+
+    /// The `n` most accessed addresses since profiling was enabled, most accessed first. Ties
+    /// are in address order.
+    pub fn top_accessed(&self, n: usize) -> Vec<(u16, u64)> {
+        let mut counts: Vec<(u16, u64)> = self.access_counts.iter()
+            .flat_map(|counts| counts.iter().map(|(address, count)| (*address, *count)))
+            .collect();
+        counts.sort_by(|a, b| b.1.cmp(&a.1));
+        counts.truncate(n);
+        counts
+    }
+
+    fn count_access(&mut self, address: u16) {
+        if let Some(counts) = self.access_counts.as_mut() {
+            *counts.entry(address).or_insert(0) += 1;
+        }
+    }
+
+    pub fn cartridge(&self) -> &Cartridge {
+        &self.cartridge
+    }
+
+    pub fn cartridge_mut(&mut self) -> &mut Cartridge {
+        &mut self.cartridge
+    }
+
+    /// Presses or releases a button on controller 0-3. Controllers 2 and 3 need a Four Score.
+    pub fn set_button(&mut self, pad: usize, button: Button, pressed: bool) {
+        self.controllers[pad].set_button(button, pressed);
+    }
+
+    /// Turns turbo on or off for a button on controller 0-3, see `Controller::set_turbo`.
+    pub fn set_turbo(&mut self, pad: usize, button: Button, rate_hz: Option<f32>) {
+        self.controllers[pad].set_turbo(button, rate_hz);
+    }
+
+    /// Moves every controller's turbo buttons on a frame.
+    pub fn next_input_frame(&mut self) {
+        self.controllers.iter_mut().for_each(Controller::next_frame);
+    }
+
+    /// Plugs in or unplugs a Four Score.
+    pub fn set_four_score(&mut self, enabled: bool) {
+        self.four_score = if enabled { Some(FourScore::new()) } else { None };
+    }
+
+    pub fn has_four_score(&self) -> bool {
+        self.four_score.is_some()
+    }
+
+    fn read_controller_port(&mut self, port: usize) -> u8 {
+        match self.four_score.as_mut() {
+            Some(four_score) => four_score.read(port, &self.controllers),
+            None => self.controllers[port].read(),
+        }
+    }
+
+    /// Sets all of the buttons for the controllers in both ports, e.g. from a movie.
+    pub fn set_controller_state(&mut self, pad1: u8, pad2: u8) {
+        self.controllers[0].set_buttons(pad1);
+        self.controllers[1].set_buttons(pad2);
+    }
+
+    pub fn ppu(&self) -> &PPU {
+        &self.ppu
+    }
+
+    pub fn ppu_mut(&mut self) -> &mut PPU {
+        &mut self.ppu
+    }
+
+    /// Advances everything on the bus other than the CPU by `cpu_cycles` CPU cycles, i.e. the APU
+    /// by the same number of cycles and the PPU by 3 dots for each. Returns the number of cycles
+    /// the CPU is stalled for by DMA, either the DMC fetching a sample or a copy into OAM.
+    pub fn tick(&mut self, cpu_cycles: u8) -> u16 {
+        let mut stall = 0;
+        for _ in 0..cpu_cycles {
+            stall += self.tick_apu() as u16;
+            for _ in 0..3 {
+                self.tick_ppu();
+            }
+            self.cycles += 1;
+        }
+        if let Some(page) = self.oam_dma_page.take() {
+            stall += self.oam_dma(page);
+        }
+
+        stall
+    }
+
+    /// Copies $XX00-$XXFF into OAM through OAMDATA, so it starts at OAMADDR. Returns the cycles the
+    /// CPU is stalled for.
+    fn oam_dma(&mut self, page: u8) -> u16 {
+        for offset in 0..=0xFF {
+            let data = self.read((page as u16) << 8 | offset);
+            self.ppu.write_register(4, data);
+        }
+
+        OAM_DMA_STALL + (self.cycles % 2) as u16
+    }
+
+    /// Ticks the PPU a single dot and lets the mapper see what it fetched.
+    pub fn tick_ppu(&mut self) {
+        let cartridge = &self.cartridge;
+        self.ppu.tick_with_chr(|address| cartridge.read_chr(address));
+        if let Some(address) = self.ppu.pattern_fetch_address() {
+            self.cartridge.mapper.ppu_fetch(address);
+        }
+    }
+
+    /// Advances the APU by a CPU cycle. Returns the number of cycles the CPU is stalled for, which
+    /// is when the DMC takes over the bus to read a sample byte.
+    pub fn tick_apu(&mut self) -> u8 {
+        self.apu.tick();
+        match self.apu.dmc_sample_request() {
+            Some(address) => {
+                let data = self.read(address);
+                self.apu.load_dmc_sample(data);
+                SAMPLE_FETCH_STALL
+            }
+            None => 0
+        }
+    }
+
+    pub fn apu(&self) -> &APU {
+        &self.apu
+    }
+
+    pub fn apu_mut(&mut self) -> &mut APU {
+        &mut self.apu
+    }
+
+    /// Whether anything on the bus is holding the IRQ line, i.e. the mapper or the DMC.
+    pub fn irq(&self) -> bool {
+        self.cartridge.mapper.irq() || self.apu.irq()
+    }
+
+    /// Puts RAM back how it was at power on, see `RamInit`.
+    pub(crate) fn clear_ram(&mut self) {
+        self.ram_init.fill(&mut self.ram.memory);
+    }
+
+    pub fn ram_init(&self) -> RamInit {
+        self.ram_init
+    }
+
+    /// RAM and everything plugged into the bus. Watchpoints, cheats and whether a Four Score is
+    /// plugged in are settings, so they're left as they are when loading.
+    pub(crate) fn save_state(&self, state: &mut StateWriter) {
+        state.bytes(&self.ram.memory);
+        for controller in self.controllers.iter() {
+            controller.save_state(state);
+        }
+        state.bool(self.four_score.is_some());
+        if let Some(four_score) = self.four_score.as_ref() {
+            four_score.save_state(state);
+        }
+        state.bool(self.oam_dma_page.is_some());
+        state.u8(self.oam_dma_page.unwrap_or(0));
+        state.u64(self.cycles);
+        state.u8(self.open_bus);
+        self.cartridge.save_state(state);
+        self.ppu.save_state(state);
+        self.apu.save_state(state);
+    }
+
+    pub(crate) fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.bytes(&mut self.ram.memory)?;
+        for controller in self.controllers.iter_mut() {
+            controller.load_state(state)?;
+        }
+        if state.bool()? {
+            // Still read when the Four Score has since been unplugged, to get past it.
+            let mut saved = FourScore::new();
+            saved.load_state(state)?;
+            if let Some(four_score) = self.four_score.as_mut() {
+                *four_score = saved;
+            }
+        }
+        let dma_pending = state.bool()?;
+        let page = state.u8()?;
+        self.oam_dma_page = if dma_pending { Some(page) } else { None };
+        self.cycles = state.u64()?;
+        self.open_bus = state.u8()?;
+        self.cartridge.load_state(state)?;
+        self.ppu.load_state(state)?;
+        self.ppu.set_mirroring(self.cartridge.mirroring());
+        self.apu.load_state(state)
+    }
 }
 
-impl MemoryMap for Bus {
+/// Each arm below calls the concrete device directly rather than going through a `dyn BusDevice`.
+/// This is the hottest path in the emulator so it's worth avoiding the vtable.
+impl AddressSpace for Bus {
     fn read(&mut self, address: u16) -> u8 {
-        let address = address as usize;
-        let (device, real_address) = self.get_mapped_device_and_real_address(address);
-        device.read(real_address)
+        let data = match address as usize {
+            RAM_START..=RAM_END => self.ram.read(address as usize),
+            PPU_START..=PPU_END => {
+                let cartridge = &self.cartridge;
+                self.ppu.read_register_with_chr((address as usize - PPU_START) % 8, |address| cartridge.read_chr(address))
+            }
+            // Only bit 0 comes from the controller. The upper bits are usually left over from the
+            // high byte of the address, i.e. $40.
+            CONTROLLER_1 => 0x40 | self.read_controller_port(0),
+            CONTROLLER_2 => 0x40 | self.read_controller_port(1),
+            APU_IO_START..=APU_IO_END => self.apu.read_register(address as usize - APU_IO_START).unwrap_or(self.open_bus),
+            // Only enabled on development hardware.
+            TEST_MODE_START..=TEST_MODE_END => self.open_bus,
+            EXPANSION_START..=EXPANSION_END => self.open_bus,
+            PRG_RAM_START..=PRG_RAM_END => self.cartridge.prg_ram()[address as usize - PRG_RAM_START],
+            ROM_START..=ROM_END => {
+                let data = self.cartridge.read(address as usize - ROM_START);
+                self.cheats.iter().fold(data, |data, cheat| cheat.apply(address, data))
+            }
+            _ => unreachable!()
+        };
+        if !self.watchpoints.is_empty() {
+            self.check_watchpoints(address, data, false);
+        }
+        self.count_access(address);
+        self.open_bus = data;
+
+        data
     }
 
     fn write(&mut self, address: u16, data: u8) -> () {
+        if !self.watchpoints.is_empty() {
+            self.check_watchpoints(address, data, true);
+        }
+        self.count_access(address);
+        self.open_bus = data;
         let address = address as usize;
-        let (device, real_address) = self.get_mapped_device_and_real_address(address);
-        device.write(real_address, data)
+        match address {
+            RAM_START..=RAM_END => self.ram.write(address, data),
+            PPU_START..=PPU_END => {
+                let cartridge = &mut self.cartridge;
+                self.ppu.write_register_with_chr((address - PPU_START) % 8, data, |address, data| cartridge.write_chr(address, data))
+            }
+            // The strobe goes to both ports. $4017 is the APU frame counter when written.
+            CONTROLLER_1 => {
+                self.controllers.iter_mut().for_each(|controller| controller.write(data));
+                if let Some(four_score) = self.four_score.as_mut() {
+                    four_score.write(data, &self.controllers);
+                }
+            }
+            OAM_DMA => self.oam_dma_page = Some(data),
+            APU_IO_START..=APU_IO_END => self.apu.write_register(address - APU_IO_START, data),
+            TEST_MODE_START..=TEST_MODE_END => (),
+            EXPANSION_START..=EXPANSION_END => (),
+            PRG_RAM_START..=PRG_RAM_END => self.cartridge.prg_ram_mut()[address - PRG_RAM_START] = data,
+            ROM_START..=ROM_END => {
+                self.cartridge.write(address - ROM_START, data);
+                // The write may have been to a mapper register that switches mirroring.
+                self.ppu.set_mirroring(self.cartridge.mirroring());
+            }
+            _ => unreachable!()
+        }
     }
-}
 
-/// Read and write functions that are expected to go through memory mapping in order to read/write
-/// to the correct memory mapped device.
-///
-/// The way this is intended to work is that something tries to write to a memory address on the
-/// Bus. This address is then passed through the memory map to a device and the address itself is
-/// converted to the literal address that the device on the bus can use.
-///
-/// e.g. A map containing two devices.
-///      One from memory $00 -> $19 and another from $20-$FF.
-///      Caller request address $A1. This calls the second device. The mapping in that second device
-///      determines that $A1 is actually $21 in the actual device.
-pub trait MemoryMap {
-    fn read(&mut self, address: u16) -> u8;
-    fn write(&mut self, address: u16, data: u8) -> ();
+    /// The registers don't get peeked since reading them can change their state, e.g. clearing
+    /// latches. They just read as 0.
+    fn peek(&self, address: u16) -> u8 {
+        let address = address as usize;
+        match address {
+            RAM_START..=RAM_END => self.ram.read(address),
+            PRG_RAM_START..=PRG_RAM_END => self.cartridge.prg_ram()[address - PRG_RAM_START],
+            ROM_START..=ROM_END => self.cartridge.read(address - ROM_START),
+            _ => 0
+        }
+    }
 }
 
 /// Read and write functions for an individual device on the bus. Params should be the literal
-/// addresses of the memory of each device. It works in tandem with the MemoryMap.
+/// addresses of the memory of each device. It works in tandem with the AddressSpace.
+///
+/// This is here to keep the devices organized. The Bus calls the devices directly so nothing is
+/// dispatched through this trait.
 trait BusDevice {
     fn read(&self, address: usize) -> u8;
     fn write(&mut self, address: usize, data: u8) -> ();
 }
 
+/// What RAM holds at power on. On a real console it's whatever the chips happen to start with,
+/// which some games read before writing.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RamInit {
+    Zeroed,
+    Filled(u8),
+    /// Alternating runs of 4 $00 and 4 $FF bytes, a common power on state.
+    Pattern,
+    /// Garbage like a real console, but the same garbage every time for the same seed.
+    Seeded(u64),
+}
+
+impl RamInit {
+    pub fn fill(&self, memory: &mut [u8]) {
+        // SplitMix64, see: https://prng.di.unimi.it/splitmix64.c
+        let mut state = match self {
+            RamInit::Seeded(seed) => *seed,
+            _ => 0,
+        };
+        let mut next_random = || {
+            state = state.wrapping_add(0x9E3779B97F4A7C15);
+            let mut z = state;
+            z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+            (z ^ (z >> 31)) as u8
+        };
+        for (address, byte) in memory.iter_mut().enumerate() {
+            *byte = match self {
+                RamInit::Zeroed => 0x00,
+                RamInit::Filled(value) => *value,
+                RamInit::Pattern => if address & 0x04 == 0 { 0x00 } else { 0xFF },
+                RamInit::Seeded(_) => next_random(),
+            };
+        }
+    }
+}
+
 struct RAM {
     memory: [u8; RAM_SIZE]
 }
 
 impl RAM {
-    pub fn new() -> Self {
+    pub fn new(ram_init: RamInit) -> Self {
+        let mut memory = [0; RAM_SIZE];
+        ram_init.fill(&mut memory);
         RAM {
-            memory: [0; RAM_SIZE]
+            memory
         }
     }
 }
@@ -105,225 +561,344 @@ impl BusDevice for RAM {
     }
 }
 
-impl BusDevice for ROM {
+impl BusDevice for Cartridge {
     fn read(&self, address: usize) -> u8 {
         self.prg[self.mapper.prg_conversion(address)]
     }
 
     fn write(&mut self, address: usize, data: u8) -> () {
-        self.prg[self.mapper.prg_conversion(address)] = data
+        self.mapper.write(address, data)
     }
 }
 
-/// IORegisters are mostly used for audio but also controller
-struct IORegisters {
-    // Below taken from https://wiki.nesdev.com/w/index.php/APU_registers
-
-    // APU
-
-    // 	Pulse 1 channel (write)
-    // $4000	DDLC NNNN	Duty, loop envelope/disable length counter, constant volume, envelope period/volume
-    // $4001	EPPP NSSS	Sweep unit: enabled, period, negative, shift count
-    // $4002	LLLL LLLL	Timer low
-    // $4003	LLLL LHHH	Length counter load, timer high (also resets duty and starts envelope)
-    pulse_1: Pulse,
-
-    // Pulse 2 channel (write)
-    // $4004	DDLC NNNN	Duty, loop envelope/disable length counter, constant volume, envelope period/volume
-    // $4005	EPPP NSSS	Sweep unit: enabled, period, negative, shift count
-    // $4006	LLLL LLLL	Timer low
-    // $4007	LLLL LHHH	Length counter load, timer high (also resets duty and starts envelope)
-    pulse_2: Pulse,
-
-    // Triangle channel (write)
-    // $4008	CRRR RRRR	Length counter disable/linear counter control, linear counter reload value
-    // $400A	LLLL LLLL	Timer low
-    // $400B	LLLL LHHH	Length counter load, timer high (also reloads linear counter)
-    triangle: Triangle,
-
-    // Noise channel (write)
-    // $400C	--LC NNNN	Loop envelope/disable length counter, constant volume, envelope period/volume
-    // $400E	L--- PPPP	Loop noise, noise period
-    // $400F	LLLL L---	Length counter load (also starts envelope)
-    noise: Noise,
-
-    // DMC channel (write)
-    // $4010	IL-- FFFF	IRQ enable, loop sample, frequency index
-    // $4011	-DDD DDDD	Direct load
-    // $4012	AAAA AAAA	Sample address %11AAAAAA.AA000000
-    // $4013	LLLL LLLL	Sample length %0000LLLL.LLLL0001
-    dmc: DMC,
-
-    // TODO: $4014 writes to OAMDATA on PPU but not sure if ever actually used. Figure out if needed
-
-    // $4015	---D NT21	Control: DMC enable, length counter enables: noise, triangle, pulse 2, pulse 1 (write)
-    // $4015	IF-D NT21	Status: DMC interrupt, frame interrupt, length counter status: noise, triangle, pulse 2, pulse 1 (read)
-    control_status: u8,
-
-    // $4017	SD-- ----	Frame counter: 5-frame sequence, disable frame interrupt (write)
-    frame_counter: u8
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rom::rom::tests::nrom_image;
+    use crate::rom::rom::DisassembleRom;
 
-impl IORegisters {
-    fn new() -> IORegisters {
-        IORegisters {
-            pulse_1: Pulse::new(),
-            pulse_2: Pulse::new(),
-            triangle: Triangle::new(),
-            noise: Noise::new(),
-            dmc: DMC::new(),
-            control_status: 0x00,
-            frame_counter: 0x00
-        }
+    #[test]
+    fn ram_is_mirrored() {
+        let mut bus = Bus::new(nrom_image(&[])).unwrap();
+
+        bus.write(0x0012, 0xAB);
+
+        assert_eq!(bus.read(0x0012), 0xAB);
+        assert_eq!(bus.read(0x0812), 0xAB);
+        assert_eq!(bus.read(0x1812), 0xAB);
     }
-}
 
-impl BusDevice for IORegisters {
-    fn read(&self, address: usize) -> u8 {
-        match address {
-            0x15 => unimplemented!(),
-            _ => unreachable!("Roms shouldn't read from other IO registers")
-        }
+    #[test]
+    fn mmc1_switches_the_ppus_nametable_mirroring() {
+        let mut image = nrom_image(&[]);
+        image[6] = 0x11; // Mapper 1, vertical mirroring
+        let mut bus = Bus::new(image).unwrap();
+        let write_mmc1_control = |bus: &mut Bus, control: u8| {
+            for bit in 0..5 {
+                bus.write(0x8000, control >> bit & 1);
+            }
+        };
+        let set_vram_addr = |bus: &mut Bus, address: u16| {
+            bus.write(0x2006, (address >> 8) as u8);
+            bus.write(0x2006, address as u8);
+        };
+        let read_vram = |bus: &mut Bus, address: u16| {
+            set_vram_addr(bus, address);
+            bus.read(0x2007); // PPUDATA reads are a read behind.
+            bus.read(0x2007)
+        };
+
+        write_mmc1_control(&mut bus, 0x0F); // Horizontal
+        set_vram_addr(&mut bus, 0x2000);
+        bus.write(0x2007, 0x42);
+        assert_eq!(read_vram(&mut bus, 0x2400), 0x42);
+        assert_eq!(read_vram(&mut bus, 0x2800), 0x00);
+
+        // Every nametable is the second 1kb, which $2800 was.
+        write_mmc1_control(&mut bus, 0x0D);
+        assert_eq!(read_vram(&mut bus, 0x2000), 0x00);
+        assert_eq!(read_vram(&mut bus, 0x2C00), 0x00);
+
+        // Every nametable is the first 1kb, which $2000 was.
+        write_mmc1_control(&mut bus, 0x0C);
+        assert_eq!(read_vram(&mut bus, 0x2C00), 0x42);
     }
 
-    fn write(&mut self, address: usize, data: u8) -> () {
-        match address {
-            // Pulse 1
-            0x00 => self.pulse_1.vol = data,
-            0x01 => self.pulse_1.sweep = data,
-            0x02 => self.pulse_1.lo = data,
-            0x03 => self.pulse_1.hi = data,
+    #[test]
+    fn prg_rom_is_mirrored() {
+        let mut bus = Bus::new(nrom_image(&[0x4C, 0xF5, 0xC5])).unwrap();
 
-            // Pulse 2
-            0x04 => self.pulse_2.vol = data,
-            0x05 => self.pulse_2.sweep = data,
-            0x06 => self.pulse_2.lo = data,
-            0x07 => self.pulse_2.hi = data,
+        assert_eq!(bus.read(0x8000), 0x4C);
+        assert_eq!(bus.read(0xC002), 0xC5);
+    }
 
-            // Triangle
-            0x08 => self.triangle.linear = data,
-            0x09 => {}, // Unused
-            0x0A => self.triangle.lo = data,
-            0x0B => self.triangle.hi = data,
+    #[test]
+    fn prg_ram_is_mapped() {
+        let mut bus = Bus::new(nrom_image(&[])).unwrap();
 
-            // Noise
-            0x0C => self.noise.vol = data,
-            0x0D => {}, // Unused
-            0x0E => self.noise.lo = data,
-            0x0F => self.noise.hi = data,
+        bus.write(0x6000, 0x12);
+        bus.write(0x7FFF, 0x34);
 
-            // DMC
-            0x10 => self.dmc.freq = data,
-            0x11 => self.dmc.raw = data,
-            0x12 => self.dmc.start = data,
-            0x13 => self.dmc.len = data,
+        assert_eq!(bus.read(0x6000), 0x12);
+        assert_eq!(bus.read(0x7FFF), 0x34);
+        assert_eq!(bus.cartridge().prg_ram()[0x1FFF], 0x34);
+    }
 
-            0x14 => {}, // TODO: Unsure if needed. The spec says this writes to PPU OAMDATA
+    #[test]
+    fn cheats_patch_reads() {
+        let mut prg = [0xEA; 0x2000];
+        prg[0x11D9] = 0x8C;
+        let mut bus = Bus::new(nrom_image(&prg)).unwrap();
 
-            0x15 => self.control_status = data,
+        bus.add_cheat("SXIOPOKE").unwrap();
+        assert_eq!(bus.read(0x91D9), 0xAD);
+        assert_eq!(bus.read(0x91DA), 0xEA);
 
-            0x16 => {}, // TODO: Unsure if needed. It says it's for feedback to joysticks
+        bus.remove_cheat("sxiopoke");
+        assert_eq!(bus.read(0x91D9), 0x8C);
 
-            0x17 => self.frame_counter = data,
+        bus.add_cheat("SXIOPO").unwrap();
+        bus.clear_cheats();
+        assert_eq!(bus.read(0x91D9), 0x8C);
+    }
 
-            _ => unreachable!()
+    #[test]
+    fn dmc_sample_fetches_stall_the_cpu() {
+        let mut bus = Bus::new(nrom_image(&[0xFF, 0xFF])).unwrap();
+        bus.write(0x4010, 0x0F);
+        bus.write(0x4012, 0x00); // $C000
+        bus.write(0x4013, 0x00); // 1 byte
+        bus.write(0x4015, 0x10);
+        assert_eq!(bus.read(0x4015), 0x10);
+
+        let stalls: Vec<u8> = (0..54 * 8).map(|_| bus.tick_apu()).filter(|stall| *stall > 0).collect();
+
+        assert_eq!(stalls, vec![4]);
+        assert_eq!(bus.read(0x4015), 0x00);
+    }
+
+    #[test]
+    fn oam_dma_copies_a_page_into_oam() {
+        let mut bus = Bus::new(nrom_image(&[])).unwrap();
+        for offset in 0..0x100 {
+            bus.write(0x0200 + offset, offset as u8);
         }
+        bus.write(0x2003, 0x00);
+
+        bus.write(0x4014, 0x02);
+        // The copy starts after the tick's cycle, which is odd.
+        assert_eq!(bus.tick(1), 513 + 1);
+        assert_eq!(bus.tick(1), 0);
+
+        let expected: Vec<u8> = (0..=0xFF).collect();
+        assert_eq!(bus.ppu().oam(), &expected[..]);
     }
-}
 
-// TODO: Design and implement interface for how APU turns into actual sound
+    #[test]
+    fn controllers_are_read_independently() {
+        let mut bus = Bus::new(nrom_image(&[])).unwrap();
+        bus.set_button(0, Button::A, true);
+        bus.set_button(1, Button::Start, true);
 
-/// Names of vars below based on: https://wiki.nesdev.com/w/index.php/2A03
+        bus.write(0x4016, 1);
+        bus.write(0x4016, 0);
+        let port_1: Vec<u8> = (0..8).map(|_| bus.read(0x4016) & 1).collect();
+        let port_2: Vec<u8> = (0..8).map(|_| bus.read(0x4017) & 1).collect();
 
-/// Pulse aka Square wave
-struct Pulse {
-    // DDLC NNNN	Duty, loop envelope/disable length counter, constant volume, envelope period/volume
-    vol: u8,
+        assert_eq!(port_1, vec![1, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(port_2, vec![0, 0, 0, 1, 0, 0, 0, 0]);
+    }
 
-    // EPPP NSSS	Sweep unit: enabled, period, negative, shift count
-    sweep: u8,
+    #[test]
+    fn four_score_reads_controllers_3_and_4() {
+        let mut bus = Bus::new(nrom_image(&[])).unwrap();
+        bus.set_four_score(true);
+        bus.set_button(2, Button::Select, true);
 
-    // LLLL LLLL	Timer low
-    lo: u8,
+        bus.write(0x4016, 1);
+        bus.write(0x4016, 0);
+        let port_1: Vec<u8> = (0..24).map(|_| bus.read(0x4016) & 1).collect();
 
-    // LLLL LHHH	Length counter load, timer high (also resets duty and starts envelope)
-    hi: u8
-}
+        // Controller 1, then controller 3, then the signature.
+        assert_eq!(&port_1[8..], &[0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0]);
+    }
 
-impl Pulse {
-    fn new() -> Pulse {
-        Pulse {
-            vol: 0x00,
-            sweep: 0x00,
-            lo: 0x00,
-            hi: 0x00
-        }
+    #[test]
+    fn chr_ram_is_writable_through_the_ppu() {
+        let mut image = nrom_image(&[]);
+        image[5] = 0x00;
+        image.truncate(16 + 0x4000);
+        let mut bus = Bus::new(image).unwrap();
+        let tile = [0x3C, 0x42, 0x81, 0x81, 0x81, 0x81, 0x42, 0x3C];
+
+        bus.write(0x2006, 0x00);
+        bus.write(0x2006, 0x00);
+        tile.iter().for_each(|row| bus.write(0x2007, *row));
+        bus.write(0x2006, 0x00);
+        bus.write(0x2006, 0x00);
+        bus.read(0x2007); // Fills the read buffer
+        let read: Vec<u8> = (0..8).map(|_| bus.read(0x2007)).collect();
+
+        assert_eq!(read, tile);
+        assert_eq!(bus.cartridge().read_chr(0x0002), 0x81);
     }
-}
-struct Triangle {
-    // CRRR RRRR	Length counter disable/linear counter control, linear counter reload value
-    linear: u8,
 
-    // LLLL LLLL	Timer low
-    lo: u8,
+    #[test]
+    fn chr_rom_ignores_writes() {
+        let mut bus = Bus::new(nrom_image(&[])).unwrap();
 
-    // LLLL LHHH	Length counter load, timer high (also reloads linear counter)
-    hi: u8
-}
+        bus.write(0x2006, 0x00);
+        bus.write(0x2006, 0x00);
+        bus.write(0x2007, 0xFF);
+
+        assert_eq!(bus.cartridge().read_chr(0x0000), 0x00);
+    }
 
-impl Triangle {
-    fn new() -> Triangle {
-        Triangle {
-            linear: 0x00,
-            lo: 0x00,
-            hi: 0x00
+    #[test]
+    fn four_screen_nametables_are_all_separate() {
+        let mut image = nrom_image(&[]);
+        image[6] = 0x08;
+        let mut bus = Bus::new(image).unwrap();
+        let nametables = [0x2000_u16, 0x2400, 0x2800, 0x2C00];
+
+        for (value, nametable) in nametables.iter().enumerate() {
+            bus.write(0x2006, (nametable >> 8) as u8);
+            bus.write(0x2006, *nametable as u8);
+            bus.write(0x2007, value as u8 + 1);
         }
+        let read: Vec<u8> = nametables.iter().map(|nametable| {
+            bus.write(0x2006, (nametable >> 8) as u8);
+            bus.write(0x2006, *nametable as u8);
+            bus.read(0x2007); // Fills the read buffer
+            bus.read(0x2007)
+        }).collect();
+
+        assert_eq!(read, vec![1, 2, 3, 4]);
     }
-}
 
-struct Noise {
-    // --LC NNNN	Loop envelope/disable length counter, constant volume, envelope period/volume
-    vol: u8,
+    #[test]
+    fn write_only_ppu_registers_read_the_latch() {
+        let mut bus = Bus::new(nrom_image(&[])).unwrap();
+
+        bus.write(0x2001, 0x1E);
+        assert_eq!(bus.read(0x2000), 0x1E);
+        assert_eq!(bus.read(0x3FF5), 0x1E); // $2005 mirrored
+        // PPUSTATUS only drives its top 3 bits.
+        assert_eq!(bus.read(0x2002), 0x1E);
+
+        bus.write(0x2003, 0x00);
+        bus.write(0x2004, 0x5A);
+        bus.write(0x2003, 0x00);
+        bus.write(0x2006, 0xFF);
+        assert_eq!(bus.read(0x2004), 0x5A);
+        assert_eq!(bus.read(0x2006), 0x5A);
+    }
 
-    // L--- PPPP	Loop noise, noise period
-    lo: u8,
+    #[test]
+    fn write_only_apu_registers_read_open_bus() {
+        let mut bus = Bus::new(nrom_image(&[])).unwrap();
+        bus.write(0x0010, 0x42);
+
+        assert_eq!(bus.read(0x0010), 0x42);
+        assert_eq!(bus.read(0x4000), 0x42);
+        assert_eq!(bus.read(0x4013), 0x42);
+        assert_eq!(bus.read(0x4018), 0x42);
+        // $4015 is readable.
+        assert_eq!(bus.read(0x4015), 0x00);
+        assert_eq!(bus.read(0x4001), 0x00);
+    }
 
-    // LLLL L---	Length counter load (also starts envelope)
-    hi: u8
-}
+    #[test]
+    fn trainer_is_loaded_at_7000() {
+        let mut image = nrom_image(&[]);
+        image[6] |= 0x04;
+        let trainer: Vec<u8> = (0..512).map(|i| i as u8).collect();
+        image.splice(16..16, trainer.iter().copied());
 
-impl Noise {
-    fn new() -> Noise {
-        Noise {
-            vol: 0x00,
-            lo: 0x00,
-            hi: 0x00
-        }
+        let mut bus = Bus::with_ram_init(image, RamInit::Filled(0xFF)).unwrap();
+
+        assert_eq!(&bus.cartridge().trainer().unwrap()[..], &trainer[..]);
+        assert_eq!(bus.dump(0x7000, 512), trainer);
+        assert_eq!(bus.read(0x6FFF), 0xFF);
+        assert_eq!(bus.read(0x7200), 0xFF);
+        assert!(Bus::new(nrom_image(&[])).unwrap().cartridge().trainer().is_none());
     }
-}
 
-struct DMC {
-    // $4010	IL-- FFFF	IRQ enable, loop sample, frequency index
-    freq: u8,
+    #[test]
+    fn ram_powers_on_as_configured() {
+        let mut bus = Bus::with_ram_init(nrom_image(&[]), RamInit::Filled(0xFF)).unwrap();
+        assert_eq!(bus.read(0x0000), 0xFF);
+        assert_eq!(bus.read(0x07FF), 0xFF);
+        assert_eq!(bus.read(0x6000), 0xFF);
 
-    // $4011	-DDD DDDD	Direct load
-    raw: u8,
+        let bus = Bus::with_ram_init(nrom_image(&[]), RamInit::Pattern).unwrap();
+        assert_eq!(bus.dump(0x0000, 8), vec![0x00, 0x00, 0x00, 0x00, 0xFF, 0xFF, 0xFF, 0xFF]);
 
-    // $4012	AAAA AAAA	Sample address %11AAAAAA.AA000000
-    start: u8,
+        let mut bus = Bus::new(nrom_image(&[])).unwrap();
+        assert_eq!(bus.read(0x0000), 0x00);
+    }
 
+    #[test]
+    fn memory_map_is_queryable() {
+        let mut bus = Bus::new(nrom_image(&[0x4C, 0xF5, 0xC5])).unwrap();
+        bus.write(0x0005, 0x42);
+
+        assert_eq!(bus.device_for(0x2001), MappedDevice::Ppu);
+        assert_eq!(bus.device_for(0x0005), MappedDevice::Ram);
+        assert_eq!(bus.device_for(0x4015), MappedDevice::Apu);
+        assert_eq!(bus.device_for(0x4017), MappedDevice::Controller);
+        assert_eq!(bus.device_for(0x6000), MappedDevice::PrgRam);
+        assert_eq!(bus.device_for(0xFFFC), MappedDevice::Cartridge);
+        assert_eq!(bus.dump(0x0004, 3), vec![0x00, 0x42, 0x00]);
+        assert_eq!(bus.dump(0xC000, 3), vec![0x4C, 0xF5, 0xC5]);
+    }
 
-    // $4013	LLLL LLLL	Sample length %0000LLLL.LLLL0001
-    len: u8
-}
+    #[test]
+    fn bus_uses_an_already_parsed_cartridge() {
+        let mut cartridge = Cartridge::from_bytes(nrom_image(&[0x4C, 0xF5, 0xC5])).unwrap();
+        cartridge.prg_ram_mut()[0] = 0x42;
+        let disassembly = cartridge.disassemble_prg_rom().unwrap();
+
+        let mut bus = Bus::from_cartridge(cartridge);
+
+        // A cartridge parsed again from the rom would have blank PRG RAM.
+        assert_eq!(bus.read(0x6000), 0x42);
+        assert_eq!(bus.read(0x8000), 0x4C);
+        assert_eq!(bus.cartridge().disassemble_prg_rom().unwrap(), disassembly);
+    }
+
+    #[test]
+    fn cartridge_space_is_split_by_device() {
+        let mut bus = Bus::new(nrom_image(&[0x4C, 0xF5, 0xC5])).unwrap();
+        bus.write(0x6001, 0x42);
+
+        assert_eq!(bus.device_and_offset(0x8000), (MappedDevice::Cartridge, 0x0000));
+        assert_eq!(bus.device_and_offset(0xFFFC), (MappedDevice::Cartridge, 0x7FFC));
+        assert_eq!(bus.device_and_offset(0x6001), (MappedDevice::PrgRam, 0x0001));
+        assert_eq!(bus.device_and_offset(0x5000), (MappedDevice::Expansion, 0x0FE0));
+        assert_eq!(bus.device_and_offset(0x0801), (MappedDevice::Ram, 0x0001));
+        assert_eq!(bus.device_and_offset(0x3FFA), (MappedDevice::Ppu, 0x0002));
+
+        assert_eq!(bus.read(0x8000), 0x4C);
+        assert_eq!(bus.cartridge().prg_ram()[1], 0x42);
+        assert_eq!(bus.read(0x6001), 0x42);
+        // Nothing drives the expansion area, so the last value on the bus comes back.
+        assert_eq!(bus.read(0x5000), 0x42);
+        bus.write(0x4020, 0x99);
+        assert_eq!(bus.peek(0x4020), 0x00);
+        assert_eq!(bus.peek(0x8001), 0xF5);
+    }
 
-impl DMC {
-    fn new() -> DMC {
-        DMC {
-            freq: 0x00,
-            raw: 0x00,
-            start: 0x00,
-            len: 0x00
+    #[test]
+    fn reads_go_to_ram_or_rom_by_address() {
+        let prg: Vec<u8> = (0..0x4000).map(|i| (i * 7) as u8).collect();
+        let mut bus = Bus::new(nrom_image(&prg)).unwrap();
+        for address in 0..0x800u16 {
+            bus.write(address, address as u8 ^ 0x5A);
         }
+
+        assert!((0..0x2000u16).all(|address| bus.read(address) == (address % 0x800) as u8 ^ 0x5A));
+        // A single 16kb bank is mirrored into both halves of $8000-$FFFF.
+        assert!((0x8000..=0xFFFFu16).all(|address| bus.read(address) == prg[address as usize % 0x4000]));
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,108 @@
+//! Game Genie codes. See: https://wiki.nesdev.com/w/index.php/Game_Genie
+//!
+//! Each letter is a 4 bit value. The bits of the letters are scrambled together into an address in
+//! $8000-$FFFF, a value to return when that address is read and, for 8 letter codes, a compare
+//! byte. With a compare byte the value is only returned if the ROM actually holds the compare byte
+//! at that address, which keeps codes from breaking other banks on bank switched games.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+const LETTERS: [char; 16] = [
+    'A', 'P', 'Z', 'L', 'G', 'I', 'T', 'Y', 'E', 'O', 'X', 'U', 'K', 'S', 'V', 'N'
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CheatError {
+    /// Codes are either 6 or 8 letters long.
+    InvalidLength { length: usize },
+    InvalidLetter { letter: char },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cheat {
+    pub code: String,
+    pub address: u16,
+    pub value: u8,
+    pub compare: Option<u8>,
+}
+
+impl Cheat {
+    pub fn decode(code: &str) -> Result<Cheat, CheatError> {
+        let code = code.to_ascii_uppercase();
+        let n = code
+            .chars()
+            .map(|letter| {
+                LETTERS
+                    .iter()
+                    .position(|l| *l == letter)
+                    .map(|value| value as u16)
+                    .ok_or(CheatError::InvalidLetter { letter })
+            })
+            .collect::<Result<Vec<u16>, CheatError>>()?;
+        if n.len() != 6 && n.len() != 8 {
+            return Err(CheatError::InvalidLength { length: n.len() });
+        }
+
+        let address = 0x8000
+            | (n[3] & 7) << 12
+            | (n[5] & 7) << 8
+            | (n[4] & 8) << 8
+            | (n[2] & 7) << 4
+            | (n[1] & 8) << 4
+            | (n[4] & 7)
+            | (n[3] & 8);
+        let value = (n[1] & 7) << 4 | (n[0] & 8) << 4 | (n[0] & 7);
+
+        let (value, compare) = if n.len() == 6 {
+            (value | (n[5] & 8), None)
+        } else {
+            let compare = (n[7] & 7) << 4 | (n[6] & 8) << 4 | (n[6] & 7) | (n[5] & 8);
+            (value | (n[7] & 8), Some(compare as u8))
+        };
+
+        Ok(Cheat { code, address, value: value as u8, compare })
+    }
+
+    /// Returns what a read of `address` should return given the ROM holds `data` there.
+    pub fn apply(&self, address: u16, data: u8) -> u8 {
+        match self.compare {
+            _ if address != self.address => data,
+            Some(compare) if compare != data => data,
+            _ => self.value,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_six_letter_code() {
+        // Super Mario Bros. infinite lives
+        let cheat = Cheat::decode("SXIOPO").unwrap();
+
+        assert_eq!(cheat.address, 0x91D9);
+        assert_eq!(cheat.value, 0xAD);
+        assert_eq!(cheat.compare, None);
+    }
+
+    #[test]
+    fn decodes_eight_letter_code() {
+        let cheat = Cheat::decode("sxiopoke").unwrap();
+
+        assert_eq!(cheat.address, 0x91D9);
+        assert_eq!(cheat.value, 0xAD);
+        assert_eq!(cheat.compare, Some(0x8C));
+        assert_eq!(cheat.apply(0x91D9, 0x8C), 0xAD);
+        assert_eq!(cheat.apply(0x91D9, 0x12), 0x12);
+        assert_eq!(cheat.apply(0x91DA, 0x8C), 0x8C);
+    }
+
+    #[test]
+    fn rejects_bad_codes() {
+        assert_eq!(Cheat::decode("SXIOP"), Err(CheatError::InvalidLength { length: 5 }));
+        assert_eq!(Cheat::decode("SXIOPB"), Err(CheatError::InvalidLetter { letter: 'B' }));
+    }
+}
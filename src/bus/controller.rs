@@ -0,0 +1,251 @@
+use crate::nes::state::{StateError, StateReader, StateWriter};
+
+/// The buttons on a standard controller. The values are the bit each button is reported in, in
+/// the order the controller shifts them out.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Button {
+    A = 0x01,
+    B = 0x02,
+    Select = 0x04,
+    Start = 0x08,
+    Up = 0x10,
+    Down = 0x20,
+    Left = 0x40,
+    Right = 0x80,
+}
+
+/// Turbo is worked out a frame at a time, taking a frame as 1/60th of a second.
+const TURBO_FRAMES_PER_SECOND: f64 = 60.0;
+
+/// A button with turbo on, see `Controller::set_turbo`.
+#[derive(Debug, Clone, Copy)]
+struct Turbo {
+    rate_hz: f32,
+    // Frames since turbo was turned on.
+    frames: u32,
+}
+
+/// A standard controller plugged into $4016 or $4017.
+///
+/// Writing 1 then 0 to $4016 (the strobe) latches the button states into a shift register. Each
+/// read then returns the next button in bit 0, A first. After all 8 buttons reads return 1.
+/// See: https://wiki.nesdev.com/w/index.php/Standard_controller
+pub struct Controller {
+    buttons: u8,
+    // Indexed by the bit each button is reported in.
+    turbo: [Option<Turbo>; 8],
+    // Which turbo buttons are pressed this frame, see `next_frame`.
+    turbo_buttons: u8,
+    shift_register: u8,
+    strobe: bool,
+}
+
+impl Controller {
+    pub fn new() -> Self {
+        Controller {
+            buttons: 0,
+            turbo: [None; 8],
+            turbo_buttons: 0,
+            shift_register: 0,
+            strobe: false,
+        }
+    }
+
+    pub fn set_button(&mut self, button: Button, pressed: bool) {
+        if pressed {
+            self.buttons |= button as u8;
+        } else {
+            self.buttons &= !(button as u8);
+        }
+    }
+
+    /// Sets every button at once, using the same bits as `Button`. Turbo buttons are let go until
+    /// the next `next_frame` so the game sees exactly `buttons`, e.g. for a movie.
+    pub fn set_buttons(&mut self, buttons: u8) {
+        self.buttons = buttons;
+        self.turbo_buttons = 0;
+    }
+
+    /// Auto-fire. With a rate the button presses and releases itself `rate_hz` times a second,
+    /// pressed for the first half of each, starting with the next frame. The button can still be
+    /// held down as usual with `set_button`. None turns turbo off again.
+    pub fn set_turbo(&mut self, button: Button, rate_hz: Option<f32>) {
+        let bit = (button as u8).trailing_zeros() as usize;
+        self.turbo[bit] = rate_hz.map(|rate_hz| Turbo { rate_hz, frames: 0 });
+        if rate_hz.is_none() {
+            self.turbo_buttons &= !(button as u8);
+        }
+    }
+
+    /// Moves the turbo buttons on to the next frame. This is called once a frame rather than
+    /// going by reads so that every read in a frame agrees.
+    pub fn next_frame(&mut self) {
+        self.turbo_buttons = 0;
+        for (bit, turbo) in self.turbo.iter_mut().enumerate() {
+            if let Some(turbo) = turbo {
+                // Counts half presses, so even is the pressed half.
+                let halves = turbo.frames as f64 * turbo.rate_hz as f64 * 2.0 / TURBO_FRAMES_PER_SECOND;
+                if (halves as u64) & 1 == 0 {
+                    self.turbo_buttons |= 1 << bit;
+                }
+                turbo.frames = turbo.frames.wrapping_add(1);
+            }
+        }
+    }
+
+    /// Held buttons and turbo buttons that are pressed this frame.
+    pub fn buttons(&self) -> u8 {
+        self.buttons | self.turbo_buttons
+    }
+
+    pub fn write(&mut self, data: u8) {
+        self.strobe = data & 0x01 != 0;
+        if self.strobe {
+            self.shift_register = self.buttons();
+        }
+    }
+
+    pub fn read(&mut self) -> u8 {
+        // While strobing the controller keeps reloading so only A is ever read.
+        if self.strobe {
+            return self.buttons() & 0x01;
+        }
+        let data = self.shift_register & 0x01;
+        self.shift_register = (self.shift_register >> 1) | 0x80;
+        data
+    }
+
+    /// Only where the game is in reading the buttons out. The buttons themselves are the host's
+    /// input so they aren't saved.
+    pub(crate) fn save_state(&self, state: &mut StateWriter) {
+        state.u8(self.shift_register);
+        state.bool(self.strobe);
+    }
+
+    pub(crate) fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.shift_register = state.u8()?;
+        self.strobe = state.bool()?;
+        Ok(())
+    }
+}
+
+/// The Four Score multitap, which lets 4 controllers share the two ports. Each port reads out its
+/// first controller, then the second one, then a signature so games can tell a Four Score is
+/// plugged in. That's 24 reads per port, after which reads return 1.
+/// See: https://wiki.nesdev.com/w/index.php/Four_Score
+pub struct FourScore {
+    shift_registers: [u32; 2],
+    strobe: bool,
+}
+
+impl FourScore {
+    /// The third byte read out of $4016 and $4017.
+    const SIGNATURES: [u32; 2] = [0x10, 0x20];
+
+    pub fn new() -> Self {
+        FourScore {
+            shift_registers: [0; 2],
+            strobe: false,
+        }
+    }
+
+    /// Port 0 is controllers 1 and 3, port 1 is controllers 2 and 4.
+    pub fn write(&mut self, data: u8, controllers: &[Controller; 4]) {
+        self.strobe = data & 0x01 != 0;
+        if self.strobe {
+            self.latch(controllers);
+        }
+    }
+
+    pub fn read(&mut self, port: usize, controllers: &[Controller; 4]) -> u8 {
+        if self.strobe {
+            self.latch(controllers);
+        }
+        let data = (self.shift_registers[port] & 0x01) as u8;
+        self.shift_registers[port] = (self.shift_registers[port] >> 1) | 0x80_0000;
+        data
+    }
+
+    pub(crate) fn save_state(&self, state: &mut StateWriter) {
+        state.u32(self.shift_registers[0]);
+        state.u32(self.shift_registers[1]);
+        state.bool(self.strobe);
+    }
+
+    pub(crate) fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.shift_registers = [state.u32()?, state.u32()?];
+        self.strobe = state.bool()?;
+        Ok(())
+    }
+
+    fn latch(&mut self, controllers: &[Controller; 4]) {
+        for port in 0..2 {
+            self.shift_registers[port] = controllers[port].buttons() as u32
+                | (controllers[port + 2].buttons() as u32) << 8
+                | FourScore::SIGNATURES[port] << 16;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buttons_are_read_in_order() {
+        let mut controller = Controller::new();
+        controller.set_button(Button::A, true);
+        controller.set_button(Button::Start, true);
+        controller.set_button(Button::Right, true);
+
+        controller.write(1);
+        controller.write(0);
+        let reads: Vec<u8> = (0..10).map(|_| controller.read()).collect();
+
+        assert_eq!(reads, vec![1, 0, 0, 1, 0, 0, 0, 1, 1, 1]);
+    }
+
+    #[test]
+    fn turbo_presses_and_releases_once_a_frame_at_30hz() {
+        let mut controller = Controller::new();
+        controller.set_turbo(Button::B, Some(30.0));
+
+        let pressed: Vec<bool> = (0..60).map(|_| {
+            controller.next_frame();
+            controller.buttons() & Button::B as u8 != 0
+        }).collect();
+
+        assert_eq!(pressed.iter().filter(|pressed| **pressed).count(), 30);
+        assert!(pressed.chunks(2).all(|frames| frames == [true, false]));
+
+        // Holding the button down still works, on any frame.
+        controller.set_button(Button::B, true);
+        controller.next_frame();
+        controller.next_frame();
+        assert_eq!(controller.buttons(), Button::B as u8);
+
+        controller.set_turbo(Button::B, None);
+        controller.set_button(Button::B, false);
+        controller.next_frame();
+        assert_eq!(controller.buttons(), 0);
+    }
+
+    #[test]
+    fn four_score_reads_both_controllers_then_signature() {
+        let mut controllers = [Controller::new(), Controller::new(), Controller::new(), Controller::new()];
+        controllers[0].set_button(Button::A, true);
+        controllers[2].set_button(Button::B, true);
+        controllers[3].set_button(Button::Right, true);
+        let mut four_score = FourScore::new();
+
+        four_score.write(1, &controllers);
+        four_score.write(0, &controllers);
+        let bits = |four_score: &mut FourScore, port| -> u32 {
+            (0..24).map(|bit| (four_score.read(port, &controllers) as u32) << bit).sum()
+        };
+
+        assert_eq!(bits(&mut four_score, 0), 0x10_02_01);
+        assert_eq!(bits(&mut four_score, 1), 0x20_80_00);
+        assert_eq!(four_score.read(0, &controllers), 1);
+    }
+}
@@ -0,0 +1,34 @@
+use crate::bus::address_space::AddressSpace;
+use alloc::boxed::Box;
+
+/// A flat 64kb address space with nothing mapped into it, i.e. every address is plain RAM. This
+/// is what 6502 test suites expect and makes it easy to test instructions without a rom.
+pub struct FlatMemory {
+    memory: Box<[u8; 0x10000]>,
+}
+
+impl FlatMemory {
+    pub fn new(memory: [u8; 0x10000]) -> Self {
+        FlatMemory { memory: Box::new(memory) }
+    }
+}
+
+impl Default for FlatMemory {
+    fn default() -> Self {
+        FlatMemory::new([0; 0x10000])
+    }
+}
+
+impl AddressSpace for FlatMemory {
+    fn read(&mut self, address: u16) -> u8 {
+        self.memory[address as usize]
+    }
+
+    fn write(&mut self, address: u16, data: u8) -> () {
+        self.memory[address as usize] = data;
+    }
+
+    fn peek(&self, address: u16) -> u8 {
+        self.memory[address as usize]
+    }
+}
@@ -1 +1,7 @@
+pub mod address_space;
 pub mod bus;
+pub mod cheat;
+pub mod controller;
+pub mod flat_memory;
+#[cfg(test)]
+pub mod recording_bus;
\ No newline at end of file
@@ -0,0 +1,55 @@
+use crate::bus::address_space::AddressSpace;
+use alloc::vec;
+use alloc::vec::Vec;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AccessKind {
+    Read,
+    Write,
+}
+
+/// Flat 64kb memory that records every access made through it, in order. This is for tests that
+/// care exactly which bus accesses an instruction makes, e.g. dummy reads and writes. Peeks aren't
+/// recorded since they aren't real accesses.
+pub struct RecordingBus {
+    pub memory: Vec<u8>,
+    pub log: Vec<(AccessKind, u16, u8)>,
+}
+
+impl RecordingBus {
+    pub fn new() -> Self {
+        RecordingBus { memory: vec![0; 0x10000], log: Vec::new() }
+    }
+
+    /// Copies `bytes` into memory starting at `address` without recording anything.
+    pub fn load(&mut self, address: u16, bytes: &[u8]) {
+        let start = address as usize;
+        self.memory[start..start + bytes.len()].copy_from_slice(bytes);
+    }
+
+    /// The accesses made to `address` in order, along with the value read or written.
+    pub fn accesses_to(&self, address: u16) -> Vec<(AccessKind, u8)> {
+        self.log
+            .iter()
+            .filter(|(_, accessed, _)| *accessed == address)
+            .map(|(kind, _, data)| (*kind, *data))
+            .collect()
+    }
+}
+
+impl AddressSpace for RecordingBus {
+    fn read(&mut self, address: u16) -> u8 {
+        let data = self.memory[address as usize];
+        self.log.push((AccessKind::Read, address, data));
+        data
+    }
+
+    fn write(&mut self, address: u16, data: u8) -> () {
+        self.memory[address as usize] = data;
+        self.log.push((AccessKind::Write, address, data));
+    }
+
+    fn peek(&self, address: u16) -> u8 {
+        self.memory[address as usize]
+    }
+}
@@ -1,12 +1,35 @@
+use crate::cpu::disassembler::*;
 use crate::cpu::opcode::*;
+use crate::cpu::snapshot::{MachineSnapshot, SnapshotError};
+use crate::cpu::trace::*;
+use crate::bus::address_space::AddressSpace;
 use crate::bus::bus::*;
-use std::fmt;
+use crate::bus::flat_memory::FlatMemory;
+use crate::nes::state::{StateError, StateReader, StateWriter};
+use alloc::collections::BTreeSet;
+use alloc::vec::Vec;
+use core::fmt;
+use core::ops::{BitAnd, BitOr};
+use core::time::Duration;
 use StatusFlags::*;
-use std::convert::TryInto;
 use crate::cpu::opcode::AddressingMode::*;
 
+/// The 6502 always keeps its stack in $0100-$01FF.
+const STACK_PAGE: u16 = 0x0100;
 
-pub struct CPU {
+/// Everything in the console is clocked off a 21.477272 MHz master clock. The NTSC CPU divides it
+/// by 12 and the PPU by 4, which is where the 3 dots per CPU cycle comes from.
+pub const MASTER_CYCLES_PER_CPU_CYCLE: u64 = 12;
+
+/// CPU cycles per second on an NTSC console, i.e. the master clock divided by 12.
+pub const NTSC_CPU_HZ: u32 = 1_789_773;
+
+/// CPU cycles per second on a PAL console. Its master clock is 26.601712 MHz, divided by 16.
+pub const PAL_CPU_HZ: u32 = 1_662_607;
+
+const NANOS_PER_SECOND: u128 = 1_000_000_000;
+
+pub struct CPU<M: AddressSpace = Bus> {
     // Accumulator
     a: u8,
 
@@ -23,65 +46,350 @@ pub struct CPU {
     // Status Register
     p: u8, // Only 6 bits needed
 
-    pub bus: Bus,
+    pub bus: M,
 
-    cycles: u8,
+    // Cycles left of the current instruction, including any the CPU is stalled for by DMA.
+    cycles: u16,
 
     pub current_instruction: u8,
 
-    pub total_cycles: u32,
+    // CPU cycles since the last reset. At NES speed a u32 would wrap after 40 minutes.
+    total_cycles: u64,
+
+    // Master clock cycles since the CPU was created. Unlike total_cycles this is never reset.
+    master_cycles: u64,
+
+    // Only used to convert between cycles and time. NTSC_CPU_HZ unless set otherwise.
+    clock_hz: u32,
 
     pub current_opcode: DecodedOpcode,
 
     current_fetched_word: u16,
+
+    // Set by the JAM instructions. A halted CPU doesn't fetch or execute anything until reset.
+    halted: bool,
+
+    // What the interrupt poll on the second to last cycle of the instruction saw. The interrupt
+    // is taken once the instruction finishes.
+    nmi_polled: bool,
+    irq_polled: bool,
+
+    // I from before the current instruction. CLI, SEI and PLP change I after the poll so it sees
+    // this instead, which is why their effect on IRQs is an instruction late.
+    i_before_instruction: bool,
+
+    // BRK and the IRQ and NMI sequences don't poll, so the first instruction of a handler always
+    // runs before another interrupt.
+    in_interrupt_sequence: bool,
+
+    // Cycles left of BRK or an IRQ sequence during which an NMI takes over the vector.
+    hijack_cycles: u8,
+
+    illegal_opcode_policy: IllegalOpcodePolicy,
+
+    // Refuse to run unofficial opcodes instead of emulating them.
+    strict_illegals: bool,
+
+    // The NES's 6502 has the decimal mode circuitry cut out so ADC and SBC ignore D unless this is set.
+    decimal_enabled: bool,
+
+    trace_hook: Option<TraceHook>,
+
+    trace_filter: TraceFilter,
+
+    // The PC of the last instruction traced, for TraceFilter::OnPcChange.
+    last_trace_pc: Option<u16>,
+
+    breakpoints: BTreeSet<u16>,
+}
+
+/// Why `run_until_break` stopped.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StopReason {
+    /// The PC reached a breakpoint. The instruction at the breakpoint hasn't run yet.
+    Breakpoint(u16),
+    /// An instruction accessed a watched address. The instruction has finished running.
+    Watchpoint { addr: u16, value: u8 },
+    Halted,
+}
+
+/// What the CPU should do when it hits an opcode it can't decode.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IllegalOpcodePolicy {
+    /// Panics. This takes down the whole wasm instance so it's only really useful for debugging.
+    Panic,
+    /// Skips over the byte as if it was a one byte NOP.
+    TreatAsNop,
+    /// Halts the CPU the same way a JAM instruction would.
+    Halt,
+    /// Returns the DecodeError to whoever is clocking the CPU.
+    Error,
+}
+
+/// What an instruction works on, worked out by `fetch` from the addressing mode. Instructions that
+/// take one of these know whether they've been given a value or an address, rather than checking
+/// the addressing mode to tell what `current_fetched_word` holds.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Operand {
+    Implied,
+    /// The byte after the opcode. For branches it's the signed offset.
+    Immediate(u8),
+    /// Where the operand is in memory.
+    Address(u16),
+    Accumulator,
 }
 
-impl fmt::Debug for CPU {
-    /// Custom implementation intended to format similarly to: nestest.log
-    /// See: http://www.qmtpro.com/~nes/misc/nestest.log for example.
-    /// Example line below:
-    /// C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD PPU:  0,  0 CYC:7
+/// What the CPU is doing after being stepped.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CpuState {
+    Running,
+    Halted,
+}
+
+impl<M: AddressSpace> fmt::Debug for CPU<M> {
+    /// Formats the same as a TraceEvent, i.e. similarly to nestest.log
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
-        write!(f, "{:X?}  {:X?} {:X?} {:X?}  {:?} {:28X?}     A:{:02X?} X:{:02X?} Y:{:02X?} P:{:02X?} SP:{:02X?} PPU:{:3} {}  CYC:{}",
-               self.pc,
-               self.current_instruction,
-               self.current_fetched_word,  // This should be the second byte
-               self.current_fetched_word,  // This should be the first byte
-               self.current_opcode.instruction,
-               self.current_fetched_word,
-               self.a,
-               self.x,
-               self.y,
-               self.p,
-               self.sp,
-               "0",  // This will be ppu
-               "0", // Don't know what this is lol
-               self.total_cycles
-        )
+        write!(f, "{}", self.trace_event())
     }
 }
 
 impl CPU {
     pub fn new(rom_vector: Vec<u8>) -> CPU {
+        // TODO: Fix error handlings
+        CPU::with_bus(Bus::new(rom_vector).unwrap_or_else(|_| { panic!("Unable to load rom") }))
+    }
+}
+
+impl CPU<FlatMemory> {
+    /// A CPU with no cartridge or memory mapped devices, just 64kb of memory. Useful for testing
+    /// instructions and running 6502 test suites. Memory and registers can be set up directly.
+    pub fn with_flat_memory(memory: [u8; 0x10000]) -> CPU<FlatMemory> {
+        CPU::with_bus(FlatMemory::new(memory))
+    }
+}
+
+impl Default for CPU<FlatMemory> {
+    fn default() -> Self {
+        CPU::with_bus(FlatMemory::default())
+    }
+}
+
+impl CPU<Bus> {
+    pub fn snapshot(&self) -> MachineSnapshot {
+        let mut state = StateWriter::new();
+        self.save_state(&mut state);
+        MachineSnapshot {
+            state: state.into_bytes(),
+            rom_hash: self.bus.cartridge().rom_hash(),
+        }
+    }
+
+    /// Runs until the PPU has finished drawing the current frame.
+    pub fn run_frame(&mut self) -> Result<(), DecodeError> {
+        let frame = self.bus.ppu().frame();
+        while self.bus.ppu().frame() == frame {
+            self.clock_system()?;
+        }
+
+        Ok(())
+    }
+
+    /// Runs until the PPU has finished drawing the current scanline. That's 341 dots, i.e. 113 2/3
+    /// CPU cycles, so scanlines don't start on the same part of a CPU cycle, or necessarily
+    /// between instructions.
+    pub fn run_scanline(&mut self) -> Result<(), DecodeError> {
+        let scanline = self.bus.ppu().scanline();
+        while self.bus.ppu().scanline() == scanline {
+            self.clock_system()?;
+        }
+
+        Ok(())
+    }
+
+    /// Like `step()` but the rest of the system runs alongside the CPU.
+    pub fn step_system(&mut self) -> Result<CpuState, DecodeError> {
+        loop {
+            self.clock_system()?;
+            if self.halted {
+                return Ok(CpuState::Halted);
+            }
+            if self.cycles == 0 {
+                return Ok(CpuState::Running);
+            }
+        }
+    }
+
+    /// Clocks the CPU once and the rest of the bus along with it, see `Bus::tick`. NMIs the PPU
+    /// raises and IRQs, e.g. from the mapper, are polled on the second to last cycle of each
+    /// instruction and taken once it finishes, so ones that show up on the last cycle wait for
+    /// the next instruction. See: https://wiki.nesdev.com/w/index.php/CPU_interrupts
+    fn clock_system(&mut self) -> Result<(), DecodeError> {
+        self.clock()?;
+        // The CPU sits out DMA stalls as if the current instruction took longer. Those cycles are
+        // still clocked one at a time through here, so they count towards total_cycles and the
+        // PPU and APU keep running during them.
+        self.cycles += self.bus.tick(1);
+        if self.hijack_cycles > 0 {
+            self.hijack_cycles -= 1;
+            // An NMI this early in BRK or an IRQ sequence gets the NMI vector instead. The pushes
+            // are the same either way, so the NMI handler sees the B flag for a hijacked BRK.
+            if self.bus.ppu_mut().take_nmi() {
+                self.hijack_cycles = 0;
+                self.pc = self.read_vector(0xFFFA);
+            }
+        }
+        if self.cycles == 1 && !self.in_interrupt_sequence {
+            self.poll_interrupts();
+        }
+        if self.cycles == 0 && self.nmi_polled {
+            self.nmi_polled = false;
+            self.irq_polled = false;
+            self.nmi();
+        } else if self.cycles == 0 && self.irq_polled {
+            self.irq_polled = false;
+            self.irq();
+        }
+
+        Ok(())
+    }
+
+    fn poll_interrupts(&mut self) {
+        // NMI is edge triggered so once it's seen it stays seen.
+        self.nmi_polled |= self.bus.ppu_mut().take_nmi();
+        let interrupts_disabled = match self.current_opcode.instruction {
+            Instruction::CLI | Instruction::SEI | Instruction::PLP => self.i_before_instruction,
+            _ => self.get_status(I),
+        };
+        // The IRQ line is level triggered so this keeps firing until the mapper is acknowledged.
+        self.irq_polled = self.bus.irq() && !interrupts_disabled;
+    }
+
+    /// Turning the console on. Unlike `reset()` this also clears RAM and the registers.
+    pub fn power_on(&mut self) {
+        self.bus.clear_ram();
+        self.a = 0;
+        self.x = 0;
+        self.y = 0;
+        self.reset();
+    }
+
+    /// Fails without changing anything if the snapshot was taken with a different rom loaded.
+    pub fn restore(&mut self, snapshot: &MachineSnapshot) -> Result<(), SnapshotError> {
+        if snapshot.rom_hash != self.bus.cartridge().rom_hash() {
+            return Err(SnapshotError::RomMismatch);
+        }
+        // The state came from this same machine so it's always in the current layout.
+        let mut state = StateReader::new(&snapshot.state);
+        self.load_state(&mut state).expect("the machine's own snapshot didn't load");
+
+        Ok(())
+    }
+
+    /// The registers, where the CPU is in the current instruction and any interrupt it's about to
+    /// take, followed by the whole bus. Settings like breakpoints and the illegal opcode policy
+    /// aren't saved. See `Nes::save_state_bytes`.
+    pub(crate) fn save_state(&self, state: &mut StateWriter) {
+        state.bytes(&[self.a, self.x, self.y, self.sp, self.p]);
+        state.u16(self.pc);
+        state.u16(self.cycles);
+        state.u8(self.current_instruction);
+        state.u16(self.current_fetched_word);
+        state.u64(self.total_cycles);
+        state.u64(self.master_cycles);
+        state.bool(self.halted);
+        state.bool(self.nmi_polled);
+        state.bool(self.irq_polled);
+        state.bool(self.i_before_instruction);
+        state.bool(self.in_interrupt_sequence);
+        state.u8(self.hijack_cycles);
+        self.bus.save_state(state);
+    }
+
+    pub(crate) fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.a = state.u8()?;
+        self.x = state.u8()?;
+        self.y = state.u8()?;
+        self.sp = state.u8()?;
+        self.p = state.u8()?;
+        self.pc = state.u16()?;
+        self.cycles = state.u16()?;
+        self.current_instruction = state.u8()?;
+        // The interrupt poll needs to know if the instruction was CLI, SEI or PLP. Bytes that
+        // don't decode were run as whatever the illegal opcode policy said, which is none of those.
+        self.current_opcode = self.current_instruction.decode().unwrap_or(DecodedOpcode {
+            instruction: Instruction::NOP,
+            mode: Implied,
+            cycles: 2,
+            is_official: false,
+        });
+        self.current_fetched_word = state.u16()?;
+        self.total_cycles = state.u64()?;
+        self.master_cycles = state.u64()?;
+        self.halted = state.bool()?;
+        self.nmi_polled = state.bool()?;
+        self.irq_polled = state.bool()?;
+        self.i_before_instruction = state.bool()?;
+        self.in_interrupt_sequence = state.bool()?;
+        self.hijack_cycles = state.u8()?;
+        self.bus.load_state(state)
+    }
+
+    /// Runs instructions until a breakpoint or watchpoint is hit or the CPU halts. If the PC is
+    /// already sitting on a breakpoint that instruction is run first.
+    ///
+    /// Watchpoints are set on the bus with `Bus::add_watchpoint`.
+    pub fn run_until_break(&mut self) -> Result<StopReason, DecodeError> {
+        loop {
+            if self.step()? == CpuState::Halted {
+                return Ok(StopReason::Halted);
+            }
+            if let Some((addr, value)) = self.bus.take_watchpoint_hit() {
+                return Ok(StopReason::Watchpoint { addr, value });
+            }
+            if self.breakpoints.contains(&self.pc) {
+                return Ok(StopReason::Breakpoint(self.pc));
+            }
+        }
+    }
+}
+
+impl<M: AddressSpace> CPU<M> {
+    /// Builds a CPU on top of any memory map. Mostly useful for testing instructions against
+    /// something simpler than the full NES bus.
+    pub(crate) fn with_bus(bus: M) -> CPU<M> {
         CPU {
             a: 0x00,
             x: 0x00,
             y: 0x00,
-            // NOTE: Change below to 0xC000 when testing with nestest.nes rom and 0x8000 otherwise
             pc: 0xC000,
             sp: 0xFD,
             p: 0x24,
-            // TODO: Fix error handlings
-            bus: Bus::new(rom_vector).unwrap_or_else(|_| { panic!("Unable to load rom") }),
+            bus,
             cycles: 0,
             current_instruction: 0,  // Useful for debugging
             total_cycles: 7, // CPU takes 7 cycles to boot up.
+            master_cycles: 0,
+            clock_hz: NTSC_CPU_HZ,
             current_opcode: DecodedOpcode {
                 instruction: Instruction::NOP,
                 mode: AddressingMode::Absolute,
                 cycles: 0,
+                is_official: true,
             },
             current_fetched_word: 0x0000,
+            halted: false,
+            nmi_polled: false,
+            irq_polled: false,
+            i_before_instruction: true,
+            in_interrupt_sequence: false,
+            hijack_cycles: 0,
+            illegal_opcode_policy: IllegalOpcodePolicy::Error,
+            strict_illegals: false,
+            decimal_enabled: false,
+            trace_hook: None,
+            trace_filter: TraceFilter::EveryInstruction,
+            last_trace_pc: None,
+            breakpoints: BTreeSet::new(),
         }
     }
 
@@ -91,110 +399,325 @@ impl CPU {
         if state { self.p |= (flag as u8) } else { self.p &= !(flag as u8) }
     }
 
-    pub fn debug_clock(&mut self) -> String {
-        let debug = if self.cycles == 0 {
-            let opcode = self.load_instruction().unwrap_or_else(|_| {
-                // TODO: put this error handling elsewhere
-                panic!("Invalid opcode!")
-            });
-            let debug = format!("{:X?}", self);
-            self.pc += 1;
-            self.cycles += self.execute(opcode);
-            //self.execute(opcode);
-            self.set_status(B, true); // This flag is unused but for accuracy should always be used
+    /// Replaces the whole status register, e.g. `cpu.set_flags(C | Z)`. Like PLP, U is always
+    /// set and B is always clear since neither exists in the register itself.
+    pub fn set_flags(&mut self, flags: Flags) {
+        self.p = (flags.bits() | U as u8) & !(B as u8);
+    }
 
-            debug
-        } else {
-            format!("{:X?}", self)
+    pub fn flags(&self) -> Flags {
+        Flags(self.p)
+    }
+
+    pub fn set_illegal_opcode_policy(&mut self, policy: IllegalOpcodePolicy) {
+        self.illegal_opcode_policy = policy;
+    }
+
+    /// Makes unofficial opcodes, e.g. SLO or LAX, return `DecodeError::UnofficialOpcode` instead of
+    /// running, e.g. to check code doesn't rely on them. Nothing about the CPU changes when that
+    /// happens. Off by default since plenty of games use them.
+    pub fn set_strict_illegals(&mut self, strict: bool) {
+        self.strict_illegals = strict;
+    }
+
+    /// Makes ADC and SBC do BCD arithmetic while D is set, like a stock NMOS 6502. Off by default
+    /// since the NES doesn't have decimal mode.
+    pub fn set_decimal_enabled(&mut self, enabled: bool) {
+        self.decimal_enabled = enabled;
+    }
+
+    /// Sets a hook that's called with the CPU state every time an instruction starts. Nothing is
+    /// formatted unless the hook does it, so tracing costs next to nothing when no hook is set.
+    pub fn set_trace_hook(&mut self, hook: Option<TraceHook>) {
+        self.trace_hook = hook;
+    }
+
+    /// Limits which instructions the trace hook is called for. Filtering happens before the hook
+    /// is called so skipped instructions cost as little as possible.
+    pub fn set_trace_filter(&mut self, filter: TraceFilter) {
+        self.trace_filter = filter;
+        self.last_trace_pc = None;
+    }
+
+    /// Disassembles `count` instructions starting at `start` from whatever is currently mapped
+    /// into memory, e.g. code copied into RAM. Memory is only peeked so this has no side effects.
+    pub fn disassemble_range(&mut self, start: u16, count: usize) -> Vec<DisassembledInstruction> {
+        let bus = &self.bus;
+        disassemble(|address| bus.peek(address), start, count)
+    }
+
+    /// `disassemble_range` with operands named from `symbols`, e.g. `SymbolMap::nes()`.
+    pub fn disassemble_range_with_symbols(&mut self, start: u16, count: usize, symbols: &SymbolMap) -> Vec<DisassembledInstruction> {
+        let bus = &self.bus;
+        disassemble_with_symbols(|address| bus.peek(address), start, count, symbols)
+    }
+
+    /// The instruction at PC, i.e. the next one to run, e.g. for a debugger. Memory is only
+    /// peeked and nothing about the CPU changes, so PPU latches and the like are left alone.
+    /// Bytes that don't decode are an `IllegalOpcode` error rather than `.byte`.
+    pub fn peek_instruction(&self) -> Result<DisassembledInstruction, DecodeError> {
+        let opcode = self.bus.peek(self.pc);
+        if opcode.decode().is_err() {
+            return Err(DecodeError::IllegalOpcode { address: self.pc as usize, opcode });
+        }
+        let bus = &self.bus;
+
+        Ok(disassemble(|address| bus.peek(address), self.pc, 1).remove(0))
+    }
+
+    pub fn trace_event(&self) -> TraceEvent {
+        TraceEvent {
+            pc: self.pc,
+            opcode: self.current_instruction,
+            decoded_opcode: self.current_opcode,
+            fetched_word: self.current_fetched_word,
+            operand_bytes: [self.bus.peek(self.pc.wrapping_add(1)), self.bus.peek(self.pc.wrapping_add(2))],
+            a: self.a,
+            x: self.x,
+            y: self.y,
+            p: self.p,
+            sp: self.sp,
+            total_cycles: self.total_cycles,
+        }
+    }
+
+    /// Loads the next instruction, falling back to the illegal opcode policy if it can't be
+    /// decoded.
+    fn fetch_instruction(&mut self) -> Result<DecodedOpcode, DecodeError> {
+        let error = match self.load_instruction() {
+            Ok(opcode) => return Ok(opcode),
+            // This one isn't up to the policy, the caller asked for it.
+            Err(error @ DecodeError::UnofficialOpcode { .. }) => return Err(error),
+            Err(error) => error,
         };
-        self.cycles -= 1;
-        self.total_cycles += 1;
 
-        debug
+        let instruction = match self.illegal_opcode_policy {
+            IllegalOpcodePolicy::Panic => panic!("Invalid opcode! {:?}", error),
+            IllegalOpcodePolicy::TreatAsNop => Instruction::NOP,
+            IllegalOpcodePolicy::Halt => Instruction::JAM,
+            IllegalOpcodePolicy::Error => return Err(error),
+        };
+        let opcode = DecodedOpcode { instruction, mode: Implied, cycles: 2, is_official: false };
+        self.current_opcode = opcode;
+        self.cycles = opcode.cycles as u16;
+
+        Ok(opcode)
     }
 
     pub fn load_instruction(&mut self) -> Result<DecodedOpcode, DecodeError> {
         let instruction = self.bus.read(self.pc) as u8;
         let opcode = Opcode::decode(&instruction)?;
-        self.set_status(B, true); // This flag is unused but for accuracy should always be used
+        if self.strict_illegals && !opcode.is_official {
+            return Err(DecodeError::UnofficialOpcode { address: self.pc as usize, opcode: instruction });
+        }
+        self.set_status(U, true); // This flag is unused but for accuracy should always be used
         self.current_instruction = instruction;
-        self.current_opcode = opcode.clone();
-        self.cycles = opcode.cycles;
+        self.current_opcode = opcode;
+        self.cycles = opcode.cycles as u16;
 
         Ok(opcode)
     }
 
+    pub fn is_halted(&self) -> bool { self.halted }
+
+    /// CPU cycles since the last reset or `reset_cycle_counter`, starting with the 7 cycles the
+    /// reset takes.
+    pub fn total_cycles(&self) -> u64 {
+        self.total_cycles
+    }
+
+    /// Zeroes `total_cycles`, e.g. to measure how long a routine takes.
+    pub fn reset_cycle_counter(&mut self) {
+        self.total_cycles = 0;
+    }
+
+    /// Master clock cycles since the CPU was created. Neither resets nor `reset_cycle_counter`
+    /// affect it so it can be used as an absolute time.
+    pub fn master_cycles(&self) -> u64 {
+        self.master_cycles
+    }
+
+    /// The clock rate used by `cycles_to_duration` and `duration_to_cycles`, e.g. `PAL_CPU_HZ`.
+    /// Nothing else runs any differently.
+    pub fn set_clock_rate(&mut self, hz: u32) {
+        self.clock_hz = hz;
+    }
+
+    pub fn clock_rate(&self) -> u32 {
+        self.clock_hz
+    }
+
+    /// How long `cycles` take on real hardware, e.g. for a frontend to pace itself.
+    pub fn cycles_to_duration(&self, cycles: u32) -> Duration {
+        Duration::from_nanos((cycles as u128 * NANOS_PER_SECOND / self.clock_hz as u128) as u64)
+    }
+
+    /// How many whole cycles real hardware runs in `duration`.
+    pub fn duration_to_cycles(&self, duration: Duration) -> u32 {
+        (duration.as_nanos() * self.clock_hz as u128 / NANOS_PER_SECOND) as u32
+    }
+
+    pub fn add_breakpoint(&mut self, pc: u16) {
+        self.breakpoints.insert(pc);
+    }
+
+    pub fn remove_breakpoint(&mut self, pc: u16) {
+        self.breakpoints.remove(&pc);
+    }
+
+    /// Runs the CPU until the current instruction has finished. If no instruction is in progress
+    /// then exactly one instruction is run. A halted CPU doesn't advance.
+    pub fn step(&mut self) -> Result<CpuState, DecodeError> {
+        loop {
+            self.clock()?;
+            if self.halted {
+                return Ok(CpuState::Halted);
+            }
+            if self.cycles == 0 {
+                return Ok(CpuState::Running);
+            }
+        }
+    }
+
+    /// Runs an instruction for every item taken from the iterator, yielding what ran. An
+    /// instruction that's already in progress is finished first. The iterator ends once the CPU
+    /// halts so `.take(n)` is needed to stop a CPU that doesn't.
+    pub fn instructions(&mut self) -> impl Iterator<Item = Result<InstructionTrace, DecodeError>> + '_ {
+        core::iter::from_fn(move || {
+            if self.cycles != 0 {
+                if let Err(error) = self.step() {
+                    return Some(Err(error));
+                }
+            }
+            if self.halted {
+                return None;
+            }
+            let pc = self.pc;
+            let operand_bytes = [self.bus.peek(pc.wrapping_add(1)), self.bus.peek(pc.wrapping_add(2))];
+            let total_cycles = self.total_cycles;
+            if let Err(error) = self.step() {
+                return Some(Err(error));
+            }
+
+            Some(Ok(InstructionTrace {
+                pc,
+                opcode: self.current_instruction,
+                decoded_opcode: self.current_opcode,
+                operand_bytes,
+                cycles: (self.total_cycles - total_cycles) as u16,
+            }))
+        })
+    }
+
+    /// Whether the CPU is part way through an instruction, i.e. the next `clock` won't start a new
+    /// one. Everything needed to carry on is kept in the CPU so it's fine to stop calling `clock`
+    /// here, e.g. to pause, and pick up later. Running in chunks gives exactly the same results
+    /// as running straight through.
+    pub fn is_mid_instruction(&self) -> bool {
+        self.cycles != 0
+    }
+
+    /// Clocks the CPU `n` times. Instructions don't have to line up with `n`, whatever's left of
+    /// the current instruction carries on in the next call. A halted CPU doesn't advance.
+    pub fn step_cycles(&mut self, n: u32) -> Result<(), DecodeError> {
+        for _ in 0..n {
+            self.clock()?;
+        }
+
+        Ok(())
+    }
+
     /// This handles the fetching, decoding and execution of an instruction. It also simulates
     /// the creation of
-    pub fn clock(&mut self) {
+    pub fn clock(&mut self) -> Result<(), DecodeError> {
+        if self.halted {
+            return Ok(());
+        }
         if self.cycles == 0 {
-            let opcode = self.load_instruction().unwrap_or_else(|_| {
-                // TODO: put this error handling elsewhere
-                panic!("Invalid opcode!")
-            });
-            self.pc += 1;
-            self.cycles += self.execute(opcode);
+            self.i_before_instruction = self.get_status(I);
+            self.in_interrupt_sequence = false;
+            let opcode = self.fetch_instruction()?;
+            if self.trace_hook.is_some() {
+                let event = self.trace_event();
+                let allowed = self.trace_filter.allows(&event, self.last_trace_pc);
+                self.last_trace_pc = Some(event.pc);
+                if let Some(hook) = self.trace_hook.as_mut().filter(|_| allowed) {
+                    hook(&event);
+                }
+            }
+            self.pc = self.pc.wrapping_add(1);
+            self.cycles += self.execute(opcode) as u16;
             //self.execute(opcode);
-            self.set_status(B, true); // This flag is unused but for accuracy should always be used
+            self.set_status(U, true); // This flag is unused but for accuracy should always be used
         }
         self.cycles -= 1;
         self.total_cycles += 1;
+        self.master_cycles += MASTER_CYCLES_PER_CPU_CYCLE;
+
+        Ok(())
     }
 
+    /// Pressing the reset button. RAM and A/X/Y are left alone, everything else is as it is right
+    /// after power on. The next 7 clocks are spent on the reset sequence itself.
     pub fn reset(&mut self) {
-        self.current_fetched_word = 0xFFFC; // This is the start address for that is read from memory
-        let lo = self.bus.read(self.current_fetched_word);
-        let hi = self.bus.read(self.current_fetched_word + 1);
+        self.pc = self.bus.read16(0xFFFC);
 
-        self.pc = ((hi as u16) << 8) | lo as u16;
-
-        self.a = 0;
-        self.x = 0;
-        self.y = 0;
         self.sp = 0xFD;
-        self.p = 0x00 | B as u8;  // FIXME: is U needed?
+        self.p = 0x24;
+        self.halted = false;
+        self.nmi_polled = false;
+        self.irq_polled = false;
+        self.in_interrupt_sequence = false;
+        self.hijack_cycles = 0;
 
-        self.cycles = 8;
+        self.cycles = 7;
+        self.total_cycles = 0;
+        self.current_instruction = 0;
+        self.current_fetched_word = 0x0000;
     }
 
-    fn irq(&mut self) {
-        if self.get_status(I) == false {  // i.e. if interrupts are allowed
-            self.bus.write(0x0100 + (self.sp as u16), (self.pc >> 8) as u8);
-            self.bus.write(0x0100 + (self.sp - 1) as u16, (self.pc & 0x00FF) as u8);
-            self.sp -= 2;
+    /// The stack is always in page 1. The stack pointer wraps around within the page.
+    fn _push(&mut self, data: u8) {
+        self.bus.write(STACK_PAGE | self.sp as u16, data);
+        self.sp = self.sp.wrapping_sub(1);
+    }
 
-            self.set_status(B, false);
-            self.set_status(B, true);
-            self.set_status(I, true);
-            self.bus.write(0x0100 + (self.sp as u16), self.p);
-            self.sp -= 1;
+    fn _pull(&mut self) -> u8 {
+        self.sp = self.sp.wrapping_add(1);
+        self.bus.read(STACK_PAGE | self.sp as u16)
+    }
 
-            self.current_fetched_word = 0xFFFE;
-            let lo = self.bus.read(self.current_fetched_word);
-            let hi = self.bus.read(self.current_fetched_word + 1);
-            self.pc = ((hi as u16) << 8) | lo as u16;
+    /// I has already been checked by the time this is called, see `poll_interrupts`.
+    fn irq(&mut self) {
+        self._push((self.pc >> 8) as u8);
+        self._push(self.pc as u8);
 
-            self.cycles = 7;
-        }
+        // Unlike BRK the break bit (B) is clear in the pushed copy. I is set after pushing so
+        // RTI restores the status from before the interrupt.
+        self._push((self.p & !(B as u8)) | U as u8);
+        self.set_status(I, true);
+
+        self.pc = self.read_vector(0xFFFE);
+        self.cycles = 7;
+        self.in_interrupt_sequence = true;
+        self.hijack_cycles = 4;
     }
 
     fn nmi(&mut self) {
-        self.bus.write(0x0100 + (self.sp as u16), (self.pc >> 8) as u8);
-        self.bus.write(0x0100 + (self.sp - 1) as u16, (self.pc & 0x00FF) as u8);
-        self.sp -= 2;
+        self._push((self.pc >> 8) as u8);
+        self._push(self.pc as u8);
 
-        self.set_status(B, false);
-        self.set_status(B, true);
+        self._push((self.p & !(B as u8)) | U as u8);
         self.set_status(I, true);
-        self.bus.write(0x0100 + (self.sp as u16), self.p);
-        self.sp -= 1;
-
-        self.current_fetched_word = 0xFFFA;
-        let lo = self.bus.read(self.current_fetched_word);
-        let hi = self.bus.read(self.current_fetched_word + 1);
-        self.pc = ((hi as u16) << 8) | lo as u16;
 
+        self.pc = self.read_vector(0xFFFA);
         self.cycles = 7;
+        self.in_interrupt_sequence = true;
+    }
+
+    fn read_vector(&mut self, vector: u16) -> u16 {
+        self.current_fetched_word = vector;
+        self.bus.read16(vector)
     }
 
     // Returns number of extra cycles to be performed if crossing page boundary
@@ -218,63 +741,55 @@ impl CPU {
     }
 
     fn fetch_accumulator(&mut self) -> u8 {
-        self.current_fetched_word = self.a.try_into().unwrap();
+        self.current_fetched_word = self.a as u16;
         0
     }
 
     fn fetch_immediate(&mut self) -> u8 {
         self.current_fetched_word = self.bus.read(self.pc).into();
-        self.pc += 1;
+        self.pc = self.pc.wrapping_add(1);
         0
     }
 
     fn fetch_zero_page(&mut self) -> u8 {
         self.current_fetched_word = self.bus.read(self.pc).into();
-        self.pc += 1;
+        self.pc = self.pc.wrapping_add(1);
         self.current_fetched_word &= 0x00FF;
         0
     }
 
     fn fetch_zero_page_x(&mut self) -> u8 {
         self.current_fetched_word = self.bus.read(self.pc) as u16 + (self.x as u16);
-        self.pc += 1;
+        self.pc = self.pc.wrapping_add(1);
         self.current_fetched_word &= 0x00FF;
         0
     }
 
     fn fetch_zero_page_y(&mut self) -> u8 {
         self.current_fetched_word = self.bus.read(self.pc) as u16 + (self.y as u16);
-        self.pc += 1;
+        self.pc = self.pc.wrapping_add(1);
         self.current_fetched_word &= 0x00FF;
         0
     }
 
     fn fetch_absolute(&mut self) -> u8 {
-        let lo = self.bus.read(self.pc);
-        let hi = self.bus.read(self.pc + 1);
-        self.pc += 2;
-        self.current_fetched_word = ((hi as u16) << 8 | lo as u16);
+        self.current_fetched_word = self.bus.read16(self.pc);
+        self.pc = self.pc.wrapping_add(2);
         0
     }
 
     fn fetch_absolute_x_indexed(&mut self) -> u8 {
-        let lo = self.bus.read(self.pc);
-        let hi = self.bus.read(self.pc + 1);
-        self.current_fetched_word = ((hi as u16) << 8 | lo as u16);
-        let (temp, _) = self.current_fetched_word.overflowing_add(self.x as u16);
-        self.current_fetched_word = temp;
-        self.pc += 2;
-        self._extra_cycles(self.current_fetched_word, hi)
+        let base = self.bus.read16(self.pc);
+        self.current_fetched_word = base.wrapping_add(self.x as u16);
+        self.pc = self.pc.wrapping_add(2);
+        self._page_cross_dummy_read((base >> 8) as u8)
     }
 
     fn fetch_absolute_y_indexed(&mut self) -> u8 {
-        let lo = self.bus.read(self.pc);
-        let hi = self.bus.read(self.pc + 1);
-        self.current_fetched_word = ((hi as u16) << 8 | lo as u16);
-        let (temp, _) = self.current_fetched_word.overflowing_add(self.y as u16);
-        self.current_fetched_word = temp;
-        self.pc += 2;
-        self._extra_cycles(self.current_fetched_word, hi)
+        let base = self.bus.read16(self.pc);
+        self.current_fetched_word = base.wrapping_add(self.y as u16);
+        self.pc = self.pc.wrapping_add(2);
+        self._page_cross_dummy_read((base >> 8) as u8)
     }
 
     /// This addressing mode purposefully does the wrong thing due to an error in 6502 hardware.
@@ -282,17 +797,13 @@ impl CPU {
     /// but instead the bug was that it wraps to the beginning of the existing page and fetches
     /// that byte.
     fn fetch_indirect(&mut self) -> u8 {
-        let lo = self.bus.read(self.pc);
-        let hi = self.bus.read(self.pc + 1);
-        self.pc += 2;
-
-        let pointer: u16 = ((hi as u16) << 8 | lo as u16).into();
+        let pointer = self.bus.read16(self.pc);
+        self.pc = self.pc.wrapping_add(2);
 
-        if lo == 0xFF {  // i.e. if about to cross page boundary emulate bug
-            self.current_fetched_word = (self.bus.read(pointer & 0xFF00) as u16) << 8 | self.bus.read(pointer) as u16
-        } else {
-            self.current_fetched_word = (self.bus.read(pointer + 1) as u16) << 8 | self.bus.read(pointer) as u16
-        }
+        // Only the low byte of the pointer is incremented, so the high byte comes from the same page.
+        let lo = self.bus.read(pointer);
+        let hi = self.bus.read((pointer & 0xFF00) | (pointer.wrapping_add(1) & 0x00FF));
+        self.current_fetched_word = u16::from_le_bytes([lo, hi]);
         0
     }
 
@@ -307,7 +818,7 @@ impl CPU {
     // FIXME: Is this right?
     fn fetch_relative(&mut self) -> u8 {
         self.current_fetched_word = self.bus.read(self.pc) as u16;
-        self.pc += 1;
+        self.pc = self.pc.wrapping_add(1);
 
         if (self.current_fetched_word & 0x80) >= 1 {
             self.current_fetched_word |= 0xFF00;
@@ -319,27 +830,57 @@ impl CPU {
     /// AKA Indirect X
     fn fetch_indexed_indirect(&mut self) -> u8 {
         self.current_fetched_word = self.bus.read(self.pc) as u16;
-        self.pc += 1;
+        self.pc = self.pc.wrapping_add(1);
 
-        let lo = (self.bus.read(self.current_fetched_word + self.x as u16 & 0x00FF) as u16);
-        let hi = self.bus.read(self.current_fetched_word + 1 + self.x as u16 & 0x00FF) as u16;
-        let indirect_address = hi << 8 | lo;
-        self.current_fetched_word = indirect_address;
+        let pointer = (self.current_fetched_word as u8).wrapping_add(self.x);
+        self.current_fetched_word = self.bus.read16_zp_wrap(pointer);
         0
     }
 
     /// AKA Indirect Y
     fn fetch_indirect_indexed(&mut self) -> u8 {
-        self.current_fetched_word = self.bus.read(self.pc).try_into().unwrap();
-        self.pc += 1;
+        self.current_fetched_word = self.bus.read(self.pc) as u16;
+        self.pc = self.pc.wrapping_add(1);
+
+        let base = self.bus.read16_zp_wrap(self.current_fetched_word as u8);
+        self.current_fetched_word = base.wrapping_add(self.y as u16);
 
-        let lo = self.bus.read(self.current_fetched_word & 0x00FF);
-        let hi = self.bus.read((self.current_fetched_word + 1) & 0x00FF);
-        self.current_fetched_word = ((hi as u16) << 8) | lo as u16;
-        let (temp, _) = self.current_fetched_word.overflowing_add(self.y as u16);
-        self.current_fetched_word = temp;
+        self._page_cross_dummy_read((base >> 8) as u8)
+    }
+
+    /// What the last `fetch` found, for the addressing mode it was given.
+    fn operand(&self, mode: AddressingMode) -> Operand {
+        match mode {
+            Implied => Operand::Implied,
+            Accumulator => Operand::Accumulator,
+            Immediate | Relative => Operand::Immediate(self.current_fetched_word as u8),
+            _ => Operand::Address(self.current_fetched_word),
+        }
+    }
 
-        self._extra_cycles(self.current_fetched_word, hi)
+    /// Indexed addressing adds the index to the low byte and reads from there while the carry is
+    /// added to the high byte, so when the index crosses a page the first read is from the page
+    /// before. Mapped registers can see that read so it's worth doing.
+    ///
+    /// Returns 1 if a page was crossed. `hi` is the high byte of the base address.
+    fn _page_cross_dummy_read(&mut self, hi: u8) -> u8 {
+        let page_cross_cycle = self._extra_cycles(self.current_fetched_word, hi);
+        if page_cross_cycle == 1 {
+            self.bus.read((hi as u16) << 8 | self.current_fetched_word & 0x00FF);
+        }
+        page_cross_cycle
+    }
+
+    /// A store can't take back a write to the wrong page so it always does the read from before
+    /// the high byte is fixed, even when there's nothing to fix. Page crosses already did the read
+    /// in `_page_cross_dummy_read`.
+    fn _dummy_read(&mut self, mode: AddressingMode, page_cross_cycle: u8) {
+        use AddressingMode::*;
+        if let IndexedAbsoluteX | IndexedAbsoluteY | IndirectIndexed = mode {
+            if page_cross_cycle == 0 {
+                self.bus.read(self.current_fetched_word);
+            }
+        }
     }
 
     /// Helper for determining if a page boundary has been crossed and needs extra cycle
@@ -349,24 +890,39 @@ impl CPU {
 
     /// Instruction functionality below here
 
+    /// Returns the cycles the instruction takes on top of the opcode table's base cycles.
+    ///
+    /// Every instruction returns its extra cycles rather than touching `self.cycles`:
+    /// * Reads with indexed addressing take 1 more cycle when the index crosses a page. `fetch`
+    ///   returns 1 on a page cross and the instruction returns 1 if it's one that pays for it, so
+    ///   the two are ANDed. Stores and read-modify-writes always take the base cycles.
+    /// * Branches take 1 more cycle when taken and another when the destination is on a different
+    ///   page. Relative addressing never reports a page cross so branches return their cycles as is.
+    /// See: http://www.oxyron.de/html/opcodes02.html
     fn execute(&mut self, opcode: DecodedOpcode) -> u8 {
-        //let mut cycles = opcode.cycles;
         // TODO: Below has the side effect of fetching and writing data to and from registers, memory, etc.
-        // FIXME: The below should both just return bools based on additoinal cycle
         let address_page_cross_cycle = self.fetch(opcode.mode);
+        if let Instruction::STA | Instruction::STX | Instruction::STY = opcode.instruction {
+            // Stores always spend the cycle for the dummy read, which is why they never take a page
+            // cross cycle.
+            self._dummy_read(opcode.mode, address_page_cross_cycle);
+        }
         // FIXME: Most instructions don't care about addressing mode. Only immediate and accumulator based instructions
-        let potential_extra_instruction_cycle = self.run_instruction(opcode.instruction, opcode.mode);
-        let additional_cycles = address_page_cross_cycle & potential_extra_instruction_cycle;
-
-        additional_cycles
+        let operand = self.operand(opcode.mode);
+        let extra_instruction_cycles = self.run_instruction(opcode.instruction, opcode.mode, operand);
+        match opcode.mode {
+            AddressingMode::Relative => extra_instruction_cycles,
+            _ => address_page_cross_cycle & extra_instruction_cycles,
+        }
     }
 
-    fn run_instruction(&mut self, instruction: Instruction, mode: AddressingMode) -> u8 {
+    /// Instructions are moving over from `mode` and `current_fetched_word` to `operand`.
+    fn run_instruction(&mut self, instruction: Instruction, mode: AddressingMode, operand: Operand) -> u8 {
         use Instruction::*;
         match instruction {
             ADC => self.add_with_carry(mode), // Add Memory to Accumulator with Carry
             AND => self.logical_and(mode), // "AND" Memory with Accumulator
-            ASL => self.arithmetic_shift_left(mode), // Shift Left One Bit (Memory or Accumulator)
+            ASL => self.arithmetic_shift_left(operand), // Shift Left One Bit (Memory or Accumulator)
 
             BCC => self.branch_if_carry_clear(), // Branch on Carry Clear
             BCS => self.branch_if_carry_set(), // Branch on Carry Set
@@ -397,16 +953,16 @@ impl CPU {
             INX => self.increment_x_register(), // Increment Index X by One
             INY => self.increment_y_register(), // Increment Index Y by One
 
-            JMP => self.jump(), // Jump to New Location
+            JMP => self.jump(operand), // Jump to New Location
 
-            JSR => self.jump_to_subroutine(), // Jump to New Location Saving Return Address
+            JSR => self.jump_to_subroutine(operand), // Jump to New Location Saving Return Address
 
             LDA => self.load_accumulator(mode), // Load Accumulator with Memory
             LDX => self.load_x_register(mode), // Load Index X with Memory
             LDY => self.load_y_register(mode), // Load Index Y with Memory
-            LSR => self.logical_shift_right(mode), // Shift Right One Bit (Memory or Accumulator)
+            LSR => self.logical_shift_right(operand), // Shift Right One Bit (Memory or Accumulator)
 
-            NOP => self.no_operation(), // No Operation
+            NOP => self.no_operation(operand), // No Operation
 
             ORA => self.logical_inclusive_or(mode), // "OR" Memory with Accumulator
 
@@ -415,8 +971,8 @@ impl CPU {
             PLA => self.pull_accumulator(), // Pull Accumulator from Stack
             PLP => self.pull_processor_status(), // Pull Processor Status from Stack
 
-            ROL => self.rotate_left(mode), // Rotate One Bit Left (Memory or Accumulator)
-            ROR => self.rotate_right(mode), // Rotate One Bit Right (Memory or Accumulator)
+            ROL => self.rotate_left(operand), // Rotate One Bit Left (Memory or Accumulator)
+            ROR => self.rotate_right(operand), // Rotate One Bit Right (Memory or Accumulator)
             RTI => self.return_from_interrupt(), // Return from Interrupt
             RTS => self.return_from_subroutine(), // Return from Subroutine
 
@@ -424,9 +980,9 @@ impl CPU {
             SEC => self.set_carry_flag(), // Set Carry Flag
             SED => self.set_decimal_flag(), // Set Decimal Mode
             SEI => self.set_interrupt_disable(), // Set Interrupt Disable Status
-            STA => self.store_accumulator(), // Store Accumulator in Memory
-            STX => self.store_x_register(), // Store Index X in Memory
-            STY => self.store_y_register(), // Store Index Y in Memory
+            STA => self.store_accumulator(operand), // Store Accumulator in Memory
+            STX => self.store_x_register(operand), // Store Index X in Memory
+            STY => self.store_y_register(operand), // Store Index Y in Memory
 
             TAX => self.transfer_accumulator_to_x(), // Transfer Accumulator to Index X
             TAY => self.transfer_accumulator_to_y(), // Transfer Accumulator to Index Y
@@ -448,6 +1004,7 @@ impl CPU {
             RRA => self.rra(),
             SLO => self.slo(),
             SRE => self.sre(),
+            JAM => self.jam(),
 
             // Below is needed for exhaustive matching but UNK is only really used when
             // disassembling a ROM
@@ -489,7 +1046,6 @@ impl CPU {
             Absolute => self.bus.read(self.current_fetched_word),
             Relative => (self.current_fetched_word & 0xFF) as u8,
             Accumulator => self.current_fetched_word as u8,
-            IndexedIndirect => (((self.bus.read(self.current_fetched_word + 1) as u16) << 8 | self.bus.read(self.current_fetched_word) as u16) & 0xFF) as u8,
             _ => self.bus.read(self.current_fetched_word)
         };
 
@@ -503,80 +1059,135 @@ impl CPU {
     /// code a bit simpler than creating a bool and doing the logic elsewhere.
     fn add_with_carry(&mut self, mode: AddressingMode) -> u8 {
         let operand = self.fetch_operand();
+        self._add_with_carry(operand);
+
+        1
+    }
+
+    fn _add_with_carry(&mut self, operand: u8) {
+        if self.decimal_enabled && self.get_status(D) {
+            self._decimal_add(operand);
+            return;
+        }
 
         let sum = (self.a as u16) + (operand as u16) + (self.get_status(C) as u16);
+        let result = sum as u8;
         self.set_status(C, sum > 0xFF);
-        self.set_status(Z, (sum & 0x00FF) == 0);
-        let overflow = (!(self.a ^ operand) as u16 & (self.a as u16 ^ sum) & 0x0080 != 0);
-        self.set_status(V, overflow);
-        self.set_status(N, (sum & 0x80) != 0);
-        self.a = (sum & 0x00FF) as u8;
+        self.set_status(Z, result == 0);
+        // Overflow is when both inputs have the same sign and the result doesn't. This is done on
+        // the 8 bit result so the carry out in bit 8 can't get mixed in.
+        self.set_status(V, (self.a ^ result) & (operand ^ result) & 0x80 != 0);
+        self.set_status(N, (result & 0x80) != 0);
+        self.a = result;
+    }
+
+    /// BCD addition the way the NMOS 6502 does it. Z is set from the binary sum and N and V from
+    /// the sum before the high digit is adjusted, so only A and C are reliable.
+    /// See: http://www.6502.org/tutorials/decimal_mode.html#A
+    fn _decimal_add(&mut self, operand: u8) {
+        let carry = self.get_status(C) as i16;
+        let mut low = (self.a & 0x0F) as i16 + (operand & 0x0F) as i16 + carry;
+        if low >= 0x0A {
+            low = ((low + 0x06) & 0x0F) + 0x10;
+        }
+        let signed_sum = (self.a & 0xF0) as i8 as i16 + (operand & 0xF0) as i8 as i16 + low;
+        let mut sum = (self.a & 0xF0) as i16 + (operand & 0xF0) as i16 + low;
+        if sum >= 0xA0 {
+            sum += 0x60;
+        }
 
-        1
+        self.set_status(Z, self.a.wrapping_add(operand).wrapping_add(carry as u8) == 0);
+        self.set_status(N, signed_sum & 0x80 != 0);
+        self.set_status(V, !(-128..=127).contains(&signed_sum));
+        self.set_status(C, sum >= 0x100);
+        self.a = sum as u8;
     }
 
-    fn logical_and(&mut self, mode: AddressingMode) -> u8 {
-        self.a = self.a & match mode {
-            _ => self.fetch_operand()
-        };
+    fn logical_and(&mut self, _mode: AddressingMode) -> u8 {
+        let operand = self.fetch_operand();
+
+        self.a &= operand;
+
         self.set_status(Z, self.a == 0x00);
-        self.set_status(N, (self.a & 0b10000000) != 0);
+        self.set_status(N, self.a.is_negative());
 
         1
     }
 
-    // TODO: Clean below up.
-    fn arithmetic_shift_left(&mut self, mode: AddressingMode) -> u8 {
-        let operand = match mode {
-            Accumulator => self.a,
-            _ => self.bus.read(self.current_fetched_word)
-        };
+    /// Read-modify-write instructions read the operand, write the unmodified value back while the
+    /// ALU works on it and then write the modified value. Memory mapped devices can see both writes
+    /// so both are performed. These instructions always take a fixed number of cycles, i.e. indexed
+    /// forms never get the page cross cycle.
+    fn _read_modify_write(&mut self, address: u16, modify: fn(&mut Self, u8) -> u8) -> u8 {
+        let operand = self.bus.read(address);
+        self.bus.write(address, operand); // Dummy write of the unmodified value
+        let modified = modify(self, operand);
+        self.bus.write(address, modified);
+
+        modified
+    }
+
+    /// ASL, LSR, ROL and ROR either shift A or do a read-modify-write of memory.
+    fn _shift_helper(&mut self, operand: Operand, shift: fn(&mut Self, u8) -> u8) {
+        match operand {
+            Operand::Accumulator => {
+                let a = self.a;
+                self.a = shift(self, a);
+            }
+            Operand::Address(address) => {
+                self._read_modify_write(address, shift);
+            }
+            Operand::Implied | Operand::Immediate(_) => unreachable!("Shifts only have accumulator and memory forms"),
+        }
+    }
 
+    fn _shift_left(&mut self, operand: u8) -> u8 {
         let shifted = (operand as u16) << 1;
         self.set_status(C, (shifted & 0xFF00) > 0);
         self.set_status(Z, (shifted & 0x00FF) == 0);
-        self.set_status(N, (shifted & 0b10000000) != 0);
+        self.set_status(N, (shifted as u8).is_negative());
 
-        match mode {
-            Accumulator => self.a = shifted as u8,
-            _ => self.bus.write(self.current_fetched_word, shifted as u8)
-        };
+        shifted as u8
+    }
+
+    fn arithmetic_shift_left(&mut self, operand: Operand) -> u8 {
+        self._shift_helper(operand, Self::_shift_left);
 
         0
     }
 
-    fn _branch_helper(&mut self) {
-        self.cycles += 1;
-        let branch_address = (self.pc as i16 + self.current_fetched_word as i16) as u16;
-        if (branch_address & 0xFF00) != (self.pc & 0xFF00) {
-            self.cycles += 1;
-        }
+    /// Takes the branch and returns the extra cycles it took.
+    fn _branch_helper(&mut self) -> u8 {
+        // The offset is already sign extended by fetch_relative.
+        let branch_address = self.pc.wrapping_add(self.current_fetched_word);
+        let page_cross_cycle = if (branch_address & 0xFF00) != (self.pc & 0xFF00) { 1 } else { 0 };
 
-        self.pc = branch_address
+        self.pc = branch_address;
+        1 + page_cross_cycle
     }
 
     fn branch_if_carry_clear(&mut self) -> u8 {
         if !self.get_status(C) {
-            self._branch_helper();
+            self._branch_helper()
+        } else {
+            0
         }
-
-        0
     }
 
     fn branch_if_carry_set(&mut self) -> u8 {
         if self.get_status(C) {
-            self._branch_helper();
+            self._branch_helper()
+        } else {
+            0
         }
-
-        0
     }
 
     fn branch_if_equal(&mut self) -> u8 {
         if self.get_status(Z) {
-            self._branch_helper();
+            self._branch_helper()
+        } else {
+            0
         }
-
-        0
     }
 
     fn bit_test(&mut self) -> u8 {
@@ -591,62 +1202,59 @@ impl CPU {
 
     fn branch_if_minus(&mut self) -> u8 {
         if self.get_status(N) {
-            self._branch_helper();
+            self._branch_helper()
+        } else {
+            0
         }
-
-        0
     }
 
     fn branch_if_not_equal(&mut self) -> u8 {
         if !self.get_status(Z) {
-            self._branch_helper();
+            self._branch_helper()
+        } else {
+            0
         }
-
-        0
     }
 
     fn branch_if_positive(&mut self) -> u8 {
         if !self.get_status(N) {
-            self._branch_helper();
+            self._branch_helper()
+        } else {
+            0
         }
-
-        0
     }
 
-    // FIXME: Check if below is correct. Different docs indicate different implementations, particularly
-    // around the state of the status flags and how they get pushed to stack.
-    /// This should advance the program counter by 2, push the pc and status (p) registers to
-    /// stack, sets the I flag, and reloads PC from $FFFE-$FFFF
+    /// The byte after BRK is padding so the pc pushed is the address of BRK + 2. The status is
+    /// pushed with the break bits set, but only in the copy on the stack. I is then set and the pc
+    /// is loaded from $FFFE-$FFFF.
     fn force_interrupt(&mut self) -> u8 {
-        self.pc += 1;
-        self.set_status(I, true); // FIXME: Some docs say this isn't needed. Figure it out.
-        self.bus.write(0x0100 + (self.sp as u16), ((self.pc >> 8) as u8) & 0x00FF);
-        self.bus.write(0x0100 + ((self.sp - 1) as u16), (self.pc as u8) & 0x00FF);
-        self.sp -= 2;
-        self.set_status(B, true);  // I think this flag is only needed when pushing the status register to stack.
-        self.bus.write(0x0100 + (self.sp as u16), self.p);
-        self.sp -= 1;
-        self.set_status(B, false);
+        self.pc = self.pc.wrapping_add(1);
+        self._push((self.pc >> 8) as u8);
+        self._push(self.pc as u8);
+        self._push(self.p | B as u8 | U as u8);
+        self.set_status(I, true);
 
-        self.pc = (self.bus.read(0xFFFF) as u16) << 8 | self.bus.read(0xFFFE) as u16;
+        self.pc = self.bus.read16(0xFFFE);
+        self.in_interrupt_sequence = true;
+        self.hijack_cycles = 4;
 
         0
     }
 
     fn branch_if_overflow_clear(&mut self) -> u8 {
         if !self.get_status(V) {
-            self._branch_helper();
+            self._branch_helper()
+        } else {
+            0
         }
-
-        0
     }
 
     fn branch_if_overflow_set(&mut self) -> u8 {
         if self.get_status(V) {
-            self._branch_helper();
+            self._branch_helper()
+        } else {
+            0
         }
-
-        0
     }
 
     fn clear_carry_flag(&mut self) -> u8 {
@@ -677,11 +1285,15 @@ impl CPU {
 
     fn _compare_helper(&mut self, register_value: u8) {
         let operand = self.fetch_operand();
+        self._compare(register_value, operand);
+    }
+
+    fn _compare(&mut self, register_value: u8, operand: u8) {
         let (temp_difference, _) = register_value.overflowing_sub(operand);
 
         self.set_status(C, register_value >= operand);
         self.set_status(Z, temp_difference == 0);
-        self.set_status(N, (temp_difference & 0b10000000) != 0);  // FIXME: Is this correct?
+        self.set_status(N, temp_difference.is_negative());
     }
 
     fn compare(&mut self, mode: AddressingMode) -> u8 {
@@ -690,16 +1302,17 @@ impl CPU {
         1
     }
 
+    /// CPX and CPY have no indexed addressing modes so they can never cross a page.
     fn compare_x_register(&mut self, mode: AddressingMode) -> u8 {
         self._compare_helper(self.x);
 
-        1
+        0
     }
 
     fn compare_y_register(&mut self, mode: AddressingMode) -> u8 {
         self._compare_helper(self.y);
 
-        1
+        0
     }
 
     // FIXME: Can I use this?
@@ -707,17 +1320,19 @@ impl CPU {
         let new = operand - 1;
 
         self.set_status(Z, operand == 0);
-        self.set_status(N, (operand & 0b10000000) != 0);
+        self.set_status(N, operand.is_negative());
 
         new
     }
 
     fn decrement_memory(&mut self) -> u8 {
-        let (operand, _) = self.fetch_operand().overflowing_sub(1);
+        self._read_modify_write(self.current_fetched_word, |cpu, operand| {
+            let (operand, _) = operand.overflowing_sub(1);
+            cpu.set_status(Z, operand == 0);
+            cpu.set_status(N, operand.is_negative());
 
-        self.bus.write(self.current_fetched_word, operand);
-        self.set_status(Z, operand == 0);
-        self.set_status(N, (operand & 0b10000000) != 0);
+            operand
+        });
 
         0
     }
@@ -726,7 +1341,7 @@ impl CPU {
         let (temp, _) = self.x.overflowing_sub(1);
         self.x = temp;
         self.set_status(Z, self.x == 0);
-        self.set_status(N, (self.x & 0b10000000) != 0);
+        self.set_status(N, self.x.is_negative());
 
         0
     }
@@ -735,7 +1350,7 @@ impl CPU {
         let (temp, _) = self.y.overflowing_sub(1);
         self.y = temp;
         self.set_status(Z, self.y == 0);
-        self.set_status(N, (self.y & 0b10000000) != 0);
+        self.set_status(N, self.y.is_negative());
 
         0
     }
@@ -745,17 +1360,19 @@ impl CPU {
         self.a ^= operand;
 
         self.set_status(Z, self.a == 0x00);
-        self.set_status(N, (self.a & 0b10000000) != 0);
+        self.set_status(N, self.a.is_negative());
 
         1
     }
 
     fn increment_memory(&mut self) -> u8 {
-        let (operand, _) = self.fetch_operand().overflowing_add(1);
-        self.bus.write(self.current_fetched_word, operand); // FIXME: Is this right?
+        self._read_modify_write(self.current_fetched_word, |cpu, operand| {
+            let (operand, _) = operand.overflowing_add(1);
+            cpu.set_status(Z, operand == 0);
+            cpu.set_status(N, operand.is_negative());
 
-        self.set_status(Z, operand == 0);
-        self.set_status(N, (operand & 0b10000000) != 0);
+            operand
+        });
 
         0
     }
@@ -764,7 +1381,7 @@ impl CPU {
         let (temp, _) = self.x.overflowing_add(1);
         self.x = temp;
         self.set_status(Z, self.x == 0);
-        self.set_status(N, (self.x & 0b10000000) != 0);
+        self.set_status(N, self.x.is_negative());
 
         0
     }
@@ -773,26 +1390,29 @@ impl CPU {
         let (temp, _) = self.y.overflowing_add(1);
         self.y = temp;
         self.set_status(Z, self.y == 0);
-        self.set_status(N, (self.y & 0b10000000) != 0);
+        self.set_status(N, self.y.is_negative());
 
         0
     }
 
-    fn jump(&mut self) -> u8 {
-        self.pc = self.current_fetched_word;
+    fn jump(&mut self, operand: Operand) -> u8 {
+        if let Operand::Address(address) = operand {
+            self.pc = address;
+        }
 
         0
     }
 
     /// This one is somewhat non-trivial. We go back one in the program counter, write the current
-    /// PC to the stack and then jump to the address in currently_fetched_word
-    fn jump_to_subroutine(&mut self) -> u8 {
+    /// PC to the stack and then jump to the operand's address.
+    fn jump_to_subroutine(&mut self, operand: Operand) -> u8 {
         self.pc -= 1;
-        self.bus.write(0x0100 + self.sp as u16, ((self.pc >> 8) & 0x00FF) as u8);
-        self.bus.write(0x0100 + (self.sp - 1) as u16, (self.pc & 0x00FF) as u8);
-        self.sp -= 2;
+        self._push((self.pc >> 8) as u8);
+        self._push(self.pc as u8);
 
-        self.pc = self.current_fetched_word;
+        if let Operand::Address(address) = operand {
+            self.pc = address;
+        }
 
         0
     }
@@ -801,7 +1421,7 @@ impl CPU {
         self.a = self.fetch_operand();
 
         self.set_status(Z, self.a == 0);
-        self.set_status(N, (self.a & 0b10000000) != 0);
+        self.set_status(N, self.a.is_negative());
 
         1
     }
@@ -810,7 +1430,7 @@ impl CPU {
         self.x = self.fetch_operand();
 
         self.set_status(Z, self.x == 0);
-        self.set_status(N, (self.x & 0b10000000) != 0);
+        self.set_status(N, self.x.is_negative());
 
         1
     }
@@ -819,7 +1439,7 @@ impl CPU {
         self.y = self.fetch_operand();
 
         self.set_status(Z, self.y == 0);
-        self.set_status(N, (self.y & 0b10000000) != 0);
+        self.set_status(N, self.y.is_negative());
 
         1
     }
@@ -832,30 +1452,33 @@ impl CPU {
     /// sense to me since 7th bit will always be "0" as part of the operation of the instruction.
     /// Still including it as it's in a lot of the documentation online.
     /// TODO: See above note. Try and reconcile this with more information.
-    fn logical_shift_right(&mut self, mode: AddressingMode) -> u8 {
-        let mut operand = match mode {
-            Accumulator => self.a,
-            _ => self.fetch_operand()
-        };
+    fn logical_shift_right(&mut self, operand: Operand) -> u8 {
+        self._shift_helper(operand, Self::_shift_right);
 
+        0
+    }
+
+    fn _shift_right(&mut self, mut operand: u8) -> u8 {
         self.set_status(C, (operand & 0b00000001) == 1);
 
         operand >>= 1;
 
         self.set_status(Z, operand == 0x00);
-        self.set_status(N, (operand & 0b10000000) != 0);
+        self.set_status(N, operand.is_negative());
 
-        match mode {
-            Accumulator => self.a = operand,
-            _ => self.bus.write(self.current_fetched_word, operand)
-        };
-
-        0
+        operand
     }
 
-    /// Some NOPs are different based on unofficial opcodes. Not implementing any for now.
-    fn no_operation(&mut self) -> u8 {
-        1 // There is the potential for an extra cycle only in illegal aka unofficial opcodes.
+    /// The unofficial NOPs come with addressing modes other than implied. Fetch already moved the PC
+    /// past their operand bytes, but they also read (and throw away) their operand so memory mapped
+    /// devices see the read. Only the absolute,X forms can take the extra page cross cycle.
+    fn no_operation(&mut self, operand: Operand) -> u8 {
+        // The unofficial NOPs with a memory operand still read it.
+        if let Operand::Address(address) = operand {
+            self.bus.read(address);
+        }
+
+        1
     }
 
     fn logical_inclusive_or(&mut self, mode: AddressingMode) -> u8 {
@@ -864,14 +1487,13 @@ impl CPU {
         self.a |= operand;
 
         self.set_status(Z, self.a == 0x00);
-        self.set_status(N, (self.a & 0b10000000) != 0);
+        self.set_status(N, self.a.is_negative());
 
         1
     }
 
     fn push_accumulator(&mut self) -> u8 {
-        self.bus.write(0x0100 + self.sp as u16, self.a);
-        self.sp -= 1;
+        self._push(self.a);
 
         0
     }
@@ -880,112 +1502,129 @@ impl CPU {
         /// The spec says to also make sure this flag is set as well, even though it isn't used.
         /// It seems like the only time this actually matter is if you pop this off the stack into
         /// the accumulator.
-        self.set_status(U, true);
-        self.bus.write(0x0100 + self.sp as u16, self.p);
-        self.sp -= 1;
-        self.set_status(U, false);
+        self.set_status(B, true);
+        self._push(self.p);
+        self.set_status(B, false);
 
 
         0
     }
 
     fn pull_accumulator(&mut self) -> u8 {
-        self.sp += 1;
-        self.a = self.bus.read(0x0100 + self.sp as u16);
+        self.a = self._pull();
         self.set_status(Z, self.a == 0x00);
-        self.set_status(N, (self.a & 0b10000000) != 0);
+        self.set_status(N, self.a.is_negative());
 
         0
     }
 
+    /// Only the pushed copy of the status ever has the break bit (B) set. The unused bit (U) is
+    /// always set.
     fn pull_processor_status(&mut self) -> u8 {
-        self.sp += 1;
-        self.p = self.bus.read(0x0100 + self.sp as u16);
-        self.set_status(U, false);
+        self.p = self._pull();
+        self.set_status(B, false);
+        self.set_status(U, true);
 
         0
     }
 
-    fn rotate_left(&mut self, mode: AddressingMode) -> u8 {
-        let operand = match mode {
-            Accumulator => self.a,
-            _ => self.fetch_operand()
-        };
+    fn rotate_left(&mut self, operand: Operand) -> u8 {
+        self._shift_helper(operand, Self::_rotate_left);
 
+        0
+    }
+
+    fn _rotate_left(&mut self, operand: u8) -> u8 {
         let shifted = operand << 1 | if self.get_status(C) { 1 } else { 0 };
 
         // Sets carry if the left most bit of the operand is set.
         self.set_status(C, (operand & 0b10000000) != 0);
         self.set_status(Z, shifted == 0x00);
-        self.set_status(N, (shifted & 0b10000000) != 0);
-
-        match mode {
-            Accumulator => self.a = shifted,
-            _ => self.bus.write(self.current_fetched_word, shifted)
-        };
+        self.set_status(N, shifted.is_negative());
 
-        0
+        shifted
     }
 
-    fn rotate_right(&mut self, mode: AddressingMode) -> u8 {
-        let operand = match mode {
-            Accumulator => self.a,
-            _ => self.fetch_operand()
-        };
+    fn rotate_right(&mut self, operand: Operand) -> u8 {
+        self._shift_helper(operand, Self::_rotate_right);
 
+        0
+    }
 
+    fn _rotate_right(&mut self, operand: u8) -> u8 {
         let shifted = operand >> 1 | if self.get_status(C) { 1 << 7 } else { 0 };
 
         // Sets carry if the right most bit of the operand is set.
         self.set_status(C, (operand & 0b00000001) == 1);
         self.set_status(Z, shifted == 0x00);
-        self.set_status(N, (shifted & 0b10000000) != 0);
-
-        match mode {
-            Accumulator => self.a = shifted,
-            _ => self.bus.write(self.current_fetched_word, shifted)
-        };
+        self.set_status(N, shifted.is_negative());
 
-        0
+        shifted
     }
 
     /// This pops status from the stack and then pops the program counter from the next portion of
     /// stack.
     fn return_from_interrupt(&mut self) -> u8 {
-        self.sp += 1;
-        self.p = self.bus.read(0x0100 + (self.sp as u16));
+        self.pull_processor_status();
 
-        self.sp += 1;
-        self.pc = self.bus.read(0x0100 + (self.sp as u16)) as u16 | (self.bus.read(0x0100 + ((self.sp + 1) as u16)) as u16) << 8;
-        self.sp += 1;
+        let lo = self._pull();
+        let hi = self._pull();
+        self.pc = (hi as u16) << 8 | lo as u16;
 
         0
     }
 
     /// This pulls the subroutine jump start point from stack. It then increments the PC to the next.
     fn return_from_subroutine(&mut self) -> u8 {
-        self.sp += 1;
-        self.pc = self.bus.read(0x0100 + (self.sp as u16)) as u16 | (self.bus.read(0x0100 + ((self.sp + 1) as u16)) as u16) << 8;
-        self.pc += 1;
-        self.sp += 1;
+        let lo = self._pull();
+        let hi = self._pull();
+        // Pushing $FFFF to return to $0000 is a valid trick.
+        self.pc = ((hi as u16) << 8 | lo as u16).wrapping_add(1);
 
         0
     }
 
     fn subtract_with_carry(&mut self) -> u8 {
         let operand = self.fetch_operand();
+        self._subtract_with_carry(operand);
+
+        1
+    }
+
+    fn _subtract_with_carry(&mut self, operand: u8) {
+        // Decimal mode only changes A, the flags are the same as in binary.
+        let decimal_difference = if self.decimal_enabled && self.get_status(D) {
+            Some(self._decimal_subtract(operand))
+        } else {
+            None
+        };
 
         let difference = self.a as i16 - operand as i16 - if self.get_status(C) { 0 } else { 1 };
 
         // FIXME: I think I might need to do some additional magic for the "sign" bit.
         self.set_status(C, difference >= 0);
-        self.set_status(Z, difference == 0);
+        // The difference can be -256 which is 0 once it's in A.
+        self.set_status(Z, (difference as u8) == 0);
         self.set_status(V, (((self.a ^ operand) & 0x80) != 0 && ((self.a ^ difference as u8) & 0x80) != 0));
-        self.set_status(N, (difference & 0b10000000) != 0);
+        self.set_status(N, (difference as u8).is_negative());
+
+        self.a = decimal_difference.unwrap_or(difference as u8);
+    }
 
-        self.a = difference as u8;
+    /// BCD subtraction the way the NMOS 6502 does it.
+    /// See: http://www.6502.org/tutorials/decimal_mode.html#A
+    fn _decimal_subtract(&self, operand: u8) -> u8 {
+        let borrow = !self.get_status(C) as i16;
+        let mut low = (self.a & 0x0F) as i16 - (operand & 0x0F) as i16 - borrow;
+        if low < 0 {
+            low = ((low - 0x06) & 0x0F) - 0x10;
+        }
+        let mut difference = (self.a & 0xF0) as i16 - (operand & 0xF0) as i16 + low;
+        if difference < 0 {
+            difference -= 0x60;
+        }
 
-        1
+        difference as u8
     }
 
     fn set_carry_flag(&mut self) -> u8 {
@@ -1007,20 +1646,26 @@ impl CPU {
         0
     }
 
-    fn store_accumulator(&mut self) -> u8 {
-        self.bus.write(self.current_fetched_word, self.a);
+    fn store_accumulator(&mut self, operand: Operand) -> u8 {
+        if let Operand::Address(address) = operand {
+            self.bus.write(address, self.a);
+        }
 
         0
     }
 
-    fn store_x_register(&mut self) -> u8 {
-        self.bus.write(self.current_fetched_word, self.x);
+    fn store_x_register(&mut self, operand: Operand) -> u8 {
+        if let Operand::Address(address) = operand {
+            self.bus.write(address, self.x);
+        }
 
         0
     }
 
-    fn store_y_register(&mut self) -> u8 {
-        self.bus.write(self.current_fetched_word, self.y);
+    fn store_y_register(&mut self, operand: Operand) -> u8 {
+        if let Operand::Address(address) = operand {
+            self.bus.write(address, self.y);
+        }
 
         0
     }
@@ -1119,60 +1764,79 @@ impl CPU {
         0
     }
 
+    // The unofficial read-modify-write instructions below do an RMW and then use the modified
+    // value in a second operation without reading it again. Flags are whatever the second
+    // operation leaves, except for C where it doesn't touch it. Like the official RMWs they
+    // always take the base cycles.
+
     // DCP
     fn dcp(&mut self) -> u8 {
-        let (operand, _) = self.fetch_operand().overflowing_sub(1);
-
-        self.bus.write(self.current_fetched_word, operand);
-        self.set_status(Z, operand == 0);
-        self.set_status(N, (operand & 0b10000000) != 0);
-
-        self._compare_helper(self.a);
+        let decremented = self._read_modify_write(self.current_fetched_word, |_, operand| operand.wrapping_sub(1));
+        self._compare(self.a, decremented);
 
         0
     }
 
     // ISC
     fn isc(&mut self) -> u8 {
-        self.increment_memory();
-        self.subtract_with_carry();
+        let incremented = self._read_modify_write(self.current_fetched_word, |_, operand| operand.wrapping_add(1));
+        self._subtract_with_carry(incremented);
+
         0
     }
 
     // RLA
     fn rla(&mut self) -> u8 {
-        self.rotate_left(self.current_opcode.mode);
-        self.logical_and(self.current_opcode.mode);
+        self.a &= self._read_modify_write(self.current_fetched_word, Self::_rotate_left);
+        self.set_status(Z, self.a == 0x00);
+        self.set_status(N, self.a.is_negative());
+
         0
     }
 
     // RRA
     fn rra(&mut self) -> u8 {
-        self.rotate_right(self.current_opcode.mode);
-        self.add_with_carry(self.current_opcode.mode);
+        // The carry out of the rotate is the carry into the add.
+        let rotated = self._read_modify_write(self.current_fetched_word, Self::_rotate_right);
+        self._add_with_carry(rotated);
+
         0
     }
 
     // SLO
     fn slo(&mut self) -> u8 {
-        self.arithmetic_shift_left(self.current_opcode.mode);
-        self.logical_inclusive_or(self.current_opcode.mode);
+        self.a |= self._read_modify_write(self.current_fetched_word, Self::_shift_left);
+        self.set_status(Z, self.a == 0x00);
+        self.set_status(N, self.a.is_negative());
+
         0
     }
 
     // SRE
     fn sre(&mut self) -> u8 {
-        self.logical_shift_right(self.current_opcode.mode);
-        self.exclusive_or(self.current_opcode.mode);
+        self.a ^= self._read_modify_write(self.current_fetched_word, Self::_shift_right);
+        self.set_status(Z, self.a == 0x00);
+        self.set_status(N, self.a.is_negative());
+
+        0
+    }
+
+    // JAM
+    /// Locks up the CPU. The PC is left pointing at the JAM so it's easy to tell where it halted.
+    fn jam(&mut self) -> u8 {
+        self.pc = self.pc.wrapping_sub(1);
+        self.halted = true;
         0
     }
 }
 
 #[repr(u8)]
-enum StatusFlags {
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StatusFlags {
     /// For ease of reference:
-    /// NVssDIZC - Bits from left to right:
-    ///     Negative, Overflow, unused but always set, unused and only set when pushed on stack, decimal, interrupt, zero, carry
+    /// NVsBDIZC - Bits from left to right:
+    ///     Negative, Overflow, unused but always set, break (only set in copies pushed by BRK and
+    ///     PHP), decimal, interrupt, zero, carry
     C = (1 << 0),
     // Carry
     Z = (1 << 1),
@@ -1181,15 +1845,67 @@ enum StatusFlags {
     // Disable Interrupts
     D = (1 << 3),
     // Unused decimal mode for emulation
-    U = (1 << 4),
-    // the 4's bit isn't used. Technically part of the Break flag which also isn't used. This appears to only be set when pushing the status register to the stack...
-    B = (1 << 5),
-    // Break, unused.
+    B = (1 << 4),
+    // Break. It doesn't exist in the register itself, it's only ever set in the pushed copy.
+    U = (1 << 5),
+    // Unused, always set.
     V = (1 << 6),
     // Overflow
     N = (1 << 7), // Negative
 }
 
+/// A whole status register, built from `StatusFlags` with `|`, e.g. `C | Z`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Flags(pub u8);
+
+impl Flags {
+    pub fn bits(self) -> u8 {
+        self.0
+    }
+
+    pub fn contains(self, flag: StatusFlags) -> bool {
+        self.0 & flag as u8 != 0
+    }
+}
+
+impl From<StatusFlags> for Flags {
+    fn from(flag: StatusFlags) -> Self {
+        Flags(flag as u8)
+    }
+}
+
+impl BitOr for StatusFlags {
+    type Output = Flags;
+
+    fn bitor(self, other: StatusFlags) -> Flags {
+        Flags(self as u8 | other as u8)
+    }
+}
+
+impl BitOr<StatusFlags> for Flags {
+    type Output = Flags;
+
+    fn bitor(self, other: StatusFlags) -> Flags {
+        Flags(self.0 | other as u8)
+    }
+}
+
+impl BitOr for Flags {
+    type Output = Flags;
+
+    fn bitor(self, other: Flags) -> Flags {
+        Flags(self.0 | other.0)
+    }
+}
+
+impl BitAnd<StatusFlags> for Flags {
+    type Output = Flags;
+
+    fn bitand(self, other: StatusFlags) -> Flags {
+        Flags(self.0 & other as u8)
+    }
+}
+
 /// Some helper traits and structs below
 
 /// Helper to determine if the unsigned integers, i.e. u8 are negative. More straight forward and
@@ -1204,3 +1920,1241 @@ impl Negative for u8 {
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::recording_bus::{AccessKind, RecordingBus};
+    use crate::rom::rom::tests::nrom_image;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn cpu_with_program(program: &[u8]) -> CPU<RecordingBus> {
+        let mut bus = RecordingBus::new();
+        bus.load(0x8000, program);
+        let mut cpu = CPU::with_bus(bus);
+        cpu.pc = 0x8000;
+        cpu
+    }
+
+    /// Clocks the cpu until the current instruction finishes and returns the cycles it took.
+    fn run_instruction(cpu: &mut CPU<RecordingBus>) -> u8 {
+        let mut cycles = 0;
+        loop {
+            cpu.clock().unwrap();
+            cycles += 1;
+            if cpu.cycles == 0 {
+                break cycles;
+            }
+        }
+    }
+
+    #[test]
+    fn peek_instruction_shows_what_runs_next() {
+        let mut cpu = cpu_with_program(&[0x8D, 0x02, 0x20]); // STA $2002
+        let cycles = cpu.cycles;
+        let total_cycles = cpu.total_cycles();
+
+        let peeked = cpu.peek_instruction().unwrap();
+
+        assert_eq!(peeked.to_string(), "8000: 8D 02 20  STA $2002");
+        assert_eq!((cpu.pc, cpu.cycles, cpu.total_cycles()), (0x8000, cycles, total_cycles));
+        assert!(cpu.bus.log.is_empty());
+
+        run_instruction(&mut cpu);
+        assert_eq!(cpu.current_instruction, peeked.bytes[0]);
+        assert_eq!(cpu.current_opcode, peeked.decoded_opcode);
+        let fetched: Vec<u8> = cpu.bus.log[..3].iter().map(|(_, _, data)| *data).collect();
+        assert_eq!(fetched, peeked.bytes);
+
+        cpu.bus.load(0x8003, &[0x8B]);
+        assert_eq!(cpu.peek_instruction(), Err(DecodeError::IllegalOpcode { address: 0x8003, opcode: 0x8B }));
+    }
+
+    #[test]
+    fn lda_immediate_with_flat_memory() {
+        let mut memory = [0; 0x10000];
+        memory[0x0400..0x0402].copy_from_slice(&[0xA9, 0x42]); // LDA #$42
+        let mut cpu = CPU::with_flat_memory(memory);
+        cpu.pc = 0x0400;
+        cpu.p = 0x24 | Z as u8 | N as u8;
+
+        cpu.step().unwrap();
+
+        assert_eq!(cpu.a, 0x42);
+        assert_eq!(cpu.pc, 0x0402);
+        assert!(!cpu.get_status(Z));
+        assert!(!cpu.get_status(N));
+    }
+
+    #[test]
+    fn brk_pushes_pc_and_status_with_break_bits() {
+        let mut cpu = cpu_with_program(&[0x00, 0xFF]); // BRK
+        cpu.bus.memory[0xFFFE] = 0x00;
+        cpu.bus.memory[0xFFFF] = 0x90;
+        cpu.sp = 0xFD;
+        cpu.p = U as u8 | C as u8;
+
+        let cycles = run_instruction(&mut cpu);
+
+        assert_eq!(cpu.bus.memory[0x01FD], 0x80);
+        assert_eq!(cpu.bus.memory[0x01FC], 0x02);
+        assert_eq!(cpu.bus.memory[0x01FB], U as u8 | B as u8 | C as u8);
+        assert_eq!(cpu.sp, 0xFA);
+        assert_eq!(cpu.pc, 0x9000);
+        assert!(cpu.get_status(I));
+        assert!(!cpu.get_status(B));
+        assert_eq!(cycles, 7);
+    }
+
+    #[test]
+    fn jmp_indirect() {
+        let mut cpu = cpu_with_program(&[0x6C, 0x10, 0x02]); // JMP ($0210)
+        cpu.bus.memory[0x0210] = 0x78;
+        cpu.bus.memory[0x0211] = 0x56;
+
+        assert_eq!(run_instruction(&mut cpu), 5);
+        assert_eq!(cpu.pc, 0x5678);
+    }
+
+    #[test]
+    fn jmp_indirect_wraps_within_page() {
+        let mut cpu = cpu_with_program(&[0x6C, 0xFF, 0x02]); // JMP ($02FF)
+        cpu.bus.memory[0x02FF] = 0x34;
+        cpu.bus.memory[0x0200] = 0x12;
+        cpu.bus.memory[0x0300] = 0x56;
+
+        run_instruction(&mut cpu);
+        assert_eq!(cpu.pc, 0x1234);
+
+        // The same goes for the last page, the high byte comes from $FF00 rather than $0000.
+        let mut cpu = cpu_with_program(&[0x6C, 0xFF, 0xFF]); // JMP ($FFFF)
+        cpu.bus.memory[0xFFFF] = 0xCD;
+        cpu.bus.memory[0xFF00] = 0xAB;
+        cpu.bus.memory[0x0000] = 0x56;
+
+        run_instruction(&mut cpu);
+        assert_eq!(cpu.pc, 0xABCD);
+    }
+
+    #[test]
+    fn jsr_pushes_the_return_address_high_byte_first() {
+        let mut cpu = cpu_with_program(&[0x20, 0x00, 0x90]); // JSR $9000
+        cpu.sp = 0xFD;
+
+        run_instruction(&mut cpu);
+
+        let writes: Vec<(u16, u8)> = cpu.bus.log.iter()
+            .filter(|(kind, _, _)| *kind == AccessKind::Write)
+            .map(|(_, address, data)| (*address, *data))
+            .collect();
+        // The pushed address is the last byte of the JSR, i.e. $8002.
+        assert_eq!(writes, vec![(0x01FD, 0x80), (0x01FC, 0x02)]);
+    }
+
+    #[test]
+    fn rts_pulls_the_return_address_low_byte_first() {
+        let mut cpu = cpu_with_program(&[0x60]); // RTS
+        cpu.sp = 0xFB;
+        cpu.bus.load(0x01FC, &[0x02, 0x90]);
+
+        run_instruction(&mut cpu);
+
+        let stack_reads: Vec<(u16, u8)> = cpu.bus.log.iter()
+            .filter(|(kind, address, _)| *kind == AccessKind::Read && address & 0xFF00 == 0x0100)
+            .map(|(_, address, data)| (*address, *data))
+            .collect();
+        assert!(stack_reads.ends_with(&[(0x01FC, 0x02), (0x01FD, 0x90)]), "{:04X?}", stack_reads);
+        assert_eq!(cpu.pc, 0x9003);
+    }
+
+    #[test]
+    fn rts_wraps_to_zero() {
+        let mut cpu = cpu_with_program(&[0x60]); // RTS
+        cpu.sp = 0xFB;
+        cpu.bus.load(0x01FC, &[0xFF, 0xFF]);
+
+        run_instruction(&mut cpu);
+
+        assert_eq!(cpu.pc, 0x0000);
+    }
+
+    #[test]
+    fn jsr_rts_round_trip() {
+        let mut program = vec![0xEA; 0x11];
+        program[0..3].copy_from_slice(&[0x20, 0x10, 0x80]); // JSR $8010
+        program[0x10] = 0x60; // RTS
+        let mut cpu = cpu_with_program(&program);
+        cpu.sp = 0xFD;
+
+        run_instruction(&mut cpu);
+        assert_eq!(cpu.pc, 0x8010);
+        assert_eq!(cpu.sp, 0xFB);
+        run_instruction(&mut cpu);
+
+        assert_eq!(cpu.pc, 0x8003);
+        assert_eq!(cpu.sp, 0xFD);
+    }
+
+    #[test]
+    fn rti_restores_status_from_before_interrupt() {
+        let mut cpu = cpu_with_program(&[0xEA]);
+        cpu.bus.memory[0x9000] = 0x40; // RTI
+        cpu.bus.memory[0xFFFA] = 0x00;
+        cpu.bus.memory[0xFFFB] = 0x90;
+        cpu.sp = 0xFD;
+        cpu.p = U as u8 | N as u8 | C as u8;
+
+        cpu.nmi();
+        run_instruction(&mut cpu);
+        assert_eq!(cpu.bus.memory[0x01FB], U as u8 | N as u8 | C as u8);
+        assert!(cpu.get_status(I));
+        run_instruction(&mut cpu);
+
+        assert_eq!(cpu.pc, 0x8000);
+        assert_eq!(cpu.sp, 0xFD);
+        assert_eq!(cpu.p, U as u8 | N as u8 | C as u8);
+    }
+
+    #[test]
+    fn compare_sets_carry_zero_and_negative() {
+        // (register, operand, C, Z, N)
+        let cases = [
+            (0x42, 0x42, true, true, false),
+            (0x43, 0x42, true, false, false),
+            (0x41, 0x42, false, false, true),
+            (0x10, 0x90, false, false, true),
+        ];
+        for (register, operand, carry, zero, negative) in cases.iter() {
+            for program in [[0xC9, *operand], [0xE0, *operand], [0xC0, *operand]].iter() {
+                let mut cpu = cpu_with_program(program); // CMP/CPX/CPY #operand
+                cpu.a = *register;
+                cpu.x = *register;
+                cpu.y = *register;
+
+                run_instruction(&mut cpu);
+
+                assert_eq!(cpu.get_status(C), *carry, "{:02X?}", program);
+                assert_eq!(cpu.get_status(Z), *zero, "{:02X?}", program);
+                assert_eq!(cpu.get_status(N), *negative, "{:02X?}", program);
+            }
+        }
+    }
+
+    #[test]
+    fn only_cmp_takes_page_cross_cycle() {
+        let mut cpu = cpu_with_program(&[0xDD, 0xFF, 0x02]); // CMP $02FF,X
+        cpu.x = 0x01;
+        assert_eq!(run_instruction(&mut cpu), 5);
+
+        let mut cpu = cpu_with_program(&[0xEC, 0xFF, 0x02]); // CPX $02FF
+        cpu.x = 0x01;
+        assert_eq!(run_instruction(&mut cpu), 4);
+    }
+
+    /// Base cycles for every opcode from http://www.oxyron.de/html/opcodes02.html with branches not
+    /// taken and no page crosses. 0 is JAM.
+    const REFERENCE_CYCLES: [u8; 256] = [
+        7, 6, 0, 8, 3, 3, 5, 5, 3, 2, 2, 2, 4, 4, 6, 6,
+        2, 5, 0, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7,
+        6, 6, 0, 8, 3, 3, 5, 5, 4, 2, 2, 2, 4, 4, 6, 6,
+        2, 5, 0, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7,
+        6, 6, 0, 8, 3, 3, 5, 5, 3, 2, 2, 2, 3, 4, 6, 6,
+        2, 5, 0, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7,
+        6, 6, 0, 8, 3, 3, 5, 5, 4, 2, 2, 2, 5, 4, 6, 6,
+        2, 5, 0, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7,
+        2, 6, 2, 6, 3, 3, 3, 3, 2, 2, 2, 2, 4, 4, 4, 4,
+        2, 6, 0, 6, 4, 4, 4, 4, 2, 5, 2, 5, 5, 5, 5, 5,
+        2, 6, 2, 6, 3, 3, 3, 3, 2, 2, 2, 2, 4, 4, 4, 4,
+        2, 5, 0, 5, 4, 4, 4, 4, 2, 4, 2, 4, 4, 4, 4, 4,
+        2, 6, 2, 8, 3, 3, 5, 5, 2, 2, 2, 2, 4, 4, 6, 6,
+        2, 5, 0, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7,
+        2, 6, 2, 8, 3, 3, 5, 5, 2, 2, 2, 2, 4, 4, 6, 6,
+        2, 5, 0, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7,
+    ];
+
+    /// The status that stops each branch from being taken.
+    fn branch_not_taken_status(opcode: u8) -> u8 {
+        match opcode {
+            0x10 => N as u8, // BPL
+            0x50 => V as u8, // BVC
+            0x90 => C as u8, // BCC
+            0xD0 => Z as u8, // BNE
+            _ => 0, // BMI, BVS, BCS and BEQ aren't taken with the flags clear
+        }
+    }
+
+    #[test]
+    fn cycles_match_reference() {
+        for opcode in 0..=0xFFu8 {
+            let decoded_opcode = match opcode.decode() {
+                Ok(decoded_opcode) if decoded_opcode.instruction != Instruction::JAM => decoded_opcode,
+                _ => continue,
+            };
+            // Operands of $0010 never cross a page with X and Y at 0.
+            let mut cpu = cpu_with_program(&[opcode, 0x10, 0x00]);
+            cpu.p = 0x24 | branch_not_taken_status(opcode);
+
+            assert_eq!(run_instruction(&mut cpu), REFERENCE_CYCLES[opcode as usize], "{:02X} {:?}", opcode, decoded_opcode);
+        }
+    }
+
+    #[test]
+    fn only_indexed_reads_take_page_cross_cycle() {
+        for opcode in 0..=0xFFu8 {
+            let decoded_opcode = match opcode.decode() {
+                Ok(decoded_opcode) => decoded_opcode,
+                _ => continue,
+            };
+            use self::Instruction::*;
+            let pays_for_page_cross = match decoded_opcode.instruction {
+                ADC | AND | CMP | EOR | LAX | LDA | LDX | LDY | NOP | ORA | SBC => true,
+                _ => false,
+            };
+            // $02FF plus 1 and the ($10),Y pointer to $02FF plus 1 both cross into page 3.
+            let mut cpu = match decoded_opcode.mode {
+                AddressingMode::IndexedAbsoluteX | AddressingMode::IndexedAbsoluteY => cpu_with_program(&[opcode, 0xFF, 0x02]),
+                AddressingMode::IndirectIndexed => cpu_with_program(&[opcode, 0x10]),
+                _ => continue,
+            };
+            cpu.bus.memory[0x0010] = 0xFF;
+            cpu.bus.memory[0x0011] = 0x02;
+            cpu.x = 1;
+            cpu.y = 1;
+
+            let expected = REFERENCE_CYCLES[opcode as usize] + if pays_for_page_cross { 1 } else { 0 };
+            assert_eq!(run_instruction(&mut cpu), expected, "{:02X} {:?}", opcode, decoded_opcode);
+        }
+    }
+
+    #[test]
+    fn lda_indexed_indirect_reads_through_pointer() {
+        let mut cpu = cpu_with_program(&[0xA1, 0x20]); // LDA ($20,X)
+        cpu.x = 0x04;
+        cpu.bus.memory[0x0024] = 0x00;
+        cpu.bus.memory[0x0025] = 0x03;
+        cpu.bus.memory[0x0300] = 0x5A;
+
+        assert_eq!(run_instruction(&mut cpu), 6);
+        assert_eq!(cpu.a, 0x5A);
+        // The target is only read once, and nothing after it is.
+        assert_eq!(cpu.bus.accesses_to(0x0300), vec![(AccessKind::Read, 0x5A)]);
+        assert!(cpu.bus.accesses_to(0x0301).is_empty());
+    }
+
+    #[test]
+    fn indexed_stores_dummy_read_before_writing() {
+        let mut cpu = cpu_with_program(&[0x9D, 0xFF, 0x02]); // STA $02FF,X
+        cpu.x = 0x01;
+        cpu.a = 0x42;
+
+        assert_eq!(run_instruction(&mut cpu), 5);
+        // The dummy read is on the page before the carry.
+        assert_eq!(&cpu.bus.log[cpu.bus.log.len() - 2..], &[(AccessKind::Read, 0x0200, 0x00), (AccessKind::Write, 0x0300, 0x42)]);
+
+        let mut cpu = cpu_with_program(&[0x91, 0x10]); // STA ($10),Y
+        cpu.bus.memory[0x0010] = 0x80;
+        cpu.bus.memory[0x0011] = 0x02;
+        cpu.y = 0x01;
+        cpu.a = 0x42;
+
+        assert_eq!(run_instruction(&mut cpu), 6);
+        assert_eq!(&cpu.bus.log[cpu.bus.log.len() - 2..], &[(AccessKind::Read, 0x0281, 0x00), (AccessKind::Write, 0x0281, 0x42)]);
+    }
+
+    #[test]
+    fn operand_fetch_wraps_at_end_of_memory() {
+        let mut cpu = cpu_with_program(&[]);
+        cpu.bus.memory[0xFFFE] = 0xAD; // LDA $0300
+        cpu.bus.memory[0xFFFF] = 0x00;
+        cpu.bus.memory[0x0000] = 0x03;
+        cpu.bus.memory[0x0300] = 0x42;
+        cpu.pc = 0xFFFE;
+
+        assert_eq!(run_instruction(&mut cpu), 4);
+        assert_eq!(cpu.a, 0x42);
+        assert_eq!(cpu.pc, 0x0001);
+        assert!(cpu.bus.log.contains(&(AccessKind::Read, 0x0000, 0x03)));
+    }
+
+    #[test]
+    fn indexed_reads_dummy_read_on_page_cross() {
+        let mut cpu = cpu_with_program(&[0xB9, 0xFF, 0x20]); // LDA $20FF,Y
+        cpu.y = 0x01;
+
+        assert_eq!(run_instruction(&mut cpu), 5);
+        assert_eq!(&cpu.bus.log[cpu.bus.log.len() - 2..], &[(AccessKind::Read, 0x2000, 0x00), (AccessKind::Read, 0x2100, 0x00)]);
+
+        let mut cpu = cpu_with_program(&[0xB1, 0x10]); // LDA ($10),Y
+        cpu.bus.memory[0x0010] = 0xFF;
+        cpu.bus.memory[0x0011] = 0x02;
+        cpu.y = 0x01;
+
+        assert_eq!(run_instruction(&mut cpu), 6);
+        assert_eq!(&cpu.bus.log[cpu.bus.log.len() - 2..], &[(AccessKind::Read, 0x0200, 0x00), (AccessKind::Read, 0x0300, 0x00)]);
+
+        // No page cross, no dummy read.
+        let mut cpu = cpu_with_program(&[0xB9, 0x10, 0x20]); // LDA $2010,Y
+        cpu.y = 0x01;
+
+        assert_eq!(run_instruction(&mut cpu), 4);
+        assert_eq!(cpu.bus.log.iter().filter(|access| **access == (AccessKind::Read, 0x2011, 0x00)).count(), 1);
+        assert_eq!(cpu.bus.log.len(), 4);
+    }
+
+    #[test]
+    fn taken_branches_take_extra_cycles() {
+        let mut cpu = cpu_with_program(&[0xD0, 0x10]); // BNE *+16
+        assert_eq!(run_instruction(&mut cpu), 3);
+        assert_eq!(cpu.pc, 0x8012);
+
+        let mut cpu = cpu_with_program(&[0xD0, 0xFC]); // BNE *-4, i.e. back into page $7F
+        assert_eq!(run_instruction(&mut cpu), 4);
+        assert_eq!(cpu.pc, 0x7FFE);
+    }
+
+    #[test]
+    fn every_branch_takes_2_3_or_4_cycles() {
+        for &opcode in [0x10, 0x30, 0x50, 0x70, 0x90, 0xB0, 0xD0, 0xF0].iter() {
+            // (offset, taken, cycles, pc after)
+            let cases = [
+                (0x10, false, 2, 0x8002),
+                (0x10, true, 3, 0x8012),
+                (0xFC, true, 4, 0x7FFE),
+            ];
+            for &(offset, taken, cycles, pc) in cases.iter() {
+                let mut cpu = cpu_with_program(&[opcode, offset]);
+                let not_taken = branch_not_taken_status(opcode);
+                // Branches that aren't taken with a flag clear need it set, and vice versa.
+                let taken_status = if not_taken == 0 { N as u8 | V as u8 | C as u8 | Z as u8 } else { 0 };
+                cpu.p = 0x24 | if taken { taken_status } else { not_taken };
+
+                assert_eq!(run_instruction(&mut cpu), cycles, "{:02X} {:02X} taken: {}", opcode, offset, taken);
+                assert_eq!(cpu.pc, pc, "{:02X} {:02X} taken: {}", opcode, offset, taken);
+                assert_eq!(cpu.cycles, 0);
+            }
+        }
+    }
+
+    #[test]
+    fn transfers_set_zero_and_negative_except_txs() {
+        // (opcode, sets flags)
+        let transfers = [(0xAA, true), (0xA8, true), (0xBA, true), (0x8A, true), (0x98, true), (0x9A, false)];
+        for (opcode, sets_flags) in transfers.iter() {
+            for value in [0x00, 0x80, 0x42].iter() {
+                let mut cpu = cpu_with_program(&[*opcode]);
+                cpu.a = *value;
+                cpu.x = *value;
+                cpu.y = *value;
+                cpu.sp = *value;
+                // The opposite of what any of the values would set.
+                cpu.p = U as u8 | if *value == 0x00 { N as u8 } else { Z as u8 };
+                let p = cpu.p;
+
+                run_instruction(&mut cpu);
+
+                if *sets_flags {
+                    assert_eq!(cpu.get_status(Z), *value == 0x00, "{:02X} {:02X}", opcode, value);
+                    assert_eq!(cpu.get_status(N), *value == 0x80, "{:02X} {:02X}", opcode, value);
+                } else {
+                    assert_eq!(cpu.p, p, "{:02X} {:02X}", opcode, value);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn same_instructions_on_flat_and_nes_address_spaces() {
+        fn run<M: AddressSpace>(cpu: &mut CPU<M>) {
+            let program = [0xAD, 0x10, 0x02, 0x8D, 0x11, 0x02]; // LDA $0210, STA $0211
+            for (offset, byte) in program.iter().enumerate() {
+                cpu.bus.write(0x0200 + offset as u16, *byte);
+            }
+            cpu.bus.write(0x0210, 0x42);
+            cpu.pc = 0x0200;
+            cpu.step().unwrap();
+            cpu.step().unwrap();
+        }
+        let mut flat = CPU::with_flat_memory([0; 0x10000]);
+        let mut nes = CPU::new(nrom_image(&[]));
+
+        run(&mut flat);
+        run(&mut nes);
+
+        assert_eq!(flat.bus.peek(0x0211), 0x42);
+        assert_eq!(nes.bus.peek(0x0211), 0x42);
+        // Only the NES mirrors its 2kb of RAM.
+        assert_eq!(flat.bus.peek(0x0A11), 0x00);
+        assert_eq!(nes.bus.peek(0x0A11), 0x42);
+    }
+
+    #[test]
+    fn reset_reads_vector() {
+        let mut cpu = cpu_with_program(&[]);
+        cpu.bus.memory[0xFFFC] = 0x34;
+        cpu.bus.memory[0xFFFD] = 0x12;
+        cpu.sp = 0x42;
+
+        cpu.reset();
+        for _ in 0..7 {
+            cpu.clock().unwrap();
+        }
+
+        assert_eq!(cpu.pc, 0x1234);
+        assert_eq!(cpu.sp, 0xFD);
+        assert_eq!(cpu.p, 0x24);
+        assert_eq!(cpu.cycles, 0);
+        assert_eq!(cpu.total_cycles, 7);
+    }
+
+    #[test]
+    fn ppu_runs_3_dots_per_cpu_cycle() {
+        // LDA $0300 takes 4 cycles
+        let mut cpu = cpu_with_routine_in_ram(&[0xAD, 0x00, 0x03]);
+        let dots = |cpu: &CPU| cpu.bus.ppu().scanline() as u32 * 341 + cpu.bus.ppu().dot() as u32;
+        let before = dots(&cpu);
+
+        cpu.step_system().unwrap();
+
+        assert_eq!(dots(&cpu) - before, 3 * 4);
+    }
+
+    #[test]
+    fn oam_dma_stalls_the_cpu() {
+        // LDA #$02, STA $4014, NOP
+        let mut cpu = cpu_with_routine_in_ram(&[0xA9, 0x02, 0x8D, 0x14, 0x40, 0xEA]);
+        let dots = |cpu: &CPU| cpu.bus.ppu().scanline() as u64 * 341 + cpu.bus.ppu().dot() as u64;
+        cpu.step_system().unwrap();
+        let total_cycles = cpu.total_cycles();
+        let master_cycles = cpu.master_cycles();
+        let before = dots(&cpu);
+
+        cpu.step_system().unwrap();
+
+        // 4 for the STA, then 513 for the DMA plus 1 if it started on an odd cycle.
+        let cycles = cpu.total_cycles() - total_cycles;
+        assert!(cycles == 4 + 513 || cycles == 4 + 514, "{}", cycles);
+        assert_eq!(cpu.master_cycles() - master_cycles, cycles * MASTER_CYCLES_PER_CPU_CYCLE);
+        assert_eq!(dots(&cpu) - before, 3 * cycles);
+        assert_eq!(cpu.pc, 0x0205);
+    }
+
+    #[test]
+    fn power_on_clears_ram_but_reset_does_not() {
+        let mut cpu = CPU::new(nrom_image(&[]));
+        cpu.bus.write(0x0010, 0xAB);
+
+        cpu.reset();
+        assert_eq!(cpu.bus.read(0x0010), 0xAB);
+
+        cpu.power_on();
+        assert_eq!(cpu.bus.read(0x0010), 0x00);
+    }
+
+    #[test]
+    fn asl_shifts_the_accumulator_or_memory() {
+        let mut cpu = cpu_with_program(&[0x0A, 0x06, 0x10]); // ASL A, ASL $10
+        cpu.a = 0x81;
+        cpu.bus.memory[0x0010] = 0x40;
+
+        run_instruction(&mut cpu);
+        assert_eq!(cpu.a, 0x02);
+        assert!(cpu.get_status(C));
+        assert_eq!(cpu.bus.memory[0x0010], 0x40);
+
+        run_instruction(&mut cpu);
+        assert_eq!(cpu.a, 0x02);
+        assert_eq!(cpu.bus.memory[0x0010], 0x80);
+        assert!(!cpu.get_status(C));
+        assert!(cpu.get_status(N));
+    }
+
+    #[test]
+    fn asl_absolute_reads_then_writes_twice() {
+        let mut cpu = cpu_with_program(&[0x0E, 0x00, 0x02]); // ASL $0200
+        cpu.bus.memory[0x0200] = 0x41;
+
+        let cycles = run_instruction(&mut cpu);
+
+        assert_eq!(cpu.bus.accesses_to(0x0200), vec![
+            (AccessKind::Read, 0x41),
+            (AccessKind::Write, 0x41),
+            (AccessKind::Write, 0x82),
+        ]);
+        assert_eq!(cycles, 6);
+    }
+
+    #[test]
+    fn rmw_and_reads_touch_the_operand_once() {
+        // ASL $2002, AND $2002, ROL A
+        let mut cpu = cpu_with_program(&[0x0E, 0x02, 0x20, 0x2D, 0x02, 0x20, 0x2A]);
+        cpu.bus.memory[0x2002] = 0x81;
+        let accesses = |cpu: &CPU<RecordingBus>| {
+            let accesses = cpu.bus.accesses_to(0x2002);
+            let reads = accesses.iter().filter(|(kind, _)| *kind == AccessKind::Read).count();
+            (reads, accesses.len() - reads)
+        };
+
+        run_instruction(&mut cpu);
+        assert_eq!(accesses(&cpu), (1, 2));
+        assert_eq!(cpu.bus.memory[0x2002], 0x02);
+
+        cpu.bus.log.clear();
+        cpu.a = 0xFF;
+        run_instruction(&mut cpu);
+        assert_eq!(accesses(&cpu), (1, 0));
+        assert_eq!(cpu.a, 0x02);
+
+        // The accumulator form doesn't touch memory.
+        cpu.bus.log.clear();
+        run_instruction(&mut cpu);
+        assert_eq!(accesses(&cpu), (0, 0));
+        assert_eq!(cpu.a, 0x05);
+    }
+
+    #[test]
+    fn unofficial_rmw_combos_match_reference() {
+        // (opcode, A, memory, P) after running with A = $0F, $10 = $C1 and C set.
+        let expected = [
+            (0x07, 0x8F, 0x82, 0xA5), // SLO $10
+            (0x27, 0x03, 0x83, 0x25), // RLA $10
+            (0x47, 0x6F, 0x60, 0x25), // SRE $10
+            (0x67, 0xF0, 0xE0, 0xA4), // RRA $10
+            (0xC7, 0x0F, 0xC0, 0x24), // DCP $10
+            (0xE7, 0x4D, 0xC2, 0x24), // ISC $10
+        ];
+        for (opcode, a, memory, p) in expected.iter() {
+            let mut cpu = cpu_with_program(&[*opcode, 0x10]);
+            cpu.a = 0x0F;
+            cpu.bus.memory[0x0010] = 0xC1;
+            cpu.p = 0x24 | C as u8;
+
+            assert_eq!(run_instruction(&mut cpu), 5, "{:02X}", opcode);
+            assert_eq!((cpu.a, cpu.bus.memory[0x0010], cpu.p), (*a, *memory, *p), "{:02X}", opcode);
+            // Read, write back and write the modified value. The second operation doesn't read again.
+            let accesses = cpu.bus.accesses_to(0x0010);
+            assert_eq!(accesses, vec![(AccessKind::Read, 0xC1), (AccessKind::Write, 0xC1), (AccessKind::Write, *memory)], "{:02X}", opcode);
+        }
+    }
+
+    #[test]
+    fn indexed_rmw_has_no_page_cross_cycle() {
+        let mut cpu = cpu_with_program(&[0xFE, 0xFF, 0x02]); // INC $02FF,X
+        cpu.x = 0x01;
+
+        assert_eq!(run_instruction(&mut cpu), 7);
+        assert_eq!(cpu.bus.memory[0x0300], 0x01);
+    }
+
+    #[test]
+    fn jam_halts_the_cpu() {
+        let mut cpu = cpu_with_program(&[0x02, 0xEA]);
+
+        assert_eq!(cpu.step(), Ok(CpuState::Halted));
+        assert!(cpu.is_halted());
+        assert_eq!(cpu.pc, 0x8000);
+
+        assert_eq!(cpu.step(), Ok(CpuState::Halted));
+        assert_eq!(cpu.pc, 0x8000);
+    }
+
+    #[test]
+    fn jam_at_the_top_of_memory_stays_there() {
+        let mut cpu = cpu_with_program(&[]);
+        cpu.bus.memory[0xFFFF] = 0x02;
+        cpu.pc = 0xFFFF;
+
+        assert_eq!(cpu.step(), Ok(CpuState::Halted));
+        assert_eq!(cpu.pc, 0xFFFF);
+    }
+
+    #[test]
+    fn illegal_opcode_returns_error_by_default() {
+        let mut cpu = cpu_with_program(&[0x8B]);
+
+        let error = cpu.step().unwrap_err();
+        assert_eq!(error, DecodeError::IllegalUnimplementedOpcode { opcode: 0x8B });
+        assert_eq!(error.to_string(), "$8B isn't a 6502 opcode");
+        assert_eq!(cpu.pc, 0x8000);
+    }
+
+    #[test]
+    fn strict_mode_rejects_unofficial_opcodes() {
+        // LAX $10
+        let mut cpu = cpu_with_program(&[0xA7, 0x10]);
+        cpu.bus.memory[0x0010] = 0x42;
+        cpu.set_illegal_opcode_policy(IllegalOpcodePolicy::TreatAsNop);
+        cpu.set_strict_illegals(true);
+        let (a, x, p, sp, total_cycles) = (cpu.a, cpu.x, cpu.p, cpu.sp, cpu.total_cycles);
+
+        assert_eq!(cpu.step(), Err(DecodeError::UnofficialOpcode { address: 0x8000, opcode: 0xA7 }));
+        assert_eq!((cpu.a, cpu.x, cpu.p, cpu.sp, cpu.pc), (a, x, p, sp, 0x8000));
+        assert_eq!((cpu.cycles, cpu.total_cycles), (0, total_cycles));
+
+        cpu.set_strict_illegals(false);
+        cpu.step().unwrap();
+        assert_eq!((cpu.a, cpu.x), (0x42, 0x42));
+    }
+
+    #[test]
+    fn illegal_opcode_treated_as_nop() {
+        let mut cpu = cpu_with_program(&[0x8B, 0xEA]);
+        cpu.set_illegal_opcode_policy(IllegalOpcodePolicy::TreatAsNop);
+
+        assert_eq!(cpu.step(), Ok(CpuState::Running));
+        assert_eq!(cpu.pc, 0x8001);
+    }
+
+    #[test]
+    fn illegal_opcode_halts() {
+        let mut cpu = cpu_with_program(&[0x8B]);
+        cpu.set_illegal_opcode_policy(IllegalOpcodePolicy::Halt);
+
+        assert_eq!(cpu.step(), Ok(CpuState::Halted));
+        assert!(cpu.is_halted());
+    }
+
+    #[test]
+    #[should_panic]
+    fn illegal_opcode_panics() {
+        let mut cpu = cpu_with_program(&[0x8B]);
+        cpu.set_illegal_opcode_policy(IllegalOpcodePolicy::Panic);
+
+        let _ = cpu.step();
+    }
+
+    #[test]
+    fn zero_page_nop_consumes_its_operand() {
+        let mut cpu = cpu_with_program(&[0x04, 0x12, 0xEA]); // NOP $12
+        let status = cpu.p;
+
+        assert_eq!(run_instruction(&mut cpu), 3);
+        assert_eq!(cpu.pc, 0x8002);
+        assert_eq!(cpu.p, status);
+        assert!(cpu.bus.log.contains(&(AccessKind::Read, 0x0012, 0x00)));
+    }
+
+    #[test]
+    fn absolute_x_nop_takes_page_cross_cycle() {
+        let mut cpu = cpu_with_program(&[0x1C, 0xFF, 0x02, 0x1C, 0x00, 0x02]); // NOP $02FF,X NOP $0200,X
+        cpu.x = 0x01;
+
+        assert_eq!(run_instruction(&mut cpu), 5);
+        assert_eq!(cpu.pc, 0x8003);
+        assert_eq!(run_instruction(&mut cpu), 4);
+        assert_eq!(cpu.pc, 0x8006);
+    }
+
+    #[test]
+    fn disassembles_code_in_ram() {
+        // LDA #$42 STA $0300 BNE $0200
+        let mut cpu = cpu_with_routine_in_ram(&[0xA9, 0x42, 0x8D, 0x00, 0x03, 0xD0, 0xF9]);
+
+        let lines: Vec<String> = cpu.disassemble_range(0x0200, 3)
+            .iter()
+            .map(|instruction| instruction.to_string())
+            .collect();
+
+        assert_eq!(lines, vec![
+            "0200: A9 42     LDA #$42",
+            "0202: 8D 00 03  STA $0300",
+            "0205: D0 F9     BNE $0200",
+        ]);
+    }
+
+    #[test]
+    fn disassembles_unknown_opcodes_as_bytes() {
+        let mut cpu = cpu_with_routine_in_ram(&[0x8B, 0xAB]);
+
+        let lines: Vec<String> = cpu.disassemble_range(0x0200, 2)
+            .iter()
+            .map(|instruction| instruction.to_string())
+            .collect();
+
+        assert_eq!(lines, vec!["0200: 8B        .byte $8B", "0201: AB        .byte $AB"]);
+    }
+
+    #[test]
+    fn disassembly_flags_illegal_opcodes() {
+        // LAX $10, NOP
+        let mut cpu = cpu_with_routine_in_ram(&[0xA7, 0x10, 0xEA]);
+
+        let lines: Vec<String> = cpu.disassemble_range(0x0200, 2)
+            .iter()
+            .map(|instruction| instruction.to_string())
+            .collect();
+
+        assert_eq!(lines, vec!["0200: A7 10     LAX $10 ; illegal", "0202: EA        NOP"]);
+    }
+
+    #[test]
+    fn disassembly_names_registers() {
+        // STA $2000, LDA $4016, STA $10, STA $0300
+        let mut cpu = cpu_with_routine_in_ram(&[0x8D, 0x00, 0x20, 0xAD, 0x16, 0x40, 0x85, 0x10, 0x8D, 0x00, 0x03]);
+        let mut symbols = SymbolMap::nes();
+        let mut game_symbols = SymbolMap::new();
+        game_symbols.insert(0x0010, "frame_count");
+        game_symbols.insert(0x4016, "controller_port");
+        symbols.merge(&game_symbols);
+
+        let lines: Vec<String> = cpu.disassemble_range_with_symbols(0x0200, 4, &symbols)
+            .iter()
+            .map(|instruction| instruction.to_string())
+            .collect();
+
+        assert_eq!(lines, vec![
+            "0200: 8D 00 20  STA $2000 ; PPUCTRL",
+            "0203: AD 16 40  LDA $4016 ; controller_port",
+            "0206: 85 10     STA $10 ; frame_count",
+            "0208: 8D 00 03  STA $0300",
+        ]);
+    }
+
+    /// A CPU with `routine` copied into RAM at $0200 and the PC pointing at it.
+    fn cpu_with_routine_in_ram(routine: &[u8]) -> CPU {
+        let mut cpu = CPU::new(nrom_image(&[]));
+        for (offset, byte) in routine.iter().enumerate() {
+            cpu.bus.write(0x0200 + offset as u16, *byte);
+        }
+        cpu.pc = 0x0200;
+        cpu
+    }
+
+    /// Like `cpu_with_routine_in_ram` but with the NMI vector at $0380, reset at $0200 and
+    /// IRQ/BRK at $0300.
+    fn cpu_with_vectors_and_routine(routine: &[u8]) -> CPU {
+        let mut prg = vec![0; 0x4000];
+        prg[0x3FFA..].copy_from_slice(&[0x80, 0x03, 0x00, 0x02, 0x00, 0x03]);
+        let mut cpu = CPU::new(nrom_image(&prg));
+        for (offset, byte) in routine.iter().enumerate() {
+            cpu.bus.write(0x0200 + offset as u16, *byte);
+        }
+        cpu.pc = 0x0200;
+        cpu
+    }
+
+    /// LDA #$80, STA $4010, LDA #$10, STA $4015. Plays a 1 byte DMC sample with its IRQ on, which
+    /// asserts the IRQ line as soon as the byte is fetched.
+    const RAISE_DMC_IRQ: [u8; 10] = [0xA9, 0x80, 0x8D, 0x10, 0x40, 0xA9, 0x10, 0x8D, 0x15, 0x40];
+
+    #[test]
+    fn irq_waits_an_instruction_after_cli() {
+        let mut routine = RAISE_DMC_IRQ.to_vec();
+        routine.extend_from_slice(&[0xEA, 0x58, 0xE8, 0xE8]); // NOP, CLI, INX, INX
+        let mut cpu = cpu_with_vectors_and_routine(&routine);
+        for _ in 0..5 {
+            cpu.step_system().unwrap();
+        }
+        assert!(cpu.bus.irq());
+        assert_eq!(cpu.pc, 0x020B);
+
+        cpu.step_system().unwrap();
+        assert_eq!(cpu.pc, 0x020C, "the IRQ was taken straight after CLI");
+
+        cpu.step_system().unwrap();
+        assert_eq!(cpu.x, 1);
+        assert_eq!(cpu.pc, 0x0300);
+        assert_eq!(cpu.bus.dump(0x01FC, 2), vec![0x0D, 0x02]);
+    }
+
+    #[test]
+    fn irq_gets_through_between_cli_and_sei() {
+        let mut routine = RAISE_DMC_IRQ.to_vec();
+        routine.extend_from_slice(&[0x58, 0x78, 0xE8]); // CLI, SEI, INX
+        let mut cpu = cpu_with_vectors_and_routine(&routine);
+        for _ in 0..5 {
+            cpu.step_system().unwrap();
+        }
+
+        // SEI is what the IRQ follows since CLI's poll still saw I set and SEI's didn't yet.
+        cpu.step_system().unwrap();
+        assert_eq!(cpu.pc, 0x0300);
+        assert_eq!(cpu.x, 0);
+        assert_eq!(cpu.bus.dump(0x01FC, 2), vec![0x0C, 0x02]);
+        assert_ne!(cpu.bus.peek(0x01FB) & I as u8, 0);
+    }
+
+    #[test]
+    fn nmi_early_in_brk_hijacks_the_vector() {
+        let mut cpu = cpu_with_vectors_and_routine(&[0x00]); // BRK
+        cpu.bus.write(0x2000, 0x80);
+        // Vblank, and so the NMI, starts 2 dots into BRK's first cycle.
+        while (cpu.bus.ppu().scanline(), cpu.bus.ppu().dot()) != (240, 340) {
+            cpu.bus.tick_ppu();
+        }
+
+        cpu.step_system().unwrap();
+
+        assert_eq!(cpu.pc, 0x0380);
+        // The pushes were BRK's, so the break bit is still set.
+        assert_ne!(cpu.bus.peek(0x01FB) & B as u8, 0);
+        assert!(!cpu.bus.ppu_mut().take_nmi());
+    }
+
+    #[test]
+    fn profiling_counts_bus_accesses() {
+        // INC $0200 LDA $0200 JMP $0300
+        let mut cpu = CPU::new(nrom_image(&[]));
+        for (offset, byte) in [0xEE, 0x00, 0x02, 0xAD, 0x00, 0x02, 0x4C, 0x00, 0x03].iter().enumerate() {
+            cpu.bus.write(0x0300 + offset as u16, *byte);
+        }
+        cpu.pc = 0x0300;
+        cpu.bus.enable_profiling(true);
+
+        for _ in 0..30 {
+            cpu.step().unwrap();
+        }
+
+        // INC reads, writes back and writes, then LDA reads.
+        assert_eq!(cpu.bus.top_accessed(2), vec![(0x0200, 40), (0x0300, 10)]);
+        cpu.bus.enable_profiling(false);
+        assert!(cpu.bus.top_accessed(2).is_empty());
+    }
+
+    #[test]
+    fn write_watchpoint_stops_execution() {
+        // LDA #$07 LDX $0300 STA $0300 JAM
+        let mut cpu = cpu_with_routine_in_ram(&[0xA9, 0x07, 0xAE, 0x00, 0x03, 0x8D, 0x00, 0x03, 0x02]);
+        cpu.bus.add_watchpoint(0x0300, WatchKind::Write);
+
+        assert_eq!(cpu.run_until_break(), Ok(StopReason::Watchpoint { addr: 0x0300, value: 0x07 }));
+        assert_eq!(cpu.pc, 0x0208);
+        assert_eq!(cpu.run_until_break(), Ok(StopReason::Halted));
+    }
+
+    #[test]
+    fn breakpoint_stops_before_the_instruction() {
+        let mut cpu = cpu_with_routine_in_ram(&[0xEA, 0xEA, 0xEA, 0x02]);
+        cpu.add_breakpoint(0x0202);
+
+        assert_eq!(cpu.run_until_break(), Ok(StopReason::Breakpoint(0x0202)));
+        assert_eq!(cpu.run_until_break(), Ok(StopReason::Halted));
+    }
+
+    #[test]
+    fn trace_hook_formats_once_per_instruction() {
+        let mut cpu = cpu_with_program(&[0xEA; 0x1000]); // NOPs take two cycles each
+        let lines = Rc::new(RefCell::new(Vec::new()));
+        let hook_lines = Rc::clone(&lines);
+        cpu.set_trace_hook(Some(Box::new(move |event: &TraceEvent| {
+            hook_lines.borrow_mut().push(event.to_string());
+        })));
+
+        for _ in 0..1000 {
+            cpu.clock().unwrap();
+        }
+
+        assert_eq!(lines.borrow().len(), 500);
+    }
+
+    #[test]
+    fn instructions_yields_what_ran() {
+        // 8 NOPs then JMP $8000
+        let mut program = [0xEA; 11];
+        program[8..].copy_from_slice(&[0x4C, 0x00, 0x80]);
+        let mut cpu = cpu_with_program(&program);
+
+        let traces: Vec<InstructionTrace> = cpu.instructions().take(10).map(Result::unwrap).collect();
+
+        let pcs: Vec<u16> = traces.iter().map(|trace| trace.pc).collect();
+        assert_eq!(pcs, vec![0x8000, 0x8001, 0x8002, 0x8003, 0x8004, 0x8005, 0x8006, 0x8007, 0x8008, 0x8000]);
+        assert_eq!(traces[0].cycles, 2);
+        assert_eq!(traces[8].decoded_opcode.instruction, Instruction::JMP);
+        assert_eq!(traces[8].operands(), &[0x00, 0x80]);
+        assert_eq!(traces[8].cycles, 3);
+        assert_eq!(cpu.pc, 0x8001);
+    }
+
+    #[test]
+    fn running_in_chunks_matches_running_straight_through() {
+        // LDX #$00, then loop: INX TXA STA $0300,X ADC $10 STA $10 JMP loop
+        let routine = [0xA2, 0x00, 0xE8, 0x8A, 0x9D, 0x00, 0x03, 0x65, 0x10, 0x85, 0x10, 0x4C, 0x02, 0x02];
+        let mut straight = cpu_with_routine_in_ram(&routine);
+        let mut chunked = cpu_with_routine_in_ram(&routine);
+
+        straight.step_cycles(100).unwrap();
+        // Pseudo random chunk sizes from 1 to 8 so pauses land all over the place.
+        let (mut seed, mut run) = (0x1234_u32, 0);
+        let mut paused_mid_instruction = false;
+        while run < 100 {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            let chunk = (1 + (seed >> 16) % 8).min(100 - run);
+            chunked.step_cycles(chunk).unwrap();
+            paused_mid_instruction |= chunked.is_mid_instruction();
+            run += chunk;
+        }
+
+        assert!(paused_mid_instruction);
+        assert_eq!(
+            (chunked.a, chunked.x, chunked.y, chunked.p, chunked.sp, chunked.pc, chunked.cycles, chunked.total_cycles),
+            (straight.a, straight.x, straight.y, straight.p, straight.sp, straight.pc, straight.cycles, straight.total_cycles)
+        );
+        assert_eq!(chunked.bus.dump(0x0000, 0x800), straight.bus.dump(0x0000, 0x800));
+    }
+
+    #[test]
+    fn step_cycles_spans_instructions() {
+        let mut cpu = cpu_with_program(&[0x20, 0x00, 0x90]); // JSR $9000, which takes 6 cycles
+        cpu.bus.memory[0x9000] = 0xEA;
+        let starts = Rc::new(RefCell::new(0));
+        let hook_starts = Rc::clone(&starts);
+        cpu.set_trace_hook(Some(Box::new(move |_: &TraceEvent| {
+            *hook_starts.borrow_mut() += 1;
+        })));
+        let total_cycles = cpu.total_cycles;
+
+        cpu.step_cycles(3).unwrap();
+        assert_eq!(cpu.cycles, 3);
+        cpu.step_cycles(3).unwrap();
+        assert_eq!(cpu.cycles, 0);
+        assert_eq!(*starts.borrow(), 1);
+        assert_eq!(cpu.total_cycles, total_cycles + 6);
+
+        // The next cycle starts the NOP.
+        cpu.step_cycles(1).unwrap();
+        assert_eq!(*starts.borrow(), 2);
+        assert_eq!(cpu.pc, 0x9001);
+    }
+
+    #[test]
+    fn cycles_convert_to_wall_clock_time() {
+        let mut cpu = CPU::new(nrom_image(&[]));
+        // 262 scanlines of 341 dots at 3 dots a cycle.
+        let frame_cycles = 262 * 341 / 3;
+
+        let frame = cpu.cycles_to_duration(frame_cycles);
+        assert!(frame > Duration::from_micros(16_630) && frame < Duration::from_micros(16_650), "{:?}", frame);
+        assert_eq!(cpu.duration_to_cycles(Duration::from_secs(1)), NTSC_CPU_HZ);
+        // The duration is truncated to whole nanoseconds so converting back comes up just short.
+        assert_eq!(cpu.duration_to_cycles(frame), frame_cycles - 1);
+
+        cpu.set_clock_rate(PAL_CPU_HZ);
+        assert_eq!(cpu.duration_to_cycles(Duration::from_millis(20)), 33_252);
+    }
+
+    #[test]
+    fn cycle_counters_do_not_wrap_at_u32() {
+        let mut cpu = cpu_with_program(&[0xEA; 4]);
+        cpu.total_cycles = u32::MAX as u64 - 1;
+
+        cpu.step_cycles(4).unwrap();
+        assert_eq!(cpu.total_cycles(), u32::MAX as u64 + 3);
+        assert_eq!(cpu.master_cycles(), 4 * MASTER_CYCLES_PER_CPU_CYCLE);
+
+        cpu.reset_cycle_counter();
+        cpu.step_cycles(2).unwrap();
+        assert_eq!(cpu.total_cycles(), 2);
+        assert_eq!(cpu.master_cycles(), 6 * MASTER_CYCLES_PER_CPU_CYCLE);
+    }
+
+    #[test]
+    fn decimal_mode_adds_and_subtracts_bcd() {
+        // SED, ADC #$01
+        let mut cpu = cpu_with_program(&[0xF8, 0x69, 0x01]);
+        cpu.set_decimal_enabled(true);
+        cpu.a = 0x09;
+        run_instruction(&mut cpu);
+        run_instruction(&mut cpu);
+        assert_eq!(cpu.a, 0x10);
+        assert!(!cpu.get_status(C));
+
+        // $99 + $01 wraps to $00 with carry, but Z and N come from the binary sum $9A.
+        let mut cpu = cpu_with_program(&[0xF8, 0x69, 0x01]);
+        cpu.set_decimal_enabled(true);
+        cpu.a = 0x99;
+        run_instruction(&mut cpu);
+        run_instruction(&mut cpu);
+        assert_eq!(cpu.a, 0x00);
+        assert!(cpu.get_status(C));
+        assert!(!cpu.get_status(Z));
+        assert!(cpu.get_status(N));
+        assert!(!cpu.get_status(V));
+
+        // SED, SEC, SBC #$01
+        let mut cpu = cpu_with_program(&[0xF8, 0x38, 0xE9, 0x01]);
+        cpu.set_decimal_enabled(true);
+        cpu.a = 0x10;
+        for _ in 0..3 {
+            run_instruction(&mut cpu);
+        }
+        assert_eq!(cpu.a, 0x09);
+        assert!(cpu.get_status(C));
+
+        // $00 - $01 borrows and wraps to $99.
+        let mut cpu = cpu_with_program(&[0xF8, 0x38, 0xE9, 0x01]);
+        cpu.set_decimal_enabled(true);
+        cpu.a = 0x00;
+        for _ in 0..3 {
+            run_instruction(&mut cpu);
+        }
+        assert_eq!(cpu.a, 0x99);
+        assert!(!cpu.get_status(C));
+    }
+
+    #[test]
+    fn flags_build_the_status_byte() {
+        let flags = C | Z | N;
+        assert_eq!(flags.bits(), 0x83);
+        assert!(flags.contains(Z));
+        assert!(!flags.contains(V));
+        assert_eq!(flags & N, Flags::from(N));
+        assert_eq!(Flags::from(I) | flags, Flags(0x87));
+
+        let mut cpu = cpu_with_program(&[0xEA]);
+        cpu.set_flags(flags);
+        assert_eq!(cpu.p, 0xA3);
+        assert!(cpu.get_status(C) && cpu.get_status(Z) && cpu.get_status(N));
+        assert_eq!(cpu.flags(), flags | U);
+
+        cpu.set_flags(Flags(0x10));
+        assert_eq!(cpu.flags(), Flags::from(U));
+    }
+
+    #[test]
+    fn adc_and_sbc_overflow() {
+        // (opcode, A, operand, carry in, A after, V, C)
+        let cases = [
+            (0x69, 0x7F, 0x01, false, 0x80, true, false),
+            (0x69, 0x7F, 0x01, true, 0x81, true, false),
+            (0x69, 0x80, 0xFF, false, 0x7F, true, true),
+            // -128 + -1 + 1 is still -128.
+            (0x69, 0x80, 0xFF, true, 0x80, false, true),
+            (0x69, 0x01, 0x01, false, 0x02, false, false),
+            (0x69, 0x01, 0x01, true, 0x03, false, false),
+            // SBC's carry in is "not borrow".
+            (0xE9, 0x80, 0x01, true, 0x7F, true, true),
+            (0xE9, 0x80, 0x01, false, 0x7E, true, true),
+            (0xE9, 0x7F, 0xFF, true, 0x80, true, false),
+            // 127 - -1 - 1 is still 127.
+            (0xE9, 0x7F, 0xFF, false, 0x7F, false, false),
+            (0xE9, 0x01, 0x01, true, 0x00, false, true),
+            (0xE9, 0x01, 0x01, false, 0xFF, false, false),
+        ];
+        for &(opcode, a, operand, carry, result, overflow, carry_out) in cases.iter() {
+            // The NES has no decimal mode so D shouldn't make a difference.
+            for &decimal in [false, true].iter() {
+                let mut cpu = cpu_with_program(&[opcode, operand]);
+                cpu.a = a;
+                cpu.set_status(C, carry);
+                cpu.set_status(D, decimal);
+
+                run_instruction(&mut cpu);
+
+                let case = format!("${:02X} {:02X} #${:02X} with C={} D={}", a, opcode, operand, carry, decimal);
+                assert_eq!(cpu.a, result, "{}", case);
+                assert_eq!(cpu.get_status(V), overflow, "{}", case);
+                assert_eq!(cpu.get_status(C), carry_out, "{}", case);
+            }
+        }
+    }
+
+    #[test]
+    fn adc_and_sbc_flags_for_every_input() {
+        // Every A, operand and carry, the way the functional test checks them. SBC is ADC of the
+        // operand's complement.
+        for a in 0..=0xFF_u8 {
+            for operand in 0..=0xFF_u8 {
+                for &carry in [false, true].iter() {
+                    for &(opcode, added) in [(0x69, operand), (0xE9, !operand)].iter() {
+                        let sum = a as u16 + added as u16 + carry as u16;
+                        let result = sum as u8;
+                        let mut cpu = cpu_with_program(&[opcode, operand]);
+                        cpu.a = a;
+                        cpu.set_status(C, carry);
+
+                        run_instruction(&mut cpu);
+
+                        let case = format!("${:02X} {:02X} #${:02X} with C={}", a, opcode, operand, carry);
+                        assert_eq!(cpu.a, result, "{}", case);
+                        assert_eq!(cpu.get_status(C), sum > 0xFF, "{}", case);
+                        assert_eq!(cpu.get_status(Z), result == 0, "{}", case);
+                        assert_eq!(cpu.get_status(N), result & 0x80 != 0, "{}", case);
+                        assert_eq!(cpu.get_status(V), (a ^ result) & (added ^ result) & 0x80 != 0, "{}", case);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn sbc_sets_zero_when_the_difference_wraps_to_zero() {
+        // $00 - $FF - 1 is -256, i.e. $00 with a borrow.
+        let mut cpu = cpu_with_program(&[0xE9, 0xFF]); // SBC #$FF
+        cpu.a = 0x00;
+        cpu.set_status(C, false);
+
+        run_instruction(&mut cpu);
+
+        assert_eq!(cpu.a, 0x00);
+        assert!(cpu.get_status(Z));
+        assert!(!cpu.get_status(C));
+        assert!(!cpu.get_status(N));
+    }
+
+    #[test]
+    fn nes_ignores_decimal_flag() {
+        // SED, ADC #$01
+        let mut cpu = cpu_with_program(&[0xF8, 0x69, 0x01]);
+        cpu.a = 0x09;
+        run_instruction(&mut cpu);
+        run_instruction(&mut cpu);
+
+        assert!(cpu.get_status(D));
+        assert_eq!(cpu.a, 0x0A);
+    }
+
+    #[test]
+    fn trace_filter_limits_what_the_hook_sees() {
+        // 4 NOPs then JMP $8000
+        let mut program = [0xEA; 7];
+        program[4..].copy_from_slice(&[0x4C, 0x00, 0x80]);
+        let mut cpu = cpu_with_program(&program);
+        let pcs = Rc::new(RefCell::new(Vec::new()));
+        let hook_pcs = Rc::clone(&pcs);
+        cpu.set_trace_hook(Some(Box::new(move |event: &TraceEvent| {
+            hook_pcs.borrow_mut().push(event.pc);
+        })));
+        cpu.set_trace_filter(TraceFilter::InRange(0x8002, 0x8004));
+
+        for _ in 0..10 {
+            cpu.step().unwrap();
+        }
+
+        assert_eq!(*pcs.borrow(), vec![0x8002, 0x8003, 0x8004, 0x8002, 0x8003, 0x8004]);
+    }
+
+    #[test]
+    fn trace_filter_skips_instructions_spinning_in_place() {
+        let mut cpu = cpu_with_program(&[0xEA, 0x4C, 0x01, 0x80]); // NOP, JMP $8001
+        let pcs = Rc::new(RefCell::new(Vec::new()));
+        let hook_pcs = Rc::clone(&pcs);
+        cpu.set_trace_hook(Some(Box::new(move |event: &TraceEvent| {
+            hook_pcs.borrow_mut().push(event.pc);
+        })));
+        cpu.set_trace_filter(TraceFilter::OnPcChange);
+
+        for _ in 0..5 {
+            cpu.step().unwrap();
+        }
+
+        assert_eq!(*pcs.borrow(), vec![0x8000, 0x8001]);
+    }
+
+    #[test]
+    fn trace_shows_only_operand_bytes() {
+        let mut cpu = cpu_with_program(&[0xA9, 0x42, 0xEA, 0x8D, 0x00, 0x03]); // LDA #$42, NOP, STA $0300
+        let lines = Rc::new(RefCell::new(Vec::new()));
+        let hook_lines = Rc::clone(&lines);
+        cpu.set_trace_hook(Some(Box::new(move |event: &TraceEvent| {
+            hook_lines.borrow_mut().push(event.to_string());
+        })));
+
+        for _ in 0..3 {
+            cpu.step().unwrap();
+        }
+
+        let lines = lines.borrow();
+        assert!(lines[0].starts_with("8000  A9 42     LDA"), "{}", lines[0]);
+        assert!(lines[1].starts_with("8002  EA        NOP"), "{}", lines[1]);
+        assert!(lines[2].starts_with("8003  8D 00 03  STA"), "{}", lines[2]);
+    }
+}
@@ -0,0 +1,347 @@
+use crate::cpu::opcode::*;
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::cmp::Reverse;
+use core::fmt;
+
+/// A single decoded instruction along with where it lives and its raw bytes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DisassembledInstruction {
+    pub address: u16,
+    /// The opcode followed by any operand bytes.
+    pub bytes: Vec<u8>,
+    /// Bytes that aren't a known opcode decode as UNK with implied addressing.
+    pub decoded_opcode: DecodedOpcode,
+    /// The name of the address the operand refers to, if the symbol map knows it.
+    pub symbol: Option<String>,
+}
+
+impl DisassembledInstruction {
+    /// The operand bytes put back together. 6502 is little endian so the second operand byte is
+    /// the high byte.
+    pub fn operand(&self) -> u16 {
+        match self.bytes.len() {
+            2 => self.bytes[1] as u16,
+            3 => (self.bytes[2] as u16) << 8 | self.bytes[1] as u16,
+            _ => 0,
+        }
+    }
+
+    /// Whether this is an unofficial instruction. Bytes that don't decode at all aren't counted since
+    /// they're most likely data.
+    pub fn is_illegal(&self) -> bool {
+        !self.decoded_opcode.is_official && self.decoded_opcode.instruction != Instruction::UNK
+    }
+
+    /// The address the operand refers to, if it's an absolute or zero page address. Indirect
+    /// addressing isn't included since the operand is only where the real address is stored.
+    pub fn operand_address(&self) -> Option<u16> {
+        use self::AddressingMode::*;
+        match self.decoded_opcode.mode {
+            _ if self.decoded_opcode.instruction == Instruction::UNK => None,
+            ZeroPage | IndexedZeroPageX | IndexedZeroPageY |
+            Absolute | IndexedAbsoluteX | IndexedAbsoluteY => Some(self.operand()),
+            _ => None,
+        }
+    }
+
+    /// The operand as it looks in 6502 assembly. See the AddressingMode comments.
+    pub fn operand_text(&self) -> String {
+        use self::AddressingMode::*;
+        let operand = self.operand();
+        if self.decoded_opcode.instruction == Instruction::UNK {
+            // Shown as `.byte $XX` so the raw opcode isn't lost.
+            return format!("${:02X}", self.bytes[0]);
+        }
+        match self.decoded_opcode.mode {
+            ZeroPage => format!("${:02X}", operand),
+            IndexedZeroPageX => format!("${:02X},X", operand),
+            IndexedZeroPageY => format!("${:02X},Y", operand),
+            Absolute => format!("${:04X}", operand),
+            IndexedAbsoluteX => format!("${:04X},X", operand),
+            IndexedAbsoluteY => format!("${:04X},Y", operand),
+            Indirect => format!("(${:04X})", operand),
+            Implied => String::new(),
+            Accumulator => "A".to_string(),
+            Immediate => format!("#${:02X}", operand),
+            Relative => {
+                // Shows the real destination rather than the *+/- offset
+                let destination = self.address
+                    .wrapping_add(self.bytes.len() as u16)
+                    .wrapping_add(operand as u8 as i8 as u16);
+                format!("${:04X}", destination)
+            }
+            IndexedIndirect => format!("(${:02X},X)", operand),
+            IndirectIndexed => format!("(${:02X}),Y", operand),
+        }
+    }
+
+    /// Everything after the address: the raw bytes padded to the width of the longest
+    /// instruction, so the mnemonics line up, then the instruction and any comments.
+    pub fn listing(&self) -> String {
+        let bytes: Vec<String> = self.bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+        let mut listing = format!("{:8}  {}", bytes.join(" "), self.decoded_opcode.instruction);
+        match self.operand_text().as_str() {
+            "" => (),
+            operand => listing.push_str(&format!(" {}", operand)),
+        }
+        if let Some(symbol) = &self.symbol {
+            listing.push_str(&format!(" ; {}", symbol));
+        }
+        if self.is_illegal() {
+            listing.push_str(" ; illegal");
+        }
+
+        listing
+    }
+}
+
+impl fmt::Display for DisassembledInstruction {
+    /// e.g. C000: 4C F5 C5  JMP $C5F5
+    /// Operands with a symbol get a trailing comment with its name, e.g. `STA $2000 ; PPUCTRL`, and
+    /// unofficial instructions get `; illegal`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        write!(f, "{:04X}: {}", self.address, self.listing())
+    }
+}
+
+/// Decodes `count` instructions starting at `start`. Bytes are read through `peek` so this can
+/// disassemble anything that can be read without side effects, e.g. the CPU's current memory.
+pub fn disassemble<F: FnMut(u16) -> u8>(peek: F, start: u16, count: usize) -> Vec<DisassembledInstruction> {
+    Disassembler::new(peek, start).take(count).collect()
+}
+
+/// The single instruction at `address`.
+fn decode_at<F: FnMut(u16) -> u8>(peek: &mut F, address: u16) -> DisassembledInstruction {
+    let opcode = peek(address);
+    let decoded_opcode = opcode.decode().unwrap_or(DecodedOpcode {
+        instruction: Instruction::UNK,
+        mode: AddressingMode::Implied,
+        cycles: 0,
+        is_official: false,
+    });
+    let length = instruction_length(decoded_opcode.mode);
+    let bytes = (0..length)
+        .map(|offset| peek(address.wrapping_add(offset as u16)))
+        .collect();
+
+    DisassembledInstruction { address, bytes, decoded_opcode, symbol: None }
+}
+
+/// How far back `Disassembler::prev_instruction` starts decoding from.
+const LOOKBEHIND: u16 = 32;
+
+/// Disassembles from an address one instruction at a time in either direction, e.g. for a
+/// debugger's scrolling view, instead of starting over like `disassemble`. Bytes are read through
+/// `peek` the same way, e.g. `|address| nes.peek(address)`.
+///
+/// Iterating is the same as calling `next_instruction` forever.
+pub struct Disassembler<F: FnMut(u16) -> u8> {
+    peek: F,
+    // Where the next instruction starts.
+    address: u16,
+}
+
+impl<F: FnMut(u16) -> u8> Disassembler<F> {
+    pub fn new(peek: F, address: u16) -> Disassembler<F> {
+        Disassembler { peek, address }
+    }
+
+    /// Moves to `address` without decoding anything.
+    pub fn at(&mut self, address: u16) {
+        self.address = address;
+    }
+
+    /// Where `next_instruction` decodes from.
+    pub fn address(&self) -> u16 {
+        self.address
+    }
+
+    /// Decodes the instruction at `address()` and moves past it.
+    pub fn next_instruction(&mut self) -> DisassembledInstruction {
+        let instruction = decode_at(&mut self.peek, self.address);
+        self.address = self.address.wrapping_add(instruction.bytes.len() as u16);
+
+        instruction
+    }
+
+    /// Moves back to the instruction that ends at `address()` and decodes it. Instructions are 1-3
+    /// bytes so there's no telling for sure where it starts. This decodes forward from each of the
+    /// last `LOOKBEHIND` bytes, keeping only runs of official opcodes, since the rest are most
+    /// likely data, that land exactly on `address()`. Runs get back in step with each other after
+    /// a few instructions, so the start most of them agree on wins. If none land, the previous
+    /// byte is taken to be an instruction on its own.
+    pub fn prev_instruction(&mut self) -> DisassembledInstruction {
+        let target = self.address;
+        let mut votes: BTreeMap<u16, usize> = BTreeMap::new();
+        for back in 1..=LOOKBEHIND {
+            let mut address = target.wrapping_sub(back);
+            let mut remaining = back as usize;
+            let mut last = address;
+            while remaining > 0 {
+                let instruction = decode_at(&mut self.peek, address);
+                if !instruction.decoded_opcode.is_official || instruction.bytes.len() > remaining {
+                    break;
+                }
+                last = address;
+                remaining -= instruction.bytes.len();
+                address = address.wrapping_add(instruction.bytes.len() as u16);
+            }
+            if remaining == 0 {
+                *votes.entry(last).or_insert(0) += 1;
+            }
+        }
+        // On a tie the closest start wins.
+        let start = votes
+            .iter()
+            .max_by_key(|(start, count)| (**count, Reverse(target.wrapping_sub(**start))))
+            .map_or(target.wrapping_sub(1), |(start, _)| *start);
+        self.address = start;
+
+        decode_at(&mut self.peek, start)
+    }
+}
+
+impl<F: FnMut(u16) -> u8> Iterator for Disassembler<F> {
+    type Item = DisassembledInstruction;
+
+    fn next(&mut self) -> Option<DisassembledInstruction> {
+        Some(self.next_instruction())
+    }
+}
+
+/// Like `disassemble` but names operands that are in `symbols`.
+pub fn disassemble_with_symbols<F: FnMut(u16) -> u8>(
+    peek: F,
+    start: u16,
+    count: usize,
+    symbols: &SymbolMap,
+) -> Vec<DisassembledInstruction> {
+    let mut instructions = disassemble(peek, start, count);
+    for instruction in instructions.iter_mut() {
+        instruction.symbol = instruction.operand_address()
+            .and_then(|address| symbols.get(address))
+            .map(|name| name.to_string());
+    }
+
+    instructions
+}
+
+/// Names for addresses, used to annotate disassembly.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SymbolMap {
+    symbols: BTreeMap<u16, String>,
+}
+
+/// The PPU and APU/IO registers. Names are the ones used on the nesdev wiki.
+/// See: https://wiki.nesdev.com/w/index.php/PPU_registers
+/// See: https://wiki.nesdev.com/w/index.php/APU_registers
+const NES_REGISTERS: [(u16, &str); 30] = [
+    (0x2000, "PPUCTRL"),
+    (0x2001, "PPUMASK"),
+    (0x2002, "PPUSTATUS"),
+    (0x2003, "OAMADDR"),
+    (0x2004, "OAMDATA"),
+    (0x2005, "PPUSCROLL"),
+    (0x2006, "PPUADDR"),
+    (0x2007, "PPUDATA"),
+    (0x4000, "SQ1_VOL"),
+    (0x4001, "SQ1_SWEEP"),
+    (0x4002, "SQ1_LO"),
+    (0x4003, "SQ1_HI"),
+    (0x4004, "SQ2_VOL"),
+    (0x4005, "SQ2_SWEEP"),
+    (0x4006, "SQ2_LO"),
+    (0x4007, "SQ2_HI"),
+    (0x4008, "TRI_LINEAR"),
+    (0x400A, "TRI_LO"),
+    (0x400B, "TRI_HI"),
+    (0x400C, "NOISE_VOL"),
+    (0x400E, "NOISE_LO"),
+    (0x400F, "NOISE_HI"),
+    (0x4010, "DMC_FREQ"),
+    (0x4011, "DMC_RAW"),
+    (0x4012, "DMC_START"),
+    (0x4013, "DMC_LEN"),
+    (0x4014, "OAMDMA"),
+    (0x4015, "SND_CHN"),
+    (0x4016, "JOY1"),
+    (0x4017, "JOY2"),
+];
+
+impl SymbolMap {
+    pub fn new() -> SymbolMap {
+        SymbolMap::default()
+    }
+
+    /// The NES's memory mapped registers.
+    pub fn nes() -> SymbolMap {
+        let mut symbols = SymbolMap::new();
+        for (address, name) in NES_REGISTERS.iter() {
+            symbols.insert(*address, name);
+        }
+
+        symbols
+    }
+
+    pub fn insert(&mut self, address: u16, name: &str) {
+        self.symbols.insert(address, name.to_string());
+    }
+
+    /// Adds all of `other`'s symbols. Where both have a name for an address `other`'s is kept, so
+    /// a game's own labels can be merged over the built in ones.
+    pub fn merge(&mut self, other: &SymbolMap) {
+        for (address, name) in other.symbols.iter() {
+            self.symbols.insert(*address, name.clone());
+        }
+    }
+
+    pub fn get(&self, address: u16) -> Option<&str> {
+        self.symbols.get(&address).map(|name| name.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// LDA #$01, STA $0200, loop: INX, BNE loop, JMP ($FFFC), RTS, then a 3 byte instruction
+    /// whose operand bytes are themselves opcodes.
+    const PROGRAM: [u8; 15] = [
+        0xA9, 0x01, 0x8D, 0x00, 0x02, 0xE8, 0xD0, 0xFD, 0x6C, 0xFC, 0xFF, 0x60, 0xAD, 0xE8, 0xE8,
+    ];
+
+    fn peek_program(address: u16) -> u8 {
+        PROGRAM.get(address.wrapping_sub(0x8000) as usize).copied().unwrap_or(0xEA)
+    }
+
+    #[test]
+    fn steps_forward_like_the_batch_disassembler() {
+        let mut disassembler = Disassembler::new(peek_program, 0x8000);
+        let batch = disassemble(peek_program, 0x8000, 8);
+
+        let stepped: Vec<DisassembledInstruction> = (0..8).map(|_| disassembler.next_instruction()).collect();
+
+        assert_eq!(stepped, batch);
+        assert_eq!(disassembler.address(), 0x8010);
+        disassembler.at(0x8005);
+        assert_eq!(disassembler.next().map(|instruction| instruction.address), Some(0x8005));
+    }
+
+    #[test]
+    fn steps_back_to_the_start_of_the_previous_instruction() {
+        let starts = [0x8000, 0x8002, 0x8005, 0x8006, 0x8008, 0x800B, 0x800C, 0x800F];
+        let mut disassembler = Disassembler::new(peek_program, 0x800F);
+
+        for start in starts.iter().rev().skip(1) {
+            let instruction = disassembler.prev_instruction();
+            assert_eq!(instruction.address, *start);
+            assert_eq!(disassembler.address(), *start);
+        }
+
+        // Going back and forward again gives the same instructions.
+        assert_eq!(disassembler.next_instruction(), disassemble(peek_program, 0x8000, 1)[0]);
+    }
+}
@@ -1,2 +1,5 @@
 pub mod opcode;
-pub mod cpu;
\ No newline at end of file
+pub mod cpu;
+pub mod disassembler;
+pub mod snapshot;
+pub mod trace;
\ No newline at end of file
@@ -1,7 +1,7 @@
-use std::string::ToString;
-use strum_macros::*;
+use alloc::vec::Vec;
+use core::fmt;
 
-#[derive(Debug, Clone, Copy, Display)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 /// List of all NES instructions. This also includes "illegal" opcode based instructions.
 pub enum Instruction {
     // Taken from: http://nesdev.com/6502.txt
@@ -95,11 +95,27 @@ pub enum Instruction {
     SLO,
     SRE,
 
+    // Locks up the CPU. Also known as KIL or HLT.
+    JAM,
+
     // Below is just a helper for unknown opcode. Useful when disassembling and you hit data and not
     // instruction.
     UNK,
 }
 
+/// The mnemonic, which is the same as the variant name. Unofficial instructions use the names from
+/// https://wiki.nesdev.com/w/index.php/Programming_with_unofficial_opcodes e.g. ISC rather than ISB.
+/// UNK isn't an instruction so it's shown as a `.byte` directive, which the disassemblers follow
+/// with the raw opcode.
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Instruction::UNK => f.write_str(".byte"),
+            instruction => fmt::Debug::fmt(instruction, f),
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Clone, Copy)]
 /// List of the addressing modes that are available in the NES. Below comments for each element in
 /// the enum represent how it looks in 6502 assembly, any other names the addressing mode is also
@@ -181,23 +197,67 @@ pub enum AddressingMode {
     IndirectIndexed,
 }
 
+impl AddressingMode {
+    /// How many bytes of operand follow the opcode. Each addressing mode always has the same
+    /// number, so the mode alone gives the length of an instruction, see `instruction_length`.
+    pub const fn operand_bytes(&self) -> usize {
+        use self::AddressingMode::*;
+        match self {
+            Implied | Accumulator => 0,
+            Immediate | ZeroPage | IndexedZeroPageX | IndexedZeroPageY | Relative | IndexedIndirect
+            | IndirectIndexed => 1,
+            Absolute | IndexedAbsoluteX | IndexedAbsoluteY | Indirect => 2,
+        }
+    }
+}
+
+/// Number of bytes an instruction takes up, i.e. the opcode plus its operand.
+pub const fn instruction_length(mode: AddressingMode) -> usize {
+    1 + mode.operand_bytes()
+}
+
 pub type Opcode = u8;
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 /// A combination of instruction, addressing mode, and number of cycles the instruction-addressing
 /// mode operation would take.
 pub struct DecodedOpcode {
     pub instruction: Instruction,
     pub mode: AddressingMode,
     pub cycles: u8,
+    /// False for the illegal aka unofficial opcodes. This includes the extra NOPs and $EB SBC even
+    /// though they behave like official instructions.
+    pub is_official: bool,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum DecodeError {
     /// Just an error representing an illegal opcode that is also unimplemented.
     IllegalUnimplementedOpcode { opcode: Opcode },
+    /// A byte that should have been an instruction but doesn't decode, along with where it was.
+    IllegalOpcode { address: usize, opcode: Opcode },
+    /// An unofficial opcode, e.g. LAX, that the CPU was told not to run. See
+    /// `CPU::set_strict_illegals`.
+    UnofficialOpcode { address: usize, opcode: Opcode },
 }
 
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::IllegalUnimplementedOpcode { opcode } => write!(f, "${:02X} isn't a 6502 opcode", opcode),
+            DecodeError::IllegalOpcode { address, opcode } => {
+                write!(f, "${:02X} at ${:04X} isn't a 6502 opcode", opcode, address)
+            }
+            DecodeError::UnofficialOpcode { address, opcode } => {
+                write!(f, "${:02X} at ${:04X} is an unofficial opcode, which strict mode doesn't run", opcode, address)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for DecodeError {}
+
 pub trait Decode {
     fn decode(&self) -> Result<DecodedOpcode, DecodeError>;
 }
@@ -208,1204 +268,1722 @@ impl Decode for Opcode {
     /// # Example
     ///
     /// ```
-    /// let decoded = 0x6C.decode();
+    /// use rust_webpack_template::cpu::opcode::{AddressingMode, Decode, Instruction};
+    ///
+    /// let decoded = 0x6C.decode().unwrap();
+    /// assert_eq!(decoded.instruction, Instruction::JMP);
+    /// assert_eq!(decoded.mode, AddressingMode::Indirect);
     /// ```
     fn decode(&self) -> Result<DecodedOpcode, DecodeError> {
-        {
-            /*
-            Below is taken from: http://nesdev.com/6502_cpu.txt See doc for number of cycles.
-            6510 Instructions by Addressing Modes
-            off- ++++++++++ Positive ++++++++++  ---------- Negative ----------
-            set  00      20      40      60      80      a0      c0      e0      mode
-            +00  BRK     JSR     RTI     RTS     NOP*    LDY     CPY     CPX     Impl/immed
-            +01  ORA     AND     EOR     ADC     STA     LDA     CMP     SBC     (indir,x)
-            +02   t       t       t       t      NOP*t   LDX     NOP*t   NOP*t     ? /immed
-            +03  SLO*    RLA*    SRE*    RRA*    SAX*    LAX*    DCP*    ISB*    (indir,x)
-            +04  NOP*    BIT     NOP*    NOP*    STY     LDY     CPY     CPX     Zeropage
-            +05  ORA     AND     EOR     ADC     STA     LDA     CMP     SBC     Zeropage
-            +06  ASL     ROL     LSR     ROR     STX     LDX     DEC     INC     Zeropage
-            +07  SLO*    RLA*    SRE*    RRA*    SAX*    LAX*    DCP*    ISB*    Zeropage
-            +08  PHP     PLP     PHA     PLA     DEY     TAY     INY     INX     Implied
-            +09  ORA     AND     EOR     ADC     NOP*    LDA     CMP     SBC     Immediate
-            +0a  ASL     ROL     LSR     ROR     TXA     TAX     DEX     NOP     Accu/impl
-            +0b  ANC**   ANC**   ASR**   ARR**   ANE**   LXA**   SBX**   SBC*    Immediate
-            +0c  NOP*    BIT     JMP     JMP ()  STY     LDY     CPY     CPX     Absolute
-            +0d  ORA     AND     EOR     ADC     STA     LDA     CMP     SBC     Absolute
-            +0e  ASL     ROL     LSR     ROR     STX     LDX     DEC     INC     Absolute
-            +0f  SLO*    RLA*    SRE*    RRA*    SAX*    LAX*    DCP*    ISB*    Absolute
-            +10  BPL     BMI     BVC     BVS     BCC     BCS     BNE     BEQ     Relative
-            +11  ORA     AND     EOR     ADC     STA     LDA     CMP     SBC     (indir),y
-            +12   t       t       t       t       t       t       t       t         ?
-            +13  SLO*    RLA*    SRE*    RRA*    SHA**   LAX*    DCP*    ISB*    (indir),y
-            +14  NOP*    NOP*    NOP*    NOP*    STY     LDY     NOP*    NOP*    Zeropage,x
-            +15  ORA     AND     EOR     ADC     STA     LDA     CMP     SBC     Zeropage,x
-            +16  ASL     ROL     LSR     ROR     STX  y) LDX  y) DEC     INC     Zeropage,x
-            +17  SLO*    RLA*    SRE*    RRA*    SAX* y) LAX* y) DCP*    ISB*    Zeropage,x
-            +18  CLC     SEC     CLI     SEI     TYA     CLV     CLD     SED     Implied
-            +19  ORA     AND     EOR     ADC     STA     LDA     CMP     SBC     Absolute,y
-            +1a  NOP*    NOP*    NOP*    NOP*    TXS     TSX     NOP*    NOP*    Implied
-            +1b  SLO*    RLA*    SRE*    RRA*    SHS**   LAS**   DCP*    ISB*    Absolute,y
-            +1c  NOP*    NOP*    NOP*    NOP*    SHY**   LDY     NOP*    NOP*    Absolute,x
-            +1d  ORA     AND     EOR     ADC     STA     LDA     CMP     SBC     Absolute,x
-            +1e  ASL     ROL     LSR     ROR     SHX**y) LDX  y) DEC     INC     Absolute,x
-            +1f  SLO*    RLA*    SRE*    RRA*    SHA**y) LAX* y) DCP*    ISB*    Absolute,x
-            */
-            use self::AddressingMode::*;
-            use self::Instruction::*;
-            // This should be generated by a macro of some sort. Right now it's generated by my parser
-            // Python script which I forgot where I put it.
-            match self {
-                0x69 => Ok(DecodedOpcode {
-                    instruction: ADC,
-                    mode: Immediate,
-                    cycles: 2,
-                }),
-                0x65 => Ok(DecodedOpcode {
-                    instruction: ADC,
-                    mode: ZeroPage,
-                    cycles: 3,
-                }),
-                0x75 => Ok(DecodedOpcode {
-                    instruction: ADC,
-                    mode: IndexedZeroPageX,
-                    cycles: 4,
-                }),
-                0x6D => Ok(DecodedOpcode {
-                    instruction: ADC,
-                    mode: Absolute,
-                    cycles: 4,
-                }),
-                0x7D => Ok(DecodedOpcode {
-                    instruction: ADC,
-                    mode: IndexedAbsoluteX,
-                    cycles: 4,
-                }),
-                0x79 => Ok(DecodedOpcode {
-                    instruction: ADC,
-                    mode: IndexedAbsoluteY,
-                    cycles: 4,
-                }),
-                0x61 => Ok(DecodedOpcode {
-                    instruction: ADC,
-                    mode: IndexedIndirect,
-                    cycles: 6,
-                }),
-                0x71 => Ok(DecodedOpcode {
-                    instruction: ADC,
-                    mode: IndirectIndexed,
-                    cycles: 5,
-                }),
-                0x29 => Ok(DecodedOpcode {
-                    instruction: AND,
-                    mode: Immediate,
-                    cycles: 2,
-                }),
-                0x25 => Ok(DecodedOpcode {
-                    instruction: AND,
-                    mode: ZeroPage,
-                    cycles: 3,
-                }),
-                0x35 => Ok(DecodedOpcode {
-                    instruction: AND,
-                    mode: IndexedZeroPageX,
-                    cycles: 4,
-                }),
-                0x2D => Ok(DecodedOpcode {
-                    instruction: AND,
-                    mode: Absolute,
-                    cycles: 4,
-                }),
-                0x3D => Ok(DecodedOpcode {
-                    instruction: AND,
-                    mode: IndexedAbsoluteX,
-                    cycles: 4,
-                }),
-                0x39 => Ok(DecodedOpcode {
-                    instruction: AND,
-                    mode: IndexedAbsoluteY,
-                    cycles: 4,
-                }),
-                0x21 => Ok(DecodedOpcode {
-                    instruction: AND,
-                    mode: IndexedIndirect,
-                    cycles: 6,
-                }),
-                0x31 => Ok(DecodedOpcode {
-                    instruction: AND,
-                    mode: IndirectIndexed,
-                    cycles: 5,
-                }),
-                0x0A => Ok(DecodedOpcode {
-                    instruction: ASL,
-                    mode: Accumulator,
-                    cycles: 2,
-                }),
-                0x06 => Ok(DecodedOpcode {
-                    instruction: ASL,
-                    mode: ZeroPage,
-                    cycles: 5,
-                }),
-                0x16 => Ok(DecodedOpcode {
-                    instruction: ASL,
-                    mode: IndexedZeroPageX,
-                    cycles: 6,
-                }),
-                0x0E => Ok(DecodedOpcode {
-                    instruction: ASL,
-                    mode: Absolute,
-                    cycles: 6,
-                }),
-                0x1E => Ok(DecodedOpcode {
-                    instruction: ASL,
-                    mode: IndexedAbsoluteX,
-                    cycles: 7,
-                }),
-                0x90 => Ok(DecodedOpcode {
-                    instruction: BCC,
-                    mode: Relative,
-                    cycles: 2,
-                }),
-                0xB0 => Ok(DecodedOpcode {
-                    instruction: BCS,
-                    mode: Relative,
-                    cycles: 2,
-                }),
-                0xF0 => Ok(DecodedOpcode {
-                    instruction: BEQ,
-                    mode: Relative,
-                    cycles: 2,
-                }),
-                0x24 => Ok(DecodedOpcode {
-                    instruction: BIT,
-                    mode: ZeroPage,
-                    cycles: 3,
-                }),
-                0x2C => Ok(DecodedOpcode {
-                    instruction: BIT,
-                    mode: Absolute,
-                    cycles: 4,
-                }),
-                0x30 => Ok(DecodedOpcode {
-                    instruction: BMI,
-                    mode: Relative,
-                    cycles: 2,
-                }),
-                0xD0 => Ok(DecodedOpcode {
-                    instruction: BNE,
-                    mode: Relative,
-                    cycles: 2,
-                }),
-                0x10 => Ok(DecodedOpcode {
-                    instruction: BPL,
-                    mode: Relative,
-                    cycles: 2,
-                }),
-                0x00 => Ok(DecodedOpcode {
-                    instruction: BRK,
-                    mode: Implied,
-                    cycles: 7,
-                }),
-                0x50 => Ok(DecodedOpcode {
-                    instruction: BVC,
-                    mode: Relative,
-                    cycles: 2,
-                }),
-                0x70 => Ok(DecodedOpcode {
-                    instruction: BVS,
-                    mode: Relative,
-                    cycles: 2,
-                }),
-                0x18 => Ok(DecodedOpcode {
-                    instruction: CLC,
-                    mode: Implied,
-                    cycles: 2,
-                }),
-                0xD8 => Ok(DecodedOpcode {
-                    instruction: CLD,
-                    mode: Implied,
-                    cycles: 2,
-                }),
-                0x58 => Ok(DecodedOpcode {
-                    instruction: CLI,
-                    mode: Implied,
-                    cycles: 2,
-                }),
-                0xB8 => Ok(DecodedOpcode {
-                    instruction: CLV,
-                    mode: Implied,
-                    cycles: 2,
-                }),
-                0xC9 => Ok(DecodedOpcode {
-                    instruction: CMP,
-                    mode: Immediate,
-                    cycles: 2,
-                }),
-                0xC5 => Ok(DecodedOpcode {
-                    instruction: CMP,
-                    mode: ZeroPage,
-                    cycles: 3,
-                }),
-                0xD5 => Ok(DecodedOpcode {
-                    instruction: CMP,
-                    mode: IndexedZeroPageX,
-                    cycles: 4,
-                }),
-                0xCD => Ok(DecodedOpcode {
-                    instruction: CMP,
-                    mode: Absolute,
-                    cycles: 4,
-                }),
-                0xDD => Ok(DecodedOpcode {
-                    instruction: CMP,
-                    mode: IndexedAbsoluteX,
-                    cycles: 4,
-                }),
-                0xD9 => Ok(DecodedOpcode {
-                    instruction: CMP,
-                    mode: IndexedAbsoluteY,
-                    cycles: 4,
-                }),
-                0xC1 => Ok(DecodedOpcode {
-                    instruction: CMP,
-                    mode: IndexedIndirect,
-                    cycles: 6,
-                }),
-                0xD1 => Ok(DecodedOpcode {
-                    instruction: CMP,
-                    mode: IndirectIndexed,
-                    cycles: 5,
-                }),
-                0xE0 => Ok(DecodedOpcode {
-                    instruction: CPX,
-                    mode: Immediate,
-                    cycles: 2,
-                }),
-                0xE4 => Ok(DecodedOpcode {
-                    instruction: CPX,
-                    mode: ZeroPage,
-                    cycles: 3,
-                }),
-                0xEC => Ok(DecodedOpcode {
-                    instruction: CPX,
-                    mode: Absolute,
-                    cycles: 4,
-                }),
-                0xC0 => Ok(DecodedOpcode {
-                    instruction: CPY,
-                    mode: Immediate,
-                    cycles: 2,
-                }),
-                0xC4 => Ok(DecodedOpcode {
-                    instruction: CPY,
-                    mode: ZeroPage,
-                    cycles: 3,
-                }),
-                0xCC => Ok(DecodedOpcode {
-                    instruction: CPY,
-                    mode: Absolute,
-                    cycles: 4,
-                }),
-                0xC6 => Ok(DecodedOpcode {
-                    instruction: DEC,
-                    mode: ZeroPage,
-                    cycles: 5,
-                }),
-                0xD6 => Ok(DecodedOpcode {
-                    instruction: DEC,
-                    mode: IndexedZeroPageX,
-                    cycles: 6,
-                }),
-                0xCE => Ok(DecodedOpcode {
-                    instruction: DEC,
-                    mode: Absolute,
-                    cycles: 6,
-                }),
-                0xDE => Ok(DecodedOpcode {
-                    instruction: DEC,
-                    mode: IndexedAbsoluteX,
-                    cycles: 7,
-                }),
-                0xCA => Ok(DecodedOpcode {
-                    instruction: DEX,
-                    mode: Implied,
-                    cycles: 2,
-                }),
-                0x88 => Ok(DecodedOpcode {
-                    instruction: DEY,
-                    mode: Implied,
-                    cycles: 2,
-                }),
-                0x49 => Ok(DecodedOpcode {
-                    instruction: EOR,
-                    mode: Immediate,
-                    cycles: 2,
-                }),
-                0x45 => Ok(DecodedOpcode {
-                    instruction: EOR,
-                    mode: ZeroPage,
-                    cycles: 3,
-                }),
-                0x55 => Ok(DecodedOpcode {
-                    instruction: EOR,
-                    mode: IndexedZeroPageX,
-                    cycles: 4,
-                }),
-                0x4D => Ok(DecodedOpcode {
-                    instruction: EOR,
-                    mode: Absolute,
-                    cycles: 4,
-                }),
-                0x5D => Ok(DecodedOpcode {
-                    instruction: EOR,
-                    mode: IndexedAbsoluteX,
-                    cycles: 4,
-                }),
-                0x59 => Ok(DecodedOpcode {
-                    instruction: EOR,
-                    mode: IndexedAbsoluteY,
-                    cycles: 4,
-                }),
-                0x41 => Ok(DecodedOpcode {
-                    instruction: EOR,
-                    mode: IndexedIndirect,
-                    cycles: 6,
-                }),
-                0x51 => Ok(DecodedOpcode {
-                    instruction: EOR,
-                    mode: IndirectIndexed,
-                    cycles: 5,
-                }),
-                0xE6 => Ok(DecodedOpcode {
-                    instruction: INC,
-                    mode: ZeroPage,
-                    cycles: 5,
-                }),
-                0xF6 => Ok(DecodedOpcode {
-                    instruction: INC,
-                    mode: IndexedZeroPageX,
-                    cycles: 6,
-                }),
-                0xEE => Ok(DecodedOpcode {
-                    instruction: INC,
-                    mode: Absolute,
-                    cycles: 6,
-                }),
-                0xFE => Ok(DecodedOpcode {
-                    instruction: INC,
-                    mode: IndexedAbsoluteX,
-                    cycles: 7,
-                }),
-                0xE8 => Ok(DecodedOpcode {
-                    instruction: INX,
-                    mode: Implied,
-                    cycles: 2,
-                }),
-                0xC8 => Ok(DecodedOpcode {
-                    instruction: INY,
-                    mode: Implied,
-                    cycles: 2,
-                }),
-                0x4C => Ok(DecodedOpcode {
-                    instruction: JMP,
-                    mode: Absolute,
-                    cycles: 3,
-                }),
-                0x6C => Ok(DecodedOpcode {
-                    instruction: JMP,
-                    mode: Indirect,
-                    cycles: 5,
-                }),
-                0x20 => Ok(DecodedOpcode {
-                    instruction: JSR,
-                    mode: Absolute,
-                    cycles: 6,
-                }),
-                0xA9 => Ok(DecodedOpcode {
-                    instruction: LDA,
-                    mode: Immediate,
-                    cycles: 2,
-                }),
-                0xA5 => Ok(DecodedOpcode {
-                    instruction: LDA,
-                    mode: ZeroPage,
-                    cycles: 3,
-                }),
-                0xB5 => Ok(DecodedOpcode {
-                    instruction: LDA,
-                    mode: IndexedZeroPageX,
-                    cycles: 4,
-                }),
-                0xAD => Ok(DecodedOpcode {
-                    instruction: LDA,
-                    mode: Absolute,
-                    cycles: 4,
-                }),
-                0xBD => Ok(DecodedOpcode {
-                    instruction: LDA,
-                    mode: IndexedAbsoluteX,
-                    cycles: 4,
-                }),
-                0xB9 => Ok(DecodedOpcode {
-                    instruction: LDA,
-                    mode: IndexedAbsoluteY,
-                    cycles: 4,
-                }),
-                0xA1 => Ok(DecodedOpcode {
-                    instruction: LDA,
-                    mode: IndexedIndirect,
-                    cycles: 6,
-                }),
-                0xB1 => Ok(DecodedOpcode {
-                    instruction: LDA,
-                    mode: IndirectIndexed,
-                    cycles: 5,
-                }),
-                0xA2 => Ok(DecodedOpcode {
-                    instruction: LDX,
-                    mode: Immediate,
-                    cycles: 2,
-                }),
-                0xA6 => Ok(DecodedOpcode {
-                    instruction: LDX,
-                    mode: ZeroPage,
-                    cycles: 3,
-                }),
-                0xB6 => Ok(DecodedOpcode {
-                    instruction: LDX,
-                    mode: IndexedZeroPageY,
-                    cycles: 4,
-                }),
-                0xAE => Ok(DecodedOpcode {
-                    instruction: LDX,
-                    mode: Absolute,
-                    cycles: 4,
-                }),
-                0xBE => Ok(DecodedOpcode {
-                    instruction: LDX,
-                    mode: IndexedAbsoluteY,
-                    cycles: 4,
-                }),
-                0xA0 => Ok(DecodedOpcode {
-                    instruction: LDY,
-                    mode: Immediate,
-                    cycles: 2,
-                }),
-                0xA4 => Ok(DecodedOpcode {
-                    instruction: LDY,
-                    mode: ZeroPage,
-                    cycles: 3,
-                }),
-                0xB4 => Ok(DecodedOpcode {
-                    instruction: LDY,
-                    mode: IndexedZeroPageX,
-                    cycles: 4,
-                }),
-                0xAC => Ok(DecodedOpcode {
-                    instruction: LDY,
-                    mode: Absolute,
-                    cycles: 4,
-                }),
-                0xBC => Ok(DecodedOpcode {
-                    instruction: LDY,
-                    mode: IndexedAbsoluteX,
-                    cycles: 4,
-                }),
-                0x4A => Ok(DecodedOpcode {
-                    instruction: LSR,
-                    mode: Accumulator,
-                    cycles: 2,
-                }),
-                0x46 => Ok(DecodedOpcode {
-                    instruction: LSR,
-                    mode: ZeroPage,
-                    cycles: 5,
-                }),
-                0x56 => Ok(DecodedOpcode {
-                    instruction: LSR,
-                    mode: IndexedZeroPageX,
-                    cycles: 6,
-                }),
-                0x4E => Ok(DecodedOpcode {
-                    instruction: LSR,
-                    mode: Absolute,
-                    cycles: 6,
-                }),
-                0x5E => Ok(DecodedOpcode {
-                    instruction: LSR,
-                    mode: IndexedAbsoluteX,
-                    cycles: 7,
-                }),
-                0xEA => Ok(DecodedOpcode {
-                    instruction: NOP,
-                    mode: Implied,
-                    cycles: 2,
-                }),
-                0x09 => Ok(DecodedOpcode {
-                    instruction: ORA,
-                    mode: Immediate,
-                    cycles: 2,
-                }),
-                0x05 => Ok(DecodedOpcode {
-                    instruction: ORA,
-                    mode: ZeroPage,
-                    cycles: 3,
-                }),
-                0x15 => Ok(DecodedOpcode {
-                    instruction: ORA,
-                    mode: IndexedZeroPageX,
-                    cycles: 4,
-                }),
-                0x0D => Ok(DecodedOpcode {
-                    instruction: ORA,
-                    mode: Absolute,
-                    cycles: 4,
-                }),
-                0x1D => Ok(DecodedOpcode {
-                    instruction: ORA,
-                    mode: IndexedAbsoluteX,
-                    cycles: 4,
-                }),
-                0x19 => Ok(DecodedOpcode {
-                    instruction: ORA,
-                    mode: IndexedAbsoluteY,
-                    cycles: 4,
-                }),
-                0x01 => Ok(DecodedOpcode {
-                    instruction: ORA,
-                    mode: IndexedIndirect,
-                    cycles: 6,
-                }),
-                0x11 => Ok(DecodedOpcode {
-                    instruction: ORA,
-                    mode: IndirectIndexed,
-                    cycles: 5,
-                }),
-                0x48 => Ok(DecodedOpcode {
-                    instruction: PHA,
-                    mode: Implied,
-                    cycles: 3,
-                }),
-                0x08 => Ok(DecodedOpcode {
-                    instruction: PHP,
-                    mode: Implied,
-                    cycles: 3,
-                }),
-                0x68 => Ok(DecodedOpcode {
-                    instruction: PLA,
-                    mode: Implied,
-                    cycles: 4,
-                }),
-                0x28 => Ok(DecodedOpcode {
-                    instruction: PLP,
-                    mode: Implied,
-                    cycles: 4,
-                }),
-                0x2A => Ok(DecodedOpcode {
-                    instruction: ROL,
-                    mode: Accumulator,
-                    cycles: 2,
-                }),
-                0x26 => Ok(DecodedOpcode {
-                    instruction: ROL,
-                    mode: ZeroPage,
-                    cycles: 5,
-                }),
-                0x36 => Ok(DecodedOpcode {
-                    instruction: ROL,
-                    mode: IndexedZeroPageX,
-                    cycles: 6,
-                }),
-                0x2E => Ok(DecodedOpcode {
-                    instruction: ROL,
-                    mode: Absolute,
-                    cycles: 6,
-                }),
-                0x3E => Ok(DecodedOpcode {
-                    instruction: ROL,
-                    mode: IndexedAbsoluteX,
-                    cycles: 7,
-                }),
-                0x6A => Ok(DecodedOpcode {
-                    instruction: ROR,
-                    mode: Accumulator,
-                    cycles: 2,
-                }),
-                0x66 => Ok(DecodedOpcode {
-                    instruction: ROR,
-                    mode: ZeroPage,
-                    cycles: 5,
-                }),
-                0x76 => Ok(DecodedOpcode {
-                    instruction: ROR,
-                    mode: IndexedZeroPageX,
-                    cycles: 6,
-                }),
-                0x6E => Ok(DecodedOpcode {
-                    instruction: ROR,
-                    mode: Absolute,
-                    cycles: 6,
-                }),
-                0x7E => Ok(DecodedOpcode {
-                    instruction: ROR,
-                    mode: IndexedAbsoluteX,
-                    cycles: 7,
-                }),
-                0x40 => Ok(DecodedOpcode {
-                    instruction: RTI,
-                    mode: Implied,
-                    cycles: 6,
-                }),
-                0x60 => Ok(DecodedOpcode {
-                    instruction: RTS,
-                    mode: Implied,
-                    cycles: 6,
-                }),
-                0xE9 => Ok(DecodedOpcode {
-                    instruction: SBC,
-                    mode: Immediate,
-                    cycles: 2,
-                }),
-                0xE5 => Ok(DecodedOpcode {
-                    instruction: SBC,
-                    mode: ZeroPage,
-                    cycles: 3,
-                }),
-                0xF5 => Ok(DecodedOpcode {
-                    instruction: SBC,
-                    mode: IndexedZeroPageX,
-                    cycles: 4,
-                }),
-                0xED => Ok(DecodedOpcode {
-                    instruction: SBC,
-                    mode: Absolute,
-                    cycles: 4,
-                }),
-                0xFD => Ok(DecodedOpcode {
-                    instruction: SBC,
-                    mode: IndexedAbsoluteX,
-                    cycles: 4,
-                }),
-                0xF9 => Ok(DecodedOpcode {
-                    instruction: SBC,
-                    mode: IndexedAbsoluteY,
-                    cycles: 4,
-                }),
-                0xE1 => Ok(DecodedOpcode {
-                    instruction: SBC,
-                    mode: IndexedIndirect,
-                    cycles: 6,
-                }),
-                0xF1 => Ok(DecodedOpcode {
-                    instruction: SBC,
-                    mode: IndirectIndexed,
-                    cycles: 5,
-                }),
-                0x38 => Ok(DecodedOpcode {
-                    instruction: SEC,
-                    mode: Implied,
-                    cycles: 2,
-                }),
-                0xF8 => Ok(DecodedOpcode {
-                    instruction: SED,
-                    mode: Implied,
-                    cycles: 2,
-                }),
-                0x78 => Ok(DecodedOpcode {
-                    instruction: SEI,
-                    mode: Implied,
-                    cycles: 2,
-                }),
-                0x85 => Ok(DecodedOpcode {
-                    instruction: STA,
-                    mode: ZeroPage,
-                    cycles: 3,
-                }),
-                0x95 => Ok(DecodedOpcode {
-                    instruction: STA,
-                    mode: IndexedZeroPageX,
-                    cycles: 4,
-                }),
-                0x8D => Ok(DecodedOpcode {
-                    instruction: STA,
-                    mode: Absolute,
-                    cycles: 4,
-                }),
-                0x9D => Ok(DecodedOpcode {
-                    instruction: STA,
-                    mode: IndexedAbsoluteX,
-                    cycles: 5,
-                }),
-                0x99 => Ok(DecodedOpcode {
-                    instruction: STA,
-                    mode: IndexedAbsoluteY,
-                    cycles: 5,
-                }),
-                0x81 => Ok(DecodedOpcode {
-                    instruction: STA,
-                    mode: IndexedIndirect,
-                    cycles: 6,
-                }),
-                0x91 => Ok(DecodedOpcode {
-                    instruction: STA,
-                    mode: IndirectIndexed,
-                    cycles: 6,
-                }),
-                0x86 => Ok(DecodedOpcode {
-                    instruction: STX,
-                    mode: ZeroPage,
-                    cycles: 3,
-                }),
-                0x96 => Ok(DecodedOpcode {
-                    instruction: STX,
-                    mode: IndexedZeroPageY,
-                    cycles: 4,
-                }),
-                0x8E => Ok(DecodedOpcode {
-                    instruction: STX,
-                    mode: Absolute,
-                    cycles: 4,
-                }),
-                0x84 => Ok(DecodedOpcode {
-                    instruction: STY,
-                    mode: ZeroPage,
-                    cycles: 3,
-                }),
-                0x94 => Ok(DecodedOpcode {
-                    instruction: STY,
-                    mode: IndexedZeroPageX,
-                    cycles: 4,
-                }),
-                0x8C => Ok(DecodedOpcode {
-                    instruction: STY,
-                    mode: Absolute,
-                    cycles: 4,
-                }),
-                0xAA => Ok(DecodedOpcode {
-                    instruction: TAX,
-                    mode: Implied,
-                    cycles: 2,
-                }),
-                0xA8 => Ok(DecodedOpcode {
-                    instruction: TAY,
-                    mode: Implied,
-                    cycles: 2,
-                }),
-                0xBA => Ok(DecodedOpcode {
-                    instruction: TSX,
-                    mode: Implied,
-                    cycles: 2,
-                }),
-                0x8A => Ok(DecodedOpcode {
-                    instruction: TXA,
-                    mode: Implied,
-                    cycles: 2,
-                }),
-                0x9A => Ok(DecodedOpcode {
-                    instruction: TXS,
-                    mode: Implied,
-                    cycles: 2,
-                }),
-                0x98 => Ok(DecodedOpcode {
-                    instruction: TYA,
-                    mode: Implied,
-                    cycles: 2,
-                }),
-                // Below are all invalid opcodes. Need to be implemented in order to support all games and to pass all tests
-                0x04 => Ok(DecodedOpcode {
-                    instruction: NOP,
-                    mode: ZeroPage,
-                    cycles: 3,
-                }),
-                0x44 => Ok(DecodedOpcode {
-                    instruction: NOP,
-                    mode: ZeroPage,
-                    cycles: 3,
-                }),
-                0x64 => Ok(DecodedOpcode {
-                    instruction: NOP,
-                    mode: ZeroPage,
-                    cycles: 3,
-                }),
-                0x0C => Ok(DecodedOpcode {
-                    instruction: NOP,
-                    mode: Absolute,
-                    cycles: 4,
-                }),
-                0x14 => Ok(DecodedOpcode {
-                    instruction: NOP,
-                    mode: IndexedZeroPageX,
-                    cycles: 4,
-                }),
-                0x34 => Ok(DecodedOpcode {
-                    instruction: NOP,
-                    mode: IndexedZeroPageX,
-                    cycles: 4,
-                }),
-                0x54 => Ok(DecodedOpcode {
-                    instruction: NOP,
-                    mode: IndexedZeroPageX,
-                    cycles: 4,
-                }),
-                0x74 => Ok(DecodedOpcode {
-                    instruction: NOP,
-                    mode: IndexedZeroPageX,
-                    cycles: 4,
-                }),
-                0xD4 => Ok(DecodedOpcode {
-                    instruction: NOP,
-                    mode: IndexedZeroPageX,
-                    cycles: 4,
-                }),
-                0xF4 => Ok(DecodedOpcode {
-                    instruction: NOP,
-                    mode: IndexedZeroPageX,
-                    cycles: 4,
-                }),
-                0x1A => Ok(DecodedOpcode {
-                    instruction: NOP,
-                    mode: Implied,
-                    cycles: 2,
-                }),
-                0x3A => Ok(DecodedOpcode {
-                    instruction: NOP,
-                    mode: Implied,
-                    cycles: 2,
-                }),
-                0x5A => Ok(DecodedOpcode {
-                    instruction: NOP,
-                    mode: Implied,
-                    cycles: 2,
-                }),
-                0x7A => Ok(DecodedOpcode {
-                    instruction: NOP,
-                    mode: Implied,
-                    cycles: 2,
-                }),
-                0xDA => Ok(DecodedOpcode {
-                    instruction: NOP,
-                    mode: Implied,
-                    cycles: 2,
-                }),
-                0xFA => Ok(DecodedOpcode {
-                    instruction: NOP,
-                    mode: Implied,
-                    cycles: 2,
-                }),
-                0x80 => Ok(DecodedOpcode {
-                    instruction: NOP,
-                    mode: Immediate,
-                    cycles: 2,
-                }),
-                0x1C => Ok(DecodedOpcode {
-                    instruction: NOP,
-                    mode: IndexedAbsoluteX,
-                    cycles: 4,
-                }),
-                0x3C => Ok(DecodedOpcode {
-                    instruction: NOP,
-                    mode: IndexedAbsoluteX,
-                    cycles: 4,
-                }),
-                0x5C => Ok(DecodedOpcode {
-                    instruction: NOP,
-                    mode: IndexedAbsoluteX,
-                    cycles: 4,
-                }),
-                0x7C => Ok(DecodedOpcode {
-                    instruction: NOP,
-                    mode: IndexedAbsoluteX,
-                    cycles: 4,
-                }),
-                0xDC => Ok(DecodedOpcode {
-                    instruction: NOP,
-                    mode: IndexedAbsoluteX,
-                    cycles: 4,
-                }),
-                0xFC => Ok(DecodedOpcode {
-                    instruction: NOP,
-                    mode: IndexedAbsoluteX,
-                    cycles: 4,
-                }),
-                // Below are the non-NOP illegal aka unofficial opcodes
-                // REMINDER:
-                //     IndexedIndirect is izx is Indirect x is ($00,X)
-                //     IndirectIndexed is izy is Indirect y is ($00),Y
-                0xA3 => Ok(DecodedOpcode {
-                    instruction: LAX,
-                    mode: IndexedIndirect,
-                    cycles: 6,
-                }),
-                0xA7 => Ok(DecodedOpcode {
-                    instruction: LAX,
-                    mode: ZeroPage,
-                    cycles: 3,
-                }),
-                0xAF => Ok(DecodedOpcode {
-                    instruction: LAX,
-                    mode: Absolute,
-                    cycles: 4,
-                }),
-                0xB3 => Ok(DecodedOpcode {
-                    instruction: LAX,
-                    mode: IndirectIndexed,
-                    cycles: 5,
-                }),
-                0xB7 => Ok(DecodedOpcode {
-                    instruction: LAX,
-                    mode: IndexedZeroPageY,
-                    cycles: 4,
-                }),
-                0xBF => Ok(DecodedOpcode {
-                    instruction: LAX,
-                    mode: IndexedAbsoluteY,
-                    cycles: 4,
-                }),
-
-                0x83 => Ok(DecodedOpcode {
-                    instruction: SAX,
-                    mode: IndexedIndirect,
-                    cycles: 6,
-                }),
-                0x87 => Ok(DecodedOpcode {
-                    instruction: SAX,
-                    mode: ZeroPage,
-                    cycles: 3,
-                }),
-                0x8F => Ok(DecodedOpcode {
-                    instruction: SAX,
-                    mode: Absolute,
-                    cycles: 4,
-                }),
-                0x97 => Ok(DecodedOpcode {
-                    instruction: SAX,
-                    mode: IndexedZeroPageY,
-                    cycles: 4,
-                }),
-
-                0xEB => Ok(DecodedOpcode {
-                    instruction: SBC,
-                    mode: Immediate,
-                    cycles: 2,
-                }),
-
-                0xC3 => Ok(DecodedOpcode {
-                    instruction: DCP,
-                    mode: IndexedIndirect,
-                    cycles: 8,
-                }),
-                0xC7 => Ok(DecodedOpcode {
-                    instruction: DCP,
-                    mode: ZeroPage,
-                    cycles: 5,
-                }),
-                0xCF => Ok(DecodedOpcode {
-                    instruction: DCP,
-                    mode: Absolute,
-                    cycles: 6,
-                }),
-                0xD3 => Ok(DecodedOpcode {
-                    instruction: DCP,
-                    mode: IndirectIndexed,
-                    cycles: 8,
-                }),
-                0xD7 => Ok(DecodedOpcode {
-                    instruction: DCP,
-                    mode: IndexedZeroPageX,
-                    cycles: 6,
-                }),
-                0xDB => Ok(DecodedOpcode {
-                    instruction: DCP,
-                    mode: IndexedAbsoluteY,
-                    cycles: 7,
-                }),
-                0xDF => Ok(DecodedOpcode {
-                    instruction: DCP,
-                    mode: IndexedAbsoluteX,
-                    cycles: 7,
-                }),
-
-                0xE3 => Ok(DecodedOpcode {
-                    instruction: ISC,
-                    mode: IndexedIndirect,
-                    cycles: 8,
-                }),
-                0xE7 => Ok(DecodedOpcode {
-                    instruction: ISC,
-                    mode: ZeroPage,
-                    cycles: 5,
-                }),
-                0xEF => Ok(DecodedOpcode {
-                    instruction: ISC,
-                    mode: Absolute,
-                    cycles: 6,
-                }),
-                0xF3 => Ok(DecodedOpcode {
-                    instruction: ISC,
-                    mode: IndirectIndexed,
-                    cycles: 8,
-                }),
-                0xF7 => Ok(DecodedOpcode {
-                    instruction: ISC,
-                    mode: IndexedZeroPageX,
-                    cycles: 6,
-                }),
-                0xFB => Ok(DecodedOpcode {
-                    instruction: ISC,
-                    mode: IndexedAbsoluteY,
-                    cycles: 7,
-                }),
-                0xFF => Ok(DecodedOpcode {
-                    instruction: ISC,
-                    mode: IndexedAbsoluteX,
-                    cycles: 7,
-                }),
-
-                0x03 => Ok(DecodedOpcode {
-                    instruction: SLO,
-                    mode: IndexedIndirect,
-                    cycles: 8,
-                }),
-                0x07 => Ok(DecodedOpcode {
-                    instruction: SLO,
-                    mode: ZeroPage,
-                    cycles: 5,
-                }),
-                0x0F => Ok(DecodedOpcode {
-                    instruction: SLO,
-                    mode: Absolute,
-                    cycles: 6,
-                }),
-                0x13 => Ok(DecodedOpcode {
-                    instruction: SLO,
-                    mode: IndirectIndexed,
-                    cycles: 8,
-                }),
-                0x17 => Ok(DecodedOpcode {
-                    instruction: SLO,
-                    mode: IndexedZeroPageX,
-                    cycles: 6,
-                }),
-                0x1B => Ok(DecodedOpcode {
-                    instruction: SLO,
-                    mode: IndexedAbsoluteY,
-                    cycles: 7,
-                }),
-                0x1F => Ok(DecodedOpcode {
-                    instruction: SLO,
-                    mode: IndexedAbsoluteX,
-                    cycles: 7,
-                }),
-
-                0x23 => Ok(DecodedOpcode {
-                    instruction: RLA,
-                    mode: IndexedIndirect,
-                    cycles: 8,
-                }),
-                0x27 => Ok(DecodedOpcode {
-                    instruction: RLA,
-                    mode: ZeroPage,
-                    cycles: 5,
-                }),
-                0x2F => Ok(DecodedOpcode {
-                    instruction: RLA,
-                    mode: Absolute,
-                    cycles: 6,
-                }),
-                0x33 => Ok(DecodedOpcode {
-                    instruction: RLA,
-                    mode: IndirectIndexed,
-                    cycles: 8,
-                }),
-                0x37 => Ok(DecodedOpcode {
-                    instruction: RLA,
-                    mode: IndexedZeroPageX,
-                    cycles: 6,
-                }),
-                0x3B => Ok(DecodedOpcode {
-                    instruction: RLA,
-                    mode: IndexedAbsoluteY,
-                    cycles: 7,
-                }),
-                0x3F => Ok(DecodedOpcode {
-                    instruction: RLA,
-                    mode: IndexedAbsoluteX,
-                    cycles: 7,
-                }),
-
-                0x43 => Ok(DecodedOpcode {
-                    instruction: SRE,
-                    mode: IndexedIndirect,
-                    cycles: 8,
-                }),
-                0x47 => Ok(DecodedOpcode {
-                    instruction: SRE,
-                    mode: ZeroPage,
-                    cycles: 5,
-                }),
-                0x4F => Ok(DecodedOpcode {
-                    instruction: SRE,
-                    mode: Absolute,
-                    cycles: 6,
-                }),
-                0x53 => Ok(DecodedOpcode {
-                    instruction: SRE,
-                    mode: IndirectIndexed,
-                    cycles: 8,
-                }),
-                0x57 => Ok(DecodedOpcode {
-                    instruction: SRE,
-                    mode: IndexedZeroPageX,
-                    cycles: 6,
-                }),
-                0x5B => Ok(DecodedOpcode {
-                    instruction: SRE,
-                    mode: IndexedAbsoluteY,
-                    cycles: 7,
-                }),
-                0x5F => Ok(DecodedOpcode {
-                    instruction: SRE,
-                    mode: IndexedAbsoluteX,
-                    cycles: 7,
-                }),
-
-                0x63 => Ok(DecodedOpcode {
-                    instruction: RRA,
-                    mode: IndexedIndirect,
-                    cycles: 8,
-                }),
-                0x67 => Ok(DecodedOpcode {
-                    instruction: RRA,
-                    mode: ZeroPage,
-                    cycles: 5,
-                }),
-                0x6F => Ok(DecodedOpcode {
-                    instruction: RRA,
-                    mode: Absolute,
-                    cycles: 6,
-                }),
-                0x73 => Ok(DecodedOpcode {
-                    instruction: RRA,
-                    mode: IndirectIndexed,
-                    cycles: 8,
-                }),
-                0x77 => Ok(DecodedOpcode {
-                    instruction: RRA,
-                    mode: IndexedZeroPageX,
-                    cycles: 6,
-                }),
-                0x7B => Ok(DecodedOpcode {
-                    instruction: RRA,
-                    mode: IndexedAbsoluteY,
-                    cycles: 7,
-                }),
-                0x7F => Ok(DecodedOpcode {
-                    instruction: RRA,
-                    mode: IndexedAbsoluteX,
-                    cycles: 7,
-                }),
-
-                _ => Err(DecodeError::IllegalUnimplementedOpcode { opcode: *self }),
+        match OPCODE_TABLE[*self as usize] {
+            DecodedOpcode { instruction: Instruction::UNK, .. } => {
+                Err(DecodeError::IllegalUnimplementedOpcode { opcode: *self })
             }
+            decoded_opcode => Ok(decoded_opcode),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AssembleError {
+    /// The instruction doesn't have an opcode with that addressing mode.
+    InvalidAddressingMode { instruction: Instruction, mode: AddressingMode },
+}
+
+/// The inverse of `decode`. Encodes the opcode for `instruction` with `mode` followed by the
+/// operand bytes `operand` needs for that mode, low byte first. Relative operands are the signed
+/// offset in the low byte, not the destination.
+///
+/// Where an instruction has an official and unofficial opcode for the same addressing mode, e.g.
+/// SBC #$LL, the official one is used.
+///
+/// # Example
+///
+/// ```
+/// use rust_webpack_template::cpu::opcode::*;
+///
+/// assert_eq!(assemble(Instruction::LDA, AddressingMode::Immediate, 0x42), Ok(vec![0xA9, 0x42]));
+/// ```
+pub fn assemble(instruction: Instruction, mode: AddressingMode, operand: u16) -> Result<Vec<u8>, AssembleError> {
+    let matches = |decoded_opcode: &&DecodedOpcode| {
+        decoded_opcode.instruction == instruction && decoded_opcode.mode == mode
+    };
+    let opcode = OPCODE_TABLE.iter().position(|decoded_opcode| matches(&decoded_opcode) && decoded_opcode.is_official)
+        .or_else(|| OPCODE_TABLE.iter().position(|decoded_opcode| matches(&decoded_opcode)))
+        .filter(|_| instruction != Instruction::UNK)
+        .ok_or(AssembleError::InvalidAddressingMode { instruction, mode })?;
+
+    let mut bytes = Vec::with_capacity(instruction_length(mode));
+    bytes.push(opcode as Opcode);
+    match instruction_length(mode) {
+        2 => bytes.push(operand as u8),
+        3 => bytes.extend_from_slice(&operand.to_le_bytes()),
+        _ => (),
+    }
+
+    Ok(bytes)
+}
+
+/// Placeholder in the opcode table for bytes that aren't a known opcode.
+const UNDEFINED_OPCODE: DecodedOpcode = DecodedOpcode {
+    instruction: Instruction::UNK,
+    mode: AddressingMode::Implied,
+    cycles: 0,
+    is_official: false,
+};
+
+/// Every opcode decoded ahead of time. Decoding happens for every instruction so this makes it a
+/// single array index instead of walking through the match below.
+static OPCODE_TABLE: [DecodedOpcode; 256] = build_opcode_table();
+
+const fn build_opcode_table() -> [DecodedOpcode; 256] {
+    let mut table = [UNDEFINED_OPCODE; 256];
+    let mut opcode = 0;
+    while opcode < table.len() {
+        table[opcode] = match decode_opcode(opcode as Opcode) {
+            Some(decoded_opcode) => decoded_opcode,
+            None => UNDEFINED_OPCODE,
+        };
+        opcode += 1;
+    }
+
+    table
+}
+
+/// The source of truth for the opcode table. Only used to build OPCODE_TABLE.
+const fn decode_opcode(opcode: Opcode) -> Option<DecodedOpcode> {
+    /*
+    Below is taken from: http://nesdev.com/6502_cpu.txt See doc for number of cycles.
+    6510 Instructions by Addressing Modes
+    off- ++++++++++ Positive ++++++++++  ---------- Negative ----------
+    set  00      20      40      60      80      a0      c0      e0      mode
+    +00  BRK     JSR     RTI     RTS     NOP*    LDY     CPY     CPX     Impl/immed
+    +01  ORA     AND     EOR     ADC     STA     LDA     CMP     SBC     (indir,x)
+    +02   t       t       t       t      NOP*t   LDX     NOP*t   NOP*t     ? /immed
+    +03  SLO*    RLA*    SRE*    RRA*    SAX*    LAX*    DCP*    ISB*    (indir,x)
+    +04  NOP*    BIT     NOP*    NOP*    STY     LDY     CPY     CPX     Zeropage
+    +05  ORA     AND     EOR     ADC     STA     LDA     CMP     SBC     Zeropage
+    +06  ASL     ROL     LSR     ROR     STX     LDX     DEC     INC     Zeropage
+    +07  SLO*    RLA*    SRE*    RRA*    SAX*    LAX*    DCP*    ISB*    Zeropage
+    +08  PHP     PLP     PHA     PLA     DEY     TAY     INY     INX     Implied
+    +09  ORA     AND     EOR     ADC     NOP*    LDA     CMP     SBC     Immediate
+    +0a  ASL     ROL     LSR     ROR     TXA     TAX     DEX     NOP     Accu/impl
+    +0b  ANC**   ANC**   ASR**   ARR**   ANE**   LXA**   SBX**   SBC*    Immediate
+    +0c  NOP*    BIT     JMP     JMP ()  STY     LDY     CPY     CPX     Absolute
+    +0d  ORA     AND     EOR     ADC     STA     LDA     CMP     SBC     Absolute
+    +0e  ASL     ROL     LSR     ROR     STX     LDX     DEC     INC     Absolute
+    +0f  SLO*    RLA*    SRE*    RRA*    SAX*    LAX*    DCP*    ISB*    Absolute
+    +10  BPL     BMI     BVC     BVS     BCC     BCS     BNE     BEQ     Relative
+    +11  ORA     AND     EOR     ADC     STA     LDA     CMP     SBC     (indir),y
+    +12   t       t       t       t       t       t       t       t         ?
+    +13  SLO*    RLA*    SRE*    RRA*    SHA**   LAX*    DCP*    ISB*    (indir),y
+    +14  NOP*    NOP*    NOP*    NOP*    STY     LDY     NOP*    NOP*    Zeropage,x
+    +15  ORA     AND     EOR     ADC     STA     LDA     CMP     SBC     Zeropage,x
+    +16  ASL     ROL     LSR     ROR     STX  y) LDX  y) DEC     INC     Zeropage,x
+    +17  SLO*    RLA*    SRE*    RRA*    SAX* y) LAX* y) DCP*    ISB*    Zeropage,x
+    +18  CLC     SEC     CLI     SEI     TYA     CLV     CLD     SED     Implied
+    +19  ORA     AND     EOR     ADC     STA     LDA     CMP     SBC     Absolute,y
+    +1a  NOP*    NOP*    NOP*    NOP*    TXS     TSX     NOP*    NOP*    Implied
+    +1b  SLO*    RLA*    SRE*    RRA*    SHS**   LAS**   DCP*    ISB*    Absolute,y
+    +1c  NOP*    NOP*    NOP*    NOP*    SHY**   LDY     NOP*    NOP*    Absolute,x
+    +1d  ORA     AND     EOR     ADC     STA     LDA     CMP     SBC     Absolute,x
+    +1e  ASL     ROL     LSR     ROR     SHX**y) LDX  y) DEC     INC     Absolute,x
+    +1f  SLO*    RLA*    SRE*    RRA*    SHA**y) LAX* y) DCP*    ISB*    Absolute,x
+    */
+    use self::AddressingMode::*;
+    use self::Instruction::*;
+    // This should be generated by a macro of some sort. Right now it's generated by my parser
+    // Python script which I forgot where I put it.
+    match opcode {
+        0x69 => Some(DecodedOpcode {
+            instruction: ADC,
+            mode: Immediate,
+            cycles: 2,
+            is_official: true,
+        }),
+        0x65 => Some(DecodedOpcode {
+            instruction: ADC,
+            mode: ZeroPage,
+            cycles: 3,
+            is_official: true,
+        }),
+        0x75 => Some(DecodedOpcode {
+            instruction: ADC,
+            mode: IndexedZeroPageX,
+            cycles: 4,
+            is_official: true,
+        }),
+        0x6D => Some(DecodedOpcode {
+            instruction: ADC,
+            mode: Absolute,
+            cycles: 4,
+            is_official: true,
+        }),
+        0x7D => Some(DecodedOpcode {
+            instruction: ADC,
+            mode: IndexedAbsoluteX,
+            cycles: 4,
+            is_official: true,
+        }),
+        0x79 => Some(DecodedOpcode {
+            instruction: ADC,
+            mode: IndexedAbsoluteY,
+            cycles: 4,
+            is_official: true,
+        }),
+        0x61 => Some(DecodedOpcode {
+            instruction: ADC,
+            mode: IndexedIndirect,
+            cycles: 6,
+            is_official: true,
+        }),
+        0x71 => Some(DecodedOpcode {
+            instruction: ADC,
+            mode: IndirectIndexed,
+            cycles: 5,
+            is_official: true,
+        }),
+        0x29 => Some(DecodedOpcode {
+            instruction: AND,
+            mode: Immediate,
+            cycles: 2,
+            is_official: true,
+        }),
+        0x25 => Some(DecodedOpcode {
+            instruction: AND,
+            mode: ZeroPage,
+            cycles: 3,
+            is_official: true,
+        }),
+        0x35 => Some(DecodedOpcode {
+            instruction: AND,
+            mode: IndexedZeroPageX,
+            cycles: 4,
+            is_official: true,
+        }),
+        0x2D => Some(DecodedOpcode {
+            instruction: AND,
+            mode: Absolute,
+            cycles: 4,
+            is_official: true,
+        }),
+        0x3D => Some(DecodedOpcode {
+            instruction: AND,
+            mode: IndexedAbsoluteX,
+            cycles: 4,
+            is_official: true,
+        }),
+        0x39 => Some(DecodedOpcode {
+            instruction: AND,
+            mode: IndexedAbsoluteY,
+            cycles: 4,
+            is_official: true,
+        }),
+        0x21 => Some(DecodedOpcode {
+            instruction: AND,
+            mode: IndexedIndirect,
+            cycles: 6,
+            is_official: true,
+        }),
+        0x31 => Some(DecodedOpcode {
+            instruction: AND,
+            mode: IndirectIndexed,
+            cycles: 5,
+            is_official: true,
+        }),
+        0x0A => Some(DecodedOpcode {
+            instruction: ASL,
+            mode: Accumulator,
+            cycles: 2,
+            is_official: true,
+        }),
+        0x06 => Some(DecodedOpcode {
+            instruction: ASL,
+            mode: ZeroPage,
+            cycles: 5,
+            is_official: true,
+        }),
+        0x16 => Some(DecodedOpcode {
+            instruction: ASL,
+            mode: IndexedZeroPageX,
+            cycles: 6,
+            is_official: true,
+        }),
+        0x0E => Some(DecodedOpcode {
+            instruction: ASL,
+            mode: Absolute,
+            cycles: 6,
+            is_official: true,
+        }),
+        0x1E => Some(DecodedOpcode {
+            instruction: ASL,
+            mode: IndexedAbsoluteX,
+            cycles: 7,
+            is_official: true,
+        }),
+        0x90 => Some(DecodedOpcode {
+            instruction: BCC,
+            mode: Relative,
+            cycles: 2,
+            is_official: true,
+        }),
+        0xB0 => Some(DecodedOpcode {
+            instruction: BCS,
+            mode: Relative,
+            cycles: 2,
+            is_official: true,
+        }),
+        0xF0 => Some(DecodedOpcode {
+            instruction: BEQ,
+            mode: Relative,
+            cycles: 2,
+            is_official: true,
+        }),
+        0x24 => Some(DecodedOpcode {
+            instruction: BIT,
+            mode: ZeroPage,
+            cycles: 3,
+            is_official: true,
+        }),
+        0x2C => Some(DecodedOpcode {
+            instruction: BIT,
+            mode: Absolute,
+            cycles: 4,
+            is_official: true,
+        }),
+        0x30 => Some(DecodedOpcode {
+            instruction: BMI,
+            mode: Relative,
+            cycles: 2,
+            is_official: true,
+        }),
+        0xD0 => Some(DecodedOpcode {
+            instruction: BNE,
+            mode: Relative,
+            cycles: 2,
+            is_official: true,
+        }),
+        0x10 => Some(DecodedOpcode {
+            instruction: BPL,
+            mode: Relative,
+            cycles: 2,
+            is_official: true,
+        }),
+        0x00 => Some(DecodedOpcode {
+            instruction: BRK,
+            mode: Implied,
+            cycles: 7,
+            is_official: true,
+        }),
+        0x50 => Some(DecodedOpcode {
+            instruction: BVC,
+            mode: Relative,
+            cycles: 2,
+            is_official: true,
+        }),
+        0x70 => Some(DecodedOpcode {
+            instruction: BVS,
+            mode: Relative,
+            cycles: 2,
+            is_official: true,
+        }),
+        0x18 => Some(DecodedOpcode {
+            instruction: CLC,
+            mode: Implied,
+            cycles: 2,
+            is_official: true,
+        }),
+        0xD8 => Some(DecodedOpcode {
+            instruction: CLD,
+            mode: Implied,
+            cycles: 2,
+            is_official: true,
+        }),
+        0x58 => Some(DecodedOpcode {
+            instruction: CLI,
+            mode: Implied,
+            cycles: 2,
+            is_official: true,
+        }),
+        0xB8 => Some(DecodedOpcode {
+            instruction: CLV,
+            mode: Implied,
+            cycles: 2,
+            is_official: true,
+        }),
+        0xC9 => Some(DecodedOpcode {
+            instruction: CMP,
+            mode: Immediate,
+            cycles: 2,
+            is_official: true,
+        }),
+        0xC5 => Some(DecodedOpcode {
+            instruction: CMP,
+            mode: ZeroPage,
+            cycles: 3,
+            is_official: true,
+        }),
+        0xD5 => Some(DecodedOpcode {
+            instruction: CMP,
+            mode: IndexedZeroPageX,
+            cycles: 4,
+            is_official: true,
+        }),
+        0xCD => Some(DecodedOpcode {
+            instruction: CMP,
+            mode: Absolute,
+            cycles: 4,
+            is_official: true,
+        }),
+        0xDD => Some(DecodedOpcode {
+            instruction: CMP,
+            mode: IndexedAbsoluteX,
+            cycles: 4,
+            is_official: true,
+        }),
+        0xD9 => Some(DecodedOpcode {
+            instruction: CMP,
+            mode: IndexedAbsoluteY,
+            cycles: 4,
+            is_official: true,
+        }),
+        0xC1 => Some(DecodedOpcode {
+            instruction: CMP,
+            mode: IndexedIndirect,
+            cycles: 6,
+            is_official: true,
+        }),
+        0xD1 => Some(DecodedOpcode {
+            instruction: CMP,
+            mode: IndirectIndexed,
+            cycles: 5,
+            is_official: true,
+        }),
+        0xE0 => Some(DecodedOpcode {
+            instruction: CPX,
+            mode: Immediate,
+            cycles: 2,
+            is_official: true,
+        }),
+        0xE4 => Some(DecodedOpcode {
+            instruction: CPX,
+            mode: ZeroPage,
+            cycles: 3,
+            is_official: true,
+        }),
+        0xEC => Some(DecodedOpcode {
+            instruction: CPX,
+            mode: Absolute,
+            cycles: 4,
+            is_official: true,
+        }),
+        0xC0 => Some(DecodedOpcode {
+            instruction: CPY,
+            mode: Immediate,
+            cycles: 2,
+            is_official: true,
+        }),
+        0xC4 => Some(DecodedOpcode {
+            instruction: CPY,
+            mode: ZeroPage,
+            cycles: 3,
+            is_official: true,
+        }),
+        0xCC => Some(DecodedOpcode {
+            instruction: CPY,
+            mode: Absolute,
+            cycles: 4,
+            is_official: true,
+        }),
+        0xC6 => Some(DecodedOpcode {
+            instruction: DEC,
+            mode: ZeroPage,
+            cycles: 5,
+            is_official: true,
+        }),
+        0xD6 => Some(DecodedOpcode {
+            instruction: DEC,
+            mode: IndexedZeroPageX,
+            cycles: 6,
+            is_official: true,
+        }),
+        0xCE => Some(DecodedOpcode {
+            instruction: DEC,
+            mode: Absolute,
+            cycles: 6,
+            is_official: true,
+        }),
+        0xDE => Some(DecodedOpcode {
+            instruction: DEC,
+            mode: IndexedAbsoluteX,
+            cycles: 7,
+            is_official: true,
+        }),
+        0xCA => Some(DecodedOpcode {
+            instruction: DEX,
+            mode: Implied,
+            cycles: 2,
+            is_official: true,
+        }),
+        0x88 => Some(DecodedOpcode {
+            instruction: DEY,
+            mode: Implied,
+            cycles: 2,
+            is_official: true,
+        }),
+        0x49 => Some(DecodedOpcode {
+            instruction: EOR,
+            mode: Immediate,
+            cycles: 2,
+            is_official: true,
+        }),
+        0x45 => Some(DecodedOpcode {
+            instruction: EOR,
+            mode: ZeroPage,
+            cycles: 3,
+            is_official: true,
+        }),
+        0x55 => Some(DecodedOpcode {
+            instruction: EOR,
+            mode: IndexedZeroPageX,
+            cycles: 4,
+            is_official: true,
+        }),
+        0x4D => Some(DecodedOpcode {
+            instruction: EOR,
+            mode: Absolute,
+            cycles: 4,
+            is_official: true,
+        }),
+        0x5D => Some(DecodedOpcode {
+            instruction: EOR,
+            mode: IndexedAbsoluteX,
+            cycles: 4,
+            is_official: true,
+        }),
+        0x59 => Some(DecodedOpcode {
+            instruction: EOR,
+            mode: IndexedAbsoluteY,
+            cycles: 4,
+            is_official: true,
+        }),
+        0x41 => Some(DecodedOpcode {
+            instruction: EOR,
+            mode: IndexedIndirect,
+            cycles: 6,
+            is_official: true,
+        }),
+        0x51 => Some(DecodedOpcode {
+            instruction: EOR,
+            mode: IndirectIndexed,
+            cycles: 5,
+            is_official: true,
+        }),
+        0xE6 => Some(DecodedOpcode {
+            instruction: INC,
+            mode: ZeroPage,
+            cycles: 5,
+            is_official: true,
+        }),
+        0xF6 => Some(DecodedOpcode {
+            instruction: INC,
+            mode: IndexedZeroPageX,
+            cycles: 6,
+            is_official: true,
+        }),
+        0xEE => Some(DecodedOpcode {
+            instruction: INC,
+            mode: Absolute,
+            cycles: 6,
+            is_official: true,
+        }),
+        0xFE => Some(DecodedOpcode {
+            instruction: INC,
+            mode: IndexedAbsoluteX,
+            cycles: 7,
+            is_official: true,
+        }),
+        0xE8 => Some(DecodedOpcode {
+            instruction: INX,
+            mode: Implied,
+            cycles: 2,
+            is_official: true,
+        }),
+        0xC8 => Some(DecodedOpcode {
+            instruction: INY,
+            mode: Implied,
+            cycles: 2,
+            is_official: true,
+        }),
+        0x4C => Some(DecodedOpcode {
+            instruction: JMP,
+            mode: Absolute,
+            cycles: 3,
+            is_official: true,
+        }),
+        0x6C => Some(DecodedOpcode {
+            instruction: JMP,
+            mode: Indirect,
+            cycles: 5,
+            is_official: true,
+        }),
+        0x20 => Some(DecodedOpcode {
+            instruction: JSR,
+            mode: Absolute,
+            cycles: 6,
+            is_official: true,
+        }),
+        0xA9 => Some(DecodedOpcode {
+            instruction: LDA,
+            mode: Immediate,
+            cycles: 2,
+            is_official: true,
+        }),
+        0xA5 => Some(DecodedOpcode {
+            instruction: LDA,
+            mode: ZeroPage,
+            cycles: 3,
+            is_official: true,
+        }),
+        0xB5 => Some(DecodedOpcode {
+            instruction: LDA,
+            mode: IndexedZeroPageX,
+            cycles: 4,
+            is_official: true,
+        }),
+        0xAD => Some(DecodedOpcode {
+            instruction: LDA,
+            mode: Absolute,
+            cycles: 4,
+            is_official: true,
+        }),
+        0xBD => Some(DecodedOpcode {
+            instruction: LDA,
+            mode: IndexedAbsoluteX,
+            cycles: 4,
+            is_official: true,
+        }),
+        0xB9 => Some(DecodedOpcode {
+            instruction: LDA,
+            mode: IndexedAbsoluteY,
+            cycles: 4,
+            is_official: true,
+        }),
+        0xA1 => Some(DecodedOpcode {
+            instruction: LDA,
+            mode: IndexedIndirect,
+            cycles: 6,
+            is_official: true,
+        }),
+        0xB1 => Some(DecodedOpcode {
+            instruction: LDA,
+            mode: IndirectIndexed,
+            cycles: 5,
+            is_official: true,
+        }),
+        0xA2 => Some(DecodedOpcode {
+            instruction: LDX,
+            mode: Immediate,
+            cycles: 2,
+            is_official: true,
+        }),
+        0xA6 => Some(DecodedOpcode {
+            instruction: LDX,
+            mode: ZeroPage,
+            cycles: 3,
+            is_official: true,
+        }),
+        0xB6 => Some(DecodedOpcode {
+            instruction: LDX,
+            mode: IndexedZeroPageY,
+            cycles: 4,
+            is_official: true,
+        }),
+        0xAE => Some(DecodedOpcode {
+            instruction: LDX,
+            mode: Absolute,
+            cycles: 4,
+            is_official: true,
+        }),
+        0xBE => Some(DecodedOpcode {
+            instruction: LDX,
+            mode: IndexedAbsoluteY,
+            cycles: 4,
+            is_official: true,
+        }),
+        0xA0 => Some(DecodedOpcode {
+            instruction: LDY,
+            mode: Immediate,
+            cycles: 2,
+            is_official: true,
+        }),
+        0xA4 => Some(DecodedOpcode {
+            instruction: LDY,
+            mode: ZeroPage,
+            cycles: 3,
+            is_official: true,
+        }),
+        0xB4 => Some(DecodedOpcode {
+            instruction: LDY,
+            mode: IndexedZeroPageX,
+            cycles: 4,
+            is_official: true,
+        }),
+        0xAC => Some(DecodedOpcode {
+            instruction: LDY,
+            mode: Absolute,
+            cycles: 4,
+            is_official: true,
+        }),
+        0xBC => Some(DecodedOpcode {
+            instruction: LDY,
+            mode: IndexedAbsoluteX,
+            cycles: 4,
+            is_official: true,
+        }),
+        0x4A => Some(DecodedOpcode {
+            instruction: LSR,
+            mode: Accumulator,
+            cycles: 2,
+            is_official: true,
+        }),
+        0x46 => Some(DecodedOpcode {
+            instruction: LSR,
+            mode: ZeroPage,
+            cycles: 5,
+            is_official: true,
+        }),
+        0x56 => Some(DecodedOpcode {
+            instruction: LSR,
+            mode: IndexedZeroPageX,
+            cycles: 6,
+            is_official: true,
+        }),
+        0x4E => Some(DecodedOpcode {
+            instruction: LSR,
+            mode: Absolute,
+            cycles: 6,
+            is_official: true,
+        }),
+        0x5E => Some(DecodedOpcode {
+            instruction: LSR,
+            mode: IndexedAbsoluteX,
+            cycles: 7,
+            is_official: true,
+        }),
+        0xEA => Some(DecodedOpcode {
+            instruction: NOP,
+            mode: Implied,
+            cycles: 2,
+            is_official: true,
+        }),
+        0x09 => Some(DecodedOpcode {
+            instruction: ORA,
+            mode: Immediate,
+            cycles: 2,
+            is_official: true,
+        }),
+        0x05 => Some(DecodedOpcode {
+            instruction: ORA,
+            mode: ZeroPage,
+            cycles: 3,
+            is_official: true,
+        }),
+        0x15 => Some(DecodedOpcode {
+            instruction: ORA,
+            mode: IndexedZeroPageX,
+            cycles: 4,
+            is_official: true,
+        }),
+        0x0D => Some(DecodedOpcode {
+            instruction: ORA,
+            mode: Absolute,
+            cycles: 4,
+            is_official: true,
+        }),
+        0x1D => Some(DecodedOpcode {
+            instruction: ORA,
+            mode: IndexedAbsoluteX,
+            cycles: 4,
+            is_official: true,
+        }),
+        0x19 => Some(DecodedOpcode {
+            instruction: ORA,
+            mode: IndexedAbsoluteY,
+            cycles: 4,
+            is_official: true,
+        }),
+        0x01 => Some(DecodedOpcode {
+            instruction: ORA,
+            mode: IndexedIndirect,
+            cycles: 6,
+            is_official: true,
+        }),
+        0x11 => Some(DecodedOpcode {
+            instruction: ORA,
+            mode: IndirectIndexed,
+            cycles: 5,
+            is_official: true,
+        }),
+        0x48 => Some(DecodedOpcode {
+            instruction: PHA,
+            mode: Implied,
+            cycles: 3,
+            is_official: true,
+        }),
+        0x08 => Some(DecodedOpcode {
+            instruction: PHP,
+            mode: Implied,
+            cycles: 3,
+            is_official: true,
+        }),
+        0x68 => Some(DecodedOpcode {
+            instruction: PLA,
+            mode: Implied,
+            cycles: 4,
+            is_official: true,
+        }),
+        0x28 => Some(DecodedOpcode {
+            instruction: PLP,
+            mode: Implied,
+            cycles: 4,
+            is_official: true,
+        }),
+        0x2A => Some(DecodedOpcode {
+            instruction: ROL,
+            mode: Accumulator,
+            cycles: 2,
+            is_official: true,
+        }),
+        0x26 => Some(DecodedOpcode {
+            instruction: ROL,
+            mode: ZeroPage,
+            cycles: 5,
+            is_official: true,
+        }),
+        0x36 => Some(DecodedOpcode {
+            instruction: ROL,
+            mode: IndexedZeroPageX,
+            cycles: 6,
+            is_official: true,
+        }),
+        0x2E => Some(DecodedOpcode {
+            instruction: ROL,
+            mode: Absolute,
+            cycles: 6,
+            is_official: true,
+        }),
+        0x3E => Some(DecodedOpcode {
+            instruction: ROL,
+            mode: IndexedAbsoluteX,
+            cycles: 7,
+            is_official: true,
+        }),
+        0x6A => Some(DecodedOpcode {
+            instruction: ROR,
+            mode: Accumulator,
+            cycles: 2,
+            is_official: true,
+        }),
+        0x66 => Some(DecodedOpcode {
+            instruction: ROR,
+            mode: ZeroPage,
+            cycles: 5,
+            is_official: true,
+        }),
+        0x76 => Some(DecodedOpcode {
+            instruction: ROR,
+            mode: IndexedZeroPageX,
+            cycles: 6,
+            is_official: true,
+        }),
+        0x6E => Some(DecodedOpcode {
+            instruction: ROR,
+            mode: Absolute,
+            cycles: 6,
+            is_official: true,
+        }),
+        0x7E => Some(DecodedOpcode {
+            instruction: ROR,
+            mode: IndexedAbsoluteX,
+            cycles: 7,
+            is_official: true,
+        }),
+        0x40 => Some(DecodedOpcode {
+            instruction: RTI,
+            mode: Implied,
+            cycles: 6,
+            is_official: true,
+        }),
+        0x60 => Some(DecodedOpcode {
+            instruction: RTS,
+            mode: Implied,
+            cycles: 6,
+            is_official: true,
+        }),
+        0xE9 => Some(DecodedOpcode {
+            instruction: SBC,
+            mode: Immediate,
+            cycles: 2,
+            is_official: true,
+        }),
+        0xE5 => Some(DecodedOpcode {
+            instruction: SBC,
+            mode: ZeroPage,
+            cycles: 3,
+            is_official: true,
+        }),
+        0xF5 => Some(DecodedOpcode {
+            instruction: SBC,
+            mode: IndexedZeroPageX,
+            cycles: 4,
+            is_official: true,
+        }),
+        0xED => Some(DecodedOpcode {
+            instruction: SBC,
+            mode: Absolute,
+            cycles: 4,
+            is_official: true,
+        }),
+        0xFD => Some(DecodedOpcode {
+            instruction: SBC,
+            mode: IndexedAbsoluteX,
+            cycles: 4,
+            is_official: true,
+        }),
+        0xF9 => Some(DecodedOpcode {
+            instruction: SBC,
+            mode: IndexedAbsoluteY,
+            cycles: 4,
+            is_official: true,
+        }),
+        0xE1 => Some(DecodedOpcode {
+            instruction: SBC,
+            mode: IndexedIndirect,
+            cycles: 6,
+            is_official: true,
+        }),
+        0xF1 => Some(DecodedOpcode {
+            instruction: SBC,
+            mode: IndirectIndexed,
+            cycles: 5,
+            is_official: true,
+        }),
+        0x38 => Some(DecodedOpcode {
+            instruction: SEC,
+            mode: Implied,
+            cycles: 2,
+            is_official: true,
+        }),
+        0xF8 => Some(DecodedOpcode {
+            instruction: SED,
+            mode: Implied,
+            cycles: 2,
+            is_official: true,
+        }),
+        0x78 => Some(DecodedOpcode {
+            instruction: SEI,
+            mode: Implied,
+            cycles: 2,
+            is_official: true,
+        }),
+        0x85 => Some(DecodedOpcode {
+            instruction: STA,
+            mode: ZeroPage,
+            cycles: 3,
+            is_official: true,
+        }),
+        0x95 => Some(DecodedOpcode {
+            instruction: STA,
+            mode: IndexedZeroPageX,
+            cycles: 4,
+            is_official: true,
+        }),
+        0x8D => Some(DecodedOpcode {
+            instruction: STA,
+            mode: Absolute,
+            cycles: 4,
+            is_official: true,
+        }),
+        0x9D => Some(DecodedOpcode {
+            instruction: STA,
+            mode: IndexedAbsoluteX,
+            cycles: 5,
+            is_official: true,
+        }),
+        0x99 => Some(DecodedOpcode {
+            instruction: STA,
+            mode: IndexedAbsoluteY,
+            cycles: 5,
+            is_official: true,
+        }),
+        0x81 => Some(DecodedOpcode {
+            instruction: STA,
+            mode: IndexedIndirect,
+            cycles: 6,
+            is_official: true,
+        }),
+        0x91 => Some(DecodedOpcode {
+            instruction: STA,
+            mode: IndirectIndexed,
+            cycles: 6,
+            is_official: true,
+        }),
+        0x86 => Some(DecodedOpcode {
+            instruction: STX,
+            mode: ZeroPage,
+            cycles: 3,
+            is_official: true,
+        }),
+        0x96 => Some(DecodedOpcode {
+            instruction: STX,
+            mode: IndexedZeroPageY,
+            cycles: 4,
+            is_official: true,
+        }),
+        0x8E => Some(DecodedOpcode {
+            instruction: STX,
+            mode: Absolute,
+            cycles: 4,
+            is_official: true,
+        }),
+        0x84 => Some(DecodedOpcode {
+            instruction: STY,
+            mode: ZeroPage,
+            cycles: 3,
+            is_official: true,
+        }),
+        0x94 => Some(DecodedOpcode {
+            instruction: STY,
+            mode: IndexedZeroPageX,
+            cycles: 4,
+            is_official: true,
+        }),
+        0x8C => Some(DecodedOpcode {
+            instruction: STY,
+            mode: Absolute,
+            cycles: 4,
+            is_official: true,
+        }),
+        0xAA => Some(DecodedOpcode {
+            instruction: TAX,
+            mode: Implied,
+            cycles: 2,
+            is_official: true,
+        }),
+        0xA8 => Some(DecodedOpcode {
+            instruction: TAY,
+            mode: Implied,
+            cycles: 2,
+            is_official: true,
+        }),
+        0xBA => Some(DecodedOpcode {
+            instruction: TSX,
+            mode: Implied,
+            cycles: 2,
+            is_official: true,
+        }),
+        0x8A => Some(DecodedOpcode {
+            instruction: TXA,
+            mode: Implied,
+            cycles: 2,
+            is_official: true,
+        }),
+        0x9A => Some(DecodedOpcode {
+            instruction: TXS,
+            mode: Implied,
+            cycles: 2,
+            is_official: true,
+        }),
+        0x98 => Some(DecodedOpcode {
+            instruction: TYA,
+            mode: Implied,
+            cycles: 2,
+            is_official: true,
+        }),
+        // Below are all invalid opcodes. Need to be implemented in order to support all games and to pass all tests
+        0x04 => Some(DecodedOpcode {
+            instruction: NOP,
+            mode: ZeroPage,
+            cycles: 3,
+            is_official: false,
+        }),
+        0x44 => Some(DecodedOpcode {
+            instruction: NOP,
+            mode: ZeroPage,
+            cycles: 3,
+            is_official: false,
+        }),
+        0x64 => Some(DecodedOpcode {
+            instruction: NOP,
+            mode: ZeroPage,
+            cycles: 3,
+            is_official: false,
+        }),
+        0x0C => Some(DecodedOpcode {
+            instruction: NOP,
+            mode: Absolute,
+            cycles: 4,
+            is_official: false,
+        }),
+        0x14 => Some(DecodedOpcode {
+            instruction: NOP,
+            mode: IndexedZeroPageX,
+            cycles: 4,
+            is_official: false,
+        }),
+        0x34 => Some(DecodedOpcode {
+            instruction: NOP,
+            mode: IndexedZeroPageX,
+            cycles: 4,
+            is_official: false,
+        }),
+        0x54 => Some(DecodedOpcode {
+            instruction: NOP,
+            mode: IndexedZeroPageX,
+            cycles: 4,
+            is_official: false,
+        }),
+        0x74 => Some(DecodedOpcode {
+            instruction: NOP,
+            mode: IndexedZeroPageX,
+            cycles: 4,
+            is_official: false,
+        }),
+        0xD4 => Some(DecodedOpcode {
+            instruction: NOP,
+            mode: IndexedZeroPageX,
+            cycles: 4,
+            is_official: false,
+        }),
+        0xF4 => Some(DecodedOpcode {
+            instruction: NOP,
+            mode: IndexedZeroPageX,
+            cycles: 4,
+            is_official: false,
+        }),
+        0x1A => Some(DecodedOpcode {
+            instruction: NOP,
+            mode: Implied,
+            cycles: 2,
+            is_official: false,
+        }),
+        0x3A => Some(DecodedOpcode {
+            instruction: NOP,
+            mode: Implied,
+            cycles: 2,
+            is_official: false,
+        }),
+        0x5A => Some(DecodedOpcode {
+            instruction: NOP,
+            mode: Implied,
+            cycles: 2,
+            is_official: false,
+        }),
+        0x7A => Some(DecodedOpcode {
+            instruction: NOP,
+            mode: Implied,
+            cycles: 2,
+            is_official: false,
+        }),
+        0xDA => Some(DecodedOpcode {
+            instruction: NOP,
+            mode: Implied,
+            cycles: 2,
+            is_official: false,
+        }),
+        0xFA => Some(DecodedOpcode {
+            instruction: NOP,
+            mode: Implied,
+            cycles: 2,
+            is_official: false,
+        }),
+        0x80 => Some(DecodedOpcode {
+            instruction: NOP,
+            mode: Immediate,
+            cycles: 2,
+            is_official: false,
+        }),
+        0x1C => Some(DecodedOpcode {
+            instruction: NOP,
+            mode: IndexedAbsoluteX,
+            cycles: 4,
+            is_official: false,
+        }),
+        0x3C => Some(DecodedOpcode {
+            instruction: NOP,
+            mode: IndexedAbsoluteX,
+            cycles: 4,
+            is_official: false,
+        }),
+        0x5C => Some(DecodedOpcode {
+            instruction: NOP,
+            mode: IndexedAbsoluteX,
+            cycles: 4,
+            is_official: false,
+        }),
+        0x7C => Some(DecodedOpcode {
+            instruction: NOP,
+            mode: IndexedAbsoluteX,
+            cycles: 4,
+            is_official: false,
+        }),
+        0xDC => Some(DecodedOpcode {
+            instruction: NOP,
+            mode: IndexedAbsoluteX,
+            cycles: 4,
+            is_official: false,
+        }),
+        0xFC => Some(DecodedOpcode {
+            instruction: NOP,
+            mode: IndexedAbsoluteX,
+            cycles: 4,
+            is_official: false,
+        }),
+        // Below are the non-NOP illegal aka unofficial opcodes
+        // REMINDER:
+        //     IndexedIndirect is izx is Indirect x is ($00,X)
+        //     IndirectIndexed is izy is Indirect y is ($00),Y
+        0xA3 => Some(DecodedOpcode {
+            instruction: LAX,
+            mode: IndexedIndirect,
+            cycles: 6,
+            is_official: false,
+        }),
+        0xA7 => Some(DecodedOpcode {
+            instruction: LAX,
+            mode: ZeroPage,
+            cycles: 3,
+            is_official: false,
+        }),
+        0xAF => Some(DecodedOpcode {
+            instruction: LAX,
+            mode: Absolute,
+            cycles: 4,
+            is_official: false,
+        }),
+        0xB3 => Some(DecodedOpcode {
+            instruction: LAX,
+            mode: IndirectIndexed,
+            cycles: 5,
+            is_official: false,
+        }),
+        0xB7 => Some(DecodedOpcode {
+            instruction: LAX,
+            mode: IndexedZeroPageY,
+            cycles: 4,
+            is_official: false,
+        }),
+        0xBF => Some(DecodedOpcode {
+            instruction: LAX,
+            mode: IndexedAbsoluteY,
+            cycles: 4,
+            is_official: false,
+        }),
+
+        0x83 => Some(DecodedOpcode {
+            instruction: SAX,
+            mode: IndexedIndirect,
+            cycles: 6,
+            is_official: false,
+        }),
+        0x87 => Some(DecodedOpcode {
+            instruction: SAX,
+            mode: ZeroPage,
+            cycles: 3,
+            is_official: false,
+        }),
+        0x8F => Some(DecodedOpcode {
+            instruction: SAX,
+            mode: Absolute,
+            cycles: 4,
+            is_official: false,
+        }),
+        0x97 => Some(DecodedOpcode {
+            instruction: SAX,
+            mode: IndexedZeroPageY,
+            cycles: 4,
+            is_official: false,
+        }),
+
+        0xEB => Some(DecodedOpcode {
+            instruction: SBC,
+            mode: Immediate,
+            cycles: 2,
+            is_official: false,
+        }),
+
+        0xC3 => Some(DecodedOpcode {
+            instruction: DCP,
+            mode: IndexedIndirect,
+            cycles: 8,
+            is_official: false,
+        }),
+        0xC7 => Some(DecodedOpcode {
+            instruction: DCP,
+            mode: ZeroPage,
+            cycles: 5,
+            is_official: false,
+        }),
+        0xCF => Some(DecodedOpcode {
+            instruction: DCP,
+            mode: Absolute,
+            cycles: 6,
+            is_official: false,
+        }),
+        0xD3 => Some(DecodedOpcode {
+            instruction: DCP,
+            mode: IndirectIndexed,
+            cycles: 8,
+            is_official: false,
+        }),
+        0xD7 => Some(DecodedOpcode {
+            instruction: DCP,
+            mode: IndexedZeroPageX,
+            cycles: 6,
+            is_official: false,
+        }),
+        0xDB => Some(DecodedOpcode {
+            instruction: DCP,
+            mode: IndexedAbsoluteY,
+            cycles: 7,
+            is_official: false,
+        }),
+        0xDF => Some(DecodedOpcode {
+            instruction: DCP,
+            mode: IndexedAbsoluteX,
+            cycles: 7,
+            is_official: false,
+        }),
+
+        0xE3 => Some(DecodedOpcode {
+            instruction: ISC,
+            mode: IndexedIndirect,
+            cycles: 8,
+            is_official: false,
+        }),
+        0xE7 => Some(DecodedOpcode {
+            instruction: ISC,
+            mode: ZeroPage,
+            cycles: 5,
+            is_official: false,
+        }),
+        0xEF => Some(DecodedOpcode {
+            instruction: ISC,
+            mode: Absolute,
+            cycles: 6,
+            is_official: false,
+        }),
+        0xF3 => Some(DecodedOpcode {
+            instruction: ISC,
+            mode: IndirectIndexed,
+            cycles: 8,
+            is_official: false,
+        }),
+        0xF7 => Some(DecodedOpcode {
+            instruction: ISC,
+            mode: IndexedZeroPageX,
+            cycles: 6,
+            is_official: false,
+        }),
+        0xFB => Some(DecodedOpcode {
+            instruction: ISC,
+            mode: IndexedAbsoluteY,
+            cycles: 7,
+            is_official: false,
+        }),
+        0xFF => Some(DecodedOpcode {
+            instruction: ISC,
+            mode: IndexedAbsoluteX,
+            cycles: 7,
+            is_official: false,
+        }),
+
+        0x03 => Some(DecodedOpcode {
+            instruction: SLO,
+            mode: IndexedIndirect,
+            cycles: 8,
+            is_official: false,
+        }),
+        0x07 => Some(DecodedOpcode {
+            instruction: SLO,
+            mode: ZeroPage,
+            cycles: 5,
+            is_official: false,
+        }),
+        0x0F => Some(DecodedOpcode {
+            instruction: SLO,
+            mode: Absolute,
+            cycles: 6,
+            is_official: false,
+        }),
+        0x13 => Some(DecodedOpcode {
+            instruction: SLO,
+            mode: IndirectIndexed,
+            cycles: 8,
+            is_official: false,
+        }),
+        0x17 => Some(DecodedOpcode {
+            instruction: SLO,
+            mode: IndexedZeroPageX,
+            cycles: 6,
+            is_official: false,
+        }),
+        0x1B => Some(DecodedOpcode {
+            instruction: SLO,
+            mode: IndexedAbsoluteY,
+            cycles: 7,
+            is_official: false,
+        }),
+        0x1F => Some(DecodedOpcode {
+            instruction: SLO,
+            mode: IndexedAbsoluteX,
+            cycles: 7,
+            is_official: false,
+        }),
+
+        0x23 => Some(DecodedOpcode {
+            instruction: RLA,
+            mode: IndexedIndirect,
+            cycles: 8,
+            is_official: false,
+        }),
+        0x27 => Some(DecodedOpcode {
+            instruction: RLA,
+            mode: ZeroPage,
+            cycles: 5,
+            is_official: false,
+        }),
+        0x2F => Some(DecodedOpcode {
+            instruction: RLA,
+            mode: Absolute,
+            cycles: 6,
+            is_official: false,
+        }),
+        0x33 => Some(DecodedOpcode {
+            instruction: RLA,
+            mode: IndirectIndexed,
+            cycles: 8,
+            is_official: false,
+        }),
+        0x37 => Some(DecodedOpcode {
+            instruction: RLA,
+            mode: IndexedZeroPageX,
+            cycles: 6,
+            is_official: false,
+        }),
+        0x3B => Some(DecodedOpcode {
+            instruction: RLA,
+            mode: IndexedAbsoluteY,
+            cycles: 7,
+            is_official: false,
+        }),
+        0x3F => Some(DecodedOpcode {
+            instruction: RLA,
+            mode: IndexedAbsoluteX,
+            cycles: 7,
+            is_official: false,
+        }),
+
+        0x43 => Some(DecodedOpcode {
+            instruction: SRE,
+            mode: IndexedIndirect,
+            cycles: 8,
+            is_official: false,
+        }),
+        0x47 => Some(DecodedOpcode {
+            instruction: SRE,
+            mode: ZeroPage,
+            cycles: 5,
+            is_official: false,
+        }),
+        0x4F => Some(DecodedOpcode {
+            instruction: SRE,
+            mode: Absolute,
+            cycles: 6,
+            is_official: false,
+        }),
+        0x53 => Some(DecodedOpcode {
+            instruction: SRE,
+            mode: IndirectIndexed,
+            cycles: 8,
+            is_official: false,
+        }),
+        0x57 => Some(DecodedOpcode {
+            instruction: SRE,
+            mode: IndexedZeroPageX,
+            cycles: 6,
+            is_official: false,
+        }),
+        0x5B => Some(DecodedOpcode {
+            instruction: SRE,
+            mode: IndexedAbsoluteY,
+            cycles: 7,
+            is_official: false,
+        }),
+        0x5F => Some(DecodedOpcode {
+            instruction: SRE,
+            mode: IndexedAbsoluteX,
+            cycles: 7,
+            is_official: false,
+        }),
+
+        0x63 => Some(DecodedOpcode {
+            instruction: RRA,
+            mode: IndexedIndirect,
+            cycles: 8,
+            is_official: false,
+        }),
+        0x67 => Some(DecodedOpcode {
+            instruction: RRA,
+            mode: ZeroPage,
+            cycles: 5,
+            is_official: false,
+        }),
+        0x6F => Some(DecodedOpcode {
+            instruction: RRA,
+            mode: Absolute,
+            cycles: 6,
+            is_official: false,
+        }),
+        0x73 => Some(DecodedOpcode {
+            instruction: RRA,
+            mode: IndirectIndexed,
+            cycles: 8,
+            is_official: false,
+        }),
+        0x77 => Some(DecodedOpcode {
+            instruction: RRA,
+            mode: IndexedZeroPageX,
+            cycles: 6,
+            is_official: false,
+        }),
+        0x7B => Some(DecodedOpcode {
+            instruction: RRA,
+            mode: IndexedAbsoluteY,
+            cycles: 7,
+            is_official: false,
+        }),
+        0x7F => Some(DecodedOpcode {
+            instruction: RRA,
+            mode: IndexedAbsoluteX,
+            cycles: 7,
+            is_official: false,
+        }),
+
+        0x02 => Some(DecodedOpcode {
+            instruction: JAM,
+            mode: Implied,
+            cycles: 2,
+            is_official: false,
+        }),
+        0x12 => Some(DecodedOpcode {
+            instruction: JAM,
+            mode: Implied,
+            cycles: 2,
+            is_official: false,
+        }),
+        0x22 => Some(DecodedOpcode {
+            instruction: JAM,
+            mode: Implied,
+            cycles: 2,
+            is_official: false,
+        }),
+        0x32 => Some(DecodedOpcode {
+            instruction: JAM,
+            mode: Implied,
+            cycles: 2,
+            is_official: false,
+        }),
+        0x42 => Some(DecodedOpcode {
+            instruction: JAM,
+            mode: Implied,
+            cycles: 2,
+            is_official: false,
+        }),
+        0x52 => Some(DecodedOpcode {
+            instruction: JAM,
+            mode: Implied,
+            cycles: 2,
+            is_official: false,
+        }),
+        0x62 => Some(DecodedOpcode {
+            instruction: JAM,
+            mode: Implied,
+            cycles: 2,
+            is_official: false,
+        }),
+        0x72 => Some(DecodedOpcode {
+            instruction: JAM,
+            mode: Implied,
+            cycles: 2,
+            is_official: false,
+        }),
+        0x92 => Some(DecodedOpcode {
+            instruction: JAM,
+            mode: Implied,
+            cycles: 2,
+            is_official: false,
+        }),
+        0xB2 => Some(DecodedOpcode {
+            instruction: JAM,
+            mode: Implied,
+            cycles: 2,
+            is_official: false,
+        }),
+        0xD2 => Some(DecodedOpcode {
+            instruction: JAM,
+            mode: Implied,
+            cycles: 2,
+            is_official: false,
+        }),
+        0xF2 => Some(DecodedOpcode {
+            instruction: JAM,
+            mode: Implied,
+            cycles: 2,
+            is_official: false,
+        }),
+
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn known_opcodes_decode() {
+        use self::AddressingMode::*;
+        use self::Instruction::*;
+        // (opcode, instruction, mode, cycles, is_official) from the 6502's opcode matrix.
+        let known = [
+            (0x00, BRK, Implied, 7, true),
+            (0x0A, ASL, Accumulator, 2, true),
+            (0x20, JSR, Absolute, 6, true),
+            (0x4C, JMP, Absolute, 3, true),
+            (0x6C, JMP, Indirect, 5, true),
+            (0x69, ADC, Immediate, 2, true),
+            (0x90, BCC, Relative, 2, true),
+            (0x9A, TXS, Implied, 2, true),
+            (0xA1, LDA, IndexedIndirect, 6, true),
+            (0xB1, LDA, IndirectIndexed, 5, true),
+            (0xB6, LDX, IndexedZeroPageY, 4, true),
+            (0xBD, LDA, IndexedAbsoluteX, 4, true),
+            (0xFE, INC, IndexedAbsoluteX, 7, true),
+            (0x02, JAM, Implied, 2, false),
+            (0xA7, LAX, ZeroPage, 3, false),
+            (0xC3, DCP, IndexedIndirect, 8, false),
+            (0xEB, SBC, Immediate, 2, false),
+        ];
+        for &(opcode, instruction, mode, cycles, is_official) in known.iter() {
+            let expected = DecodedOpcode { instruction, mode, cycles, is_official };
+            assert_eq!((opcode as Opcode).decode(), Ok(expected), "opcode {:02X}", opcode);
+        }
+
+        // SHX absolute,Y isn't emulated.
+        assert_eq!(0x9E.decode(), Err(DecodeError::IllegalUnimplementedOpcode { opcode: 0x9E }));
+    }
+
+    #[test]
+    fn instruction_lengths() {
+        use self::AddressingMode::*;
+        let lengths = [
+            (Implied, 1), (Accumulator, 1),
+            (Immediate, 2), (ZeroPage, 2), (IndexedZeroPageX, 2), (IndexedZeroPageY, 2), (Relative, 2),
+            (IndexedIndirect, 2), (IndirectIndexed, 2),
+            (Absolute, 3), (IndexedAbsoluteX, 3), (IndexedAbsoluteY, 3), (Indirect, 3),
+        ];
+        for (mode, length) in lengths.iter() {
+            assert_eq!(instruction_length(*mode), *length, "{:?}", mode);
+        }
+    }
+
+    #[test]
+    fn opcode_table_modes_have_the_right_lengths() {
+        // The length of every opcode, from the 6502's opcode matrix. BRK and the JAMs count as 1.
+        let lengths: [[usize; 16]; 16] = [
+            [1, 2, 1, 2, 2, 2, 2, 2, 1, 2, 1, 2, 3, 3, 3, 3], // $0x
+            [2, 2, 1, 2, 2, 2, 2, 2, 1, 3, 1, 3, 3, 3, 3, 3], // $1x
+            [3, 2, 1, 2, 2, 2, 2, 2, 1, 2, 1, 2, 3, 3, 3, 3], // $2x
+            [2, 2, 1, 2, 2, 2, 2, 2, 1, 3, 1, 3, 3, 3, 3, 3], // $3x
+            [1, 2, 1, 2, 2, 2, 2, 2, 1, 2, 1, 2, 3, 3, 3, 3], // $4x
+            [2, 2, 1, 2, 2, 2, 2, 2, 1, 3, 1, 3, 3, 3, 3, 3], // $5x
+            [1, 2, 1, 2, 2, 2, 2, 2, 1, 2, 1, 2, 3, 3, 3, 3], // $6x
+            [2, 2, 1, 2, 2, 2, 2, 2, 1, 3, 1, 3, 3, 3, 3, 3], // $7x
+            [2, 2, 2, 2, 2, 2, 2, 2, 1, 2, 1, 2, 3, 3, 3, 3], // $8x
+            [2, 2, 1, 2, 2, 2, 2, 2, 1, 3, 1, 3, 3, 3, 3, 3], // $9x
+            [2, 2, 2, 2, 2, 2, 2, 2, 1, 2, 1, 2, 3, 3, 3, 3], // $Ax
+            [2, 2, 1, 2, 2, 2, 2, 2, 1, 3, 1, 3, 3, 3, 3, 3], // $Bx
+            [2, 2, 2, 2, 2, 2, 2, 2, 1, 2, 1, 2, 3, 3, 3, 3], // $Cx
+            [2, 2, 1, 2, 2, 2, 2, 2, 1, 3, 1, 3, 3, 3, 3, 3], // $Dx
+            [2, 2, 2, 2, 2, 2, 2, 2, 1, 2, 1, 2, 3, 3, 3, 3], // $Ex
+            [2, 2, 1, 2, 2, 2, 2, 2, 1, 3, 1, 3, 3, 3, 3, 3], // $Fx
+        ];
+        for opcode in 0..=0xFF {
+            if let Ok(decoded_opcode) = opcode.decode() {
+                let length = lengths[opcode as usize >> 4][opcode as usize & 0x0F];
+                let mode = decoded_opcode.mode;
+                assert_eq!(1 + mode.operand_bytes(), length, "opcode {:02X} {:?}", opcode, mode);
+            }
+        }
+    }
+
+    #[test]
+    fn mnemonics_match_standard_names() {
+        let mnemonics = [
+            (0x69, "ADC"), (0x29, "AND"), (0x0A, "ASL"), (0x90, "BCC"), (0xB0, "BCS"), (0xF0, "BEQ"),
+            (0x24, "BIT"), (0x30, "BMI"), (0xD0, "BNE"), (0x10, "BPL"), (0x00, "BRK"), (0x50, "BVC"),
+            (0x70, "BVS"), (0x18, "CLC"), (0xD8, "CLD"), (0x58, "CLI"), (0xB8, "CLV"), (0xC9, "CMP"),
+            (0xE0, "CPX"), (0xC0, "CPY"), (0xC6, "DEC"), (0xCA, "DEX"), (0x88, "DEY"), (0x49, "EOR"),
+            (0xE6, "INC"), (0xE8, "INX"), (0xC8, "INY"), (0x4C, "JMP"), (0x20, "JSR"), (0xA9, "LDA"),
+            (0xA2, "LDX"), (0xA0, "LDY"), (0x4A, "LSR"), (0xEA, "NOP"), (0x09, "ORA"), (0x48, "PHA"),
+            (0x08, "PHP"), (0x68, "PLA"), (0x28, "PLP"), (0x2A, "ROL"), (0x6A, "ROR"), (0x40, "RTI"),
+            (0x60, "RTS"), (0xE9, "SBC"), (0x38, "SEC"), (0xF8, "SED"), (0x78, "SEI"), (0x85, "STA"),
+            (0x86, "STX"), (0x84, "STY"), (0xAA, "TAX"), (0xA8, "TAY"), (0xBA, "TSX"), (0x8A, "TXA"),
+            (0x9A, "TXS"), (0x98, "TYA"), (0xA3, "LAX"), (0x83, "SAX"), (0xC3, "DCP"), (0xE3, "ISC"),
+            (0x03, "SLO"), (0x23, "RLA"), (0x43, "SRE"), (0x63, "RRA"), (0x02, "JAM"),
+        ];
+        for (opcode, mnemonic) in mnemonics.iter() {
+            assert_eq!(opcode.decode().unwrap().instruction.to_string(), *mnemonic, "opcode {:02X}", opcode);
+        }
+
+        // These aren't in the opcode table yet.
+        assert_eq!(Instruction::ALR.to_string(), "ALR");
+        assert_eq!(Instruction::ANC.to_string(), "ANC");
+        assert_eq!(Instruction::ARR.to_string(), "ARR");
+        assert_eq!(Instruction::AXS.to_string(), "AXS");
+        assert_eq!(Instruction::UNK.to_string(), ".byte");
+    }
+
+    #[test]
+    fn assemble_round_trips_official_opcodes() {
+        for opcode in (0..=0xFF).filter(|opcode: &u8| opcode.decode().map_or(false, |d| d.is_official)) {
+            let decoded_opcode = opcode.decode().unwrap();
+
+            let bytes = assemble(decoded_opcode.instruction, decoded_opcode.mode, 0x1234).unwrap();
+
+            assert_eq!(bytes[0].decode(), Ok(decoded_opcode), "opcode {:02X}", opcode);
+            assert_eq!(bytes.len(), instruction_length(decoded_opcode.mode), "opcode {:02X}", opcode);
+        }
+        assert_eq!(assemble(Instruction::LDA, AddressingMode::Immediate, 0x42), Ok(vec![0xA9, 0x42]));
+        assert_eq!(assemble(Instruction::JMP, AddressingMode::Absolute, 0xC5F5), Ok(vec![0x4C, 0xF5, 0xC5]));
+        assert_eq!(assemble(Instruction::SBC, AddressingMode::Immediate, 0x01), Ok(vec![0xE9, 0x01]));
+        assert_eq!(
+            assemble(Instruction::STA, AddressingMode::Immediate, 0x42),
+            Err(AssembleError::InvalidAddressingMode { instruction: Instruction::STA, mode: AddressingMode::Immediate })
+        );
+    }
+
+    #[test]
+    fn unofficial_opcodes_are_flagged() {
+        for opcode in [0x03, 0xA7, 0xC3, 0xE3, 0x23, 0x43, 0x63, 0x87, 0x02, 0x1A, 0x80, 0xEB].iter() {
+            assert!(!opcode.decode().unwrap().is_official, "opcode {:02X}", opcode);
+        }
+        for opcode in [0x69, 0xA9, 0x00, 0x20, 0x4C, 0x6C, 0x9A, 0xE9, 0xEA, 0xFE].iter() {
+            assert!(opcode.decode().unwrap().is_official, "opcode {:02X}", opcode);
         }
+        // Every official opcode, i.e. 56 instructions over their addressing modes.
+        assert_eq!((0..=0xFF).filter(|opcode: &u8| opcode.decode().map_or(false, |d| d.is_official)).count(), 151);
     }
 }
@@ -0,0 +1,137 @@
+use crate::cpu::cpu::CPU;
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+
+/// Everything needed to put the machine back into an earlier state: the CPU, RAM, PPU, APU,
+/// mapper and cartridge RAM. The rom isn't included since it never changes. Unlike
+/// `Nes::save_state_bytes` this only lives in memory, so there's no header or version.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MachineSnapshot {
+    /// What `CPU::save_state` wrote, see `nes::state`.
+    pub(crate) state: Vec<u8>,
+    /// See `Cartridge::rom_hash`.
+    pub(crate) rom_hash: [u8; 16],
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SnapshotError {
+    /// The snapshot was taken with a different rom loaded.
+    RomMismatch,
+}
+
+/// Keeps the last N frames worth of snapshots so a frontend can step backwards frame by frame.
+/// Once full, pushing a frame drops the oldest one so memory use is bounded by N snapshots.
+pub struct Rewind {
+    frames: VecDeque<MachineSnapshot>,
+    capacity: usize,
+}
+
+impl Rewind {
+    pub fn new(capacity: usize) -> Rewind {
+        Rewind {
+            frames: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    pub fn push_frame(&mut self, cpu: &CPU) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.frames.len() == self.capacity {
+            self.frames.pop_front();
+        }
+        self.frames.push_back(cpu.snapshot());
+    }
+
+    /// Returns the most recently pushed frame, removing it from the buffer.
+    pub fn pop_frame(&mut self) -> Option<MachineSnapshot> {
+        self.frames.pop_back()
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::address_space::AddressSpace;
+    use crate::rom::rom::tests::nrom_image;
+
+    #[test]
+    fn rewinds_to_earlier_frame() {
+        // INC $10 JMP $8000
+        let mut cpu = CPU::new(nrom_image(&[0xE6, 0x10, 0x4C, 0x00, 0x80]));
+        cpu.pc = 0x8000;
+        let mut rewind = Rewind::new(120);
+        let mut recorded = Vec::new();
+
+        for _ in 0..120 {
+            rewind.push_frame(&cpu);
+            recorded.push(cpu.snapshot());
+            for _ in 0..3 {
+                cpu.step().unwrap();
+            }
+        }
+
+        let mut snapshot = None;
+        for _ in 0..30 {
+            snapshot = rewind.pop_frame();
+        }
+        let snapshot = snapshot.unwrap();
+        assert_eq!(snapshot, recorded[90]);
+
+        cpu.restore(&snapshot).unwrap();
+        assert_eq!(cpu.snapshot(), recorded[90]);
+        // Every other step is the INC, so that's 270 steps in.
+        assert_eq!(cpu.bus.read(0x0010), 135);
+    }
+
+    #[test]
+    fn restores_more_than_the_cpu() {
+        // LDA #$01 STA $6000
+        let mut cpu = CPU::new(nrom_image(&[0xA9, 0x01, 0x8D, 0x00, 0x60]));
+        cpu.pc = 0x8000;
+        let snapshot = cpu.snapshot();
+        let ppu_before = (cpu.bus.ppu().scanline(), cpu.bus.ppu().dot());
+        for _ in 0..2 {
+            cpu.step().unwrap();
+        }
+        assert_eq!(cpu.bus.peek(0x6000), 1);
+
+        cpu.restore(&snapshot).unwrap();
+        assert_eq!(cpu.pc, 0x8000);
+        assert_eq!(cpu.bus.peek(0x6000), 0);
+        assert_eq!((cpu.bus.ppu().scanline(), cpu.bus.ppu().dot()), ppu_before);
+        assert_eq!(cpu.snapshot(), snapshot);
+    }
+
+    #[test]
+    fn memory_is_bounded() {
+        let cpu = CPU::new(nrom_image(&[]));
+        let mut rewind = Rewind::new(60);
+
+        for _ in 0..120 {
+            rewind.push_frame(&cpu);
+        }
+
+        assert_eq!(rewind.len(), 60);
+    }
+
+    #[test]
+    fn snapshot_from_another_rom_is_rejected() {
+        let cpu = CPU::new(nrom_image(&[0xEA]));
+        let snapshot = cpu.snapshot();
+        let mut other_cpu = CPU::new(nrom_image(&[0xE8]));
+
+        assert_eq!(snapshot.rom_hash, cpu.bus.cartridge().rom_hash());
+        assert_ne!(snapshot.rom_hash, other_cpu.bus.cartridge().rom_hash());
+        assert_eq!(other_cpu.restore(&snapshot), Err(SnapshotError::RomMismatch));
+    }
+}
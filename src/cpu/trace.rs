@@ -0,0 +1,101 @@
+use crate::cpu::opcode::*;
+use alloc::boxed::Box;
+use alloc::format;
+use core::fmt;
+
+/// State of the CPU right before an instruction executes.
+///
+/// Building one of these is cheap so it's handed to the trace hook for every instruction. Turning
+/// it into text is the expensive part and only happens if whoever receives it formats it.
+#[derive(Debug, Clone, Copy)]
+pub struct TraceEvent {
+    pub pc: u16,
+    pub opcode: Opcode,
+    pub decoded_opcode: DecodedOpcode,
+    pub fetched_word: u16,
+    /// The bytes after the opcode. Only the first `instruction_length - 1` of them are operands.
+    pub operand_bytes: [u8; 2],
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub p: u8,
+    pub sp: u8,
+    pub total_cycles: u64,
+}
+
+/// An instruction that has been run, see `CPU::instructions`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct InstructionTrace {
+    /// Where the instruction was, i.e. the PC before it ran.
+    pub pc: u16,
+    pub opcode: Opcode,
+    pub decoded_opcode: DecodedOpcode,
+    /// The bytes after the opcode, see `operands`.
+    pub operand_bytes: [u8; 2],
+    pub cycles: u16,
+}
+
+impl InstructionTrace {
+    /// Just the bytes that are operands of the instruction.
+    pub fn operands(&self) -> &[u8] {
+        &self.operand_bytes[..instruction_length(self.decoded_opcode.mode) - 1]
+    }
+}
+
+/// Called by the CPU with a TraceEvent every time it starts an instruction.
+pub type TraceHook = Box<dyn FnMut(&TraceEvent)>;
+
+/// Which instructions the trace hook is called for.
+pub enum TraceFilter {
+    EveryInstruction,
+    /// Skips an instruction at the same PC as the one before it, e.g. a JMP to itself spinning
+    /// while it waits for an interrupt.
+    OnPcChange,
+    /// Instructions with a PC from the first address to the second, inclusive.
+    InRange(u16, u16),
+    Custom(Box<dyn Fn(&TraceEvent) -> bool>),
+}
+
+impl TraceFilter {
+    /// `previous_pc` is the PC of the instruction before, whether or not it was allowed.
+    pub fn allows(&self, event: &TraceEvent, previous_pc: Option<u16>) -> bool {
+        match self {
+            TraceFilter::EveryInstruction => true,
+            TraceFilter::OnPcChange => previous_pc != Some(event.pc),
+            TraceFilter::InRange(start, end) => (*start..=*end).contains(&event.pc),
+            TraceFilter::Custom(filter) => filter(event),
+        }
+    }
+}
+
+impl Default for TraceFilter {
+    fn default() -> Self {
+        TraceFilter::EveryInstruction
+    }
+}
+
+impl fmt::Display for TraceEvent {
+    /// Custom implementation intended to format similarly to: nestest.log
+    /// See: http://www.qmtpro.com/~nes/misc/nestest.log for example.
+    /// Example line below:
+    /// C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD PPU:  0,  0 CYC:7
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        let bytes = match instruction_length(self.decoded_opcode.mode) {
+            1 => format!("{:02X}", self.opcode),
+            2 => format!("{:02X} {:02X}", self.opcode, self.operand_bytes[0]),
+            _ => format!("{:02X} {:02X} {:02X}", self.opcode, self.operand_bytes[0], self.operand_bytes[1]),
+        };
+        write!(f, "{:04X}  {:8}  {:?} {:28X?}     A:{:02X?} X:{:02X?} Y:{:02X?} P:{:02X?} SP:{:02X?} PPU:0   0  CYC:{}",
+               self.pc,
+               bytes,
+               self.decoded_opcode.instruction,
+               self.fetched_word,
+               self.a,
+               self.x,
+               self.y,
+               self.p,
+               self.sp,
+               self.total_cycles
+        )
+    }
+}
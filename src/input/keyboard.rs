@@ -0,0 +1,61 @@
+use crate::bus::controller::Button;
+
+/// Which keyboard key presses which button. Keys are `KeyboardEvent.key` names and are matched
+/// case insensitively so holding shift doesn't change the mapping.
+pub struct KeyMap {
+    bindings: Vec<(String, Button)>,
+}
+
+impl KeyMap {
+    pub fn new() -> Self {
+        KeyMap { bindings: Vec::new() }
+    }
+
+    /// Binds `key` to `button`, replacing whatever the key was bound to before.
+    pub fn bind(&mut self, key: &str, button: Button) {
+        self.bindings.retain(|(bound, _)| !bound.eq_ignore_ascii_case(key));
+        self.bindings.push((key.to_string(), button));
+    }
+
+    pub fn button_for_key(&self, key: &str) -> Option<Button> {
+        self.bindings
+            .iter()
+            .find(|(bound, _)| bound.eq_ignore_ascii_case(key))
+            .map(|(_, button)| *button)
+    }
+}
+
+impl Default for KeyMap {
+    fn default() -> Self {
+        let mut key_map = KeyMap::new();
+        key_map.bind("ArrowUp", Button::Up);
+        key_map.bind("ArrowDown", Button::Down);
+        key_map.bind("ArrowLeft", Button::Left);
+        key_map.bind("ArrowRight", Button::Right);
+        key_map.bind("z", Button::B);
+        key_map.bind("x", Button::A);
+        key_map.bind("Enter", Button::Start);
+        key_map.bind("Shift", Button::Select);
+        key_map
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_keys_map_to_buttons() {
+        let key_map = KeyMap::default();
+
+        assert_eq!(key_map.button_for_key("ArrowUp"), Some(Button::Up));
+        assert_eq!(key_map.button_for_key("ArrowDown"), Some(Button::Down));
+        assert_eq!(key_map.button_for_key("ArrowLeft"), Some(Button::Left));
+        assert_eq!(key_map.button_for_key("ArrowRight"), Some(Button::Right));
+        assert_eq!(key_map.button_for_key("z"), Some(Button::B));
+        assert_eq!(key_map.button_for_key("X"), Some(Button::A));
+        assert_eq!(key_map.button_for_key("Enter"), Some(Button::Start));
+        assert_eq!(key_map.button_for_key("Shift"), Some(Button::Select));
+        assert_eq!(key_map.button_for_key("q"), None);
+    }
+}
@@ -0,0 +1 @@
+pub mod keyboard;
\ No newline at end of file
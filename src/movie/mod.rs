@@ -0,0 +1 @@
+pub mod movie;
\ No newline at end of file
@@ -0,0 +1,184 @@
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// Per frame controller input that can be recorded and played back. The format on disk is FCEUX's
+/// .fm2. See: http://fceux.com/web/FM2.html
+///
+/// Only the parts needed to play back input are handled: the joypads for ports 0 and 1 and the
+/// reset command. Other header lines are ignored when reading.
+///
+/// Controller bytes use the order the NES reads them in, i.e. bit 0 is A through bit 7 being Right.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Movie {
+    frames: Vec<(u8, u8)>,
+    /// Whether the console is reset at the start of the first frame.
+    pub starts_with_reset: bool,
+    /// The rom the movie was recorded with, see `Cartridge::rom_hash`. Movies from other
+    /// emulators don't have one so they're played on any rom.
+    pub rom_hash: Option<[u8; 16]>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MovieError {
+    /// Line numbers start at 1 like a text editor.
+    InvalidInputLine { line: usize },
+    InvalidRomHash { line: usize },
+    /// The movie was recorded with a different rom.
+    RomMismatch,
+}
+
+/// The letters .fm2 uses for each button, starting at bit 7 (Right) down to bit 0 (A).
+const BUTTONS: [char; 8] = ['R', 'L', 'D', 'U', 'T', 'S', 'B', 'A'];
+
+/// Header line holding `rom_hash` as 32 hex digits.
+const ROM_HASH_KEY: &str = "romHash";
+
+/// The commands field in an input line.
+const SOFT_RESET: u8 = 1;
+const HARD_RESET: u8 = 2;
+
+impl Movie {
+    pub fn new() -> Movie {
+        Movie {
+            frames: Vec::new(),
+            starts_with_reset: false,
+            rom_hash: None,
+        }
+    }
+
+    pub fn record(&mut self, frame: u64, pad1: u8, pad2: u8) {
+        let frame = frame as usize;
+        if frame >= self.frames.len() {
+            self.frames.resize(frame + 1, (0, 0));
+        }
+        self.frames[frame] = (pad1, pad2);
+    }
+
+    /// Frames past the end of the movie have nothing pressed.
+    pub fn input_for_frame(&self, frame: u64) -> (u8, u8) {
+        self.frames.get(frame as usize).cloned().unwrap_or((0, 0))
+    }
+
+    pub fn len(&self) -> u64 {
+        self.frames.len() as u64
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    pub fn to_fm2(&self) -> String {
+        let mut fm2 = String::from("version 3\nemuVersion 22020\npalFlag 0\nport0 1\nport1 1\nport2 0\n");
+        if let Some(rom_hash) = self.rom_hash {
+            // Not part of .fm2. FCEUX's romChecksum is an MD5 of the whole file so it can't be used.
+            let hex: Vec<String> = rom_hash.iter().map(|byte| format!("{:02x}", byte)).collect();
+            fm2.push_str(&format!("{} {}\n", ROM_HASH_KEY, hex.concat()));
+        }
+        for (frame, (pad1, pad2)) in self.frames.iter().enumerate() {
+            let commands = if frame == 0 && self.starts_with_reset { SOFT_RESET } else { 0 };
+            fm2.push_str(&format!("|{}|{}|{}||\n", commands, pad_to_fm2(*pad1), pad_to_fm2(*pad2)));
+        }
+
+        fm2
+    }
+
+    pub fn from_fm2(fm2: &str) -> Result<Movie, MovieError> {
+        let mut movie = Movie::new();
+        for (index, line) in fm2.lines().enumerate() {
+            if let Some(hex) = line.strip_prefix(ROM_HASH_KEY) {
+                let rom_hash = rom_hash_from_hex(hex.trim());
+                movie.rom_hash = Some(rom_hash.ok_or(MovieError::InvalidRomHash { line: index + 1 })?);
+                continue;
+            }
+            if !line.starts_with('|') {
+                continue; // Header
+            }
+            let error = MovieError::InvalidInputLine { line: index + 1 };
+            let fields: Vec<&str> = line.split('|').collect();
+            if fields.len() < 4 {
+                return Err(error);
+            }
+            let commands: u8 = fields[1].parse().map_err(|_| error.clone())?;
+            if movie.frames.is_empty() {
+                movie.starts_with_reset = commands & (SOFT_RESET | HARD_RESET) != 0;
+            }
+            let pad1 = pad_from_fm2(fields[2]).ok_or(error.clone())?;
+            let pad2 = pad_from_fm2(fields[3]).ok_or(error)?;
+            movie.frames.push((pad1, pad2));
+        }
+
+        Ok(movie)
+    }
+}
+
+fn rom_hash_from_hex(hex: &str) -> Option<[u8; 16]> {
+    if hex.len() != 32 || !hex.is_ascii() {
+        return None;
+    }
+    let mut rom_hash = [0; 16];
+    for (index, byte) in rom_hash.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[index * 2..index * 2 + 2], 16).ok()?;
+    }
+
+    Some(rom_hash)
+}
+
+fn pad_to_fm2(pad: u8) -> String {
+    BUTTONS
+        .iter()
+        .enumerate()
+        .map(|(index, letter)| if pad & (0x80 >> index) != 0 { *letter } else { '.' })
+        .collect()
+}
+
+/// An empty field means the port has no controller plugged in.
+fn pad_from_fm2(field: &str) -> Option<u8> {
+    if field.is_empty() {
+        return Some(0);
+    }
+    if field.chars().count() != BUTTONS.len() {
+        return None;
+    }
+
+    // Anything other than '.' or a space counts as pressed.
+    Some(field.chars().enumerate().fold(0, |pad, (index, letter)| match letter {
+        '.' | ' ' => pad,
+        _ => pad | (0x80 >> index),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fm2_round_trip() {
+        let mut movie = Movie::new();
+        assert!(movie.is_empty());
+        movie.starts_with_reset = true;
+        movie.record(0, 0x00, 0x00);
+        movie.record(1, 0x01, 0x80); // A, Right
+        movie.record(2, 0x88, 0x00); // Right + Start
+        movie.record(4, 0xFF, 0x42);
+        movie.rom_hash = Some([0xAB; 16]);
+
+        let fm2 = movie.to_fm2();
+        let parsed = Movie::from_fm2(&fm2).unwrap();
+
+        assert!(fm2.contains("|1|........|........||"));
+        assert!(fm2.contains("|0|R...T...|........||"));
+        assert!(fm2.contains("romHash abababababababababababababababab\n"));
+        assert_eq!(parsed, movie);
+        for frame in 0..6 {
+            assert_eq!(parsed.input_for_frame(frame), movie.input_for_frame(frame));
+        }
+    }
+
+    #[test]
+    fn rejects_bad_input_line() {
+        let fm2 = "version 3\n|0|R...T...|........||\n|0|R..|........||\n";
+
+        assert_eq!(Movie::from_fm2(fm2), Err(MovieError::InvalidInputLine { line: 3 }));
+    }
+}
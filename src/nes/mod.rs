@@ -0,0 +1,2 @@
+pub mod nes;
+pub mod state;
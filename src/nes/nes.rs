@@ -0,0 +1,466 @@
+use crate::apu::resampler::DEFAULT_SAMPLE_RATE;
+use crate::bus::address_space::AddressSpace;
+use crate::bus::bus::{Bus, RamInit};
+use crate::bus::controller::Button;
+use crate::cpu::cpu::{CpuState, CPU};
+use crate::cpu::opcode::DecodeError;
+use crate::cpu::snapshot::{MachineSnapshot, SnapshotError};
+use crate::movie::movie::{Movie, MovieError};
+use crate::nes::state::{StateError, StateReader, StateWriter, MAGIC, VERSION};
+#[cfg(feature = "image")]
+use crate::ppu::palette::framebuffer_to_rgba;
+use crate::ppu::palette::{MasterPalette, NTSC_PALETTE};
+#[cfg(feature = "image")]
+use crate::ppu::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::rom::rom::{Cartridge, ROMError};
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum NesError {
+    /// There's nothing to run, see `Nes::new` and `Nes::eject`.
+    NoCartridge,
+    Decode(DecodeError),
+}
+
+impl From<DecodeError> for NesError {
+    fn from(error: DecodeError) -> Self {
+        NesError::Decode(error)
+    }
+}
+
+impl fmt::Display for NesError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NesError::NoCartridge => write!(f, "there's no cartridge loaded"),
+            NesError::Decode(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for NesError {}
+
+/// What the PPU just finished drawing, passed to the frame complete hook.
+pub struct FrameOutput<'a> {
+    /// The PPU's frame count, see `PPU::frame`.
+    pub frame: u64,
+    /// See `Nes::framebuffer`.
+    pub framebuffer: &'a [u8],
+    /// See `Nes::emphasis`.
+    pub emphasis: u8,
+    /// See `Nes::master_palette`.
+    pub palette: &'a MasterPalette,
+}
+
+/// Called every time the PPU finishes a frame.
+pub type FrameHook = Box<dyn FnMut(&FrameOutput)>;
+
+/// Called with audio, a fixed number of samples at a time, see `Nes::set_audio_ready_hook`.
+pub type AudioHook = Box<dyn FnMut(&[f32])>;
+
+/// The whole console. This is what frontends should use rather than reaching into the CPU and
+/// its bus. The CPU owns the bus, which in turn owns the cartridge, PPU and everything else.
+///
+/// There may be no cartridge, e.g. before the user has picked a rom, in which case there's no CPU
+/// either. Nothing runs and anything that reads the machine gets `None` or nothing back.
+pub struct Nes {
+    cpu: Option<CPU>,
+    // Settings, kept here too so they're still there for the next cartridge after an eject.
+    ram_init: RamInit,
+    palette: MasterPalette,
+    sample_rate: u32,
+    four_score: bool,
+    movie: Option<Movie>,
+    // Frames run since the rom was loaded. Used to find the movie input for the next frame.
+    frame: u64,
+    frame_complete_hook: Option<FrameHook>,
+    // The last PPU frame the hook was called for.
+    hooked_frame: u64,
+    audio_ready_hook: Option<AudioHook>,
+    // Samples are handed to the audio hook this many at a time.
+    audio_chunk: Vec<f32>,
+}
+
+impl Nes {
+    /// A console with no cartridge in it, see `load_cartridge`.
+    pub fn new() -> Nes {
+        Nes {
+            cpu: None,
+            ram_init: RamInit::Zeroed,
+            palette: NTSC_PALETTE,
+            sample_rate: DEFAULT_SAMPLE_RATE,
+            four_score: false,
+            movie: None,
+            frame: 0,
+            frame_complete_hook: None,
+            hooked_frame: 0,
+            audio_ready_hook: None,
+            audio_chunk: Vec::new(),
+        }
+    }
+
+    /// Loads the rom and powers the console on.
+    pub fn load(rom: Vec<u8>) -> Result<Nes, ROMError> {
+        Nes::load_with_ram_init(rom, RamInit::Zeroed)
+    }
+
+    /// Like `load` but with RAM powering on as `ram_init` says, see `RamInit`.
+    pub fn load_with_ram_init(rom: Vec<u8>, ram_init: RamInit) -> Result<Nes, ROMError> {
+        let mut nes = Nes { ram_init, ..Nes::new() };
+        nes.load_cartridge(rom)?;
+
+        Ok(nes)
+    }
+
+    /// Like `load` but RAM powers on full of garbage generated from `seed`, see
+    /// `RamInit::Seeded`. RAM is the only thing that isn't already the same at every power on;
+    /// e.g. the noise channel's shift register always starts as 1. So the same seed with the same
+    /// inputs on the same frames gives the same frames and audio, for tests and TAS replays.
+    pub fn with_seed(rom: Vec<u8>, seed: u64) -> Result<Nes, ROMError> {
+        Nes::load_with_ram_init(rom, RamInit::Seeded(seed))
+    }
+
+    /// Runs a single CPU instruction.
+    pub fn step(&mut self) -> Result<CpuState, NesError> {
+        let state = self.cpu.as_mut().ok_or(NesError::NoCartridge)?.step_system()?;
+        self.run_hooks();
+
+        Ok(state)
+    }
+
+    /// Runs until the PPU has finished drawing a frame. If a movie is playing its input for this
+    /// frame is used instead of the controllers, otherwise turbo buttons move on a frame.
+    pub fn run_frame(&mut self) -> Result<(), NesError> {
+        let cpu = self.cpu.as_mut().ok_or(NesError::NoCartridge)?;
+        if let Some(movie) = self.movie.as_ref() {
+            let (pad1, pad2) = movie.input_for_frame(self.frame);
+            cpu.bus.set_controller_state(pad1, pad2);
+        } else {
+            cpu.bus.next_input_frame();
+        }
+        cpu.run_frame()?;
+        self.frame += 1;
+        self.run_hooks();
+
+        Ok(())
+    }
+
+    /// Runs until the PPU has finished drawing the current scanline, e.g. for stepping through
+    /// raster effects like a status bar split. Like `step` this doesn't advance a playing movie.
+    pub fn step_scanline(&mut self) -> Result<(), NesError> {
+        self.cpu.as_mut().ok_or(NesError::NoCartridge)?.run_scanline()?;
+        self.run_hooks();
+
+        Ok(())
+    }
+
+    /// Sets a hook that's called once for every frame the PPU finishes, for hosts that would
+    /// rather be told than check after `run_frame` or `step`. It's called as soon as whichever of
+    /// those finished the frame returns.
+    pub fn set_frame_complete_hook(&mut self, hook: Option<FrameHook>) {
+        self.hooked_frame = self.cpu.as_ref().map_or(0, |cpu| cpu.bus.ppu().frame());
+        self.frame_complete_hook = hook;
+    }
+
+    /// Sets a hook that's given audio `samples` at a time, as soon as that many have been made.
+    /// Samples handed to the hook are drained, so it's either this or `drain_samples`.
+    pub fn set_audio_ready_hook(&mut self, samples: usize, hook: Option<AudioHook>) {
+        self.audio_chunk = vec![0.0; samples];
+        self.audio_ready_hook = hook;
+    }
+
+    fn run_hooks(&mut self) {
+        let cpu = match self.cpu.as_mut() {
+            Some(cpu) => cpu,
+            None => return,
+        };
+        let ppu = cpu.bus.ppu();
+        if let Some(hook) = self.frame_complete_hook.as_mut() {
+            if ppu.frame() != self.hooked_frame {
+                self.hooked_frame = ppu.frame();
+                hook(&FrameOutput {
+                    frame: ppu.frame(),
+                    framebuffer: ppu.framebuffer(),
+                    emphasis: ppu.emphasis(),
+                    palette: ppu.master_palette(),
+                });
+            }
+        }
+        if let Some(hook) = self.audio_ready_hook.as_mut() {
+            let apu = cpu.bus.apu_mut();
+            while !self.audio_chunk.is_empty() && apu.samples_available() >= self.audio_chunk.len() {
+                apu.drain_samples(&mut self.audio_chunk);
+                hook(&self.audio_chunk);
+            }
+        }
+    }
+
+    /// One NES colour index per pixel, see `ppu::palette` for converting it to RGBA. Empty with
+    /// no cartridge.
+    pub fn framebuffer(&self) -> &[u8] {
+        self.cpu.as_ref().map_or(&[], |cpu| cpu.bus.ppu().framebuffer())
+    }
+
+    /// Colour emphasis to convert the framebuffer with, see `PPU::emphasis`.
+    pub fn emphasis(&self) -> u8 {
+        self.cpu.as_ref().map_or(0, |cpu| cpu.bus.ppu().emphasis())
+    }
+
+    /// The RGB for each colour index, see `PPU::set_palette`.
+    pub fn master_palette(&self) -> &MasterPalette {
+        &self.palette
+    }
+
+    pub fn set_palette(&mut self, palette: MasterPalette) {
+        self.palette = palette;
+        if let Some(cpu) = self.cpu.as_mut() {
+            cpu.bus.ppu_mut().set_palette(palette);
+        }
+    }
+
+    /// For debuggers, see `PPU::render_nametable_with_chr`. Empty with no cartridge.
+    pub fn render_nametable(&self, index: usize) -> Vec<u8> {
+        self.cpu.as_ref().map_or_else(Vec::new, |cpu| {
+            let cartridge = cpu.bus.cartridge();
+            cpu.bus.ppu().render_nametable_with_chr(index, |address| cartridge.read_chr(address))
+        })
+    }
+
+    /// For debuggers, see `PPU::render_pattern_table_with_chr`. Empty with no cartridge.
+    pub fn render_pattern_table(&self, table: usize) -> Vec<u8> {
+        self.cpu.as_ref().map_or_else(Vec::new, |cpu| {
+            let cartridge = cpu.bus.cartridge();
+            cpu.bus.ppu().render_pattern_table_with_chr(table, |address| cartridge.read_chr(address))
+        })
+    }
+
+    /// For debuggers, see `PPU::palette_rgb`. All black with no cartridge.
+    pub fn palette_rgb(&self) -> [[u8; 3]; 32] {
+        self.cpu.as_ref().map_or([[0; 3]; 32], |cpu| cpu.bus.ppu().palette_rgb())
+    }
+
+    /// The current frame as a 256x240 RGBA PNG file. With no cartridge it's all transparent.
+    #[cfg(feature = "image")]
+    pub fn screenshot_png(&self) -> Vec<u8> {
+        let mut rgba = vec![0; SCREEN_WIDTH * SCREEN_HEIGHT * 4];
+        if self.cpu.is_some() {
+            framebuffer_to_rgba(self.framebuffer(), self.master_palette(), self.emphasis(), &mut rgba);
+        }
+
+        let mut png = Vec::new();
+        let mut encoder = png::Encoder::new(&mut png, SCREEN_WIDTH as u32, SCREEN_HEIGHT as u32);
+        encoder.set_color(png::ColorType::RGBA);
+        encoder.set_depth(png::BitDepth::Eight);
+        // Writing to a Vec can't fail and the data is always the size the header says.
+        let mut writer = encoder.write_header().expect("writing a png header to memory failed");
+        writer.write_image_data(&rgba).expect("writing png data to memory failed");
+        drop(writer);
+
+        png
+    }
+
+    /// Fills `out` with audio at the sample rate, see `set_sample_rate`. Returns how many samples
+    /// were written. Only the DMC channel is emulated so far.
+    pub fn drain_samples(&mut self, out: &mut [f32]) -> usize {
+        self.cpu.as_mut().map_or(0, |cpu| cpu.bus.apu_mut().drain_samples(out))
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_rate = sample_rate;
+        if let Some(cpu) = self.cpu.as_mut() {
+            cpu.bus.apu_mut().set_sample_rate(sample_rate);
+        }
+    }
+
+    /// `pad` is 0-3. Pads 2 and 3 are only seen by the game with a Four Score plugged in.
+    pub fn set_button(&mut self, pad: usize, button: Button, pressed: bool) {
+        if let Some(cpu) = self.cpu.as_mut() {
+            cpu.bus.set_button(pad, button, pressed);
+        }
+    }
+
+    /// Auto-fire for a button on pad 0-3, see `Controller::set_turbo`. Turbo buttons only move
+    /// on with `run_frame`.
+    pub fn set_turbo(&mut self, pad: usize, button: Button, rate_hz: Option<f32>) {
+        if let Some(cpu) = self.cpu.as_mut() {
+            cpu.bus.set_turbo(pad, button, rate_hz);
+        }
+    }
+
+    pub fn set_four_score(&mut self, enabled: bool) {
+        self.four_score = enabled;
+        if let Some(cpu) = self.cpu.as_mut() {
+            cpu.bus.set_four_score(enabled);
+        }
+    }
+
+    pub fn reset(&mut self) {
+        if let Some(cpu) = self.cpu.as_mut() {
+            cpu.reset();
+        }
+    }
+
+    /// Swaps in another rom, or puts one in if there wasn't one, and powers the console on again,
+    /// as if the cartridge were changed with the power off. RAM powers on the way it did for the
+    /// first rom, see `RamInit`. Settings are kept, i.e. the palette, sample rate, Four Score,
+    /// hooks and anything set on the CPU, but the movie is stopped. PRG RAM only carries over if
+    /// it's the same rom being reloaded, so one game's saves don't show up in another. On error
+    /// the current rom, if any, is left running.
+    pub fn load_cartridge(&mut self, rom: Vec<u8>) -> Result<(), ROMError> {
+        let mut bus = Bus::with_ram_init(rom, self.ram_init)?;
+        if let Some(old_cartridge) = self.cartridge() {
+            if bus.cartridge().rom_hash() == old_cartridge.rom_hash() {
+                bus.cartridge_mut().prg_ram_mut().copy_from_slice(old_cartridge.prg_ram());
+            }
+        }
+        bus.ppu_mut().set_palette(self.palette);
+        bus.apu_mut().set_sample_rate(self.sample_rate);
+        bus.set_four_score(self.four_score);
+
+        let cpu = match self.cpu.as_mut() {
+            Some(cpu) => {
+                cpu.bus = bus;
+                cpu
+            }
+            None => self.cpu.get_or_insert(CPU::with_bus(bus)),
+        };
+        cpu.power_on();
+        self.movie = None;
+        self.frame = 0;
+        self.hooked_frame = 0;
+
+        Ok(())
+    }
+
+    /// Takes the cartridge out. Until another is loaded `step` and `run_frame` return
+    /// `NesError::NoCartridge`. The CPU goes with it, along with anything set on it, e.g.
+    /// breakpoints, while the settings on `Nes` are kept.
+    pub fn eject(&mut self) {
+        self.cpu = None;
+        self.movie = None;
+        self.frame = 0;
+        self.hooked_frame = 0;
+    }
+
+    /// Plays back the movie from the start, restarting the console first if the movie does.
+    /// Movies recorded with another rom, or when there's no rom, are rejected.
+    pub fn play_movie(&mut self, movie: Movie) -> Result<(), MovieError> {
+        let rom_hash = self.cartridge().map(|cartridge| cartridge.rom_hash());
+        if movie.rom_hash.is_some() && movie.rom_hash != rom_hash {
+            return Err(MovieError::RomMismatch);
+        }
+        if movie.starts_with_reset {
+            self.reset();
+        }
+        self.frame = 0;
+        self.movie = Some(movie);
+
+        Ok(())
+    }
+
+    pub fn stop_movie(&mut self) {
+        self.movie = None;
+    }
+
+    /// None with no cartridge.
+    pub fn save_state(&self) -> Option<MachineSnapshot> {
+        self.cpu.as_ref().map(|cpu| cpu.snapshot())
+    }
+
+    /// Fails if the state was saved with a different rom loaded, or there's no rom.
+    pub fn load_state(&mut self, snapshot: &MachineSnapshot) -> Result<(), SnapshotError> {
+        self.cpu.as_mut().ok_or(SnapshotError::RomMismatch)?.restore(snapshot)
+    }
+
+    /// Everything needed to carry on from exactly this point later, as bytes to write to a file or
+    /// local storage. Unlike `save_state` this has a header so it can be checked when it's loaded.
+    /// Settings, e.g. the palette, sample rate and hooks, aren't included. See `nes::state`. Empty
+    /// with no cartridge.
+    pub fn save_state_bytes(&self) -> Vec<u8> {
+        let cpu = match self.cpu.as_ref() {
+            Some(cpu) => cpu,
+            None => return Vec::new(),
+        };
+        let mut state = StateWriter::new();
+        state.bytes(&MAGIC);
+        state.u16(VERSION);
+        state.bytes(&cpu.bus.cartridge().rom_hash());
+        Nes::write_state(self.frame, cpu, &mut state);
+
+        state.into_bytes()
+    }
+
+    /// Loads what `save_state_bytes` made. States from another version of the format or with a
+    /// different rom loaded, or with no rom, are rejected. On error nothing is changed.
+    pub fn load_state_bytes(&mut self, data: &[u8]) -> Result<(), StateError> {
+        let mut state = StateReader::new(data);
+        let mut magic = [0; 4];
+        state.bytes(&mut magic)?;
+        if magic != MAGIC {
+            return Err(StateError::NotAState);
+        }
+        let version = state.u16()?;
+        if version != VERSION {
+            return Err(StateError::UnknownVersion { version });
+        }
+        let mut rom_hash = [0; 16];
+        state.bytes(&mut rom_hash)?;
+        if Some(rom_hash) != self.cartridge().map(|cartridge| cartridge.rom_hash()) {
+            return Err(StateError::RomMismatch);
+        }
+        let cpu = self.cpu.as_mut().ok_or(StateError::RomMismatch)?;
+
+        // Loading goes straight into the machine, so it's put back if the state turns out to be
+        // bad partway through.
+        let mut backup = StateWriter::new();
+        Nes::write_state(self.frame, cpu, &mut backup);
+        if let Err(error) = Nes::read_state(&mut self.frame, cpu, state) {
+            let backup = backup.into_bytes();
+            Nes::read_state(&mut self.frame, cpu, StateReader::new(&backup)).expect("the machine's own state didn't load");
+            return Err(error);
+        }
+        // Frames before the state was loaded have nothing to do with the hook.
+        self.hooked_frame = cpu.bus.ppu().frame();
+
+        Ok(())
+    }
+
+    fn write_state(frame: u64, cpu: &CPU, state: &mut StateWriter) {
+        state.u64(frame);
+        cpu.save_state(state);
+    }
+
+    fn read_state(frame: &mut u64, cpu: &mut CPU, mut state: StateReader) -> Result<(), StateError> {
+        *frame = state.u64()?;
+        cpu.load_state(&mut state)?;
+        state.finish()
+    }
+
+    /// Reads memory without any side effects, see `AddressSpace::peek`. Everything reads as 0
+    /// with no cartridge.
+    pub fn peek(&self, address: u16) -> u8 {
+        self.cpu.as_ref().map_or(0, |cpu| cpu.bus.peek(address))
+    }
+
+    pub fn cartridge(&self) -> Option<&Cartridge> {
+        self.cpu.as_ref().map(|cpu| cpu.bus.cartridge())
+    }
+
+    pub fn cartridge_mut(&mut self) -> Option<&mut Cartridge> {
+        self.cpu.as_mut().map(|cpu| cpu.bus.cartridge_mut())
+    }
+
+    /// For debugging tools, e.g. tracing and disassembling. None with no cartridge.
+    pub fn cpu_mut(&mut self) -> Option<&mut CPU> {
+        self.cpu.as_mut()
+    }
+}
+
+impl Default for Nes {
+    fn default() -> Self {
+        Nes::new()
+    }
+}
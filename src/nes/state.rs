@@ -0,0 +1,179 @@
+use alloc::vec::Vec;
+use core::fmt;
+
+/// Save states as bytes, e.g. for writing to a file or local storage. These cover the same parts
+/// of the machine as a `MachineSnapshot`: the CPU, RAM, PPU, APU, mapper and cartridge RAM. The
+/// layout is just each part's fields one after another, little endian, so it's only readable by
+/// the same version of the format. See `Nes::save_state_bytes`.
+///
+/// Starts with `MAGIC`, then the version as a u16 and `Cartridge::rom_hash`.
+pub const MAGIC: [u8; 4] = *b"MESS";
+
+/// Bumped whenever the layout changes. Older states are rejected rather than misread.
+pub const VERSION: u16 = 3;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StateError {
+    /// The data doesn't start with `MAGIC`, so it isn't a save state.
+    NotAState,
+    UnknownVersion { version: u16 },
+    /// The state was saved with a different rom loaded.
+    RomMismatch,
+    /// The data ends before the state does.
+    Truncated,
+    /// Something in the state is out of range, or there's data left over after it.
+    Corrupt,
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StateError::NotAState => write!(f, "the data isn't a save state"),
+            StateError::UnknownVersion { version } => {
+                write!(f, "save state version {} isn't supported, only version {} is", version, VERSION)
+            }
+            StateError::RomMismatch => write!(f, "the save state is for a different rom"),
+            StateError::Truncated => write!(f, "the save state is cut short"),
+            StateError::Corrupt => write!(f, "the save state is corrupt"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for StateError {}
+
+/// Builds up a save state. Everything is written little endian.
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> StateWriter {
+        StateWriter { data: Vec::new() }
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.u8(value as u8);
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    /// No length is written, the reader has to know it, e.g. from the rom.
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.data.extend_from_slice(bytes);
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+impl Default for StateWriter {
+    fn default() -> Self {
+        StateWriter::new()
+    }
+}
+
+/// Reads back what a `StateWriter` wrote, in the same order.
+pub struct StateReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> StateReader<'a> {
+        StateReader { data }
+    }
+
+    pub fn u8(&mut self) -> Result<u8, StateError> {
+        let mut byte = [0];
+        self.bytes(&mut byte)?;
+        Ok(byte[0])
+    }
+
+    pub fn bool(&mut self) -> Result<bool, StateError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(StateError::Corrupt),
+        }
+    }
+
+    pub fn u16(&mut self) -> Result<u16, StateError> {
+        let mut bytes = [0; 2];
+        self.bytes(&mut bytes)?;
+        Ok(u16::from_le_bytes(bytes))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, StateError> {
+        let mut bytes = [0; 4];
+        self.bytes(&mut bytes)?;
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, StateError> {
+        let mut bytes = [0; 8];
+        self.bytes(&mut bytes)?;
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    /// Fills all of `out`.
+    pub fn bytes(&mut self, out: &mut [u8]) -> Result<(), StateError> {
+        if self.data.len() < out.len() {
+            return Err(StateError::Truncated);
+        }
+        let (bytes, rest) = self.data.split_at(out.len());
+        out.copy_from_slice(bytes);
+        self.data = rest;
+
+        Ok(())
+    }
+
+    /// Fails if anything is left unread, since then the state wasn't what it claimed to be.
+    pub fn finish(self) -> Result<(), StateError> {
+        if self.data.is_empty() { Ok(()) } else { Err(StateError::Corrupt) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_back_what_was_written() {
+        let mut writer = StateWriter::new();
+        writer.u8(0x12);
+        writer.bool(true);
+        writer.u16(0x3456);
+        writer.u32(0x789A_BCDE);
+        writer.u64(0x0123_4567_89AB_CDEF);
+        writer.bytes(&[1, 2, 3]);
+        let data = writer.into_bytes();
+
+        let mut reader = StateReader::new(&data);
+        assert_eq!(reader.u8(), Ok(0x12));
+        assert_eq!(reader.bool(), Ok(true));
+        assert_eq!(reader.u16(), Ok(0x3456));
+        assert_eq!(reader.u32(), Ok(0x789A_BCDE));
+        assert_eq!(reader.u64(), Ok(0x0123_4567_89AB_CDEF));
+        let mut bytes = [0; 3];
+        assert_eq!(reader.bytes(&mut bytes), Ok(()));
+        assert_eq!(bytes, [1, 2, 3]);
+        assert_eq!(reader.u8(), Err(StateError::Truncated));
+        assert_eq!(StateReader::new(&[2]).bool(), Err(StateError::Corrupt));
+        assert_eq!(StateReader::new(&[0]).finish(), Err(StateError::Corrupt));
+    }
+}
@@ -0,0 +1,2 @@
+pub mod ppu;
+pub mod palette;
\ No newline at end of file
@@ -0,0 +1,100 @@
+use crate::ppu::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+/// The RGB for each of the 64 colours the NES can output. The PPU works in indexes into this.
+/// There's no one right set of values since it depends on the TV, so emulators all differ a bit.
+pub type MasterPalette = [[u8; 3]; 64];
+
+/// The commonly used 2C02 values, and the default. See:
+/// https://wiki.nesdev.com/w/index.php/PPU_palettes
+pub const NTSC_PALETTE: MasterPalette = [
+    [84, 84, 84], [0, 30, 116], [8, 16, 144], [48, 0, 136],
+    [68, 0, 100], [92, 0, 48], [84, 4, 0], [60, 24, 0],
+    [32, 42, 0], [8, 58, 0], [0, 64, 0], [0, 60, 0],
+    [0, 50, 60], [0, 0, 0], [0, 0, 0], [0, 0, 0],
+    [152, 150, 152], [8, 76, 196], [48, 50, 236], [92, 30, 228],
+    [136, 20, 176], [160, 20, 100], [152, 34, 32], [120, 60, 0],
+    [84, 90, 0], [40, 114, 0], [8, 124, 0], [0, 118, 40],
+    [0, 102, 120], [0, 0, 0], [0, 0, 0], [0, 0, 0],
+    [236, 238, 236], [76, 154, 236], [120, 124, 236], [176, 98, 236],
+    [228, 84, 236], [236, 88, 180], [236, 106, 100], [212, 136, 32],
+    [160, 170, 0], [116, 196, 0], [76, 208, 32], [56, 204, 108],
+    [56, 180, 204], [60, 60, 60], [0, 0, 0], [0, 0, 0],
+    [236, 238, 236], [168, 204, 236], [188, 188, 236], [212, 178, 236],
+    [236, 174, 236], [236, 174, 212], [236, 180, 176], [228, 196, 144],
+    [204, 210, 120], [180, 222, 120], [168, 226, 144], [152, 226, 180],
+    [160, 214, 228], [160, 162, 160], [0, 0, 0], [0, 0, 0],
+];
+
+/// Reads a .pal file, i.e. 64 RGB triples. Some have another 7 sets of 64 after that for each
+/// combination of emphasis bits; those are ignored since emphasis is applied separately.
+pub fn from_pal_file(bytes: &[u8]) -> Option<MasterPalette> {
+    if bytes.len() < 64 * 3 {
+        return None;
+    }
+    let mut palette = [[0; 3]; 64];
+    for (colour, rgb) in palette.iter_mut().zip(bytes.chunks_exact(3)) {
+        colour.copy_from_slice(rgb);
+    }
+
+    Some(palette)
+}
+
+/// Converts a framebuffer of colour indexes into RGBA bytes, 4 per pixel with the rows top to
+/// bottom. This is the layout canvas ImageData expects. `emphasis` is PPUMASK's emphasis bits,
+/// see `PPU::emphasis`.
+pub fn framebuffer_to_rgba(framebuffer: &[u8], palette: &MasterPalette, emphasis: u8, rgba: &mut [u8]) {
+    debug_assert_eq!(framebuffer.len(), SCREEN_WIDTH * SCREEN_HEIGHT);
+    debug_assert_eq!(rgba.len(), framebuffer.len() * 4);
+
+    for (pixel, colour) in rgba.chunks_exact_mut(4).zip(framebuffer.iter()) {
+        let [r, g, b] = emphasize(palette[(*colour & 0x3F) as usize], emphasis);
+        pixel.copy_from_slice(&[r, g, b, 0xFF]);
+    }
+}
+
+/// Each emphasis bit darkens the other two channels, by roughly 18%. Bits 5-7 are red, green and
+/// blue on NTSC.
+fn emphasize(rgb: [u8; 3], emphasis: u8) -> [u8; 3] {
+    let mut emphasized = rgb;
+    for (channel, value) in emphasized.iter_mut().enumerate() {
+        if emphasis & !(0x20 << channel) & 0xE0 != 0 {
+            *value = (*value as u16 * 209 / 256) as u8;
+        }
+    }
+    emphasized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn framebuffer_converts_to_rgba() {
+        let mut framebuffer = vec![0x0F; SCREEN_WIDTH * SCREEN_HEIGHT];
+        framebuffer[1] = 0x16;
+        framebuffer[SCREEN_WIDTH] = 0x30;
+        let mut rgba = vec![0; SCREEN_WIDTH * SCREEN_HEIGHT * 4];
+
+        framebuffer_to_rgba(&framebuffer, &NTSC_PALETTE, 0x00, &mut rgba);
+
+        assert_eq!(&rgba[0..4], &[0, 0, 0, 0xFF]);
+        assert_eq!(&rgba[4..8], &[152, 34, 32, 0xFF]);
+        assert_eq!(&rgba[SCREEN_WIDTH * 4..SCREEN_WIDTH * 4 + 4], &[236, 238, 236, 0xFF]);
+
+        // Emphasizing red darkens green and blue.
+        framebuffer_to_rgba(&framebuffer, &NTSC_PALETTE, 0x20, &mut rgba);
+        assert_eq!(&rgba[SCREEN_WIDTH * 4..SCREEN_WIDTH * 4 + 4], &[236, 194, 192, 0xFF]);
+    }
+
+    #[test]
+    fn pal_files_are_read() {
+        let mut pal: Vec<u8> = NTSC_PALETTE.iter().flatten().cloned().collect();
+        pal[0x16 * 3..0x16 * 3 + 3].copy_from_slice(&[1, 2, 3]);
+
+        let palette = from_pal_file(&pal).unwrap();
+
+        assert_eq!(palette[0x16], [1, 2, 3]);
+        assert_eq!(palette[0x30], NTSC_PALETTE[0x30]);
+        assert_eq!(from_pal_file(&pal[..100]), None);
+    }
+}
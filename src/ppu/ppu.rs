@@ -0,0 +1,1039 @@
+use crate::nes::state::{StateError, StateReader, StateWriter};
+use crate::ppu::palette::{MasterPalette, NTSC_PALETTE};
+use crate::rom::rom::Mirroring;
+use alloc::vec;
+use alloc::vec::Vec;
+
+pub const SCREEN_WIDTH: usize = 256;
+pub const SCREEN_HEIGHT: usize = 240;
+
+const DOTS_PER_SCANLINE: u16 = 341;
+const SCANLINES_PER_FRAME: u16 = 262;
+const VBLANK_SCANLINE: u16 = 241;
+const PRE_RENDER_SCANLINE: u16 = 261;
+
+// PPUCTRL
+const NAMETABLE_SELECT: u8 = 0x03;
+const VRAM_INCREMENT_32: u8 = 0x04;
+const SPRITE_PATTERN_TABLE: u8 = 0x08;
+const BACKGROUND_PATTERN_TABLE: u8 = 0x10;
+const SPRITE_SIZE_16: u8 = 0x20;
+const GENERATE_NMI: u8 = 0x80;
+
+// PPUMASK
+const GRAYSCALE: u8 = 0x01;
+const SHOW_BACKGROUND_LEFT: u8 = 0x02;
+const SHOW_SPRITES_LEFT: u8 = 0x04;
+const SHOW_BACKGROUND: u8 = 0x08;
+const SHOW_SPRITES: u8 = 0x10;
+const EMPHASIS: u8 = 0xE0;
+
+// PPUSTATUS
+const VBLANK: u8 = 0x80;
+const SPRITE_ZERO_HIT: u8 = 0x40;
+const SPRITE_OVERFLOW: u8 = 0x20;
+
+// Sprite attributes, the third byte of each sprite in OAM.
+const SPRITE_PALETTE: u8 = 0x03;
+const BEHIND_BACKGROUND: u8 = 0x20;
+const FLIP_HORIZONTAL: u8 = 0x40;
+const FLIP_VERTICAL: u8 = 0x80;
+
+/// Bits of the open bus latch that aren't refreshed for about 600ms decay to 0. This is roughly
+/// 36 frames.
+const LATCH_DECAY_DOTS: u64 = 36 * SCANLINES_PER_FRAME as u64 * DOTS_PER_SCANLINE as u64;
+
+/// The PPU only fetches 8 sprites for each scanline.
+const SPRITES_PER_SCANLINE: usize = 8;
+
+/// Palette RAM's contents at power on are unspecified. Black keeps the screen blank until the game
+/// sets the palette up.
+const BACKDROP: u8 = 0x0F;
+
+const NAMETABLE_START: u16 = 0x2000;
+const NAMETABLE_SIZE: usize = 0x400; // i.e. 1kb.
+const PALETTE_START: u16 = 0x3F00;
+
+/// Pattern tables are 16x16 tiles of 8x8 pixels.
+const PATTERN_TABLE_SIZE: usize = 128;
+
+/// The 2C02 picture processing unit. The CPU talks to it through 8 registers at $2000-$2007 which
+/// are mirrored up to $3FFF.
+///
+/// This handles the registers, frame timing, i.e. vblank and NMI, and draws backgrounds and
+/// sprites a pixel at a time as PPUMASK says to. Scrolling is ignored so far.
+/// See: https://wiki.nesdev.com/w/index.php/PPU_registers
+pub struct PPU {
+    ctrl: u8,
+    mask: u8,
+    status: u8,
+    oam_addr: u8,
+    oam: [u8; 256],
+    vram_addr: u16,
+    // $2000-$2FFF. The console only has 2kb for 2 nametables. The other 2kb is only there for
+    // four screen cartridges, which bring their own.
+    vram: [u8; 4 * NAMETABLE_SIZE],
+    mirroring: Mirroring,
+    // $3F00-$3F1F. 4 background palettes then 4 sprite palettes, 4 colours each.
+    palette: [u8; 32],
+    // PPUDATA reads return what the previous read fetched.
+    read_buffer: u8,
+    // Whatever was last on the PPU's data bus. Write only registers read back as this. Each bit
+    // decays to 0 if it isn't refreshed for a while, see `latch`.
+    decay_latch: u8,
+    // When each bit of `decay_latch` was last refreshed, see `dots_elapsed`.
+    latch_refreshed: [u64; 8],
+    // Shared by PPUSCROLL and PPUADDR to tell whether the next write is the first or second.
+    write_latch: bool,
+    scanline: u16,
+    dot: u16,
+    frame: u64,
+    nmi_pending: bool,
+    // Indexes into OAM of the sprites found on the current scanline, see `evaluate_sprites`.
+    line_sprites: [u8; SPRITES_PER_SCANLINE],
+    line_sprite_count: usize,
+    // One NES colour index per pixel, see palette.rs
+    framebuffer: Vec<u8>,
+    // What each colour index looks like when the framebuffer is converted to RGB.
+    master_palette: MasterPalette,
+}
+
+impl PPU {
+    pub fn new() -> Self {
+        PPU {
+            ctrl: 0,
+            mask: 0,
+            status: 0,
+            oam_addr: 0,
+            oam: [0; 256],
+            vram_addr: 0,
+            vram: [0; 4 * NAMETABLE_SIZE],
+            mirroring: Mirroring::Horizontal,
+            palette: [BACKDROP; 32],
+            read_buffer: 0,
+            decay_latch: 0,
+            latch_refreshed: [0; 8],
+            write_latch: false,
+            scanline: 0,
+            dot: 0,
+            frame: 0,
+            nmi_pending: false,
+            line_sprites: [0; SPRITES_PER_SCANLINE],
+            line_sprite_count: 0,
+            framebuffer: vec![BACKDROP; SCREEN_WIDTH * SCREEN_HEIGHT],
+            master_palette: NTSC_PALETTE,
+        }
+    }
+
+    /// `register` is 0-7, i.e. the address with the mirroring already removed. PPUDATA reads of
+    /// the pattern tables read as 0, see `read_register_with_chr`.
+    pub fn read_register(&mut self, register: usize) -> u8 {
+        self.read_register_with_chr(register, |_| 0)
+    }
+
+    /// Like `read_register` but PPUDATA reads of the pattern tables, $0000-$1FFF, go through
+    /// `read_chr` since they're on the cartridge.
+    pub fn read_register_with_chr<F: FnMut(u16) -> u8>(&mut self, register: usize, mut read_chr: F) -> u8 {
+        let data = match register {
+            2 => {
+                // Only the top 3 bits are driven, the rest is the latch.
+                let status = self.status | (self.latch() & 0x1F);
+                self.refresh_latch(status, 0xE0);
+                self.status &= !VBLANK;
+                self.write_latch = false;
+                return status;
+            }
+            4 => self.oam[self.oam_addr as usize],
+            7 => {
+                let address = self.vram_addr & 0x3FFF;
+                let data = if address >= PALETTE_START {
+                    // Palette reads skip the buffer but the nametable byte "under" the palette
+                    // still ends up in it.
+                    self.read_buffer = self.read_vram(address - 0x1000);
+                    self.read_vram(address)
+                } else {
+                    let buffered = self.read_buffer;
+                    self.read_buffer = if address < NAMETABLE_START { read_chr(address) } else { self.read_vram(address) };
+                    buffered
+                };
+                self.increment_vram_addr();
+                data
+            }
+            _ => return self.latch() // Write only
+        };
+        self.refresh_latch(data, 0xFF);
+
+        data
+    }
+
+    /// PPUDATA writes to the pattern tables are dropped, see `write_register_with_chr`.
+    pub fn write_register(&mut self, register: usize, data: u8) {
+        self.write_register_with_chr(register, data, |_, _| ())
+    }
+
+    /// Like `write_register` but PPUDATA writes to the pattern tables, $0000-$1FFF, go through
+    /// `write_chr`. Only cartridges with CHR RAM do anything with them.
+    pub fn write_register_with_chr<F: FnMut(u16, u8)>(&mut self, register: usize, data: u8, mut write_chr: F) {
+        self.refresh_latch(data, 0xFF);
+        match register {
+            0 => {
+                // Turning NMIs on during vblank triggers one straight away.
+                if self.ctrl & GENERATE_NMI == 0 && data & GENERATE_NMI != 0 && self.status & VBLANK != 0 {
+                    self.nmi_pending = true;
+                }
+                self.ctrl = data;
+            }
+            1 => self.mask = data,
+            3 => self.oam_addr = data,
+            4 => {
+                self.oam[self.oam_addr as usize] = data;
+                self.oam_addr = self.oam_addr.wrapping_add(1);
+            }
+            5 => self.write_latch = !self.write_latch,
+            6 => {
+                self.vram_addr = if self.write_latch {
+                    (self.vram_addr & 0xFF00) | data as u16
+                } else {
+                    ((data as u16 & 0x3F) << 8) | (self.vram_addr & 0x00FF)
+                };
+                self.write_latch = !self.write_latch;
+            }
+            7 => {
+                let address = self.vram_addr & 0x3FFF;
+                if address < NAMETABLE_START {
+                    write_chr(address, data);
+                } else {
+                    self.write_vram(address, data);
+                }
+                self.increment_vram_addr();
+            }
+            _ => () // PPUSTATUS is read only
+        }
+    }
+
+    /// The open bus latch as of now, with any bits that have gone too long without a refresh
+    /// decayed to 0.
+    fn latch(&mut self) -> u8 {
+        let now = self.dots_elapsed();
+        for (bit, refreshed) in self.latch_refreshed.iter().enumerate() {
+            // A loaded state can have bits refreshed after now, which count as just refreshed.
+            if now.saturating_sub(*refreshed) >= LATCH_DECAY_DOTS {
+                self.decay_latch &= !(1 << bit);
+            }
+        }
+
+        self.decay_latch
+    }
+
+    /// Puts the bits of `data` in `mask` on the latch. Only those bits are driven so the others
+    /// carry on decaying.
+    fn refresh_latch(&mut self, data: u8, mask: u8) {
+        let now = self.dots_elapsed();
+        for (bit, refreshed) in self.latch_refreshed.iter_mut().enumerate() {
+            if mask & (1 << bit) != 0 {
+                *refreshed = now;
+            }
+        }
+        self.decay_latch = (self.latch() & !mask) | (data & mask);
+    }
+
+    /// Dots since power on. Every frame is the same length, so this is just the position in the
+    /// frame plus the frames before it.
+    fn dots_elapsed(&self) -> u64 {
+        (self.frame * SCANLINES_PER_FRAME as u64 + self.scanline as u64) * DOTS_PER_SCANLINE as u64 + self.dot as u64
+    }
+
+    /// Every PPUDATA access moves along by 1, i.e. across, or 32, i.e. down a row of tiles.
+    fn increment_vram_addr(&mut self) {
+        let increment = if self.ctrl & VRAM_INCREMENT_32 != 0 { 32 } else { 1 };
+        self.vram_addr = self.vram_addr.wrapping_add(increment);
+    }
+
+    /// Set by whoever owns the cartridge, since the cartridge decides how nametables are mirrored.
+    pub fn set_mirroring(&mut self, mirroring: Mirroring) {
+        self.mirroring = mirroring;
+    }
+
+    /// $2000-$3FFF as the PPU sees it. The pattern tables at $0000-$1FFF are on the cartridge so
+    /// they're read through the `*_with_chr` functions instead.
+    fn read_vram(&self, address: u16) -> u8 {
+        match address & 0x3FFF {
+            NAMETABLE_START..=0x3EFF => self.vram[nametable_index(address, self.mirroring)],
+            PALETTE_START..=0x3FFF => self.palette[palette_index(address)],
+            _ => 0
+        }
+    }
+
+    fn write_vram(&mut self, address: u16, data: u8) {
+        match address & 0x3FFF {
+            NAMETABLE_START..=0x3EFF => self.vram[nametable_index(address, self.mirroring)] = data,
+            PALETTE_START..=0x3FFF => self.palette[palette_index(address)] = data,
+            _ => ()
+        }
+    }
+
+    /// Advances the PPU by a single dot with empty pattern tables, see `tick_with_chr`.
+    pub fn tick(&mut self) {
+        self.tick_with_chr(|_| 0)
+    }
+
+    /// Advances the PPU by a single dot. The PPU runs 3 dots for every CPU cycle. `read_chr` reads
+    /// the pattern tables at $0000-$1FFF, which are on the cartridge.
+    pub fn tick_with_chr<F: FnMut(u16) -> u8>(&mut self, mut read_chr: F) {
+        let scanline = self.scanline as usize;
+        let dot = self.dot as usize;
+        if scanline < SCREEN_HEIGHT && dot >= 1 && dot <= SCREEN_WIDTH {
+            self.framebuffer[scanline * SCREEN_WIDTH + dot - 1] = self.pixel_colour(dot - 1, scanline, &mut read_chr);
+
+            if self.status & SPRITE_ZERO_HIT == 0 && self.sprite_zero_hits(dot - 1, scanline, &mut read_chr) {
+                self.status |= SPRITE_ZERO_HIT;
+            }
+        }
+        if dot == 257 {
+            self.evaluate_sprites(scanline);
+        }
+
+        if self.dot == 1 {
+            if self.scanline == VBLANK_SCANLINE {
+                self.status |= VBLANK;
+                if self.ctrl & GENERATE_NMI != 0 {
+                    self.nmi_pending = true;
+                }
+            } else if self.scanline == PRE_RENDER_SCANLINE {
+                self.status &= !(VBLANK | SPRITE_ZERO_HIT | SPRITE_OVERFLOW);
+            }
+        }
+
+        self.dot += 1;
+        if self.dot == DOTS_PER_SCANLINE {
+            self.dot = 0;
+            self.scanline += 1;
+            if self.scanline == SCANLINES_PER_FRAME {
+                self.scanline = 0;
+                self.frame += 1;
+            }
+        }
+    }
+
+    /// Sprite evaluation for the next scanline finishes on dot 256. Only the first 8 sprites found
+    /// get drawn and any more set the overflow flag. Real hardware has a bug that makes the flag
+    /// unreliable, this just counts. Nothing is evaluated while rendering is off.
+    fn evaluate_sprites(&mut self, scanline: usize) {
+        self.line_sprite_count = 0;
+        if scanline >= SCREEN_HEIGHT || self.mask & (SHOW_BACKGROUND | SHOW_SPRITES) == 0 {
+            return;
+        }
+
+        let mut found = 0;
+        for sprite in 0..self.oam.len() / 4 {
+            if self.sprite_row(self.oam[sprite * 4], scanline + 1).is_some() {
+                if found < SPRITES_PER_SCANLINE {
+                    self.line_sprites[found] = sprite as u8;
+                }
+                found += 1;
+            }
+        }
+        self.line_sprite_count = found.min(SPRITES_PER_SCANLINE);
+        if found > SPRITES_PER_SCANLINE {
+            self.status |= SPRITE_OVERFLOW;
+        }
+    }
+
+    /// The NES colour at `x`. Layers PPUMASK turns off, or clips in the leftmost 8 pixels, aren't
+    /// fetched or drawn. Where nothing opaque is drawn it's the backdrop colour.
+    fn pixel_colour<F: FnMut(u16) -> u8>(&self, x: usize, scanline: usize, read_chr: &mut F) -> u8 {
+        let background = if self.shows(SHOW_BACKGROUND, SHOW_BACKGROUND_LEFT, x) {
+            self.background_pixel(x, scanline, read_chr)
+        } else {
+            0
+        };
+        let sprite = if self.shows(SHOW_SPRITES, SHOW_SPRITES_LEFT, x) {
+            self.sprite_pixel(x, scanline, read_chr)
+        } else {
+            None
+        };
+
+        let index = match sprite {
+            Some((colour, attributes)) if background == 0 || attributes & BEHIND_BACKGROUND == 0 => {
+                0x10 + (attributes & SPRITE_PALETTE) * 4 + colour
+            }
+            _ if background != 0 => self.background_palette(x, scanline) * 4 + background,
+            _ => 0,
+        };
+        let colour = self.palette[index as usize];
+        // Grayscale keeps only the brightness, i.e. the $x0 column of the NES's colours.
+        if self.mask & GRAYSCALE != 0 { colour & 0x30 } else { colour }
+    }
+
+    fn shows(&self, layer: u8, left_column: u8, x: usize) -> bool {
+        self.mask & layer != 0 && (x >= 8 || self.mask & left_column != 0)
+    }
+
+    /// The colour within its palette and the attributes of the frontmost opaque sprite at `x`.
+    /// Lower sprites in OAM are in front.
+    fn sprite_pixel<F: FnMut(u16) -> u8>(&self, x: usize, scanline: usize, read_chr: &mut F) -> Option<(u8, u8)> {
+        self.line_sprites[..self.line_sprite_count].iter().find_map(|sprite| {
+            let sprite = *sprite as usize;
+            match self.sprite_pattern_pixel(sprite, x, scanline, read_chr) {
+                0 => None,
+                colour => Some((colour, self.oam[sprite * 4 + 2])),
+            }
+        })
+    }
+
+    /// Sprite 0 hits when an opaque pixel of it is drawn over an opaque background pixel. Pixels
+    /// hidden by PPUMASK's left column clipping don't count and neither does the last column.
+    fn sprite_zero_hits<F: FnMut(u16) -> u8>(&self, x: usize, scanline: usize, read_chr: &mut F) -> bool {
+        let both_shown = SHOW_BACKGROUND | SHOW_SPRITES;
+        let both_shown_left = SHOW_BACKGROUND_LEFT | SHOW_SPRITES_LEFT;
+        if self.mask & both_shown != both_shown || (x < 8 && self.mask & both_shown_left != both_shown_left) || x == 255 {
+            return false;
+        }
+
+        self.sprite_pattern_pixel(0, x, scanline, read_chr) != 0 && self.background_pixel(x, scanline, read_chr) != 0
+    }
+
+    /// Which row of a sprite at `y` in OAM is drawn on `scanline`, if any. Sprites are drawn a
+    /// scanline lower than their y.
+    fn sprite_row(&self, y: u8, scanline: usize) -> Option<usize> {
+        let height = if self.ctrl & SPRITE_SIZE_16 != 0 { 16 } else { 8 };
+        let row = scanline.checked_sub(y as usize + 1)?;
+        if row < height { Some(row) } else { None }
+    }
+
+    /// The colour within its palette, 0 being transparent, of the pixel at `x` of the `sprite`th
+    /// sprite in OAM.
+    fn sprite_pattern_pixel<F: FnMut(u16) -> u8>(&self, sprite: usize, x: usize, scanline: usize, read_chr: &mut F) -> u8 {
+        let sprite = &self.oam[sprite * 4..sprite * 4 + 4];
+        let (y, tile, attributes, sprite_x) = (sprite[0], sprite[1] as u16, sprite[2], sprite[3] as usize);
+        let (mut row, mut column) = match (self.sprite_row(y, scanline), x.checked_sub(sprite_x)) {
+            (Some(row), Some(column)) if column < 8 => (row as u16, column as u8),
+            _ => return 0,
+        };
+
+        if attributes & FLIP_VERTICAL != 0 {
+            row = if self.ctrl & SPRITE_SIZE_16 != 0 { 15 } else { 7 } - row;
+        }
+        if attributes & FLIP_HORIZONTAL != 0 {
+            column = 7 - column;
+        }
+        let address = if self.ctrl & SPRITE_SIZE_16 != 0 {
+            // 8x16 sprites pick their pattern table with bit 0 of the tile and the bottom half is
+            // the next tile.
+            (tile & 0x01) * 0x1000 + ((tile & 0xFE) + row / 8) * 16 + row % 8
+        } else {
+            self.pattern_table(SPRITE_PATTERN_TABLE) + tile * 16 + row
+        };
+
+        pattern_pixel(read_chr, address, column)
+    }
+
+    /// The colour within its palette, 0 being transparent, of the background at `x`.
+    fn background_pixel<F: FnMut(u16) -> u8>(&self, x: usize, scanline: usize, read_chr: &mut F) -> u8 {
+        self.nametable_pixel((self.ctrl & NAMETABLE_SELECT) as usize, x, scanline, read_chr)
+    }
+
+    /// Which of the 4 background palettes the background at `x` uses.
+    fn background_palette(&self, x: usize, scanline: usize) -> u8 {
+        self.nametable_palette((self.ctrl & NAMETABLE_SELECT) as usize, x, scanline)
+    }
+
+    /// Like `background_pixel` but for any of the 4 nametables, with `x` and `y` within it.
+    fn nametable_pixel<F: FnMut(u16) -> u8>(&self, nametable: usize, x: usize, y: usize, read_chr: &mut F) -> u8 {
+        let tile = self.read_vram(nametable_address(nametable) + (y / 8 * 32 + x / 8) as u16) as u16;
+        let address = self.pattern_table(BACKGROUND_PATTERN_TABLE) + tile * 16 + (y % 8) as u16;
+
+        pattern_pixel(read_chr, address, (x % 8) as u8)
+    }
+
+    /// Each attribute table byte covers 4x4 tiles, 2 bits for each 2x2 tile quarter.
+    fn nametable_palette(&self, nametable: usize, x: usize, y: usize) -> u8 {
+        let attributes = self.read_vram(nametable_address(nametable) + 0x3C0 + (y / 32 * 8 + x / 32) as u16);
+        let shift = (y / 16 % 2) * 4 + (x / 16 % 2) * 2;
+        (attributes >> shift) & 0x03
+    }
+
+    fn pattern_table(&self, bit: u8) -> u16 {
+        if self.ctrl & bit != 0 { 0x1000 } else { 0x0000 }
+    }
+
+    /// The pattern table the PPU is fetching from on the current dot, if it's fetching at all.
+    /// Nothing is drawn from these yet but mappers that watch the PPU's address lines, e.g. MMC3,
+    /// need them. 8x16 sprites pick their own pattern table per sprite which isn't handled.
+    pub fn pattern_fetch_address(&self) -> Option<u16> {
+        let rendering = self.mask & (SHOW_BACKGROUND | SHOW_SPRITES) != 0;
+        let fetching_scanline = (self.scanline as usize) < SCREEN_HEIGHT || self.scanline == PRE_RENDER_SCANLINE;
+        if !rendering || !fetching_scanline {
+            return None;
+        }
+        match self.dot {
+            1..=256 | 321..=336 => Some(self.pattern_table(BACKGROUND_PATTERN_TABLE)),
+            257..=320 => Some(self.pattern_table(SPRITE_PATTERN_TABLE)),
+            _ => None
+        }
+    }
+
+    /// Returns whether an NMI has been raised since the last time this was called.
+    pub fn take_nmi(&mut self) -> bool {
+        let nmi_pending = self.nmi_pending;
+        self.nmi_pending = false;
+        nmi_pending
+    }
+
+    /// 0-261, where 0-239 are drawn, 241 starts vblank and 261 is the pre-render scanline.
+    pub fn scanline(&self) -> u16 {
+        self.scanline
+    }
+
+    /// 0-340 within the scanline.
+    pub fn dot(&self) -> u16 {
+        self.dot
+    }
+
+    /// Sprite memory, 4 bytes per sprite: y, tile, attributes and x.
+    pub fn oam(&self) -> &[u8] {
+        &self.oam
+    }
+
+    /// Number of frames that have been fully drawn.
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    pub fn framebuffer(&self) -> &[u8] {
+        &self.framebuffer
+    }
+
+    /// PPUMASK's colour emphasis bits, red, green then blue from bit 5. These tint the whole
+    /// picture so they're applied when converting the framebuffer to RGB, see `ppu::palette`.
+    pub fn emphasis(&self) -> u8 {
+        self.mask & EMPHASIS
+    }
+
+    /// Changes the RGB the framebuffer's colour indexes convert to, e.g. to one loaded with
+    /// `palette::from_pal_file`. `NTSC_PALETTE` until set.
+    pub fn set_palette(&mut self, palette: MasterPalette) {
+        self.master_palette = palette;
+    }
+
+    /// Pass to `palette::framebuffer_to_rgba` along with the framebuffer.
+    pub fn master_palette(&self) -> &MasterPalette {
+        &self.master_palette
+    }
+
+    /// RGBA for the whole of nametable `index`, 0-3, with empty pattern tables. See
+    /// `render_nametable_with_chr`.
+    pub fn render_nametable(&self, index: usize) -> Vec<u8> {
+        self.render_nametable_with_chr(index, |_| 0)
+    }
+
+    /// RGBA for the whole of nametable `index`, 0-3, i.e. $2000, $2400, $2800 or $2C00 after
+    /// mirroring, drawn with the background pattern table and palettes as they are now. Scrolling
+    /// and PPUMASK are ignored so a debugger sees everything. 256x240, rows top to bottom.
+    pub fn render_nametable_with_chr<F: FnMut(u16) -> u8>(&self, index: usize, mut read_chr: F) -> Vec<u8> {
+        let mut rgba = vec![0; SCREEN_WIDTH * SCREEN_HEIGHT * 4];
+        for (i, pixel) in rgba.chunks_exact_mut(4).enumerate() {
+            let (x, y) = (i % SCREEN_WIDTH, i / SCREEN_WIDTH);
+            let colour = match self.nametable_pixel(index, x, y, &mut read_chr) {
+                0 => 0,
+                colour => self.nametable_palette(index, x, y) * 4 + colour,
+            };
+            self.palette_colour_to_rgba(colour as usize, pixel);
+        }
+
+        rgba
+    }
+
+    /// RGBA for pattern table `table`, 0 for $0000 or 1 for $1000, with empty pattern tables. See
+    /// `render_pattern_table_with_chr`.
+    pub fn render_pattern_table(&self, table: usize) -> Vec<u8> {
+        self.render_pattern_table_with_chr(table, |_| 0)
+    }
+
+    /// RGBA for all 256 tiles of pattern table `table`, 0 for $0000 or 1 for $1000, coloured with
+    /// the first background palette. 16x16 tiles, i.e. 128x128, rows top to bottom.
+    pub fn render_pattern_table_with_chr<F: FnMut(u16) -> u8>(&self, table: usize, mut read_chr: F) -> Vec<u8> {
+        let mut rgba = vec![0; PATTERN_TABLE_SIZE * PATTERN_TABLE_SIZE * 4];
+        for (i, pixel) in rgba.chunks_exact_mut(4).enumerate() {
+            let (x, y) = (i % PATTERN_TABLE_SIZE, i / PATTERN_TABLE_SIZE);
+            let tile = (y / 8 * 16 + x / 8) as u16;
+            let address = (table as u16 & 1) * 0x1000 + tile * 16 + (y % 8) as u16;
+            let colour = pattern_pixel(&mut read_chr, address, (x % 8) as u8);
+            self.palette_colour_to_rgba(colour as usize, pixel);
+        }
+
+        rgba
+    }
+
+    /// Palette RAM, $3F00-$3F1F, as RGB through the master palette. The sprite palettes' first
+    /// colours show what they mirror.
+    pub fn palette_rgb(&self) -> [[u8; 3]; 32] {
+        let mut rgb = [[0; 3]; 32];
+        for (index, colour) in rgb.iter_mut().enumerate() {
+            *colour = self.master_palette[(self.read_vram(PALETTE_START + index as u16) & 0x3F) as usize];
+        }
+
+        rgb
+    }
+
+    /// `index` is into palette RAM.
+    fn palette_colour_to_rgba(&self, index: usize, pixel: &mut [u8]) {
+        let [r, g, b] = self.master_palette[(self.palette[index] & 0x3F) as usize];
+        pixel.copy_from_slice(&[r, g, b, 0xFF]);
+    }
+
+    /// The master palette is a setting rather than state so it isn't saved. Neither is the
+    /// mirroring, which whoever owns the cartridge sets again after loading it.
+    pub(crate) fn save_state(&self, state: &mut StateWriter) {
+        state.u8(self.ctrl);
+        state.u8(self.mask);
+        state.u8(self.status);
+        state.u8(self.oam_addr);
+        state.bytes(&self.oam);
+        state.u16(self.vram_addr);
+        state.bytes(&self.vram);
+        state.bytes(&self.palette);
+        state.u8(self.read_buffer);
+        state.u8(self.decay_latch);
+        for refreshed in &self.latch_refreshed {
+            state.u64(*refreshed);
+        }
+        state.bool(self.write_latch);
+        state.u16(self.scanline);
+        state.u16(self.dot);
+        state.u64(self.frame);
+        state.bool(self.nmi_pending);
+        state.bytes(&self.line_sprites);
+        state.u8(self.line_sprite_count as u8);
+        state.bytes(&self.framebuffer);
+    }
+
+    pub(crate) fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.ctrl = state.u8()?;
+        self.mask = state.u8()?;
+        self.status = state.u8()?;
+        self.oam_addr = state.u8()?;
+        state.bytes(&mut self.oam)?;
+        self.vram_addr = state.u16()?;
+        state.bytes(&mut self.vram)?;
+        state.bytes(&mut self.palette)?;
+        self.read_buffer = state.u8()?;
+        self.decay_latch = state.u8()?;
+        for refreshed in self.latch_refreshed.iter_mut() {
+            *refreshed = state.u64()?;
+        }
+        self.write_latch = state.bool()?;
+        self.scanline = state.u16()?;
+        self.dot = state.u16()?;
+        self.frame = state.u64()?;
+        self.nmi_pending = state.bool()?;
+        state.bytes(&mut self.line_sprites)?;
+        self.line_sprite_count = state.u8()? as usize;
+        state.bytes(&mut self.framebuffer)?;
+        // Past the end of a scanline or frame the counters would never wrap back around. There
+        // are only 64 sprites in OAM.
+        if self.scanline >= SCANLINES_PER_FRAME
+            || self.dot >= DOTS_PER_SCANLINE
+            || self.line_sprite_count > SPRITES_PER_SCANLINE
+            || self.line_sprites.iter().any(|sprite| *sprite as usize >= self.oam.len() / 4)
+        {
+            return Err(StateError::Corrupt);
+        }
+
+        Ok(())
+    }
+}
+
+/// Maps an address in $2000-$3EFF onto VRAM. There are 4 nametables at $2000, $2400, $2800 and
+/// $2C00 (mirrored again at $3000-$3EFF) but usually only 2 nametables of RAM, so the mirroring
+/// decides which pairs share RAM.
+/// See: https://wiki.nesdev.com/w/index.php/Mirroring#Nametable_Mirroring
+pub fn nametable_index(address: u16, mirroring: Mirroring) -> usize {
+    let address = (address - NAMETABLE_START) as usize % (4 * NAMETABLE_SIZE);
+    let nametable = address / NAMETABLE_SIZE;
+    let physical_nametable = match mirroring {
+        // $2000 = $2400 and $2800 = $2C00
+        Mirroring::Horizontal => nametable / 2,
+        // $2000 = $2800 and $2400 = $2C00
+        Mirroring::Vertical => nametable % 2,
+        Mirroring::SingleScreenLower => 0,
+        Mirroring::SingleScreenUpper => 1,
+        Mirroring::FourScreen => nametable,
+    };
+
+    physical_nametable * NAMETABLE_SIZE + address % NAMETABLE_SIZE
+}
+
+/// Where nametable `index`, 0-3, starts on the PPU's bus.
+fn nametable_address(index: usize) -> u16 {
+    NAMETABLE_START + (index % 4) as u16 * NAMETABLE_SIZE as u16
+}
+
+/// Tiles are 16 bytes, 8 rows of the colour's low bits then 8 rows of the high bits, with the
+/// leftmost pixel in bit 7. Returns the colour of `column` in the row at `address`.
+fn pattern_pixel<F: FnMut(u16) -> u8>(read_chr: &mut F, address: u16, column: u8) -> u8 {
+    let low = read_chr(address) >> (7 - column) & 1;
+    let high = read_chr(address + 8) >> (7 - column) & 1;
+    high << 1 | low
+}
+
+/// Palette RAM is 32 bytes mirrored all the way up to $3FFF. On top of that the sprite palettes'
+/// first colours, $3F10/$3F14/$3F18/$3F1C, are the same bytes as $3F00/$3F04/$3F08/$3F0C.
+fn palette_index(address: u16) -> usize {
+    let index = (address & 0x1F) as usize;
+    if index & 0x13 == 0x10 {
+        index & !0x10
+    } else {
+        index
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ppu::palette::framebuffer_to_rgba;
+    use super::*;
+
+    #[test]
+    fn vblank_starts_on_scanline_241() {
+        let mut ppu = PPU::new();
+        ppu.write_register(0, GENERATE_NMI);
+
+        for _ in 0..(VBLANK_SCANLINE as usize * DOTS_PER_SCANLINE as usize + 1) {
+            ppu.tick();
+        }
+        assert_eq!(ppu.status & VBLANK, 0);
+        assert!(!ppu.take_nmi());
+
+        ppu.tick();
+        assert!(ppu.take_nmi());
+        assert_eq!(ppu.read_register(2) & VBLANK, VBLANK);
+        assert_eq!(ppu.read_register(2) & VBLANK, 0);
+    }
+
+    fn set_vram_addr(ppu: &mut PPU, address: u16) {
+        ppu.write_register(6, (address >> 8) as u8);
+        ppu.write_register(6, address as u8);
+    }
+
+    #[test]
+    fn sprite_backdrop_colours_mirror_background() {
+        let mut ppu = PPU::new();
+        set_vram_addr(&mut ppu, 0x3F10);
+        ppu.write_register(7, 0x0E);
+        set_vram_addr(&mut ppu, 0x3F00);
+        assert_eq!(ppu.read_register(7), 0x0E);
+
+        set_vram_addr(&mut ppu, 0x3F0C);
+        ppu.write_register(7, 0x21);
+        set_vram_addr(&mut ppu, 0x3F1C);
+        assert_eq!(ppu.read_register(7), 0x21);
+
+        // Other sprite colours aren't mirrored.
+        set_vram_addr(&mut ppu, 0x3F11);
+        ppu.write_register(7, 0x30);
+        set_vram_addr(&mut ppu, 0x3F01);
+        assert_eq!(ppu.read_register(7), BACKDROP);
+    }
+
+    #[test]
+    fn palette_is_mirrored_up_to_3fff() {
+        let mut ppu = PPU::new();
+        set_vram_addr(&mut ppu, 0x3FE5);
+        ppu.write_register(7, 0x16);
+        set_vram_addr(&mut ppu, 0x3F05);
+
+        assert_eq!(ppu.read_register(7), 0x16);
+    }
+
+    #[test]
+    fn vertical_mirroring_shares_2000_and_2800() {
+        let mut ppu = PPU::new();
+        ppu.set_mirroring(Mirroring::Vertical);
+        set_vram_addr(&mut ppu, 0x2000);
+        ppu.write_register(7, 0xAB);
+
+        assert_eq!(ppu.read_vram(0x2800), 0xAB);
+        assert_eq!(ppu.read_vram(0x2400), 0x00);
+    }
+
+    #[test]
+    fn horizontal_mirroring_shares_2000_and_2400() {
+        let mut ppu = PPU::new();
+        ppu.set_mirroring(Mirroring::Horizontal);
+        set_vram_addr(&mut ppu, 0x2012);
+        ppu.write_register(7, 0xAB);
+
+        assert_eq!(ppu.read_vram(0x2412), 0xAB);
+        assert_eq!(ppu.read_vram(0x2812), 0x00);
+        // $3000-$3EFF mirrors $2000-$2EFF
+        assert_eq!(ppu.read_vram(0x3012), 0xAB);
+    }
+
+    #[test]
+    fn nametables_map_onto_vram() {
+        use Mirroring::*;
+        let nametables = [0x2000, 0x2400, 0x2800, 0x2C00];
+        let expected = [
+            (Horizontal, [0, 0, 1, 1]),
+            (Vertical, [0, 1, 0, 1]),
+            (SingleScreenLower, [0, 0, 0, 0]),
+            (SingleScreenUpper, [1, 1, 1, 1]),
+            (FourScreen, [0, 1, 2, 3]),
+        ];
+        for (mirroring, physical) in expected.iter() {
+            for (address, physical) in nametables.iter().zip(physical.iter()) {
+                assert_eq!(nametable_index(*address + 5, *mirroring), physical * NAMETABLE_SIZE + 5, "{:?} {:04X}", mirroring, address);
+            }
+        }
+    }
+
+    #[test]
+    fn ppudata_reads_are_a_read_behind() {
+        let mut ppu = PPU::new();
+        set_vram_addr(&mut ppu, 0x2000);
+        ppu.write_register(7, 0x11);
+        ppu.write_register(7, 0x22);
+        set_vram_addr(&mut ppu, 0x2000);
+
+        assert_eq!(ppu.read_register(7), 0x00); // Whatever was in the buffer
+        assert_eq!(ppu.read_register(7), 0x11);
+        assert_eq!(ppu.read_register(7), 0x22);
+    }
+
+    #[test]
+    fn vram_addr_increments_by_32_in_increment_mode() {
+        let mut ppu = PPU::new();
+        ppu.write_register(0, VRAM_INCREMENT_32);
+        set_vram_addr(&mut ppu, 0x2000);
+        ppu.write_register(7, 0x11);
+        assert_eq!(ppu.vram_addr, 0x2020);
+        ppu.write_register(7, 0x22);
+        ppu.read_register(7);
+        assert_eq!(ppu.vram_addr, 0x2060);
+
+        ppu.write_register(0, 0);
+        ppu.write_register(7, 0x33);
+        assert_eq!(ppu.vram_addr, 0x2061);
+        assert_eq!(ppu.read_vram(0x2020), 0x22);
+    }
+
+    /// Pattern tables where tile 1 is solid colour 1 and everything else is transparent.
+    fn solid_tile_1(address: u16) -> u8 {
+        if (0x10..0x18).contains(&address) { 0xFF } else { 0x00 }
+    }
+
+    /// Ticks up to and including the next time the PPU is on `dot` of `scanline`.
+    fn tick_to(ppu: &mut PPU, scanline: u16, dot: u16) {
+        loop {
+            let last = ppu.scanline == scanline && ppu.dot == dot;
+            ppu.tick_with_chr(solid_tile_1);
+            if last {
+                break;
+            }
+        }
+    }
+
+    #[test]
+    fn open_bus_latch_decays_a_bit_at_a_time() {
+        let mut ppu = PPU::new();
+        // Sprites off screen so they don't overflow and set PPUSTATUS bits.
+        ppu.oam = [0xFF; 256];
+        ppu.write_register(1, 0x1E);
+        assert_eq!(ppu.read_register(2) & 0x1F, 0x1E);
+
+        // Reading PPUSTATUS in vblank only refreshes the top 3 bits, so bit 7 outlasts the rest.
+        while ppu.status & VBLANK == 0 {
+            ppu.tick();
+        }
+        assert_eq!(ppu.read_register(2), VBLANK | 0x1E);
+        while ppu.dots_elapsed() < LATCH_DECAY_DOTS {
+            ppu.tick();
+        }
+        assert_eq!(ppu.read_register(0), VBLANK);
+        assert_eq!(ppu.read_register(2) & 0x1F, 0x00);
+
+        for _ in 0..LATCH_DECAY_DOTS {
+            ppu.tick();
+        }
+        assert_eq!(ppu.read_register(0), 0x00);
+    }
+
+    #[test]
+    fn debug_views_draw_nametables_and_pattern_tables() {
+        let mut ppu = PPU::new();
+        ppu.set_mirroring(Mirroring::Vertical);
+        // Tile 1 at column 3, row 2 of $2400. It's in the bottom right quarter of the first
+        // attribute byte, which picks palette 1 for it.
+        set_vram_addr(&mut ppu, 0x2400 + 2 * 32 + 3);
+        ppu.write_register(7, 0x01);
+        set_vram_addr(&mut ppu, 0x27C0);
+        ppu.write_register(7, 0x01 << 6);
+        set_vram_addr(&mut ppu, 0x3F01);
+        ppu.write_register(7, 0x21);
+        set_vram_addr(&mut ppu, 0x3F05);
+        ppu.write_register(7, 0x16);
+        let pixel = |rgba: &[u8], width: usize, x: usize, y: usize| rgba[(y * width + x) * 4..(y * width + x) * 4 + 4].to_vec();
+        let rgba = |colour: u8| {
+            let [r, g, b] = NTSC_PALETTE[colour as usize];
+            vec![r, g, b, 0xFF]
+        };
+
+        let nametable = ppu.render_nametable_with_chr(1, solid_tile_1);
+        assert_eq!(nametable.len(), 256 * 240 * 4);
+        assert_eq!(pixel(&nametable, 256, 24, 16), rgba(0x16));
+        assert_eq!(pixel(&nametable, 256, 31, 23), rgba(0x16));
+        assert_eq!(pixel(&nametable, 256, 32, 16), rgba(BACKDROP));
+        assert_eq!(pixel(&nametable, 256, 24, 24), rgba(BACKDROP));
+        // $2C00 is $2400 with vertical mirroring and $2000 is the other nametable.
+        assert_eq!(ppu.render_nametable_with_chr(3, solid_tile_1), nametable);
+        assert_eq!(pixel(&ppu.render_nametable_with_chr(0, solid_tile_1), 256, 24, 16), rgba(BACKDROP));
+
+        let patterns = ppu.render_pattern_table_with_chr(0, solid_tile_1);
+        assert_eq!(patterns.len(), 128 * 128 * 4);
+        assert_eq!(pixel(&patterns, 128, 8, 0), rgba(0x21));
+        assert_eq!(pixel(&patterns, 128, 15, 7), rgba(0x21));
+        assert_eq!(pixel(&patterns, 128, 7, 0), rgba(BACKDROP));
+        assert_eq!(pixel(&patterns, 128, 16, 0), rgba(BACKDROP));
+        assert_eq!(pixel(&ppu.render_pattern_table_with_chr(1, solid_tile_1), 128, 8, 0), rgba(BACKDROP));
+
+        let palette = ppu.palette_rgb();
+        assert_eq!(palette[0x01], NTSC_PALETTE[0x21]);
+        assert_eq!(palette[0x05], NTSC_PALETTE[0x16]);
+        assert_eq!(palette[0x11], NTSC_PALETTE[BACKDROP as usize]);
+    }
+
+    #[test]
+    fn sprite_zero_hit_sets_on_first_overlapping_pixel() {
+        let mut ppu = PPU::new();
+        ppu.write_register(1, SHOW_BACKGROUND | SHOW_SPRITES);
+        // Background tile 1 covers x 16-23 and y 8-15.
+        ppu.write_vram(0x2000 + 32 + 2, 0x01);
+        // Sprite 0 covers x 20-27 and y 10-17.
+        ppu.oam[..4].copy_from_slice(&[9, 0x01, 0x00, 20]);
+
+        // x 20 on scanline 10 is dot 21.
+        tick_to(&mut ppu, 10, 20);
+        assert_eq!(ppu.status & SPRITE_ZERO_HIT, 0);
+        ppu.tick_with_chr(solid_tile_1);
+        assert_eq!(ppu.status & SPRITE_ZERO_HIT, SPRITE_ZERO_HIT);
+        assert_eq!(ppu.read_register(2) & SPRITE_ZERO_HIT, SPRITE_ZERO_HIT);
+
+        tick_to(&mut ppu, PRE_RENDER_SCANLINE, 1);
+        assert_eq!(ppu.status & SPRITE_ZERO_HIT, 0);
+    }
+
+    #[test]
+    fn sprite_zero_hit_needs_opaque_background() {
+        let mut ppu = PPU::new();
+        ppu.write_register(1, SHOW_BACKGROUND | SHOW_SPRITES);
+        ppu.oam[..4].copy_from_slice(&[9, 0x01, 0x00, 20]);
+
+        tick_to(&mut ppu, VBLANK_SCANLINE, 0);
+        assert_eq!(ppu.status & SPRITE_ZERO_HIT, 0);
+    }
+
+    #[test]
+    fn disabled_background_shows_the_backdrop() {
+        let mut ppu = PPU::new();
+        ppu.palette[0] = 0x21;
+        ppu.palette[1] = 0x16;
+        ppu.vram[..0x3C0].iter_mut().for_each(|tile| *tile = 0x01);
+
+        ppu.write_register(1, SHOW_BACKGROUND | SHOW_BACKGROUND_LEFT);
+        tick_to(&mut ppu, VBLANK_SCANLINE, 0);
+        assert!(ppu.framebuffer().iter().all(|colour| *colour == 0x16));
+
+        ppu.write_register(1, 0);
+        tick_to(&mut ppu, VBLANK_SCANLINE, 0);
+        assert!(ppu.framebuffer().iter().all(|colour| *colour == 0x21));
+
+        ppu.write_register(1, SHOW_BACKGROUND);
+        tick_to(&mut ppu, VBLANK_SCANLINE, 0);
+        assert_eq!(&ppu.framebuffer()[7..9], &[0x21, 0x16]);
+
+        ppu.write_register(1, SHOW_BACKGROUND | SHOW_BACKGROUND_LEFT | GRAYSCALE);
+        tick_to(&mut ppu, VBLANK_SCANLINE, 0);
+        assert!(ppu.framebuffer().iter().all(|colour| *colour == 0x10));
+    }
+
+    #[test]
+    fn framebuffer_converts_with_the_set_palette() {
+        let mut ppu = PPU::new();
+        ppu.palette[0] = 0x21;
+        let mut palette = NTSC_PALETTE;
+        palette[0x21] = [1, 2, 3];
+        ppu.set_palette(palette);
+        tick_to(&mut ppu, VBLANK_SCANLINE, 0);
+        let mut rgba = vec![0; SCREEN_WIDTH * SCREEN_HEIGHT * 4];
+
+        framebuffer_to_rgba(ppu.framebuffer(), ppu.master_palette(), ppu.emphasis(), &mut rgba);
+
+        assert_eq!(&rgba[..4], &[1, 2, 3, 0xFF]);
+    }
+
+    #[test]
+    fn sprites_are_drawn_with_priority() {
+        let mut ppu = PPU::new();
+        ppu.palette[0x01] = 0x16;
+        ppu.palette[0x15] = 0x2A;
+        ppu.palette[0x19] = 0x30;
+        ppu.write_register(1, SHOW_BACKGROUND | SHOW_SPRITES);
+        // Background tile 1 covers x 16-23 and y 8-15.
+        ppu.write_vram(0x2000 + 32 + 2, 0x01);
+        // Palette 1 at x 20-27, then palette 2 behind the background at x 12-19, both on y 10-17.
+        ppu.oam[..8].copy_from_slice(&[9, 0x01, 0x01, 20, 9, 0x01, 0x02 | BEHIND_BACKGROUND, 12]);
+
+        tick_to(&mut ppu, VBLANK_SCANLINE, 0);
+
+        let row = &ppu.framebuffer()[10 * SCREEN_WIDTH..11 * SCREEN_WIDTH];
+        assert_eq!(&row[11..13], &[BACKDROP, 0x30]);
+        assert_eq!(&row[15..17], &[0x30, 0x16]);
+        assert_eq!(&row[19..21], &[0x16, 0x2A]);
+        assert_eq!(&row[27..29], &[0x2A, BACKDROP]);
+        // The sprites start on scanline 10.
+        assert_eq!(ppu.framebuffer()[9 * SCREEN_WIDTH + 24], BACKDROP);
+    }
+
+    #[test]
+    fn more_than_8_sprites_on_a_scanline_overflow() {
+        let mut ppu = PPU::new();
+        ppu.write_register(1, SHOW_SPRITES);
+        for sprite in 0..8 {
+            ppu.oam[sprite * 4] = 30;
+        }
+        // Sprites at y 255 are off screen.
+        for y in ppu.oam.iter_mut().skip(32).step_by(4) {
+            *y = 0xFF;
+        }
+
+        tick_to(&mut ppu, VBLANK_SCANLINE, 0);
+        assert_eq!(ppu.status & SPRITE_OVERFLOW, 0);
+
+        ppu.oam[32] = 33;
+        tick_to(&mut ppu, 33, 257);
+        assert_eq!(ppu.status & SPRITE_OVERFLOW, SPRITE_OVERFLOW);
+
+        tick_to(&mut ppu, PRE_RENDER_SCANLINE, 1);
+        assert_eq!(ppu.status & SPRITE_OVERFLOW, 0);
+    }
+
+    #[test]
+    fn states_with_sprites_past_oam_are_corrupt() {
+        let mut ppu = PPU::new();
+        ppu.line_sprites[0] = 64;
+        let mut state = StateWriter::new();
+        ppu.save_state(&mut state);
+        let state = state.into_bytes();
+
+        assert_eq!(PPU::new().load_state(&mut StateReader::new(&state)), Err(StateError::Corrupt));
+    }
+
+    #[test]
+    fn latch_refreshed_after_now_hasnt_decayed() {
+        let mut ppu = PPU::new();
+        ppu.decay_latch = 0xFF;
+        ppu.latch_refreshed = [u64::MAX; 8];
+
+        assert_eq!(ppu.latch(), 0xFF);
+    }
+}
@@ -0,0 +1,49 @@
+//! Battery backed PRG RAM is saved as a hex string since browser storage only holds strings.
+//! These are kept separate from the wasm glue so they can be tested natively.
+
+use crate::rom::rom::Cartridge;
+
+/// Where the PRG RAM for a cartridge is saved. Keyed by the rom's contents so renaming the rom
+/// file doesn't lose the save. See `Cartridge::rom_hash`.
+pub fn storage_key(cartridge: &Cartridge) -> String {
+    format!("mes-prg-ram-{}", hex::encode(cartridge.rom_hash()))
+}
+
+pub fn serialize_prg_ram(prg_ram: &[u8]) -> String {
+    hex::encode(prg_ram)
+}
+
+/// Returns None if the saved data is corrupt or isn't the size of PRG RAM.
+pub fn deserialize_prg_ram(saved: &str) -> Option<Vec<u8>> {
+    hex::decode(saved)
+        .ok()
+        .filter(|prg_ram| prg_ram.len() == Cartridge::PRG_RAM_SIZE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rom::rom::tests::nrom_image;
+
+    #[test]
+    fn prg_ram_round_trips() {
+        let prg_ram: Vec<u8> = (0..Cartridge::PRG_RAM_SIZE).map(|index| (index * 7) as u8).collect();
+
+        let saved = serialize_prg_ram(&prg_ram);
+
+        assert_eq!(deserialize_prg_ram(&saved), Some(prg_ram));
+        assert_eq!(deserialize_prg_ram("not hex"), None);
+        assert_eq!(deserialize_prg_ram("00ff"), None);
+    }
+
+    #[test]
+    fn storage_key_follows_the_rom() {
+        let cartridge = Cartridge::from_bytes(nrom_image(&[0xEA])).unwrap();
+        let same = Cartridge::from_bytes(nrom_image(&[0xEA])).unwrap();
+        let other = Cartridge::from_bytes(nrom_image(&[0xE8])).unwrap();
+
+        assert_eq!(storage_key(&cartridge), storage_key(&same));
+        assert_ne!(storage_key(&cartridge), storage_key(&other));
+        assert_eq!(storage_key(&cartridge), format!("mes-prg-ram-{}", hex::encode(cartridge.rom_hash())));
+    }
+}
@@ -0,0 +1,84 @@
+use std::io::{Cursor, Read};
+use zip::result::ZipError;
+use zip::ZipArchive;
+
+/// Local file header signature that every zip file starts with.
+const ZIP_SIGNATURE: [u8; 4] = [0x50, 0x4B, 0x03, 0x04];
+
+#[derive(Debug)]
+pub enum LoadError {
+    InvalidZip { error: ZipError },
+    NoRomInZip,
+}
+
+impl From<ZipError> for LoadError {
+    fn from(error: ZipError) -> Self {
+        LoadError::InvalidZip { error }
+    }
+}
+
+/// Turns the bytes of whatever file was opened into the bytes of an iNES rom. Roms are commonly
+/// distributed zipped so if the file is a zip the first .nes file in it is extracted. Anything else
+/// is assumed to already be a rom and is returned as is.
+pub fn load_rom_bytes(data: &[u8]) -> Result<Vec<u8>, LoadError> {
+    if !data.starts_with(&ZIP_SIGNATURE) {
+        return Ok(data.to_vec());
+    }
+
+    let mut archive = ZipArchive::new(Cursor::new(data))?;
+    for index in 0..archive.len() {
+        let mut file = archive.by_index(index)?;
+        if file.is_file() && file.name().to_lowercase().ends_with(".nes") {
+            let mut rom = Vec::with_capacity(file.size() as usize);
+            file.read_to_end(&mut rom)
+                .map_err(|error| LoadError::InvalidZip { error: error.into() })?;
+            return Ok(rom);
+        }
+    }
+
+    Err(LoadError::NoRomInZip)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rom::rom::tests::nrom_image;
+    use std::io::Write;
+    use zip::write::FileOptions;
+    use zip::{CompressionMethod, ZipWriter};
+
+    fn zip(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+        for (name, contents) in files {
+            writer.start_file(*name, options).unwrap();
+            writer.write_all(contents).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn extracts_rom_from_zip() {
+        let rom = nrom_image(&[0xA9, 0x42, 0xEA]);
+        let zipped = zip(&[("readme.txt", b"not a rom"), ("Game (U).NES", &rom)]);
+
+        assert_eq!(load_rom_bytes(&zipped).unwrap(), rom);
+    }
+
+    #[test]
+    fn unzipped_rom_is_unchanged() {
+        let rom = nrom_image(&[0xEA]);
+
+        assert_eq!(load_rom_bytes(&rom).unwrap(), rom);
+    }
+
+    #[test]
+    fn zip_without_rom_is_rejected() {
+        let zipped = zip(&[("readme.txt", b"not a rom")]);
+
+        match load_rom_bytes(&zipped) {
+            Err(LoadError::NoRomInZip) => (),
+            result => panic!("Expected NoRomInZip, got {:?}", result),
+        }
+    }
+}
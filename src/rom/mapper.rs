@@ -1,15 +1,121 @@
+use crate::nes::state::{StateError, StateReader, StateWriter};
+use crate::rom::rom::Mirroring;
+use alloc::boxed::Box;
+
 pub trait Mapper {
     /// Since there can be bank switching address, the memory of the ROM is actually greater than
     /// 16-bit, but depending on stuff like which bank you're currently on, these functions will
     /// convert the 16-bit address coming from the cpu bus to athe actual memory location emulated
     fn prg_conversion(&self, address: usize) -> usize;
     fn chr_conversion(&self, address: usize) -> usize;
+
+    /// Writes to the cartridge's address space go to the mapper's registers rather than to ROM.
+    fn write(&mut self, _address: usize, _data: u8) {}
+
+    /// Mappers that can switch mirroring at runtime, e.g. MMC1 and AxROM, override the mirroring
+    /// from the header. None leaves it to the header.
+    fn mirroring(&self) -> Option<Mirroring> {
+        None
+    }
+
+    /// Called with every address the PPU fetches from the pattern tables. Some mappers, e.g. MMC3,
+    /// watch these to count scanlines.
+    fn ppu_fetch(&mut self, _address: u16) {}
+
+    /// Whether the mapper is holding the CPU's IRQ line low, i.e. asking for an interrupt.
+    fn irq(&self) -> bool {
+        false
+    }
+
+    /// Writes out the mapper's registers for a save state. Mappers without any, e.g. NROM, don't
+    /// need to do anything.
+    fn save_state(&self, _state: &mut StateWriter) {}
+
+    /// Reads back what `save_state` wrote.
+    fn load_state(&mut self, _state: &mut StateReader) -> Result<(), StateError> {
+        Ok(())
+    }
+}
+
+/// The usual name for iNES mapper `id`, for showing to people. Mappers the emulator doesn't
+/// support are "Unknown".
+pub fn mapper_name(id: u8) -> &'static str {
+    match id {
+        0 => "NROM",
+        1 => "MMC1",
+        2 => "UxROM",
+        4 => "MMC3",
+        7 => "AxROM",
+        _ => "Unknown",
+    }
+}
+
+/// Every mapper the emulator knows about. Conversions happen on every cartridge access so going
+/// through a `match` instead of a `dyn Mapper` lets the compiler inline them. Mappers from outside
+/// the crate can still be used through `Custom`.
+pub enum Mappers {
+    Nrom(Nrom),
+    Mmc1(Mmc1),
+    Uxrom(Uxrom),
+    Mmc3(Mmc3),
+    Axrom(Axrom),
+    Custom(Box<dyn Mapper>),
+}
+
+/// Forwards a call to whichever mapper is inside.
+macro_rules! dispatch {
+    ($mappers:expr, $mapper:ident => $call:expr) => {
+        match $mappers {
+            Mappers::Nrom($mapper) => $call,
+            Mappers::Mmc1($mapper) => $call,
+            Mappers::Uxrom($mapper) => $call,
+            Mappers::Mmc3($mapper) => $call,
+            Mappers::Axrom($mapper) => $call,
+            Mappers::Custom($mapper) => $call,
+        }
+    };
+}
+
+impl Mapper for Mappers {
+    #[inline]
+    fn prg_conversion(&self, address: usize) -> usize {
+        dispatch!(self, mapper => mapper.prg_conversion(address))
+    }
+
+    #[inline]
+    fn chr_conversion(&self, address: usize) -> usize {
+        dispatch!(self, mapper => mapper.chr_conversion(address))
+    }
+
+    fn write(&mut self, address: usize, data: u8) {
+        dispatch!(self, mapper => mapper.write(address, data))
+    }
+
+    fn mirroring(&self) -> Option<Mirroring> {
+        dispatch!(self, mapper => mapper.mirroring())
+    }
+
+    fn ppu_fetch(&mut self, address: u16) {
+        dispatch!(self, mapper => mapper.ppu_fetch(address))
+    }
+
+    fn irq(&self) -> bool {
+        dispatch!(self, mapper => mapper.irq())
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        dispatch!(self, mapper => mapper.save_state(state))
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        dispatch!(self, mapper => mapper.load_state(state))
+    }
 }
 
 /// Mapper 000 aka NROM
 ///
 /// This is a simple rom mapping with no extra features.
-pub(crate) struct Nrom {
+pub struct Nrom {
     pub num_prg_banks: usize,
     pub num_chr_banks: usize
 }
@@ -40,4 +146,535 @@ impl Mapper for Nrom {
 
         actual_address
     }
-}
\ No newline at end of file
+}
+/// Mapper 001 aka MMC1
+///
+/// Registers are written a bit at a time through a serial port: 5 writes of bit 0 to anywhere in
+/// $8000-$FFFF, and the address of the 5th picks the register. Writing with bit 7 set resets the
+/// port instead. The control register sets the nametable mirroring at runtime along with how PRG
+/// and CHR are banked.
+/// See: https://wiki.nesdev.com/w/index.php/MMC1
+pub struct Mmc1 {
+    num_prg_banks: usize, // 16kb banks
+    num_chr_banks: usize, // 4kb banks
+    // The bits written so far, from bit 4 down as they come in. Full once the marker bit that
+    // starts at bit 4 reaches bit 0.
+    shift_register: u8,
+    // CPPMM. C is the CHR mode, PP the PRG mode and MM the mirroring.
+    control: u8,
+    chr_banks: [u8; 2],
+    prg_bank: u8
+}
+
+impl Mmc1 {
+    const PRG_BANK_SIZE: usize = 0x4000; // i.e. 16kb.
+    const CHR_BANK_SIZE: usize = 0x1000; // i.e. 4kb.
+    const SHIFT_RESET: u8 = 0x10;
+
+    /// Bank counts are in the header's units, i.e. 16kb PRG and 8kb CHR.
+    pub fn new(num_prg_banks: usize, num_chr_banks: usize) -> Self {
+        Mmc1 {
+            num_prg_banks: num_prg_banks.max(1),
+            // No CHR ROM means 8kb of CHR RAM.
+            num_chr_banks: (num_chr_banks * 2).max(2),
+            shift_register: Mmc1::SHIFT_RESET,
+            // Powers on with the last PRG bank fixed at $C000, which is where the reset vector is.
+            control: 0x0C,
+            chr_banks: [0; 2],
+            prg_bank: 0
+        }
+    }
+}
+
+/// The register is picked by which 8kb of $8000-$FFFF the 5th write goes to: control, CHR bank 0,
+/// CHR bank 1 then the PRG bank.
+impl Mapper for Mmc1 {
+    fn prg_conversion(&self, address: usize) -> usize {
+        let bank = self.prg_bank as usize & 0x0F;
+        let bank = match (self.control >> 2) & 0x03 {
+            // 32kb at a time, ignoring the low bit of the bank.
+            0 | 1 => (bank & !1) | (address / Mmc1::PRG_BANK_SIZE),
+            // $8000 fixed to the first bank.
+            2 if address < Mmc1::PRG_BANK_SIZE => 0,
+            2 => bank,
+            // $C000 fixed to the last bank.
+            _ if address < Mmc1::PRG_BANK_SIZE => bank,
+            _ => self.num_prg_banks - 1,
+        };
+
+        (bank % self.num_prg_banks) * Mmc1::PRG_BANK_SIZE + address % Mmc1::PRG_BANK_SIZE
+    }
+
+    fn chr_conversion(&self, address: usize) -> usize {
+        let slot = address / Mmc1::CHR_BANK_SIZE % 2;
+        let bank = if self.control & 0x10 != 0 {
+            self.chr_banks[slot] as usize
+        } else {
+            // 8kb at a time, ignoring the low bit of the bank.
+            (self.chr_banks[0] as usize & !1) | slot
+        };
+
+        (bank % self.num_chr_banks) * Mmc1::CHR_BANK_SIZE + address % Mmc1::CHR_BANK_SIZE
+    }
+
+    /// Writes on back to back cycles are meant to be ignored, which games rarely rely on so it
+    /// isn't emulated.
+    fn write(&mut self, address: usize, data: u8) {
+        if data & 0x80 != 0 {
+            self.shift_register = Mmc1::SHIFT_RESET;
+            self.control |= 0x0C;
+            return;
+        }
+        let full = self.shift_register & 1 != 0;
+        self.shift_register = (self.shift_register >> 1) | ((data & 1) << 4);
+        if !full {
+            return;
+        }
+
+        let value = self.shift_register;
+        self.shift_register = Mmc1::SHIFT_RESET;
+        match address & 0x6000 {
+            0x0000 => self.control = value,
+            0x2000 => self.chr_banks[0] = value,
+            0x4000 => self.chr_banks[1] = value,
+            _ => self.prg_bank = value,
+        }
+    }
+
+    fn mirroring(&self) -> Option<Mirroring> {
+        Some(match self.control & 0x03 {
+            0 => Mirroring::SingleScreenLower,
+            1 => Mirroring::SingleScreenUpper,
+            2 => Mirroring::Vertical,
+            _ => Mirroring::Horizontal,
+        })
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.bytes(&[self.shift_register, self.control, self.chr_banks[0], self.chr_banks[1], self.prg_bank]);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.shift_register = state.u8()?;
+        self.control = state.u8()?;
+        self.chr_banks = [state.u8()?, state.u8()?];
+        self.prg_bank = state.u8()?;
+        Ok(())
+    }
+}
+
+/// Mapper 002 aka UxROM
+///
+/// $8000-$BFFF is a switchable 16kb bank and $C000-$FFFF is fixed to the last bank.
+pub struct Uxrom {
+    pub num_prg_banks: usize,
+    bank: usize
+}
+
+impl Uxrom {
+    const BANK_SIZE: usize = 0x4000; // i.e. 16kb.
+
+    pub fn new(num_prg_banks: usize) -> Self {
+        Uxrom {
+            num_prg_banks,
+            bank: 0
+        }
+    }
+}
+
+/// Any write to $8000-$FFFF picks the bank at $8000. Boards only decode as many bits as they have
+/// banks, so bigger values wrap.
+impl Mapper for Uxrom {
+    fn prg_conversion(&self, address: usize) -> usize {
+        let bank = if address < Uxrom::BANK_SIZE { self.bank } else { self.num_prg_banks - 1 };
+        (bank % self.num_prg_banks) * Uxrom::BANK_SIZE + address % Uxrom::BANK_SIZE
+    }
+
+    /// UxROM boards have 8kb of CHR RAM and no CHR banking.
+    fn chr_conversion(&self, address: usize) -> usize {
+        address % 0x2000
+    }
+
+    fn write(&mut self, _address: usize, data: u8) {
+        self.bank = data as usize;
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.u8(self.bank as u8);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.bank = state.u8()? as usize;
+        Ok(())
+    }
+}
+
+/// Mapper 007 aka AxROM
+///
+/// Switches all 32kb of PRG at once. Instead of horizontal or vertical mirroring every nametable
+/// shows the same 1kb, and which of the two 1kb nametables is picked by the same write.
+pub struct Axrom {
+    pub num_prg_banks: usize,
+    bank: usize,
+    mirroring: Mirroring
+}
+
+impl Axrom {
+    const BANK_SIZE: usize = 0x8000; // i.e. 32kb.
+
+    pub fn new(num_prg_banks: usize) -> Self {
+        Axrom {
+            num_prg_banks,
+            bank: 0,
+            mirroring: Mirroring::SingleScreenLower
+        }
+    }
+}
+
+/// Any write to $8000-$FFFF is the bank select. xxxM xPPP where PPP is the 32kb PRG bank and M is
+/// the nametable.
+impl Mapper for Axrom {
+    fn prg_conversion(&self, address: usize) -> usize {
+        // num_prg_banks is in 16kb banks.
+        let num_banks = (self.num_prg_banks / 2).max(1);
+        (self.bank % num_banks) * Axrom::BANK_SIZE + address % Axrom::BANK_SIZE
+    }
+
+    /// AxROM boards have 8kb of CHR RAM and no CHR banking.
+    fn chr_conversion(&self, address: usize) -> usize {
+        address % 0x2000
+    }
+
+    fn write(&mut self, _address: usize, data: u8) {
+        self.bank = (data & 0x07) as usize;
+        self.mirroring = if data & 0x10 != 0 {
+            Mirroring::SingleScreenUpper
+        } else {
+            Mirroring::SingleScreenLower
+        };
+    }
+
+    fn mirroring(&self) -> Option<Mirroring> {
+        Some(self.mirroring)
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.u8(self.bank as u8);
+        state.bool(self.mirroring == Mirroring::SingleScreenUpper);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.bank = state.u8()? as usize;
+        self.mirroring = if state.bool()? { Mirroring::SingleScreenUpper } else { Mirroring::SingleScreenLower };
+        Ok(())
+    }
+}
+
+/// Mapper 004 aka MMC3
+///
+/// Splits PRG into 8kb banks and CHR into 1kb banks. It also counts scanlines by watching for the
+/// PPU's A12 line going high, which happens once per scanline when backgrounds and sprites use
+/// different pattern tables, and can raise an IRQ when the count runs out. Games use this for
+/// split screens like status bars.
+/// See: https://wiki.nesdev.com/w/index.php/MMC3
+pub struct Mmc3 {
+    num_prg_banks: usize, // 8kb banks
+    num_chr_banks: usize, // 1kb banks
+    // CPxx xRRR. C inverts the CHR banks, P swaps the PRG banks and R is which register the next
+    // bank data write goes to.
+    bank_select: u8,
+    // R0-R1 are 2kb CHR banks, R2-R5 are 1kb CHR banks and R6-R7 are 8kb PRG banks.
+    registers: [usize; 8],
+    // None until the game picks one, so the header's mirroring is used until then.
+    mirroring: Option<Mirroring>,
+    irq_latch: u8,
+    irq_counter: u8,
+    irq_reload: bool,
+    irq_enabled: bool,
+    irq_pending: bool,
+    a12: bool
+}
+
+impl Mmc3 {
+    const PRG_BANK_SIZE: usize = 0x2000; // i.e. 8kb.
+    const CHR_BANK_SIZE: usize = 0x0400; // i.e. 1kb.
+
+    /// Bank counts are in the header's units, i.e. 16kb PRG and 8kb CHR.
+    pub fn new(num_prg_banks: usize, num_chr_banks: usize) -> Self {
+        Mmc3 {
+            num_prg_banks: num_prg_banks * 2,
+            // No CHR ROM means 8kb of CHR RAM.
+            num_chr_banks: (num_chr_banks * 8).max(8),
+            bank_select: 0,
+            registers: [0; 8],
+            mirroring: None,
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_reload: false,
+            irq_enabled: false,
+            irq_pending: false,
+            a12: false
+        }
+    }
+
+    fn clock_irq_counter(&mut self) {
+        if self.irq_counter == 0 || self.irq_reload {
+            self.irq_counter = self.irq_latch;
+            self.irq_reload = false;
+        } else {
+            self.irq_counter -= 1;
+        }
+        if self.irq_counter == 0 && self.irq_enabled {
+            self.irq_pending = true;
+        }
+    }
+}
+
+/// The registers are picked by which 8kb of $8000-$FFFF is written to and whether the address is
+/// even or odd.
+impl Mapper for Mmc3 {
+    fn prg_conversion(&self, address: usize) -> usize {
+        let second_last = self.num_prg_banks - 2;
+        let swapped = self.bank_select & 0x40 != 0;
+        let bank = match address / Mmc3::PRG_BANK_SIZE {
+            0 if swapped => second_last,
+            0 => self.registers[6],
+            1 => self.registers[7],
+            2 if swapped => self.registers[6],
+            2 => second_last,
+            _ => self.num_prg_banks - 1,
+        };
+
+        (bank % self.num_prg_banks) * Mmc3::PRG_BANK_SIZE + address % Mmc3::PRG_BANK_SIZE
+    }
+
+    fn chr_conversion(&self, address: usize) -> usize {
+        let mut slot = address / Mmc3::CHR_BANK_SIZE % 8;
+        if self.bank_select & 0x80 != 0 {
+            // The 2kb banks move to $1000 and the 1kb banks move to $0000.
+            slot ^= 4;
+        }
+        let bank = match slot {
+            0..=3 => (self.registers[slot / 2] & !1) | (slot & 1),
+            _ => self.registers[slot - 2],
+        };
+
+        (bank % self.num_chr_banks) * Mmc3::CHR_BANK_SIZE + address % Mmc3::CHR_BANK_SIZE
+    }
+
+    fn write(&mut self, address: usize, data: u8) {
+        let even = address & 1 == 0;
+        match address & 0x6000 {
+            0x0000 if even => self.bank_select = data,
+            0x0000 => self.registers[(self.bank_select & 0x07) as usize] = data as usize,
+            0x2000 if even => {
+                self.mirroring = Some(if data & 1 == 0 { Mirroring::Vertical } else { Mirroring::Horizontal })
+            }
+            0x2000 => (), // PRG RAM protect. Not emulated since most emulators ignore it.
+            0x4000 if even => self.irq_latch = data,
+            0x4000 => {
+                self.irq_counter = 0;
+                self.irq_reload = true;
+            }
+            _ if even => {
+                // Disabling also acknowledges any pending IRQ.
+                self.irq_enabled = false;
+                self.irq_pending = false;
+            }
+            _ => self.irq_enabled = true,
+        }
+    }
+
+    fn mirroring(&self) -> Option<Mirroring> {
+        self.mirroring
+    }
+
+    fn ppu_fetch(&mut self, address: u16) {
+        let a12 = address & 0x1000 != 0;
+        if a12 && !self.a12 {
+            self.clock_irq_counter();
+        }
+        self.a12 = a12;
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.u8(self.bank_select);
+        for register in self.registers.iter() {
+            state.u8(*register as u8);
+        }
+        state.u8(match self.mirroring {
+            None => 0,
+            Some(Mirroring::Vertical) => 1,
+            Some(_) => 2,
+        });
+        state.u8(self.irq_latch);
+        state.u8(self.irq_counter);
+        state.bool(self.irq_reload);
+        state.bool(self.irq_enabled);
+        state.bool(self.irq_pending);
+        state.bool(self.a12);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.bank_select = state.u8()?;
+        for register in self.registers.iter_mut() {
+            *register = state.u8()? as usize;
+        }
+        self.mirroring = match state.u8()? {
+            0 => None,
+            1 => Some(Mirroring::Vertical),
+            2 => Some(Mirroring::Horizontal),
+            _ => return Err(StateError::Corrupt),
+        };
+        self.irq_latch = state.u8()?;
+        self.irq_counter = state.u8()?;
+        self.irq_reload = state.bool()?;
+        self.irq_enabled = state.bool()?;
+        self.irq_pending = state.bool()?;
+        self.a12 = state.bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mmc3_decodes_bank_registers() {
+        // 128kb PRG i.e. 16 8kb banks, 128kb CHR i.e. 128 1kb banks.
+        let mut mapper = Mmc3::new(8, 16);
+        for (register, bank) in [0x10, 0x21, 0x05, 0x06, 0x07, 0x08, 0x03, 0x04].iter().enumerate() {
+            mapper.write(0x0000, register as u8);
+            mapper.write(0x0001, *bank);
+        }
+
+        assert_eq!(mapper.prg_conversion(0x0000), 0x3 * 0x2000);
+        assert_eq!(mapper.prg_conversion(0x2001), 0x4 * 0x2000 + 1);
+        assert_eq!(mapper.prg_conversion(0x4000), 0xE * 0x2000);
+        assert_eq!(mapper.prg_conversion(0x6000), 0xF * 0x2000);
+        assert_eq!(mapper.chr_conversion(0x0000), 0x10 * 0x400);
+        assert_eq!(mapper.chr_conversion(0x0401), 0x11 * 0x400 + 1);
+        // The low bit of the 2kb banks is ignored.
+        assert_eq!(mapper.chr_conversion(0x0800), 0x20 * 0x400);
+        assert_eq!(mapper.chr_conversion(0x1C00), 0x08 * 0x400);
+
+        // PRG mode 1 swaps $8000 and $C000, CHR inversion swaps $0000 and $1000.
+        mapper.write(0x0000, 0xC0);
+        assert_eq!(mapper.prg_conversion(0x0000), 0xE * 0x2000);
+        assert_eq!(mapper.prg_conversion(0x4000), 0x3 * 0x2000);
+        assert_eq!(mapper.chr_conversion(0x0000), 0x05 * 0x400);
+        assert_eq!(mapper.chr_conversion(0x1000), 0x10 * 0x400);
+
+        assert_eq!(mapper.mirroring(), None);
+        mapper.write(0x2000, 0x01);
+        assert_eq!(mapper.mirroring(), Some(Mirroring::Horizontal));
+    }
+
+    #[test]
+    fn mmc3_irq_fires_when_counter_runs_out() {
+        let mut mapper = Mmc3::new(8, 16);
+        mapper.write(0x4000, 2); // Latch
+        mapper.write(0x4001, 0); // Reload
+        mapper.write(0x6001, 0); // Enable
+
+        let scanline = |mapper: &mut Mmc3| {
+            mapper.ppu_fetch(0x0000);
+            mapper.ppu_fetch(0x1000);
+        };
+        scanline(&mut mapper); // Reloads to 2
+        scanline(&mut mapper); // 1
+        assert!(!mapper.irq());
+        scanline(&mut mapper); // 0
+        assert!(mapper.irq());
+
+        // A12 staying high isn't another edge.
+        mapper.write(0x6000, 0);
+        assert!(!mapper.irq());
+        mapper.ppu_fetch(0x1000);
+        assert_eq!(mapper.irq_counter, 0);
+    }
+
+    #[test]
+    fn enum_and_trait_object_convert_the_same() {
+        let enum_mapper = Mappers::Nrom(Nrom { num_prg_banks: 1, num_chr_banks: 1 });
+        let boxed_mapper: Box<dyn Mapper> = Box::new(Nrom { num_prg_banks: 1, num_chr_banks: 1 });
+
+        for address in 0..0x8000 {
+            assert_eq!(enum_mapper.prg_conversion(address), boxed_mapper.prg_conversion(address), "${:04X}", address);
+        }
+    }
+
+    /// MMC1 registers take 5 writes, low bit first.
+    fn write_mmc1(mapper: &mut Mmc1, address: usize, value: u8) {
+        for bit in 0..5 {
+            mapper.write(address, value >> bit & 1);
+        }
+    }
+
+    #[test]
+    fn mmc1_loads_registers_a_bit_at_a_time() {
+        // 128kb PRG i.e. 8 16kb banks, 32kb CHR i.e. 8 4kb banks.
+        let mut mapper = Mmc1::new(8, 4);
+        assert_eq!(mapper.prg_conversion(0x0000), 0);
+        assert_eq!(mapper.prg_conversion(0x4000), 7 * 0x4000);
+
+        write_mmc1(&mut mapper, 0x6000, 3);
+        assert_eq!(mapper.prg_conversion(0x0001), 3 * 0x4000 + 1);
+        assert_eq!(mapper.prg_conversion(0x4000), 7 * 0x4000);
+
+        // Bit 7 throws away the bits written so far.
+        mapper.write(0x6000, 1);
+        mapper.write(0x6000, 1);
+        mapper.write(0x6000, 0x80);
+        write_mmc1(&mut mapper, 0x6000, 2);
+        assert_eq!(mapper.prg_conversion(0x0000), 2 * 0x4000);
+
+        // 4kb CHR banks, 32kb PRG banks and single screen mirroring.
+        write_mmc1(&mut mapper, 0x0000, 0x10);
+        write_mmc1(&mut mapper, 0x2000, 5);
+        write_mmc1(&mut mapper, 0x4000, 2);
+        assert_eq!(mapper.chr_conversion(0x0000), 5 * 0x1000);
+        assert_eq!(mapper.chr_conversion(0x1001), 2 * 0x1000 + 1);
+        assert_eq!(mapper.prg_conversion(0x0000), 2 * 0x4000);
+        assert_eq!(mapper.prg_conversion(0x4000), 3 * 0x4000);
+        assert_eq!(mapper.mirroring(), Some(Mirroring::SingleScreenLower));
+
+        // 8kb CHR banks ignore the low bit of the first bank and the second bank altogether.
+        write_mmc1(&mut mapper, 0x0000, 0x0E);
+        assert_eq!(mapper.chr_conversion(0x0000), 4 * 0x1000);
+        assert_eq!(mapper.chr_conversion(0x1000), 5 * 0x1000);
+        assert_eq!(mapper.mirroring(), Some(Mirroring::Vertical));
+    }
+
+    #[test]
+    fn uxrom_switches_the_low_bank_only() {
+        let mut mapper = Uxrom::new(4);
+        assert_eq!(mapper.prg_conversion(0x0123), 0x0123);
+        assert_eq!(mapper.prg_conversion(0x4123), 0xC123);
+
+        mapper.write(0x0000, 0x02);
+        assert_eq!(mapper.prg_conversion(0x0123), 0x8123);
+        assert_eq!(mapper.prg_conversion(0x7FFF), 0xFFFF);
+    }
+
+    #[test]
+    fn axrom_switches_banks_and_nametables() {
+        let mut mapper = Axrom::new(8);
+        assert_eq!(mapper.prg_conversion(0x0123), 0x0123);
+        assert_eq!(mapper.mirroring(), Some(Mirroring::SingleScreenLower));
+
+        mapper.write(0x0000, 0x12);
+        assert_eq!(mapper.prg_conversion(0x0123), 0x10123);
+        assert_eq!(mapper.mirroring(), Some(Mirroring::SingleScreenUpper));
+
+        // Only 4 banks, so bank 7 wraps around to bank 3.
+        mapper.write(0x7FFF, 0x07);
+        assert_eq!(mapper.prg_conversion(0x7FFF), 0x1FFFF);
+        assert_eq!(mapper.mirroring(), Some(Mirroring::SingleScreenLower));
+    }
+}
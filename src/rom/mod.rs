@@ -1,2 +1,6 @@
 pub mod rom;
-mod mapper;
+#[cfg(feature = "std")]
+pub mod battery;
+#[cfg(feature = "std")]
+pub mod loader;
+pub mod mapper;
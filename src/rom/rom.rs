@@ -1,56 +1,283 @@
-use crate::cpu::opcode::DecodeError::IllegalUnimplementedOpcode;
 use crate::cpu::opcode::Instruction::UNK;
+use crate::cpu::disassembler::DisassembledInstruction;
 use crate::cpu::opcode::*;
+use crate::nes::state::{StateError, StateReader, StateWriter};
 use crate::rom::mapper::*;
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
 
 #[derive(Debug, Clone)]
 pub enum ROMError {
     InvalidHeader {
         header_bytes: [u8; ROMHeader::HEADER_SIZE],
     },
+    /// The file is shorter than the header says it should be.
+    SizeMismatch {
+        expected: usize,
+        actual: usize,
+    },
+    /// The header says there's no PRG ROM, so there'd be nothing to run.
+    NoPrgBanks,
+    /// A mapper that isn't emulated, see `mapper_name` for the ones that are.
+    UnsupportedMapper {
+        id: u8,
+    },
+}
+
+impl fmt::Display for ROMError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ROMError::InvalidHeader { header_bytes } => write!(
+                f,
+                "not an iNES rom: expected it to start with 4E 45 53 1A (\"NES\" then EOF) but found {:02X?}",
+                &header_bytes[0..4]
+            ),
+            ROMError::SizeMismatch { expected, actual } => {
+                write!(f, "rom is {} bytes but its header says it needs at least {}", actual, expected)
+            }
+            ROMError::NoPrgBanks => write!(f, "rom's header says it has no PRG ROM"),
+            ROMError::UnsupportedMapper { id } => write!(f, "mapper {} isn't supported", id),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ROMError {}
+
+/// How the PPU's 2kb of nametable RAM is laid out across its four nametables. See:
+/// https://wiki.nesdev.com/w/index.php/Mirroring
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mirroring {
+    Horizontal,
+    Vertical,
+    /// The cartridge has its own extra VRAM so there's no mirroring.
+    FourScreen,
+    /// Every nametable is the first 1kb.
+    SingleScreenLower,
+    /// Every nametable is the second 1kb.
+    SingleScreenUpper,
 }
 
-pub struct ROM {
+/// Which version of the iNES format a rom's header is in. See:
+/// https://wiki.nesdev.com/w/index.php/NES_2.0
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InesVersion {
+    Ines,
+    /// NES 2.0 headers are backwards compatible. Only the iNES fields are read so far.
+    Nes2,
+}
+
+/// A summary of a cartridge for showing to people, see `Cartridge::info`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CartridgeInfo {
+    pub mapper_id: u8,
+    pub mapper_name: &'static str,
+    /// In bytes.
+    pub prg_rom_size: usize,
+    /// In bytes. 0 when the cartridge has CHR RAM instead.
+    pub chr_rom_size: usize,
+    pub has_chr_ram: bool,
+    pub mirroring: Mirroring,
+    pub has_battery: bool,
+    pub has_trainer: bool,
+    pub ines_version: InesVersion,
+}
+
+/// A parsed iNES rom. The header is only parsed once here and everything else (the bus, the
+/// disassembler) works off of the parsed cartridge.
+pub struct Cartridge {
     header: ROMHeader,
     pub prg: Vec<u8>,
     chr: Vec<u8>,
-    pub mapper: Box<dyn Mapper>
+    // Cartridges without CHR ROM have 8kb of CHR RAM instead, which the game fills in itself.
+    chr_is_ram: bool,
+    /// $6000-$7FFF. Not every cartridge has this but there's no harm in always having it.
+    prg_ram: Vec<u8>,
+    /// The 512 bytes between the header and PRG, if the header says there are any.
+    trainer: Option<Box<[u8; Cartridge::TRAINER_SIZE]>>,
+    // Junk after the PRG and CHR, e.g. from an overdump.
+    trailing_bytes: usize,
+    pub mapper: Mappers
 }
 
-impl ROM {
-    /// iNES format states that the minimum size of a rom is 16kb. There are a handful of games that
-    /// are less than 16kb like Galaxian, but they are overdumps where there is junk data making up
-    /// the difference. That data is just ignored by the emulator but requires by the iNES rom spec.
-    const MINIMUM_ROM_SIZE: u16 = 16384;
+impl Cartridge {
+    pub const PRG_BANK_SIZE: usize = 0x4000; // i.e. 16kb.
+    pub const PRG_RAM_SIZE: usize = 0x2000; // i.e. 8kb.
+    pub const TRAINER_SIZE: usize = 512;
+    pub const CHR_RAM_SIZE: usize = 0x2000; // i.e. 8kb.
+    /// Where the trainer goes in PRG RAM, i.e. $7000.
+    const TRAINER_OFFSET: usize = 0x1000;
 
-    pub fn new(rom_bytes: Vec<u8>) -> Result<Self, ROMError> {
+    /// Overdumps, i.e. roms with junk after the CHR, still load. See `trailing_bytes`.
+    pub fn from_bytes(rom_bytes: Vec<u8>) -> Result<Self, ROMError> {
+        if rom_bytes.len() < ROMHeader::HEADER_SIZE {
+            return Err(ROMError::SizeMismatch { expected: ROMHeader::HEADER_SIZE, actual: rom_bytes.len() });
+        }
         let mut header_bytes: [u8; ROMHeader::HEADER_SIZE] = [0; ROMHeader::HEADER_SIZE];
         header_bytes.copy_from_slice(&rom_bytes[0..ROMHeader::HEADER_SIZE]);
         let header = ROMHeader::new(header_bytes)?;
+        if header.num_prg_banks == 0 {
+            return Err(ROMError::NoPrgBanks);
+        }
+        let mapper = create_mapper(&header)?;
 
         // 0x4000 is the "bank" size I think. I've seen the words "chunks" and "pages" also used
         // 0x4000 is 16kb.
         // For ease of reference 16kb is the size of the upper/lower rom banks. If ROM is only 16kb
         // then it is mirrored.
         let prg_end = header.prg_rom_start_offset() + (header.num_prg_banks * 0x4000);
-        let chr_end = prg_end + header.num_chr_banks * (header.num_chr_banks * 0x2000);
+        let chr_end = prg_end + header.num_chr_banks * 0x2000;
+        if rom_bytes.len() < chr_end {
+            return Err(ROMError::SizeMismatch { expected: chr_end, actual: rom_bytes.len() });
+        }
 
+        let trainer = if header.has_trainer() {
+            let mut trainer = Box::new([0; Cartridge::TRAINER_SIZE]);
+            trainer.copy_from_slice(&rom_bytes[ROMHeader::HEADER_SIZE..header.prg_rom_start_offset()]);
+            Some(trainer)
+        } else {
+            None
+        };
         let prg = rom_bytes[header.prg_rom_start_offset()..prg_end].to_vec();
-        let chr = rom_bytes[prg_end..chr_end].to_vec();
-        let mapper = create_mapper(&header);
+        let chr_is_ram = header.num_chr_banks == 0;
+        let chr = if chr_is_ram { vec![0; Cartridge::CHR_RAM_SIZE] } else { rom_bytes[prg_end..chr_end].to_vec() };
+        let trailing_bytes = rom_bytes.len() - chr_end;
 
-        Ok(ROM { header, prg, chr, mapper })
+        let prg_ram = vec![0; Cartridge::PRG_RAM_SIZE];
+
+        let mut cartridge = Cartridge { header, prg, chr, chr_is_ram, prg_ram, trainer, trailing_bytes, mapper };
+        cartridge.load_trainer();
+        Ok(cartridge)
+    }
+
+    pub fn trainer(&self) -> Option<&[u8; Cartridge::TRAINER_SIZE]> {
+        self.trainer.as_deref()
+    }
+
+    /// Copies the trainer, if there is one, to $7000-$71FF in PRG RAM. Needs redoing whenever PRG
+    /// RAM is overwritten, e.g. when it's filled at power on.
+    pub fn load_trainer(&mut self) {
+        if let Some(trainer) = &self.trainer {
+            self.prg_ram[Cartridge::TRAINER_OFFSET..Cartridge::TRAINER_OFFSET + Cartridge::TRAINER_SIZE]
+                .copy_from_slice(&trainer[..]);
+        }
+    }
+
+    /// Number of bytes in the file after the PRG and CHR the header declares. Anything other than
+    /// 0 most likely means an overdump, which callers may want to warn about. The extra bytes are
+    /// ignored.
+    pub fn trailing_bytes(&self) -> usize {
+        self.trailing_bytes
+    }
+
+    /// Whether the PRG RAM is battery backed, i.e. the game expects it to still be there the next
+    /// time it's turned on.
+    pub fn has_battery(&self) -> bool {
+        self.header.lower_mapper_bits & 0x02 != 0
+    }
+
+    /// The header's mirroring unless the mapper is currently overriding it. Four screen
+    /// cartridges have their own nametable RAM wired up in place of the console's, so no mapper
+    /// can change that.
+    pub fn mirroring(&self) -> Mirroring {
+        match self.header.mirroring() {
+            Mirroring::FourScreen => Mirroring::FourScreen,
+            mirroring => self.mapper.mirroring().unwrap_or(mirroring),
+        }
+    }
+
+    /// Reads the pattern tables, $0000-$1FFF on the PPU's bus, through the mapper.
+    pub fn read_chr(&self, address: u16) -> u8 {
+        self.chr.get(self.mapper.chr_conversion(address as usize)).copied().unwrap_or(0)
+    }
+
+    /// Writes to the pattern tables only stick if they're CHR RAM.
+    pub fn write_chr(&mut self, address: u16, data: u8) {
+        if self.chr_is_ram {
+            let address = self.mapper.chr_conversion(address as usize);
+            if let Some(byte) = self.chr.get_mut(address) {
+                *byte = data;
+            }
+        }
+    }
+
+    pub fn has_chr_ram(&self) -> bool {
+        self.chr_is_ram
+    }
+
+    pub fn info(&self) -> CartridgeInfo {
+        let mapper_id = self.header.mapper_id();
+        CartridgeInfo {
+            mapper_id,
+            mapper_name: mapper_name(mapper_id),
+            prg_rom_size: self.prg.len(),
+            chr_rom_size: self.chr_rom().len(),
+            has_chr_ram: self.chr_is_ram,
+            mirroring: self.mirroring(),
+            has_battery: self.has_battery(),
+            has_trainer: self.trainer.is_some(),
+            ines_version: self.header.ines_version(),
+        }
+    }
+
+    /// CHR RAM changes as the game runs so it's not part of what identifies the rom.
+    fn chr_rom(&self) -> &[u8] {
+        if self.chr_is_ram { &[] } else { &self.chr }
+    }
+
+    pub fn prg_ram(&self) -> &[u8] {
+        &self.prg_ram
+    }
+
+    pub fn prg_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.prg_ram
+    }
+
+    /// 128 bit FNV-1a hash of the PRG and CHR rom. Used to tell roms apart, e.g. save states and
+    /// movies are tagged with this so they aren't applied to a different game, and battery saves
+    /// are named after it. It doesn't need to be cryptographically secure.
+    pub fn rom_hash(&self) -> [u8; 16] {
+        self.prg.iter().chain(self.chr_rom().iter()).fold(0x6C62272E07BB014262B821756295C58D_u128, |hash, byte| {
+            (hash ^ *byte as u128).wrapping_mul(0x0000000001000000000000000000013B)
+        }).to_be_bytes()
+    }
+
+    /// PRG RAM, CHR RAM and the mapper's registers, i.e. everything on the cartridge that changes.
+    pub(crate) fn save_state(&self, state: &mut StateWriter) {
+        state.bytes(&self.prg_ram);
+        if self.chr_is_ram {
+            state.bytes(&self.chr);
+        }
+        self.mapper.save_state(state);
+    }
+
+    pub(crate) fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.bytes(&mut self.prg_ram)?;
+        if self.chr_is_ram {
+            state.bytes(&mut self.chr)?;
+        }
+        self.mapper.load_state(state)
     }
 }
 
-fn create_mapper(header: &ROMHeader) -> Box<dyn Mapper> {
-    match header.mapper_id() {
-        0 => Box::new(Nrom {
+fn create_mapper(header: &ROMHeader) -> Result<Mappers, ROMError> {
+    let mapper = match header.mapper_id() {
+        0 => Mappers::Nrom(Nrom {
             num_prg_banks: header.num_prg_banks,
             num_chr_banks: header.num_chr_banks
         }),
-        _ => unimplemented!()
-    }
+        1 => Mappers::Mmc1(Mmc1::new(header.num_prg_banks, header.num_chr_banks)),
+        2 => Mappers::Uxrom(Uxrom::new(header.num_prg_banks)),
+        4 => Mappers::Mmc3(Mmc3::new(header.num_prg_banks, header.num_chr_banks)),
+        7 => Mappers::Axrom(Axrom::new(header.num_prg_banks)),
+        id => return Err(ROMError::UnsupportedMapper { id }),
+    };
+
+    Ok(mapper)
 }
 
 /// This is the header for a ROM. It contains information for the following things:
@@ -60,28 +287,22 @@ fn create_mapper(header: &ROMHeader) -> Box<dyn Mapper> {
 /// * Bits used to determine what mapper the ROM uses.
 /// * Bits used to determine V or H mirroring.
 struct ROMHeader {
-    // First 4 bytes of header should be N E S in hex + "1A" which is a character break. Storing it
-    // here for informational purposes.
-    nes: [u8; 4],
     num_prg_banks: usize,
     num_chr_banks: usize,
     // Lower mapper byte also includes V or H mirroring, Battery, 4 screen VRAM and trainer switches
     // V or H mirroring is the only pertinent piece for this emulator right now.
     lower_mapper_bits: u8,
     upper_mapper_bits: u8,
-    // Due to the NES rom spec there from byte 8 (assuming starting from 0) to byte 15 are just
-    // zeros. Leaving that here also for informational purposes
-    zeros: [u8; 8],
 }
 
 impl ROMHeader {
     const HEADER_SIZE: usize = 16;
 
     pub fn new(header_bytes: [u8; ROMHeader::HEADER_SIZE]) -> Result<Self, ROMError> {
-        let mut nes: [u8; 4] = [0; 4];
-        nes.copy_from_slice(&header_bytes[0..=3]);
+        // The first 4 bytes are N E S in hex + "1A" which is a character break. Bytes 8 to 15 are
+        // meant to be zeros so they're not read.
         let expected_nes: [u8; 4] = [0x4E, 0x45, 0x53, 0x1A];
-        if nes != expected_nes {
+        if header_bytes[0..=3] != expected_nes {
             Err(ROMError::InvalidHeader { header_bytes })
         } else {
             let num_prg_banks = header_bytes[4] as usize;
@@ -89,151 +310,366 @@ impl ROMHeader {
             // Lower mapper byte also includes V or H mirroring, Battery, 4 Screen VRAM and trainer switches
             let lower_mapper_bits = header_bytes[6];
             let upper_mapper_bits = header_bytes[7];
-            let mut zeros: [u8; 8] = [0; 8];
-            zeros.copy_from_slice(&header_bytes[8..=15]);
 
             Ok(ROMHeader {
-                nes,
                 num_prg_banks,
                 num_chr_banks,
                 lower_mapper_bits,
                 upper_mapper_bits,
-                zeros,
             })
         }
     }
 
-    /// If true there's trainer switches. According to most sources trainers are no longer
-    /// really used however. This means we can just skip over them if they exist. If they exist
-    /// in the rom they're the 512 bytes after the header.
+    /// Trainers are 512 bytes right after the header that get loaded at $7000. According to most
+    /// sources they're no longer really used, mostly just by old hacked roms.
+    pub fn has_trainer(&self) -> bool {
+        self.lower_mapper_bits & 0x04 != 0
+    }
+
+    /// PRG starts after the trainer if there is one.
     pub fn prg_rom_start_offset(&self) -> usize {
-        if self.lower_mapper_bits & 0x04 != 0 {
-            (ROMHeader::HEADER_SIZE + 512) as usize
+        if self.has_trainer() {
+            (ROMHeader::HEADER_SIZE + Cartridge::TRAINER_SIZE) as usize
         } else {
             ROMHeader::HEADER_SIZE as usize
         }
     }
 
+    pub fn mirroring(&self) -> Mirroring {
+        if self.lower_mapper_bits & 0x08 != 0 {
+            Mirroring::FourScreen
+        } else if self.lower_mapper_bits & 0x01 != 0 {
+            Mirroring::Vertical
+        } else {
+            Mirroring::Horizontal
+        }
+    }
+
+    /// NES 2.0 headers have 0b10 in bits 2 and 3 of byte 7.
+    pub fn ines_version(&self) -> InesVersion {
+        if self.upper_mapper_bits & 0x0C == 0x08 {
+            InesVersion::Nes2
+        } else {
+            InesVersion::Ines
+        }
+    }
+
     pub fn mapper_id(&self) -> u8 {
         (self.lower_mapper_bits & 0xF0) >> 4 | self.upper_mapper_bits & 0xF0
     }
 }
 
 pub trait DisassembleRom {
+    /// Bytes that can't be decoded are shown as `.byte $XX`.
     fn disassemble_prg_rom(&self) -> Result<String, DecodeError>;
+
+    /// Like `disassemble_prg_rom` but stops at the first byte that can't be decoded, returning
+    /// `DecodeError::IllegalOpcode` with its offset in PRG.
+    fn disassemble_prg_rom_strict(&self) -> Result<String, DecodeError>;
 }
 
-impl DisassembleRom for ROM {
-    /// Disassembles a rom into 6502 assembly. I assume this will fail on overdumped roms due to
-    /// potential for junk data passed into the prg rom.
+impl DisassembleRom for Cartridge {
+    fn disassemble_prg_rom(&self) -> Result<String, DecodeError> {
+        self.disassemble_prg(false)
+    }
+
+    fn disassemble_prg_rom_strict(&self) -> Result<String, DecodeError> {
+        self.disassemble_prg(true)
+    }
+}
+
+impl Cartridge {
+    /// Disassembles a rom into 6502 assembly, one `DisassembledInstruction` per line. Only the
+    /// PRG the header declares is read so junk at the end of overdumped roms is ignored.
+    /// Addresses are offsets into PRG, since which bank ends up where is up to the mapper, and
+    /// relative branches are shown going to an offset too.
     /// Currently this is a naive way of disassembling. A better way is to actually step through
     /// instructions.
     /// This can't currently differentiate between actual instructions + operands and data.
-    /// This also currently uses *-/*+ relative addressing. A better way is to convert relative
-    /// addresses to real addresses.
-    fn disassemble_prg_rom(&self) -> Result<String, DecodeError> {
+    fn disassemble_prg(&self, strict: bool) -> Result<String, DecodeError> {
         let mut head: usize = 0;
         let mut disassembled = String::new();
-        while head < self.prg.len() - 1 {
+        while head < self.prg.len() {
             let opcode = self.prg[head];
-            disassembled.push_str(&format!("{:04X?} {:02X?} ", head, opcode));
-            let result = opcode.decode();
-            let decoded_opcode: DecodedOpcode = match result {
-                Err(IllegalUnimplementedOpcode { opcode }) => DecodedOpcode {
+            let decoded_opcode: DecodedOpcode = match opcode.decode() {
+                // The operands would run past the end of PRG so it can't be an instruction.
+                Ok(decoded_opcode) if head + instruction_length(decoded_opcode.mode) <= self.prg.len() => decoded_opcode,
+                _ if strict => return Err(DecodeError::IllegalOpcode { address: head, opcode }),
+                _ => DecodedOpcode {
                     instruction: UNK,
                     mode: AddressingMode::Implied,
                     cycles: 0,
+                    is_official: false,
                 },
-                Ok(decoded_opcode) => decoded_opcode,
             };
-            head += 1;
-            let instruction = decoded_opcode.instruction.to_string();
-            // See the Addressing mode comments for what the operands look like disassembled.
-            // Reminder: 6502 is little endian, so two byte operands are reversed when disassembled.
-            let line = match decoded_opcode.mode {
-                AddressingMode::ZeroPage => {
-                    let operand = self.prg[head];
-                    head += 1;
-                    format!("{:02X?}    {} ${:02X?}", operand, instruction, operand)
-                }
-                AddressingMode::IndexedZeroPageX => {
-                    let operand = self.prg[head];
-                    head += 1;
-                    format!("{:02X?}    {} ${:02X?},X", operand, instruction, operand)
-                }
-                AddressingMode::IndexedZeroPageY => {
-                    let operand = self.prg[head];
-                    head += 1;
-                    format!("{:02X?}    {} ${:02X?},Y", operand, instruction, operand)
-                }
-                AddressingMode::Absolute => {
-                    let lower = self.prg[head];
-                    let higher = self.prg[head + 1];
-                    head += 2;
-                    format!(
-                        "{:02X?} {:02X?} {} ${:02X?}{:02X?}",
-                        lower, higher, instruction, higher, lower
-                    )
-                }
-                AddressingMode::IndexedAbsoluteX => {
-                    let lower = self.prg[head];
-                    let higher = self.prg[head + 1];
-                    head += 2;
-                    format!(
-                        "{:02X?} {:02X?} {} ${:02X?}{:02X?},X",
-                        lower, higher, instruction, higher, lower
-                    )
-                }
-                AddressingMode::IndexedAbsoluteY => {
-                    let lower = self.prg[head];
-                    let higher = self.prg[head + 1];
-                    head += 2;
-                    format!(
-                        "{:02X?} {:02X?} {} ${:02X?}{:02X?},Y",
-                        lower, higher, instruction, higher, lower
-                    )
-                }
-                AddressingMode::Indirect => {
-                    let lower = self.prg[head];
-                    let higher = self.prg[head + 1];
-                    head += 2;
-                    format!(
-                        "{:02X?} {:02X?} {} $({:02X?}{:02X?})",
-                        lower, higher, instruction, higher, lower
-                    )
-                }
-                AddressingMode::Implied => format!("      {}", instruction),
-                AddressingMode::Accumulator => format!("      {} A", instruction),
-                AddressingMode::Immediate => {
-                    let operand = self.prg[head];
-                    head += 1;
-                    format!("{:02X?}    {} #${:02X?}", operand, instruction, operand)
-                }
-                AddressingMode::Relative => {
-                    let operand = self.prg[head];
-                    head += 1;
-                    format!(
-                        "{:02X?}    {} *{}{}",
-                        operand,
-                        instruction,
-                        if operand as i8 > 0 { "+" } else { "" },
-                        operand as i8
-                    )
-                }
-                AddressingMode::IndexedIndirect => {
-                    let operand = self.prg[head];
-                    head += 1;
-                    format!("{:02X?}    {} (${:02X?}, X)", operand, instruction, operand)
-                }
-                AddressingMode::IndirectIndexed => {
-                    let operand = self.prg[head];
-                    head += 1;
-                    format!("{:02X?}    {} (${:02X?}), Y", operand, instruction, operand)
-                }
+            let length = instruction_length(decoded_opcode.mode);
+            let instruction = DisassembledInstruction {
+                address: head as u16,
+                bytes: self.prg[head..head + length].to_vec(),
+                decoded_opcode,
+                symbol: None,
             };
-            disassembled.push_str(&format!("{}\n", line));
+            // Not the instruction's Display since PRG can be bigger than 64kb.
+            disassembled.push_str(&format!("{:04X}: {}\n", head, instruction.listing()));
+            head += length;
         }
 
         Ok(disassembled)
     }
+
+    /// Disassembles each 16kb PRG bank on its own, as if it were mapped in at the window it can
+    /// occupy, so code in banks that aren't currently switched in still gets real addresses.
+    /// Returns each bank's number with its instructions; the base address is the first
+    /// instruction's address.
+    /// The last bank goes at $C000 since that's where the vectors are and most mappers fix it
+    /// there, and the rest at $8000. AxROM switches 32kb at a time so its banks alternate.
+    pub fn disassemble_all_banks(&self) -> Vec<(usize, Vec<DisassembledInstruction>)> {
+        let num_banks = self.prg.len() / Cartridge::PRG_BANK_SIZE;
+        (0..num_banks)
+            .map(|bank| {
+                let base: u16 = match self.mapper {
+                    Mappers::Axrom(_) if bank % 2 == 1 => 0xC000,
+                    Mappers::Axrom(_) => 0x8000,
+                    _ if bank == num_banks - 1 => 0xC000,
+                    _ => 0x8000,
+                };
+                let start = bank * Cartridge::PRG_BANK_SIZE;
+                (bank, disassemble_bank(&self.prg[start..start + Cartridge::PRG_BANK_SIZE], base))
+            })
+            .collect()
+    }
+}
+
+/// Disassembles `bank` as if it starts at `base`. Instructions can't run off the end of a bank
+/// since the next bank usually isn't the one mapped after it, so those bytes are left undecoded.
+fn disassemble_bank(bank: &[u8], base: u16) -> Vec<DisassembledInstruction> {
+    let mut head: usize = 0;
+    let mut instructions = Vec::new();
+    while head < bank.len() {
+        let decoded_opcode = match bank[head].decode() {
+            Ok(decoded_opcode) if head + instruction_length(decoded_opcode.mode) <= bank.len() => decoded_opcode,
+            _ => DecodedOpcode {
+                instruction: UNK,
+                mode: AddressingMode::Implied,
+                cycles: 0,
+                is_official: false,
+            },
+        };
+        let length = instruction_length(decoded_opcode.mode);
+        instructions.push(DisassembledInstruction {
+            address: base.wrapping_add(head as u16),
+            bytes: bank[head..head + length].to_vec(),
+            decoded_opcode,
+            symbol: None,
+        });
+        head += length;
+    }
+
+    instructions
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Builds a minimal NROM image with one 16kb PRG bank and one 8kb CHR bank.
+    pub(crate) fn nrom_image(prg: &[u8]) -> Vec<u8> {
+        let mut image = vec![0x4E, 0x45, 0x53, 0x1A, 0x01, 0x01, 0x00, 0x00];
+        image.extend_from_slice(&[0; 8]);
+        let mut prg_bank = vec![0; 0x4000];
+        prg_bank[..prg.len()].copy_from_slice(prg);
+        image.extend(prg_bank);
+        image.extend(vec![0; 0x2000]);
+        image
+    }
+
+    #[test]
+    fn mapper_overrides_header_mirroring() {
+        let mut image = nrom_image(&[]);
+        image[6] = 0x71; // Mapper 7, vertical mirroring
+        image[4] = 0x02;
+        image.splice(16..16, vec![0; 0x4000]);
+        let mut cartridge = Cartridge::from_bytes(image).unwrap();
+        assert_eq!(cartridge.mirroring(), Mirroring::SingleScreenLower);
+
+        cartridge.mapper.write(0x0000, 0x11);
+
+        assert_eq!(cartridge.mirroring(), Mirroring::SingleScreenUpper);
+        assert_eq!(cartridge.mapper.prg_conversion(0x0000), 0x0000);
+        assert_eq!(Cartridge::from_bytes(nrom_image(&[])).unwrap().mirroring(), Mirroring::Horizontal);
+
+        let mut image = nrom_image(&[]);
+        image[6] = 0x78; // Mapper 7, four screen
+        image[4] = 0x02;
+        image.splice(16..16, vec![0; 0x4000]);
+        let mut cartridge = Cartridge::from_bytes(image).unwrap();
+        cartridge.mapper.write(0x0000, 0x10);
+        assert_eq!(cartridge.mirroring(), Mirroring::FourScreen);
+    }
+
+    #[test]
+    fn exact_size_rom_has_no_trailing_bytes() {
+        let cartridge = Cartridge::from_bytes(nrom_image(&[])).unwrap();
+
+        assert_eq!(cartridge.trailing_bytes(), 0);
+        assert_eq!(cartridge.prg.len(), 0x4000);
+    }
+
+    #[test]
+    fn overdumped_rom_loads() {
+        let mut image = nrom_image(&[]);
+        image.extend_from_slice(&[0xFF; 100]);
+
+        let cartridge = Cartridge::from_bytes(image).unwrap();
+
+        assert_eq!(cartridge.trailing_bytes(), 100);
+        assert_eq!(cartridge.prg.len(), 0x4000);
+    }
+
+    #[test]
+    fn short_rom_is_rejected() {
+        let mut image = nrom_image(&[]);
+        image.truncate(0x4000);
+
+        match Cartridge::from_bytes(image) {
+            Err(ROMError::SizeMismatch { expected, actual }) => {
+                assert_eq!(expected, 16 + 0x4000 + 0x2000);
+                assert_eq!(actual, 0x4000);
+            }
+            _ => panic!("expected a size mismatch"),
+        }
+        assert!(Cartridge::from_bytes(vec![0x4E, 0x45, 0x53]).is_err());
+    }
+
+    #[test]
+    fn roms_that_cant_run_are_rejected() {
+        let mut image = nrom_image(&[]);
+        image[6] = 0x30; // Mapper 3, CNROM
+        assert!(matches!(Cartridge::from_bytes(image), Err(ROMError::UnsupportedMapper { id: 3 })));
+
+        // Each of these would otherwise divide by, or subtract from, the number of banks.
+        for mapper in [0x00, 0x20, 0x40].iter() {
+            let mut image = nrom_image(&[]);
+            image[4] = 0;
+            image[6] = *mapper;
+            image.drain(16..16 + 0x4000);
+            assert!(matches!(Cartridge::from_bytes(image), Err(ROMError::NoPrgBanks)), "mapper {:02X}", mapper);
+        }
+    }
+
+    #[test]
+    fn disassembly_stays_within_prg() {
+        let mut prg = [0xEA; 0x4000];
+        prg[0x3FFE] = 0x4C; // JMP missing its last operand byte
+        let cartridge = Cartridge::from_bytes(nrom_image(&prg)).unwrap();
+
+        let disassembled = cartridge.disassemble_prg_rom().unwrap();
+
+        assert!(disassembled.ends_with("3FFE: 4C        .byte $4C\n3FFF: EA        NOP\n"), "{}", &disassembled[disassembled.len() - 60..]);
+    }
+
+    #[test]
+    fn strict_disassembly_reports_where_it_failed() {
+        // NOP, ISC $0000,X then a byte that isn't an opcode.
+        let cartridge = Cartridge::from_bytes(nrom_image(&[0xEA, 0xFF, 0x00, 0x00, 0x8B])).unwrap();
+
+        assert_eq!(cartridge.disassemble_prg_rom_strict(), Err(DecodeError::IllegalOpcode { address: 4, opcode: 0x8B }));
+        assert!(cartridge.disassemble_prg_rom().unwrap().contains("0004: 8B        .byte $8B"));
+    }
+
+    #[test]
+    fn info_summarises_the_cartridge() {
+        let mut image = nrom_image(&[]);
+        image[6] = 0x03; // Vertical mirroring, battery
+
+        let info = Cartridge::from_bytes(image).unwrap().info();
+
+        assert_eq!(info, CartridgeInfo {
+            mapper_id: 0,
+            mapper_name: "NROM",
+            prg_rom_size: 0x4000,
+            chr_rom_size: 0x2000,
+            has_chr_ram: false,
+            mirroring: Mirroring::Vertical,
+            has_battery: true,
+            has_trainer: false,
+            ines_version: InesVersion::Ines,
+        });
+
+        let mut image = nrom_image(&[]);
+        image[5] = 0x00;
+        image[7] = 0x08;
+        image.truncate(16 + 0x4000);
+        let info = Cartridge::from_bytes(image).unwrap().info();
+        assert_eq!((info.chr_rom_size, info.has_chr_ram, info.ines_version), (0, true, InesVersion::Nes2));
+    }
+
+    #[test]
+    fn disassembly_columns_line_up() {
+        // NOP, LDA #$42, STA $0300, BNE back to the LDA
+        let cartridge = Cartridge::from_bytes(nrom_image(&[0xEA, 0xA9, 0x42, 0x8D, 0x00, 0x03, 0xD0, 0xF9])).unwrap();
+
+        let disassembled = cartridge.disassemble_prg_rom().unwrap();
+        let lines: Vec<&str> = disassembled.lines().take(4).collect();
+
+        assert_eq!(lines, vec![
+            "0000: EA        NOP",
+            "0001: A9 42     LDA #$42",
+            "0003: 8D 00 03  STA $0300",
+            "0006: D0 F9     BNE $0001",
+        ]);
+        // The bytes always start at column 6 and the mnemonic at column 16.
+        for line in lines {
+            assert_eq!(&line[4..6], ": ", "{}", line);
+            assert_eq!(&line[14..16], "  ", "{}", line);
+            assert!(line[16..].starts_with(char::is_alphabetic), "{}", line);
+        }
+    }
+
+    #[test]
+    fn every_bank_is_disassembled_at_its_window() {
+        // UxROM with 4 banks. Each bank starts with LDA #bank so they can be told apart.
+        let mut image = vec![0x4E, 0x45, 0x53, 0x1A, 0x04, 0x00, 0x20, 0x00];
+        image.extend_from_slice(&[0; 8]);
+        for bank in 0..4 {
+            let mut prg_bank = vec![0xEA; 0x4000];
+            prg_bank[..2].copy_from_slice(&[0xA9, bank]);
+            image.extend(prg_bank);
+        }
+        let cartridge = Cartridge::from_bytes(image).unwrap();
+
+        let blocks = cartridge.disassemble_all_banks();
+
+        assert_eq!(blocks.len(), 4);
+        let bases: Vec<(usize, u16)> = blocks.iter().map(|(bank, block)| (*bank, block[0].address)).collect();
+        assert_eq!(bases, vec![(0, 0x8000), (1, 0x8000), (2, 0x8000), (3, 0xC000)]);
+        for (bank, block) in &blocks {
+            assert_eq!(block[0].to_string(), format!("{:04X}: A9 {:02X}     LDA #${:02X}", block[0].address, bank, bank));
+            assert_eq!(block.last().unwrap().address, block[0].address + 0x3FFF);
+        }
+    }
+
+    #[test]
+    fn invalid_header_is_rejected() {
+        let mut image = nrom_image(&[]);
+        image[0] = 0x00;
+
+        assert!(Cartridge::from_bytes(image).is_err());
+    }
+
+    #[test]
+    fn errors_explain_themselves() {
+        let mut image = nrom_image(&[]);
+        image[0..4].copy_from_slice(b"PK\x03\x04");
+        let error: Box<dyn std::error::Error> = Box::new(Cartridge::from_bytes(image).err().unwrap());
+
+        assert_eq!(
+            error.to_string(),
+            "not an iNES rom: expected it to start with 4E 45 53 1A (\"NES\" then EOF) but found [50, 4B, 03, 04]"
+        );
+        assert_eq!(
+            ROMError::SizeMismatch { expected: 0x6010, actual: 0x4010 }.to_string(),
+            "rom is 16400 bytes but its header says it needs at least 24592"
+        );
+    }
 }
@@ -0,0 +1,329 @@
+use crate::rom::battery::*;
+use crate::rom::loader::load_rom_bytes;
+use crate::rom::rom::*;
+use std::cell::Cell;
+use std::f64;
+use std::panic;
+use std::rc::Rc;
+use wasm_bindgen::JsCast;
+use wasm_bindgen::__rt::core::cell::RefCell;
+use wasm_bindgen::prelude::*;
+use web_sys::console;
+use crate::cpu::trace::{TraceEvent, TraceFilter};
+use crate::nes::nes::{Nes, NesError};
+use crate::input::keyboard::KeyMap;
+use crate::ppu::palette::framebuffer_to_rgba;
+use crate::ppu::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use wasm_bindgen::Clamped;
+use web_sys::console::debug;
+
+/// Shows the disassembly and a nestest trace of the loaded rom instead of running it.
+const DEBUG_PANES: bool = false;
+
+/// The canvas is drawn at the NES resolution and scaled up by this much with CSS.
+const CANVAS_SCALE: usize = 2;
+
+/// How often battery backed PRG RAM is written to localStorage, on top of when the page is closed.
+const BATTERY_FLUSH_INTERVAL_MS: i32 = 5000;
+
+pub struct State {
+    nes: Nes,
+    key_map: KeyMap,
+}
+
+impl State {
+    /// Starts with no cartridge until the user picks a rom.
+    pub fn new() -> Self {
+        State { nes: Nes::new(), key_map: KeyMap::default() }
+    }
+
+    /// Returns whether the key is bound to a button so the browser's default action for it, e.g.
+    /// scrolling with the arrow keys, can be skipped.
+    pub fn handle_key(&mut self, key: &str, pressed: bool) -> bool {
+        let button = match self.key_map.button_for_key(key) {
+            Some(button) => button,
+            None => return false,
+        };
+        self.nes.set_button(0, button, pressed);
+        true
+    }
+
+    /// Parses the rom once and loads it into a freshly powered on console. Everything else, e.g.
+    /// the disassembler, reads the rom through the console's cartridge. The outgoing cartridge's
+    /// battery backed RAM is saved first so switching roms doesn't lose it.
+    pub fn load(&mut self, rom_bytes: Vec<u8>) -> Result<(), ROMError> {
+        self.save_battery_ram();
+        self.nes.load_cartridge(rom_bytes)
+    }
+
+    pub fn cartridge(&self) -> Option<&Cartridge> {
+        self.nes.cartridge()
+    }
+
+    /// Restores the PRG RAM saved for the current rom, if it's battery backed and was saved.
+    pub fn load_battery_ram(&mut self) {
+        let cartridge = match self.nes.cartridge_mut() {
+            Some(cartridge) if cartridge.has_battery() => cartridge,
+            _ => return,
+        };
+        let saved = local_storage().and_then(|storage| {
+            storage.get_item(&storage_key(cartridge)).ok().flatten()
+        });
+        if let Some(prg_ram) = saved.as_ref().and_then(|saved| deserialize_prg_ram(saved)) {
+            cartridge.prg_ram_mut().copy_from_slice(&prg_ram);
+        }
+    }
+
+    pub fn save_battery_ram(&self) {
+        let cartridge = match self.cartridge() {
+            Some(cartridge) if cartridge.has_battery() => cartridge,
+            _ => return,
+        };
+        if let Some(storage) = local_storage() {
+            let saved = serialize_prg_ram(cartridge.prg_ram());
+            if let Err(error) = storage.set_item(&storage_key(cartridge), &saved) {
+                console::log_1(&error);
+            }
+        }
+    }
+}
+
+fn local_storage() -> Option<web_sys::Storage> {
+    web_sys::window()?.local_storage().ok().flatten()
+}
+
+fn request_animation_frame(callback: &Closure<dyn FnMut()>) {
+    web_sys::window()
+        .unwrap()
+        .request_animation_frame(callback.as_ref().unchecked_ref())
+        .unwrap();
+}
+
+/// Runs the loaded rom for a frame every time the browser repaints and draws the result.
+fn start_render_loop(
+    state: Rc<RefCell<State>>,
+    context: web_sys::CanvasRenderingContext2d,
+) {
+    let mut rgba = vec![0; SCREEN_WIDTH * SCREEN_HEIGHT * 4];
+    // The closure needs to be able to request itself for the next frame.
+    let render_frame: Rc<RefCell<Option<Closure<dyn FnMut()>>>> = Rc::new(RefCell::new(None));
+    let first_frame = Rc::clone(&render_frame);
+    *first_frame.borrow_mut() = Some(Closure::wrap(Box::new(move || {
+        {
+            let nes = &mut state.borrow_mut().nes;
+            match nes.run_frame() {
+                Ok(()) => {
+                    framebuffer_to_rgba(nes.framebuffer(), nes.master_palette(), nes.emphasis(), &mut rgba);
+                    let image = web_sys::ImageData::new_with_u8_clamped_array_and_sh(
+                        Clamped(&rgba),
+                        SCREEN_WIDTH as u32,
+                        SCREEN_HEIGHT as u32,
+                    )
+                    .unwrap();
+                    context.put_image_data(&image, 0.0, 0.0).unwrap();
+                }
+                // Nothing to do until a rom is picked.
+                Err(NesError::NoCartridge) => (),
+                Err(error) => {
+                    console::log_1(&JsValue::from_str(&format!("{:?}", error)));
+                    nes.eject();
+                }
+            }
+        }
+        request_animation_frame(render_frame.borrow().as_ref().unwrap());
+    }) as Box<dyn FnMut()>));
+    request_animation_frame(first_frame.borrow().as_ref().unwrap());
+}
+
+// This is like the `main` function, except for JavaScript.
+#[wasm_bindgen(start)]
+pub fn main_js() -> Result<(), JsValue> {
+    // This provides better error messages in debug mode.
+    // It's disabled in release mode so it doesn't bloat up the file size.
+    #[cfg(debug_assertions)]
+    console_error_panic_hook::set_once();
+
+    let mut state = Rc::new(RefCell::new(State::new()));
+
+    {
+        let state = Rc::clone(&state);
+        let window = web_sys::window().unwrap();
+        let closure = Closure::wrap(Box::new(move || {
+            state.borrow().save_battery_ram();
+        }) as Box<dyn FnMut()>);
+        window.set_interval_with_callback_and_timeout_and_arguments_0(
+            closure.as_ref().unchecked_ref(),
+            BATTERY_FLUSH_INTERVAL_MS,
+        )?;
+        window.add_event_listener_with_callback("beforeunload", closure.as_ref().unchecked_ref())?;
+        closure.forget();
+    }
+
+    let document = web_sys::window().unwrap().document().unwrap();
+    let canvas = document
+        .create_element("canvas")?
+        .dyn_into::<web_sys::HtmlCanvasElement>()?;
+    canvas.set_width(SCREEN_WIDTH as u32);
+    canvas.set_height(SCREEN_HEIGHT as u32);
+    canvas.set_attribute(
+        "style",
+        &format!(
+            "width: {}px; height: {}px; image-rendering: pixelated;",
+            SCREEN_WIDTH * CANVAS_SCALE,
+            SCREEN_HEIGHT * CANVAS_SCALE
+        ),
+    )?;
+    document.body().unwrap().append_child(&canvas)?;
+    if !DEBUG_PANES {
+        let context = canvas
+            .get_context("2d")?
+            .unwrap()
+            .dyn_into::<web_sys::CanvasRenderingContext2d>()?;
+        start_render_loop(Rc::clone(&state), context);
+    }
+
+    for (event_name, pressed) in &[("keydown", true), ("keyup", false)] {
+        let state = Rc::clone(&state);
+        let pressed = *pressed;
+        let closure = Closure::wrap(Box::new(move |event: web_sys::KeyboardEvent| {
+            // Holding a key down fires repeated keydowns. The button is already held so they're
+            // ignored rather than toggling it.
+            if event.repeat() {
+                return;
+            }
+            if state.borrow_mut().handle_key(&event.key(), pressed) {
+                event.prevent_default();
+            }
+        }) as Box<dyn FnMut(_)>);
+        document.add_event_listener_with_callback(event_name, closure.as_ref().unchecked_ref())?;
+        closure.forget();
+    }
+
+    let file_selector = document.create_element("input")?;
+
+    file_selector.set_attribute("type", "file")?;
+    file_selector.set_attribute("id", "rom-selector")?;
+    file_selector.set_attribute("accept", ".nes,.zip")?;
+
+    document.body().unwrap().append_child(&file_selector)?;
+
+    let disassembler_output_div = Rc::new(RefCell::new(document.create_element("pre")?));
+    disassembler_output_div
+        .borrow_mut()
+        .set_attribute("id", "disassembler-output")?;
+
+    let debug_output_div = Rc::new(RefCell::new(document.create_element("pre")?));
+    debug_output_div
+        .borrow_mut()
+        .set_attribute("id", "debug-output")?;
+
+    if DEBUG_PANES {
+        document
+            .body()
+            .unwrap()
+            .append_child(&disassembler_output_div.borrow())?;
+
+        document
+            .body()
+            .unwrap()
+            .append_child(&debug_output_div.borrow())?;
+    }
+
+    let rom_selector: web_sys::HtmlInputElement = document
+        .get_element_by_id("rom-selector")
+        .unwrap()
+        .dyn_into::<web_sys::HtmlInputElement>()?;
+    {
+        let file_reader = web_sys::FileReader::new()?;
+        let closure = Closure::wrap(Box::new(move |event: web_sys::InputEvent| {
+            let rom_selector: web_sys::HtmlInputElement =
+                event.target().unwrap().dyn_into().unwrap();
+            let file_list = rom_selector.files().unwrap();
+            let file = file_list.get(0).unwrap();
+            file_reader.read_as_array_buffer(&file);
+            {
+                let state = Rc::clone(&state);
+                let disassembler_output_div = Rc::clone(&disassembler_output_div);
+                let debug_output_div = Rc::clone(&debug_output_div);
+                // Most of below based on this github issue: https://github.com/rustwasm/wasm-bindgen/issues/1292
+                let mut closure = Closure::wrap(Box::new(move |event: web_sys::Event| {
+                    let file_reader: web_sys::FileReader =
+                        event.target().unwrap().dyn_into().unwrap();
+                    let rom = file_reader.result().unwrap();
+                    let rom = js_sys::Uint8Array::new(&rom);
+                    let mut file_bytes: Vec<u8> = vec![0; rom.length() as usize];
+                    rom.copy_to(&mut file_bytes);
+                    let rom_vec = match load_rom_bytes(&file_bytes) {
+                        Ok(rom_vec) => rom_vec,
+                        Err(error) => {
+                            console::log_1(&JsValue::from_str(&format!("{:?}", error)));
+                            return;
+                        }
+                    };
+
+                    if let Err(error) = state.borrow_mut().load(rom_vec) {
+                        console::log_1(&JsValue::from_str(&format!("{:?}", error)));
+                        return;
+                    }
+                    state.borrow_mut().load_battery_ram();
+                    if !DEBUG_PANES {
+                        return;
+                    }
+
+                    let mut debug_string = String::new();
+                    for byte in &state.borrow().cartridge().unwrap().prg {
+                        debug_string.push_str(&format!("{:X} ", byte));
+                    }
+                    console::log_1(&JsValue::from_str(&debug_string));
+                    let disassembler_output = &state
+                        .borrow()
+                        .cartridge()
+                        .unwrap()
+                        .disassemble_prg_rom()
+                        .unwrap();
+                    // FIXME: Make document a Rc RefCell which will allow borrows correctly in this closure.
+                    let document = web_sys::window().unwrap().document().unwrap();
+                    let node = document.create_text_node(disassembler_output);
+                    disassembler_output_div
+                        .borrow_mut()
+                        .append_child(&node)
+                        .unwrap();
+
+                    // FIXME: fix below
+                    let nestest_output = Rc::new(RefCell::new(String::new()));
+                    let mut loc_state = state.borrow_mut();
+                    let cpu = loc_state.nes.cpu_mut().unwrap();
+                    cpu.pc = 0xC000; // nestest's automated mode starts here rather than the reset vector
+                    {
+                        let nestest_output = Rc::clone(&nestest_output);
+                        cpu.set_trace_hook(Some(Box::new(move |event: &TraceEvent| {
+                            nestest_output.borrow_mut().push_str(&format!("{}\n", event));
+                        })));
+                        cpu.set_trace_filter(TraceFilter::OnPcChange);
+                    }
+                    while cpu.total_cycles() <= 26554 {
+                        if let Err(error) = cpu.clock() {
+                            console::log_1(&JsValue::from_str(&format!("{:?}", error)));
+                            break;
+                        }
+                    }
+                    cpu.set_trace_hook(None);
+                    cpu.set_trace_filter(TraceFilter::EveryInstruction);
+
+                    let debug_node = document.create_text_node(&nestest_output.borrow());
+                    debug_output_div
+                        .borrow_mut()
+                        .append_child(&debug_node)
+                        .unwrap();
+                }) as Box<dyn FnMut(_)>);
+                file_reader.set_onload(Some(closure.as_ref().unchecked_ref()));
+                closure.forget();
+            }
+        }) as Box<dyn FnMut(_)>);
+        rom_selector
+            .add_event_listener_with_callback("change", closure.as_ref().unchecked_ref())?;
+        closure.forget();
+    }
+
+    Ok(())
+}
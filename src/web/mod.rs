@@ -0,0 +1 @@
+pub mod frontend;
\ No newline at end of file
@@ -0,0 +1,39 @@
+//! Runs Klaus Dormann's 6502 functional test suite. It tests every official opcode in every
+//! addressing mode along with the flags and stack. Each test jumps to itself when it fails so the
+//! address it got stuck on can be looked up in the listing to find which test failed.
+//!
+//! See tests/roms/README.md for where the binary comes from and how it was built.
+
+use rust_webpack_template::cpu::cpu::CPU;
+use std::fs;
+use std::path::Path;
+
+const BINARY: &str = "tests/roms/6502_functional_test.bin";
+const ENTRY_POINT: u16 = 0x0400;
+// Where the prebuilt binary traps when every test has passed.
+const SUCCESS: u16 = 0x3469;
+
+#[test]
+fn klaus_dormann_functional_test() {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join(BINARY);
+    let binary = fs::read(&path).unwrap_or_else(|error| {
+        panic!("Unable to read {}: {}. Run tests/roms/fetch.sh to download it.", path.display(), error)
+    });
+    let mut memory = [0; 0x10000];
+    memory.copy_from_slice(&binary);
+    let mut cpu = CPU::with_flat_memory(memory);
+    // The prebuilt binary includes the decimal mode tests.
+    cpu.set_decimal_enabled(true);
+    cpu.pc = ENTRY_POINT;
+
+    loop {
+        let pc = cpu.pc;
+        cpu.step()
+            .unwrap_or_else(|error| panic!("{:?} at ${:04X}", error, pc));
+        if cpu.pc == pc {
+            break;
+        }
+    }
+
+    assert_eq!(cpu.pc, SUCCESS, "Trapped at ${:04X}", cpu.pc);
+}
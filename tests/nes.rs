@@ -0,0 +1,303 @@
+//! Drives a rom purely through the public `Nes` API.
+
+use rust_webpack_template::bus::bus::RamInit;
+use rust_webpack_template::bus::controller::Button;
+use rust_webpack_template::cpu::snapshot::SnapshotError;
+use rust_webpack_template::movie::movie::{Movie, MovieError};
+use rust_webpack_template::nes::nes::{FrameOutput, Nes, NesError};
+use rust_webpack_template::nes::state::{StateError, VERSION};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// A rom that turns on NMIs and then spins. The NMI handler increments $10 so it counts frames.
+fn frame_counter_rom() -> Vec<u8> {
+    let program = [
+        0x78, // $8000 SEI
+        0xA9, 0x80, // LDA #$80
+        0x8D, 0x00, 0x20, // STA $2000
+        0xD0, 0xFE, // $8006 BNE $8006
+        0xE6, 0x10, // $8008 INC $10
+        0x40, // RTI
+    ];
+    nrom(&program, 0x8008)
+}
+
+/// A rom that plays the noise channel. Every frame the NMI handler reads controller 1 into $11,
+/// uses that as the noise period and keeps a history of it from $0200, indexed by $10.
+fn noise_controller_rom() -> Vec<u8> {
+    let program = [
+        0x78, // $8000 SEI
+        0xA9, 0x3F, // LDA #$3F
+        0x8D, 0x0C, 0x40, // STA $400C, i.e. volume 15
+        0xA9, 0x08, // LDA #$08
+        0x8D, 0x15, 0x40, // STA $4015, i.e. noise on
+        0xA9, 0x80, // LDA #$80
+        0x8D, 0x00, 0x20, // STA $2000
+        0xD0, 0xFE, // $8010 BNE $8010
+        0xE6, 0x10, // $8012 INC $10
+        0xA9, 0x01, // LDA #$01
+        0x8D, 0x16, 0x40, // STA $4016
+        0xA9, 0x00, // LDA #$00
+        0x8D, 0x16, 0x40, // STA $4016
+        0xA2, 0x08, // LDX #$08
+        0xAD, 0x16, 0x40, // $8020 LDA $4016
+        0x4A, // LSR A
+        0x26, 0x11, // ROL $11
+        0xCA, // DEX
+        0xD0, 0xF7, // BNE $8020
+        0xA5, 0x11, // LDA $11
+        0x8D, 0x0E, 0x40, // STA $400E
+        0xA4, 0x10, // LDY $10
+        0x99, 0x00, 0x02, // STA $0200,Y
+        0x40, // RTI
+    ];
+    nrom(&program, 0x8012)
+}
+
+/// An NROM image with `program` at $8000, which is also where it resets to.
+fn nrom(program: &[u8], nmi: u16) -> Vec<u8> {
+    let mut prg = vec![0xEA; 0x4000];
+    prg[..program.len()].copy_from_slice(program);
+    let [nmi_low, nmi_high] = nmi.to_le_bytes();
+    prg[0x3FFA..].copy_from_slice(&[nmi_low, nmi_high, 0x00, 0x80, 0x00, 0x80]); // NMI, reset, IRQ
+
+    let mut rom = vec![0x4E, 0x45, 0x53, 0x1A, 0x01, 0x01, 0x00, 0x00, 0, 0, 0, 0, 0, 0, 0, 0];
+    rom.extend(prg);
+    rom.extend(vec![0; 0x2000]);
+    rom
+}
+
+#[test]
+fn runs_frames_through_nes() {
+    let mut nes = Nes::load(frame_counter_rom()).unwrap();
+
+    for _ in 0..3 {
+        nes.run_frame().unwrap();
+    }
+
+    assert_eq!(nes.peek(0x0010), 3);
+    assert_eq!(nes.framebuffer().len(), 256 * 240);
+
+    let state = nes.save_state().unwrap();
+    nes.run_frame().unwrap();
+    assert_eq!(nes.peek(0x0010), 4);
+    nes.load_state(&state).unwrap();
+    assert_eq!(nes.peek(0x0010), 3);
+
+    nes.reset();
+    nes.run_frame().unwrap();
+    assert_eq!(nes.peek(0x0010), 4);
+}
+
+#[test]
+fn hooks_fire_as_frames_and_audio_are_made() {
+    let mut nes = Nes::load(frame_counter_rom()).unwrap();
+    let frames = Rc::new(RefCell::new(Vec::new()));
+    let hook_frames = Rc::clone(&frames);
+    nes.set_frame_complete_hook(Some(Box::new(move |output: &FrameOutput| {
+        assert_eq!(output.framebuffer.len(), 256 * 240);
+        hook_frames.borrow_mut().push(output.frame);
+    })));
+    let chunks = Rc::new(RefCell::new(0));
+    let hook_chunks = Rc::clone(&chunks);
+    nes.set_sample_rate(44_100);
+    nes.set_audio_ready_hook(100, Some(Box::new(move |samples: &[f32]| {
+        assert_eq!(samples.len(), 100);
+        *hook_chunks.borrow_mut() += 1;
+    })));
+
+    nes.run_frame().unwrap();
+    nes.run_frame().unwrap();
+
+    assert_eq!(*frames.borrow(), vec![1, 2]);
+    // About 735 samples a frame at 44.1kHz.
+    assert_eq!(*chunks.borrow(), 14);
+    let mut leftover = [0.0; 100];
+    assert!(nes.drain_samples(&mut leftover) < 100);
+}
+
+#[test]
+fn a_frame_is_262_scanlines() {
+    let mut nes = Nes::load(frame_counter_rom()).unwrap();
+    let frames = Rc::new(RefCell::new(Vec::new()));
+    let hook_frames = Rc::clone(&frames);
+    nes.set_frame_complete_hook(Some(Box::new(move |output: &FrameOutput| {
+        hook_frames.borrow_mut().push(output.frame);
+    })));
+
+    for _ in 0..261 {
+        nes.step_scanline().unwrap();
+    }
+    assert!(frames.borrow().is_empty());
+    nes.step_scanline().unwrap();
+    assert_eq!(*frames.borrow(), vec![1]);
+
+    // The dots left over from a scanline's 113 2/3 CPU cycles add up to whole frames.
+    for _ in 0..262 * 3 {
+        nes.step_scanline().unwrap();
+    }
+    assert_eq!(*frames.borrow(), vec![1, 2, 3, 4]);
+    assert_eq!(Nes::new().step_scanline(), Err(NesError::NoCartridge));
+}
+
+#[test]
+fn nothing_runs_without_a_cartridge() {
+    let mut nes = Nes::new();
+
+    assert_eq!(nes.run_frame(), Err(NesError::NoCartridge));
+    assert_eq!(nes.step(), Err(NesError::NoCartridge));
+    assert!(nes.cartridge().is_none());
+    assert!(nes.framebuffer().is_empty());
+
+    // Settings made before there's a cartridge apply once there is one.
+    nes.set_palette([[1, 2, 3]; 64]);
+    let palettes = Rc::new(RefCell::new(Vec::new()));
+    let hook_palettes = Rc::clone(&palettes);
+    nes.set_frame_complete_hook(Some(Box::new(move |output: &FrameOutput| {
+        hook_palettes.borrow_mut().push(output.palette[0]);
+    })));
+    nes.load_cartridge(frame_counter_rom()).unwrap();
+    nes.run_frame().unwrap();
+    assert_eq!(nes.peek(0x0010), 1);
+    assert_eq!(*palettes.borrow(), vec![[1, 2, 3]]);
+
+    nes.eject();
+    assert_eq!(nes.run_frame(), Err(NesError::NoCartridge));
+    assert_eq!(nes.peek(0x0010), 0);
+    assert!(nes.save_state_bytes().is_empty());
+}
+
+#[test]
+fn state_and_movies_from_another_rom_are_rejected() {
+    let mut nes = Nes::load(frame_counter_rom()).unwrap();
+    let mut other_rom = frame_counter_rom();
+    other_rom[16 + 0x100] = 0x00;
+    let mut other_nes = Nes::load(other_rom).unwrap();
+    let state = nes.save_state().unwrap();
+    let mut movie = Movie::new();
+    movie.rom_hash = Some(nes.cartridge().unwrap().rom_hash());
+
+    assert_eq!(other_nes.load_state(&state), Err(SnapshotError::RomMismatch));
+    assert_eq!(other_nes.load_state_bytes(&nes.save_state_bytes()), Err(StateError::RomMismatch));
+    assert_eq!(other_nes.play_movie(movie.clone()), Err(MovieError::RomMismatch));
+    assert_eq!(nes.play_movie(movie), Ok(()));
+}
+
+#[test]
+fn state_bytes_carry_on_where_they_left_off() {
+    let mut nes = Nes::load(frame_counter_rom()).unwrap();
+    for _ in 0..3 {
+        nes.run_frame().unwrap();
+    }
+    // Part way through a frame so the PPU isn't at the start of one.
+    for _ in 0..1000 {
+        nes.step().unwrap();
+    }
+    let state = nes.save_state_bytes();
+    let run = |nes: &mut Nes| {
+        for _ in 0..2 {
+            nes.run_frame().unwrap();
+        }
+        (nes.peek(0x0010), nes.framebuffer().to_vec(), nes.save_state_bytes())
+    };
+    let expected = run(&mut nes);
+
+    let mut loaded = Nes::load(frame_counter_rom()).unwrap();
+    loaded.load_state_bytes(&state).unwrap();
+
+    assert_eq!(loaded.save_state_bytes(), state);
+    assert_eq!(loaded.peek(0x0010), 3);
+    assert_eq!(run(&mut loaded), expected);
+    assert_eq!(expected.0, 5);
+}
+
+#[test]
+fn bad_state_bytes_are_rejected() {
+    let mut nes = Nes::load(frame_counter_rom()).unwrap();
+    nes.run_frame().unwrap();
+    let state = nes.save_state_bytes();
+    let mut newer = state.clone();
+    newer[4..6].copy_from_slice(&(VERSION + 1).to_le_bytes());
+    let mut longer = state.clone();
+    longer.push(0);
+    nes.run_frame().unwrap();
+    let current = nes.save_state_bytes();
+
+    assert_eq!(nes.load_state_bytes(&state[..state.len() - 1]), Err(StateError::Truncated));
+    assert_eq!(nes.load_state_bytes(&state[..2]), Err(StateError::Truncated));
+    assert_eq!(nes.load_state_bytes(&newer), Err(StateError::UnknownVersion { version: VERSION + 1 }));
+    assert_eq!(nes.load_state_bytes(&longer), Err(StateError::Corrupt));
+    assert_eq!(nes.load_state_bytes(b"not a save state"), Err(StateError::NotAState));
+    // Nothing was half loaded.
+    assert_eq!(nes.save_state_bytes(), current);
+    assert_eq!(nes.load_state_bytes(&state), Ok(()));
+}
+
+#[test]
+fn loading_another_rom_powers_on_fresh() {
+    let mut nes = Nes::load_with_ram_init(frame_counter_rom(), RamInit::Filled(0xFF)).unwrap();
+    for _ in 0..3 {
+        nes.run_frame().unwrap();
+    }
+    nes.cartridge_mut().unwrap().prg_ram_mut()[0] = 0x55;
+    let mut other_rom = frame_counter_rom();
+    other_rom[16 + 0x3FFC] = 0x06; // Reset to the spin at $8006
+
+    nes.load_cartridge(other_rom.clone()).unwrap();
+
+    assert_eq!(nes.cpu_mut().unwrap().pc, 0x8006);
+    assert_eq!(nes.peek(0x0010), 0xFF);
+    assert_eq!(nes.cartridge().unwrap().prg_ram()[0], 0xFF);
+
+    // Reloading the same rom keeps its PRG RAM, e.g. its saves.
+    nes.cartridge_mut().unwrap().prg_ram_mut()[0] = 0x55;
+    nes.load_cartridge(other_rom).unwrap();
+    assert_eq!(nes.cartridge().unwrap().prg_ram()[0], 0x55);
+}
+
+#[test]
+fn same_seed_and_inputs_give_the_same_run() {
+    let run = |seed: u64, right: fn(u32) -> bool| {
+        let mut nes = Nes::with_seed(noise_controller_rom(), seed).unwrap();
+        nes.set_sample_rate(44_100);
+        let mut audio = Vec::new();
+        for frame in 0..60 {
+            nes.set_button(0, Button::A, frame % 3 == 0);
+            nes.set_button(0, Button::Right, right(frame));
+            nes.run_frame().unwrap();
+            let mut samples = [0.0; 1024];
+            let count = nes.drain_samples(&mut samples);
+            audio.extend_from_slice(&samples[..count]);
+        }
+        let ram: Vec<u8> = (0..0x800).map(|address| nes.peek(address)).collect();
+        (ram, audio, nes.framebuffer().to_vec())
+    };
+
+    let (ram, audio, framebuffer) = run(0xC0FFEE, |frame| frame % 7 < 4);
+    assert!(audio.iter().any(|sample| *sample != 0.0));
+    assert!(ram[0x200..0x300].iter().any(|input| input & 0x01 != 0));
+
+    assert_eq!(run(0xC0FFEE, |frame| frame % 7 < 4), (ram.clone(), audio.clone(), framebuffer));
+    assert_ne!(run(1, |frame| frame % 7 < 4).0, ram);
+    let other_inputs = run(0xC0FFEE, |frame| frame % 5 < 2);
+    assert_ne!(other_inputs.0, ram);
+    assert_ne!(other_inputs.1, audio);
+}
+
+#[cfg(feature = "image")]
+#[test]
+fn screenshots_are_pngs_of_the_frame() {
+    // Rendering is never turned on so the whole frame is the backdrop colour.
+    let mut nes = Nes::load(frame_counter_rom()).unwrap();
+    nes.run_frame().unwrap();
+    let backdrop = nes.master_palette()[nes.framebuffer()[0] as usize];
+
+    let png = nes.screenshot_png();
+
+    let (info, mut reader) = png::Decoder::new(png.as_slice()).read_info().unwrap();
+    assert_eq!((info.width, info.height), (256, 240));
+    let mut rgba = vec![0; info.buffer_size()];
+    reader.next_frame(&mut rgba).unwrap();
+    assert_eq!(&rgba[..4], &[backdrop[0], backdrop[1], backdrop[2], 0xFF]);
+    assert_eq!(&rgba[rgba.len() - 4..], &rgba[..4]);
+}
@@ -0,0 +1,133 @@
+//! Runs nestest and checks the CPU against the log of what it should do, line by line. nestest
+//! covers every official opcode and most of the unofficial ones, so a full match is a regression
+//! test for the whole instruction set.
+//!
+//! See tests/roms/README.md for where the rom and log come from.
+
+use rust_webpack_template::cpu::cpu::CPU;
+use rust_webpack_template::cpu::opcode::instruction_length;
+use rust_webpack_template::cpu::trace::TraceEvent;
+use std::cell::RefCell;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::rc::Rc;
+
+const ROM: &str = "tests/roms/nestest.nes";
+const LOG: &str = "tests/roms/nestest.log";
+// nestest's automated mode starts here rather than at the reset vector.
+const ENTRY_POINT: u16 = 0xC000;
+
+/// The first line of the log that the CPU didn't match. Lines count from 1.
+#[derive(Debug)]
+struct Mismatch {
+    line: usize,
+    expected: String,
+    actual: String,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {} differs\nexpected: {}\n  actual: {}", self.line, self.expected, self.actual)
+    }
+}
+
+/// The parts of a nestest.log line that are compared, i.e. the PC, the instruction's bytes, the
+/// registers and the cycle count. The disassembly is left out since it's only a different way of
+/// showing the bytes, e.g. nestest calls ISC ISB. So is the PPU position since only the CPU runs.
+/// None if the line isn't in the log's format.
+fn nestest_line(line: &str) -> Option<String> {
+    let registers = line.find("A:")?;
+    let ppu = line.find(" PPU:")?;
+    let cycles = line.find("CYC:")?;
+    let bytes = line.get(..14)?.trim_end();
+
+    Some(format!("{}  {}  {}", bytes, &line[registers..ppu], line[cycles..].trim_end()))
+}
+
+/// `event` the same way `nestest_line` cuts down a line of the log.
+fn format_event(event: &TraceEvent) -> String {
+    let length = instruction_length(event.decoded_opcode.mode);
+    let bytes: Vec<String> = Some(event.opcode)
+        .iter()
+        .chain(event.operand_bytes.iter())
+        .take(length)
+        .map(|byte| format!("{:02X}", byte))
+        .collect();
+
+    format!(
+        "{:04X}  {}  A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X}  CYC:{}",
+        event.pc, bytes.join(" "), event.a, event.x, event.y, event.p, event.sp, event.total_cycles
+    )
+}
+
+/// Runs `rom` from `entry` an instruction at a time for as many instructions as `reference_log`
+/// has lines, stopping at the first that doesn't match.
+fn run_and_compare(rom: Vec<u8>, entry: u16, reference_log: &str) -> Result<(), Mismatch> {
+    let mut cpu = CPU::new(rom);
+    cpu.pc = entry;
+    let traced = Rc::new(RefCell::new(None));
+    {
+        let traced = Rc::clone(&traced);
+        cpu.set_trace_hook(Some(Box::new(move |event: &TraceEvent| {
+            *traced.borrow_mut() = Some(format_event(event));
+        })));
+    }
+
+    for (index, line) in reference_log.lines().enumerate() {
+        let expected = nestest_line(line).unwrap_or_else(|| line.trim_end().to_string());
+        let actual = match cpu.step() {
+            Ok(_) => traced.borrow_mut().take().unwrap_or_else(|| "halted".to_string()),
+            Err(error) => format!("{:?}", error),
+        };
+        if actual != expected {
+            return Err(Mismatch { line: index + 1, expected, actual });
+        }
+    }
+
+    Ok(())
+}
+
+/// An NROM image with `program` at $C000.
+fn rom_with_program(program: &[u8]) -> Vec<u8> {
+    let mut prg = vec![0xEA; 0x4000];
+    prg[..program.len()].copy_from_slice(program);
+
+    let mut rom = vec![0x4E, 0x45, 0x53, 0x1A, 0x01, 0x01, 0x00, 0x00, 0, 0, 0, 0, 0, 0, 0, 0];
+    rom.extend(prg);
+    rom.extend(vec![0; 0x2000]);
+    rom
+}
+
+#[test]
+fn stops_at_the_first_line_that_differs() {
+    let rom = rom_with_program(&[0xA9, 0x01, 0xA2, 0x02]); // LDA #$01, LDX #$02
+    let log = "\
+C000  A9 01     LDA #$01                        A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7
+C002  A2 02     LDX #$02                        A:01 X:00 Y:00 P:24 SP:FD PPU:  0, 27 CYC:9
+C004  EA        NOP                             A:01 X:02 Y:00 P:24 SP:FD PPU:  0, 33 CYC:11
+";
+    assert!(run_and_compare(rom.clone(), 0xC000, log).is_ok());
+
+    let wrong_log = log.replace("A:01 X:00", "A:01 X:05").replace("A:01 X:02", "A:FF X:FF");
+    let mismatch = run_and_compare(rom, 0xC000, &wrong_log).unwrap_err();
+    assert_eq!(mismatch.line, 2);
+    assert_eq!(mismatch.expected, "C002  A2 02  A:01 X:05 Y:00 P:24 SP:FD  CYC:9");
+    assert_eq!(mismatch.actual, "C002  A2 02  A:01 X:00 Y:00 P:24 SP:FD  CYC:9");
+}
+
+#[test]
+fn nestest_matches_the_log() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let read = |file: &str| {
+        let path = root.join(file);
+        fs::read(&path).unwrap_or_else(|error| {
+            panic!("Unable to read {}: {}. Run tests/roms/fetch.sh to download it.", path.display(), error)
+        })
+    };
+    let log = String::from_utf8(read(LOG)).unwrap();
+
+    if let Err(mismatch) = run_and_compare(read(ROM), ENTRY_POINT, &log) {
+        panic!("{}", mismatch);
+    }
+}
@@ -0,0 +1,15 @@
+#!/bin/sh
+# Downloads the test roms described in README.md into this folder. Once they're here they should
+# be committed so the tests that use them run without a network.
+set -e
+cd "$(dirname "$0")"
+
+fetch() {
+    curl -sSfL -o "$1" "$2"
+    echo "$1"
+}
+
+fetch 6502_functional_test.bin \
+    https://raw.githubusercontent.com/Klaus2m5/6502_65C02_functional_tests/master/bin_files/6502_functional_test.bin
+fetch nestest.nes http://nickmass.com/images/nestest.nes
+fetch nestest.log http://www.qmtpro.com/~nes/misc/nestest.log